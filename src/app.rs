@@ -7,7 +7,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use cgmath::{InnerSpace, Matrix3, One, Quaternion, Vector3, Vector4};
+use cgmath::{Matrix3, One, Quaternion, Vector3, Vector4};
 use legion::*;
 
 // use rand::Rng;
@@ -24,8 +24,8 @@ use crate::{
         Camera, GameWorld, Inputs, MaterialSwapper, WorldLoader,
     },
     load_object,
-    physics::{quick_inverse, CuboidCollider, RigidBody},
-    prefabs::{init_char_test, init_phys_test, init_ui_test, init_world},
+    physics::{quick_inverse, Collider, ContactSolver, RigidBody},
+    prefabs::{init_char_test, init_phys_test, init_terrain_test, init_ui_test, init_world},
     render::{resource_manager::ResourceManager, DeferredRenderer, RenderLoop, RenderObject},
     shaders::{DirectionLight, GPUGlobalData, GPUAABB},
     ui::{self, MenuOption},
@@ -51,6 +51,7 @@ struct InputState {
     ctrl: bool,
 
     q: ButtonState,
+    c: ButtonState,
     r: ButtonState,
     i: ButtonState,
     o: ButtonState,
@@ -58,6 +59,7 @@ struct InputState {
     lmb: ButtonState,
     escape: ButtonState,
     equals: ButtonState,
+    minus: ButtonState,
 }
 
 impl Into<Inputs> for &InputState {
@@ -132,7 +134,10 @@ impl App {
         println!("Welcome to THE RUSTY RENDERER!");
         println!("Press WASD, SPACE and LSHIFT to move and Q to swap materials");
         println!("Press O to spawn a cube at the camera, press I to filter the depth shown");
-        println!("Press P to pause the logic loop and = to advance it by 1 frame");
+        println!(
+            "Press P to pause the logic loop, = to advance it by 1 frame and - to step it back"
+        );
+        println!("Press C to swap between the heap and sequential impulse contact solvers");
         println!("[TODO] Press F to toggle camera light");
 
         let world = Arc::new(Mutex::new(GameWorld::new()));
@@ -171,6 +176,7 @@ impl App {
             1 => init_ui_test,
             2 => init_phys_test,
             3 => init_char_test,
+            4 => init_terrain_test,
             _ => {
                 return Err(format!("Tried to load invalid level id: {id}"));
             }
@@ -178,11 +184,15 @@ impl App {
 
         let world = &mut *self.world.lock().unwrap();
         world.clear();
+        // record while running too, so pausing can step back to before it was paused
+        world.set_history_enabled(true);
         let resources = &mut graphics
             .resources
             .begin_retrieving(&graphics.render_loop.context, &mut graphics.renderer);
 
         loader(WorldLoader { world, resources });
+        // levels add their colliders one by one, build a better tree with all of them
+        world.colliders.rebuild_tree();
 
         // camera light, child of the camera
         let camera_light = world.transforms.add_transform(
@@ -372,6 +382,16 @@ impl App {
                         });
                     }
 
+                    // swap contact solver
+                    if self.inputs.c.consume_button_down() {
+                        let solver = match colliders.get_contact_solver() {
+                            ContactSolver::Heap => ContactSolver::SequentialImpulse,
+                            ContactSolver::SequentialImpulse => ContactSolver::Heap,
+                        };
+                        println!("Contact solver: {:?}", solver);
+                        colliders.set_contact_solver(solver);
+                    }
+
                     // add random bounds
                     if self.inputs.o.consume_button_down() {
                         // let mut rng = rand::thread_rng();
@@ -404,9 +424,10 @@ impl App {
                         let mut rigidbody = RigidBody::new(transform);
                         // rigidbody.gravity_multiplier = 0.0;
                         rigidbody.set_moi_as_cuboid((1., 1., 1.).into());
+                        rigidbody.ccd = true;
                         let rigidbody = Arc::new(RwLock::new(rigidbody));
 
-                        let collider = CuboidCollider::new(transform, Some(rigidbody.clone()));
+                        let collider = Collider::cuboid(transform, Some(rigidbody.clone()));
                         let collider = colliders.add(collider, transforms);
 
                         let mut resource_loader =
@@ -655,12 +676,16 @@ impl App {
                     }
 
                     // raycast
-                    let cam_model = transforms.get_global_model(&camera.transform).unwrap();
+                    let cam_position = transforms.get_global_position(&camera.transform).unwrap();
+                    let cam_forward = transforms
+                        .transform_direction(&camera.transform, -Vector3::unit_z())
+                        .unwrap();
                     let raycast_result = colliders.raycast(
                         transforms,
-                        cam_model.w.truncate(),
-                        -cam_model.z.truncate(),
+                        cam_position,
+                        cam_forward,
                         20.,
+                        Collider::ALL_LAYERS,
                     );
                     if let Some((point, coll)) = raycast_result {
                         let min_cast: [f32; 3] = (point - Vector3::new(0.1, 0.1, 0.1)).into();
@@ -676,7 +701,7 @@ impl App {
                                 let mut model =
                                     transforms.get_global_model(coll.get_transform()).unwrap();
                                 quick_inverse(&mut model);
-                                // let normal = CuboidCollider::point_normal(point, &model).normalize();
+                                // let normal = Collider::point_normal(point, &model).normalize();
 
                                 let rotation = transforms
                                     .get_transform(coll.get_transform())
@@ -686,7 +711,7 @@ impl App {
                                 let point = point + model.w.truncate();
                                 rigidbody.write().unwrap().apply_impulse(
                                     point,
-                                    -1.5 * cam_model.z.truncate().normalize(),
+                                    1.5 * cam_forward,
                                     *rotation,
                                 );
                             }
@@ -793,6 +818,9 @@ impl App {
             PhysicalKey::Code(KeyCode::KeyQ) => {
                 self.inputs.q.update_state(state);
             }
+            PhysicalKey::Code(KeyCode::KeyC) => {
+                self.inputs.c.update_state(state);
+            }
             PhysicalKey::Code(KeyCode::KeyR) => {
                 if self.game_state == GameState::Playing && self.inputs.r.update_state(state) {
                     let _ = self.load_level(self.current_level);
@@ -849,6 +877,18 @@ impl App {
                     self.game_thread.step();
                 }
             }
+            PhysicalKey::Code(KeyCode::Minus) => {
+                // step logic loop back
+                if self.inputs.minus.update_state(state) {
+                    let paused = self
+                        .game_thread
+                        .paused
+                        .load(std::sync::atomic::Ordering::Acquire);
+                    if paused && !self.world.lock().unwrap().step_back() {
+                        println!("[Warning] No more history to step back through");
+                    }
+                }
+            }
             PhysicalKey::Code(KeyCode::KeyO) => {
                 // add bounding box
                 self.inputs.o.update_state(state);
@@ -1035,6 +1075,8 @@ impl GameWorldThread {
                     }
 
                     world.update(update_period.as_secs_f32());
+                    // interpolation is timed from here so the update itself never reads the clock
+                    world.transforms.update_last_fixed();
                     update_period = Duration::from_micros(new_micros);
 
                     // skip frames if update took too long
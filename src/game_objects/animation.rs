@@ -0,0 +1,433 @@
+use std::{
+    ops::{Add, Mul, Sub},
+    sync::Arc,
+};
+
+use cgmath::{InnerSpace, Quaternion, Vector3};
+
+use super::transform::Transform;
+
+/// how values between keyframes are calculated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// hold the value of the previous keyframe
+    Step,
+    Linear,
+    /// catmull-rom spline through the keyframes, smooth across them
+    Cubic,
+}
+
+/// what an animator does once it reaches the end of its clip
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    /// stop on the last keyframes
+    Once,
+    /// start again from the beginning
+    Loop,
+    /// play backwards to the beginning, then forwards again
+    PingPong,
+}
+
+/// value that can be keyframed
+pub trait Animatable:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self>
+{
+    /// equivalent of self nearest to other, so interpolating between them takes the shortest path
+    fn align(self, _other: Self) -> Self {
+        self
+    }
+    /// put an interpolated value back into a valid state
+    fn normalise(self) -> Self {
+        self
+    }
+    fn lerp(self, other: Self, amount: f32) -> Self {
+        self + (other - self) * amount
+    }
+}
+
+impl Animatable for Vector3<f32> {}
+
+impl Animatable for Quaternion<f32> {
+    fn align(self, other: Self) -> Self {
+        match self.dot(other) < 0. {
+            true => -self,
+            false => self,
+        }
+    }
+    fn normalise(self) -> Self {
+        self.normalize()
+    }
+    fn lerp(self, other: Self, amount: f32) -> Self {
+        self.slerp(other.align(self), amount).normalize()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    /// seconds from the start of the clip
+    pub time: f32,
+    pub value: T,
+}
+
+/// keyframes of one value, sorted by time
+#[derive(Clone, Debug)]
+pub struct Track<T: Animatable> {
+    keyframes: Vec<Keyframe<T>>,
+    interpolation: Interpolation,
+}
+
+/// translation, rotation and scale tracks played together on a transform,
+/// values without a track are left as they are
+#[derive(Clone, Debug, Default)]
+pub struct AnimationClip {
+    translation: Option<Track<Vector3<f32>>>,
+    rotation: Option<Track<Quaternion<f32>>>,
+    scale: Option<Track<Vector3<f32>>>,
+    duration: f32,
+}
+
+/// plays a clip on the transform of its entity, advanced by `GameWorld::update`
+#[derive(Clone)]
+pub struct Animator {
+    clip: Arc<AnimationClip>,
+    mode: LoopMode,
+    /// multiplier of the time passed, negative plays backwards
+    pub speed: f32,
+    pub paused: bool,
+    /// seconds played, wrapped by twice the clip's duration when looping to keep its precision
+    time: f32,
+}
+
+impl<T: Animatable> Track<T> {
+    /// keyframes as (time, value), panics if there are none
+    pub fn new(
+        keyframes: impl IntoIterator<Item = (f32, T)>,
+        interpolation: Interpolation,
+    ) -> Self {
+        let mut keyframes: Vec<_> = keyframes
+            .into_iter()
+            .map(|(time, value)| Keyframe { time, value })
+            .collect();
+        assert!(!keyframes.is_empty(), "track without keyframes");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self {
+            keyframes,
+            interpolation,
+        }
+    }
+
+    /// time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().unwrap().time
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe<T>] {
+        &self.keyframes
+    }
+
+    /// value at the time, held at the first and last keyframes outside of them
+    pub fn sample(&self, time: f32) -> T {
+        let keys = &self.keyframes;
+        // index of the keyframe ending the segment the time is in
+        let next = keys.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return keys[0].value;
+        }
+        if next == keys.len() {
+            return keys[next - 1].value;
+        }
+
+        let (start, end) = (&keys[next - 1], &keys[next]);
+        let segment = end.time - start.time;
+        let amount = (time - start.time) / segment;
+        match self.interpolation {
+            Interpolation::Step => start.value,
+            Interpolation::Linear => start.value.lerp(end.value, amount),
+            Interpolation::Cubic => {
+                let p_1 = start.value;
+                let p_2 = end.value.align(p_1);
+                let before = keys.get(next.wrapping_sub(2)).unwrap_or(start);
+                let after = keys.get(next + 1).unwrap_or(end);
+                let p_0 = before.value.align(p_1);
+                let p_3 = after.value.align(p_2);
+
+                // tangents scaled to the segment, so uneven keyframe spacing doesn't overshoot
+                let m_1 = tangent(p_0, p_2, before.time, end.time) * segment;
+                let m_2 = tangent(p_1, p_3, start.time, after.time) * segment;
+                hermite(p_1, m_1, p_2, m_2, amount).normalise()
+            }
+        }
+    }
+}
+
+impl AnimationClip {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_translation(mut self, track: Track<Vector3<f32>>) -> Self {
+        self.duration = self.duration.max(track.duration());
+        self.translation = Some(track);
+        self
+    }
+    pub fn with_rotation(mut self, track: Track<Quaternion<f32>>) -> Self {
+        self.duration = self.duration.max(track.duration());
+        self.rotation = Some(track);
+        self
+    }
+    pub fn with_scale(mut self, track: Track<Vector3<f32>>) -> Self {
+        self.duration = self.duration.max(track.duration());
+        self.scale = Some(track);
+        self
+    }
+
+    /// time of the last keyframe of any track
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// set the local values of the transform that have tracks to their values at the time
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        if let Some(track) = &self.translation {
+            transform.set_translation(track.sample(time));
+        }
+        if let Some(track) = &self.rotation {
+            transform.set_rotation(track.sample(time));
+        }
+        if let Some(track) = &self.scale {
+            transform.set_scale(track.sample(time));
+        }
+    }
+}
+
+impl Animator {
+    pub fn new(clip: Arc<AnimationClip>, mode: LoopMode) -> Self {
+        Self {
+            clip,
+            mode,
+            speed: 1.,
+            paused: false,
+            time: 0.,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn clip(&self) -> &Arc<AnimationClip> {
+        &self.clip
+    }
+
+    /// play a different clip from the start
+    pub fn set_clip(&mut self, clip: Arc<AnimationClip>, mode: LoopMode) {
+        self.clip = clip;
+        self.mode = mode;
+        self.time = 0.;
+    }
+
+    /// jump to seconds from the start
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    /// a clip played once has reached its end, in the direction it's playing
+    pub fn is_finished(&self) -> bool {
+        self.mode == LoopMode::Once
+            && match self.speed < 0. {
+                true => self.time <= 0.,
+                false => self.time >= self.clip.duration,
+            }
+    }
+
+    /// time in the clip after wrapping by the loop mode
+    pub fn clip_time(&self) -> f32 {
+        let duration = self.clip.duration;
+        if duration <= 0. {
+            return 0.;
+        }
+        match self.mode {
+            LoopMode::Once => self.time.clamp(0., duration),
+            LoopMode::Loop => self.time.rem_euclid(duration),
+            LoopMode::PingPong => {
+                let time = self.time.rem_euclid(2. * duration);
+                match time > duration {
+                    true => 2. * duration - time,
+                    false => time,
+                }
+            }
+        }
+    }
+
+    /// move forwards by the time step and pose the transform, does nothing while paused
+    pub fn update(&mut self, seconds_passed: f32, transform: &mut Transform) {
+        if self.paused {
+            return;
+        }
+        self.time += seconds_passed * self.speed;
+        let duration = self.clip.duration;
+        self.time = match self.mode {
+            LoopMode::Once => self.time.clamp(0., duration),
+            // a multiple of the duration for both modes, so the clip time doesn't change
+            _ if duration > 0. => self.time.rem_euclid(2. * duration),
+            _ => self.time,
+        };
+        self.clip.apply(self.clip_time(), transform);
+    }
+}
+
+/// slope at a keyframe from the ones either side of it, per second
+fn tangent<T: Animatable>(before: T, after: T, before_time: f32, after_time: f32) -> T {
+    let span = after_time - before_time;
+    match span > 0. {
+        true => (after - before) * (1. / span),
+        false => (after - before) * 0.,
+    }
+}
+
+/// cubic hermite spline between p_1 and p_2 with the tangents at each
+fn hermite<T: Animatable>(p_1: T, m_1: T, p_2: T, m_2: T, t: f32) -> T {
+    let t_2 = t * t;
+    let t_3 = t_2 * t;
+    p_1 * (2. * t_3 - 3. * t_2 + 1.)
+        + m_1 * (t_3 - 2. * t_2 + t)
+        + p_2 * (-2. * t_3 + 3. * t_2)
+        + m_2 * (t_3 - t_2)
+}
+
+#[cfg(test)]
+mod animation_tests {
+    use super::{AnimationClip, Animator, Interpolation, LoopMode, Track};
+    use crate::game_objects::{transform::TransformCreateInfo, GameWorld};
+    use cgmath::{Deg, InnerSpace, Quaternion, Rotation, Rotation3, Vector3};
+    use std::sync::Arc;
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{a:?} != {b:?}");
+    }
+
+    fn track(interpolation: Interpolation) -> Track<Vector3<f32>> {
+        Track::new(
+            [
+                (2., Vector3::new(4., 0., 0.)),
+                (0., Vector3::new(0., 0., 0.)),
+                (1., Vector3::new(1., 0., 0.)),
+            ],
+            interpolation,
+        )
+    }
+
+    #[test]
+    fn sample_interpolations() {
+        let step = track(Interpolation::Step);
+        assert_close(step.sample(0.9), Vector3::new(0., 0., 0.));
+        assert_close(step.sample(1.5), Vector3::new(1., 0., 0.));
+
+        let linear = track(Interpolation::Linear);
+        assert_close(linear.sample(-1.), Vector3::new(0., 0., 0.));
+        assert_close(linear.sample(0.5), Vector3::new(0.5, 0., 0.));
+        assert_close(linear.sample(1.5), Vector3::new(2.5, 0., 0.));
+        assert_close(linear.sample(3.), Vector3::new(4., 0., 0.));
+
+        // passes through the keyframes, speeding up towards the steeper segment
+        let cubic = track(Interpolation::Cubic);
+        assert_close(cubic.sample(1.), Vector3::new(1., 0., 0.));
+        assert!(cubic.sample(0.5).x < 0.5);
+        assert!(cubic.sample(1.5).x < 2.5);
+        assert!(cubic.sample(1.5).x > 1.);
+    }
+
+    #[test]
+    fn rotation_shortest_arc() {
+        let rotation = Track::new(
+            [
+                (0., Quaternion::from_angle_y(Deg(10.))),
+                // same rotation as -10 degrees from the other hemisphere
+                (1., -Quaternion::from_angle_y(Deg(-10.))),
+            ],
+            Interpolation::Cubic,
+        );
+        let halfway = rotation.sample(0.5);
+        assert!((halfway.magnitude() - 1.).abs() < 1e-5);
+        assert_close(halfway.rotate_vector(Vector3::unit_z()), Vector3::unit_z());
+    }
+
+    #[test]
+    fn loop_modes() {
+        let clip = Arc::new(AnimationClip::new().with_translation(track(Interpolation::Linear)));
+        let times = |mode, speed| {
+            let mut animator = Animator::new(clip.clone(), mode).with_speed(speed);
+            let mut transforms = crate::game_objects::transform::TransformSystem::new();
+            let id = transforms.add_transform(TransformCreateInfo::default());
+            [0.5, 1., 1.5, 2.]
+                .map(|step| {
+                    animator.update(step, transforms.get_transform_mut(&id).unwrap());
+                    animator.clip_time()
+                })
+                .to_vec()
+        };
+
+        assert_eq!(times(LoopMode::Once, 1.), vec![0.5, 1.5, 2., 2.]);
+        assert_eq!(times(LoopMode::Loop, 1.), vec![0.5, 1.5, 1., 1.]);
+        assert_eq!(times(LoopMode::PingPong, 1.), vec![0.5, 1.5, 1., 1.]);
+        assert_eq!(times(LoopMode::PingPong, 0.5), vec![0.25, 0.75, 1.5, 1.5]);
+        assert_eq!(times(LoopMode::Loop, -1.), vec![1.5, 0.5, 1., 1.]);
+
+        let mut animator = Animator::new(clip.clone(), LoopMode::Once);
+        let mut transforms = crate::game_objects::transform::TransformSystem::new();
+        let id = transforms.add_transform(TransformCreateInfo::default());
+        animator.update(1.5, transforms.get_transform_mut(&id).unwrap());
+        assert!(!animator.is_finished());
+        animator.update(1., transforms.get_transform_mut(&id).unwrap());
+        assert!(animator.is_finished());
+
+        // looping for a long time keeps the played time within a cycle
+        let mut animator = Animator::new(clip, LoopMode::PingPong);
+        for _ in 0..1000 {
+            animator.update(0.75, transforms.get_transform_mut(&id).unwrap());
+        }
+        // 750 seconds is 187 and a half cycles of 4 seconds
+        assert_eq!(animator.time, 2.);
+        assert_eq!(animator.clip_time(), 2.);
+    }
+
+    #[test]
+    fn animator_in_game_world() {
+        let mut world = GameWorld::new();
+        world.set_history_enabled(true);
+        let clip = AnimationClip::new()
+            .with_translation(track(Interpolation::Linear))
+            .with_scale(Track::new(
+                [
+                    (0., Vector3::new(1., 1., 1.)),
+                    (2., Vector3::new(3., 1., 1.)),
+                ],
+                Interpolation::Linear,
+            ));
+        let transform = world
+            .transforms
+            .add_transform(TransformCreateInfo::from([0., 5., 0.]));
+        world
+            .world
+            .push((transform, Animator::new(Arc::new(clip), LoopMode::Loop)));
+
+        for _ in 0..3 {
+            world.update(0.5);
+        }
+        let view = world.transforms.get_transform(&transform).unwrap();
+        let local = view.get_local_transform();
+        assert_close(*local.translation, Vector3::new(2.5, 0., 0.));
+        assert_close(*local.scale, Vector3::new(2.5, 1., 1.));
+
+        // stepping back rewinds the animator too
+        assert!(world.step_back());
+        world.update(0.5);
+        let local = world.transforms.get_transform(&transform).unwrap();
+        assert_close(
+            *local.get_local_transform().translation,
+            Vector3::new(2.5, 0., 0.),
+        );
+    }
+}
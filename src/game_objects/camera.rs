@@ -16,6 +16,8 @@ pub struct Camera {
     rotation: Quaternion<f32>,
     smooth_pos: Vector3<f32>,
     pub transform: TransformID,
+    /// transform of the character followed by the camera, moved with the inputs when None
+    pub target: Option<TransformID>,
     damper: VectorDamp,
 }
 
@@ -69,6 +71,7 @@ impl Camera {
             rotation: Quaternion::one(),
             smooth_pos: Vector3::zero(),
             transform,
+            target: None,
             damper: VectorDamp::new(CAM_DAMP_STRENGTH),
         }
     }
@@ -1,20 +1,38 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
 
 use cgmath::{InnerSpace, Quaternion, Rotation, Rotation3, Vector3, Zero};
 
 use crate::{
-    physics::{ColliderSystem, LeafInHierachy, RigidBody},
+    physics::{
+        solve_joints, AnchoredSpring, Buoyancy, CharacterController, ColliderRef, ColliderSnapshot,
+        ColliderSystem, CollisionEvent, ConstantForce, Drag, ForceGenerator, Joint, PointAttractor,
+        RigidBody,
+    },
+    utilities::RingBuffer,
     LOGIC_PROFILER,
 };
 
 use super::{
-    transform::{Transform, TransformID, TransformSystem},
+    animation::Animator,
+    transform::{RemoveMode, Transform, TransformID, TransformSnapshot, TransformSystem},
     Camera, Rotate, TransformTracker,
 };
 use legion::*;
 
 const CAM_SPEED: f32 = 6.;
+/// updates kept in the history to step back through
+const HISTORY_LENGTH: usize = 128;
 const SLOW_COEFF: f32 = 0.1;
+/// camera position relative to the character it follows, in camera space
+const CHARACTER_CAM_OFFSET: Vector3<f32> = Vector3 {
+    x: 0.,
+    y: 1.,
+    z: 5.,
+};
 
 pub struct Inputs {
     pub movement: Vector3<f32>,
@@ -30,9 +48,8 @@ impl Default for Inputs {
     }
 }
 impl Inputs {
-    pub fn move_transform(&self, transform: &mut Transform, seconds_passed: f32) {
-        let view = transform.get_local_transform();
-
+    /// normalised horizontal movement relative to the rotation, slowed if slow is held
+    pub fn planar_direction(&self, rotation: Quaternion<f32>) -> Vector3<f32> {
         let mut final_move = self.movement;
         final_move.y = 0.;
 
@@ -47,16 +64,26 @@ impl Inputs {
         //     movement.x += 1.; // right
         // }
 
-        final_move = view.rotation.rotate_vector(final_move);
+        final_move = rotation.rotate_vector(final_move);
         final_move.y = 0.;
         if final_move != Vector3::zero() {
             final_move = final_move.normalize();
         }
 
+        if self.slow {
+            final_move *= SLOW_COEFF;
+        }
+        final_move
+    }
+
+    pub fn move_transform(&self, transform: &mut Transform, seconds_passed: f32) {
+        let view = transform.get_local_transform();
+
+        let mut final_move = self.planar_direction(*view.rotation);
         final_move.y = self.movement.y;
 
         if self.slow {
-            final_move *= SLOW_COEFF;
+            final_move.y *= SLOW_COEFF;
         }
 
         // apply movement
@@ -73,6 +100,27 @@ pub struct GameWorld {
     pub fixed_seconds: f32,
     pub last_delta_time: f32,
     pub inputs: Inputs,
+    /// iterate in transform order and never read the clock, so replaying inputs gives the same world
+    deterministic: bool,
+    /// record a snapshot before each update, off by default since every snapshot clones the world
+    history_enabled: bool,
+    /// snapshots from before each recent update, newest last
+    pub history: RingBuffer<WorldSnapshot>,
+}
+
+/// simulation state of a game world at one point, restored with `GameWorld::restore`
+///
+/// covers transforms, rigidbodies (including their cached contacts), character controllers,
+/// animators and colliders, the camera and inputs aren't included
+#[derive(Clone)]
+pub struct WorldSnapshot {
+    fixed_seconds: f32,
+    entities: HashSet<Entity>,
+    transforms: TransformSnapshot,
+    rigid_bodies: Vec<(Arc<RwLock<RigidBody>>, RigidBody)>,
+    characters: HashMap<Entity, CharacterController>,
+    animators: HashMap<Entity, Animator>,
+    colliders: ColliderSnapshot,
 }
 
 impl GameWorld {
@@ -93,25 +141,228 @@ impl GameWorld {
             fixed_seconds: 0.,
             last_delta_time: 0.,
             inputs: Inputs::default(),
+            deterministic: false,
+            history_enabled: false,
+            history: RingBuffer::new(HISTORY_LENGTH),
+        }
+    }
+
+    /// step the same way every run, bodies, colliders and contacts are processed in transform order
+    /// instead of legion's archetype order and profiling is skipped
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+        self.colliders.set_deterministic(deterministic);
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// record a snapshot before each update so the world can step back, disabling drops the history
+    pub fn set_history_enabled(&mut self, enabled: bool) {
+        self.history_enabled = enabled;
+        if !enabled {
+            self.history.clear();
+        }
+    }
+
+    pub fn is_history_enabled(&self) -> bool {
+        self.history_enabled
+    }
+
+    /// capture the current simulation state
+    pub fn snapshot(&self) -> WorldSnapshot {
+        let mut query = <Entity>::query();
+        let entities = query.iter(&self.world).copied().collect();
+        let mut query = <&Arc<RwLock<RigidBody>>>::query();
+        let rigid_bodies = query
+            .iter(&self.world)
+            .map(|rigid_body| (rigid_body.clone(), rigid_body.read().unwrap().clone()))
+            .collect();
+        let mut query = <(Entity, &CharacterController)>::query();
+        let characters = query
+            .iter(&self.world)
+            .map(|(entity, character)| (*entity, character.clone()))
+            .collect();
+        let mut query = <(Entity, &Animator)>::query();
+        let animators = query
+            .iter(&self.world)
+            .map(|(entity, animator)| (*entity, animator.clone()))
+            .collect();
+
+        WorldSnapshot {
+            fixed_seconds: self.fixed_seconds,
+            entities,
+            transforms: self.transforms.snapshot(),
+            rigid_bodies,
+            characters,
+            animators,
+            colliders: self.colliders.snapshot(),
+        }
+    }
+
+    /// go back to the state of the snapshot, entities and transforms added since it was taken are
+    /// removed
+    ///
+    /// the camera stays where it is so the restored world can be looked around
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) {
+        let mut query = <Entity>::query();
+        let spawned: Vec<_> = query
+            .iter(&self.world)
+            .filter(|entity| !snapshot.entities.contains(entity))
+            .copied()
+            .collect();
+        for entity in spawned {
+            self.world.remove(entity);
+        }
+
+        let camera = self
+            .transforms
+            .get_transform(&self.camera.transform)
+            .cloned();
+        self.transforms.restore(&snapshot.transforms);
+        if let Some(camera) = camera {
+            *self
+                .transforms
+                .get_transform_mut(&self.camera.transform)
+                .unwrap() = camera;
+        }
+
+        for (rigid_body, state) in snapshot.rigid_bodies.iter() {
+            *rigid_body.write().unwrap() = state.clone();
         }
+        let mut query = <(Entity, &mut CharacterController)>::query();
+        for (entity, character) in query.iter_mut(&mut self.world) {
+            if let Some(state) = snapshot.characters.get(entity) {
+                *character = state.clone();
+            }
+        }
+        let mut query = <(Entity, &mut Animator)>::query();
+        for (entity, animator) in query.iter_mut(&mut self.world) {
+            if let Some(state) = snapshot.animators.get(entity) {
+                *animator = state.clone();
+            }
+        }
+        self.colliders.restore(&snapshot.colliders);
+        self.fixed_seconds = snapshot.fixed_seconds;
+    }
+
+    /// restore the snapshot from before the last update in the history, false if there are none left
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop() {
+            Some(snapshot) => {
+                self.restore(&snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// enter/stay/exit events of touching colliders from the last update, events left undrained
+    /// are dropped by the next one
+    pub fn drain_collision_events(&mut self) -> std::vec::Drain<'_, CollisionEvent> {
+        self.colliders.drain_events()
+    }
+
+    /// remove the entity along with its transform's descendants and every entity on them,
+    /// false if it doesn't exist or the camera is on its transform or a descendant
+    ///
+    /// their colliders are removed, render objects and lights are dropped with the entities and
+    /// joints to removed rigidbodies are removed, the history is cleared since it could bring
+    /// back colliders without transforms
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        let Some(entry) = self.world.entry(entity) else {
+            return false;
+        };
+        let transform = entry.get_component::<TransformID>().ok().copied();
+        let Some(transform) = transform else {
+            return self.world.remove(entity);
+        };
+        if self
+            .transforms
+            .is_descendant(&self.camera.transform, &transform)
+        {
+            return false;
+        }
+
+        let removed: HashSet<_> = self
+            .transforms
+            .remove_transform(&transform, RemoveMode::Recursive)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+
+        // entities on removed transforms
+        let mut query = <(Entity, &TransformID)>::query();
+        let entities: Vec<_> = query
+            .iter(&self.world)
+            .filter(|(_, id)| removed.contains(id))
+            .map(|(entity, _)| *entity)
+            .collect();
+        for entity in entities {
+            if let Some(entry) = self.world.entry(entity) {
+                if let Ok(collider) = entry.get_component::<ColliderRef>() {
+                    let _ = self.colliders.remove(*collider);
+                }
+            }
+            self.world.remove(entity);
+        }
+
+        // joints to rigidbodies on removed transforms
+        let mut query = <(Entity, &Joint)>::query();
+        let joints: Vec<_> = query
+            .iter(&self.world)
+            .filter(|(_, joint)| {
+                let (rb_1, rb_2) = joint.get_rigidbodies();
+                std::iter::once(rb_1)
+                    .chain(rb_2)
+                    .any(|rb| removed.contains(&rb.read().unwrap().transform))
+            })
+            .map(|(entity, _)| *entity)
+            .collect();
+        for joint in joints {
+            self.world.remove(joint);
+        }
+
+        self.history.clear();
+        true
     }
 
-    /// update world logic with a time step
+    /// update world logic with a time step, recording a snapshot from before it in the history if
+    /// it's enabled
     ///
     /// # Order
-    /// 1. Rigidbody movement
-    /// 2. Collision resolution
-    /// 3. Other logic
+    /// 1. Force generators and rigidbody movement
+    /// 2. Character controllers
+    /// 3. Animations
+    /// 4. Collision resolution
+    /// 5. Joint resolution
+    /// 6. Island sleeping
+    /// 7. Rotate components
+    /// 8. Other logic
     pub fn update(&mut self, seconds_passed: f32) {
+        // let mut profiler = unsafe { LOGIC_PROFILER.lock().unwrap() };
+        let mut lap_start = (!self.deterministic).then(Instant::now);
+
+        if self.history_enabled {
+            let snapshot = self.snapshot();
+            self.history.push(snapshot);
+        }
+        let history_time = lap(&mut lap_start);
+
         self.last_delta_time = seconds_passed;
         self.fixed_seconds += seconds_passed;
 
-        // let mut profiler = unsafe { LOGIC_PROFILER.lock().unwrap() };
-        let logic_start = std::time::Instant::now();
+        // add forces before integrating
+        apply_forces::<ConstantForce>(&self.world, &self.transforms);
+        apply_forces::<PointAttractor>(&self.world, &self.transforms);
+        apply_forces::<AnchoredSpring>(&self.world, &self.transforms);
+        apply_forces::<Drag>(&self.world, &self.transforms);
+        apply_forces::<Buoyancy>(&self.world, &self.transforms);
 
         // physics update
-        let mut query = <(&TransformID, &mut Arc<RwLock<RigidBody>>)>::query();
-        for (transfrom, rigid_body) in query.iter_mut(&mut self.world) {
+        let rigid_bodies = by_transform::<Arc<RwLock<RigidBody>>>(&self.world, self.deterministic);
+        for (transfrom, rigid_body) in rigid_bodies {
             rigid_body.write().unwrap().update(
                 self.transforms.get_transform_mut(transfrom).unwrap(),
                 seconds_passed,
@@ -123,33 +374,86 @@ impl GameWorld {
             // );
         }
 
+        // characters walk relative to the camera
+        let camera_rotation = *self
+            .transforms
+            .get_transform(&self.camera.transform)
+            .unwrap()
+            .get_local_transform()
+            .rotation;
+        let direction = self.inputs.planar_direction(camera_rotation);
+        let jump = self.inputs.movement.y > 0.;
+        let mut query = <(&TransformID, &mut CharacterController)>::query();
+        let mut characters: Vec<_> = query.iter_mut(&mut self.world).collect();
+        if self.deterministic {
+            characters.sort_by_key(|(id, _)| **id);
+        }
+        for (transform_id, character) in characters {
+            character.update(
+                transform_id,
+                &mut self.transforms,
+                &self.colliders,
+                direction,
+                jump,
+                seconds_passed,
+            );
+        }
+
+        // play animations by the fixed time step, before colliders update so animated colliders
+        // push bodies and trigger events in the same update
+        let mut query = <(&TransformID, &mut Animator)>::query();
+        for (transform_id, animator) in query.iter_mut(&mut self.world) {
+            if let Some(transform) = self.transforms.get_transform_mut(transform_id) {
+                animator.update(seconds_passed, transform);
+            }
+        }
+
         // [Profiling] Physics
-        let phys_time = logic_start.elapsed().as_micros() as u32;
-        let coll_start = std::time::Instant::now();
+        let phys_time = lap(&mut lap_start);
 
-        // update bounds
-        let mut query = <(&TransformID, &mut LeafInHierachy)>::query();
+        // update bounds, children of moved transforms are only flagged once their models update
+        self.transforms.update_global_models();
         // println!("frame start");
-        for (id, collider) in query.iter_mut(&mut self.world) {
+        for (id, collider) in by_transform::<ColliderRef>(&self.world, self.deterministic) {
             if let Some(transform) = self.transforms.get_transform(id) {
                 if transform.phys_modified {
-                    self.colliders.update(collider, &mut self.transforms);
+                    self.colliders.update(*collider, &mut self.transforms);
                     self.transforms.reset_phys_modified(id);
                 }
             }
         }
+        self.colliders.update_modified(&mut self.transforms);
 
+        self.colliders.resolve_ccd(&mut self.transforms);
         let contact_resolver = self.colliders.get_contacts(&mut self.transforms);
         contact_resolver.resolve(&mut self.transforms, seconds_passed);
+        // joints are solved in the same step, after contacts
+        let mut query = <&Joint>::query();
+        let mut joints: Vec<_> = query.iter(&self.world).collect();
+        if self.deterministic {
+            joints.sort_by_key(|joint| joint.get_rigidbodies().0.read().unwrap().transform);
+        }
+        solve_joints(joints.iter().copied(), &mut self.transforms, seconds_passed);
+        // bodies connected by contacts or joints sleep and wake together
+        let mut islands = self.colliders.take_islands();
+        for joint in joints {
+            let (rb_1, rb_2) = joint.get_rigidbodies();
+            islands.connect(rb_1, rb_2);
+        }
         // store old velocity
-        let mut query = <&mut Arc<RwLock<RigidBody>>>::query();
-        for rigid_body in query.iter_mut(&mut self.world) {
+        let mut query = <&Arc<RwLock<RigidBody>>>::query();
+        let mut rigid_bodies: Vec<_> = query.iter(&self.world).collect();
+        if self.deterministic {
+            rigid_bodies.sort_by_key(|rigid_body| rigid_body.read().unwrap().transform);
+        }
+        for rigid_body in rigid_bodies {
             rigid_body.write().unwrap().set_old_velocity();
+            islands.add(rigid_body);
         }
+        islands.update_sleep();
 
         // [Profiling] Colliders
-        let coll_time = coll_start.elapsed().as_micros() as u32;
-        let lerp_start = std::time::Instant::now();
+        let coll_time = lap(&mut lap_start);
 
         // update interpolation models
         let mut query = <&TransformID>::query();
@@ -160,19 +464,27 @@ impl GameWorld {
                 println!("[Error] Failed to find transform of interpolated object");
             }
         }
-        self.transforms.update_last_fixed();
 
         // [Profiling] Interpolation
-        let lerp_time = lerp_start.elapsed().as_micros() as u32;
-        let others_start = std::time::Instant::now();
+        let lerp_time = lap(&mut lap_start);
 
-        // move cam
-        self.inputs.move_transform(
-            self.transforms
-                .get_transform_mut(&self.camera.transform)
-                .unwrap(),
-            seconds_passed,
-        );
+        // move cam, following its target if it still exists
+        let follow_position = self.camera.target.and_then(|target| {
+            let transform = self.transforms.get_transform(&target)?;
+            Some(*transform.get_local_transform().translation)
+        });
+        let camera = self
+            .transforms
+            .get_transform_mut(&self.camera.transform)
+            .unwrap();
+        match follow_position {
+            Some(position) => {
+                camera.set_translation(
+                    position + camera_rotation.rotate_vector(CHARACTER_CAM_OFFSET),
+                );
+            }
+            None => self.inputs.move_transform(camera, seconds_passed),
+        }
 
         // update rotate
         let mut query = <(&TransformID, &Rotate)>::query();
@@ -190,24 +502,406 @@ impl GameWorld {
             println!("[Transform] {}: {:?}", tag, model);
         }
 
-        let mut profiler = LOGIC_PROFILER.lock().unwrap();
-        profiler.add_sample(phys_time, 1);
-        profiler.add_sample(coll_time, 2);
-        profiler.add_sample(lerp_time, 3);
-        profiler.add_sample(others_start.elapsed().as_micros() as u32, 4);
+        if !self.deterministic {
+            let others_time = lap(&mut lap_start);
+            let mut profiler = LOGIC_PROFILER.lock().unwrap();
+            profiler.add_sample(phys_time, 1);
+            profiler.add_sample(coll_time, 2);
+            profiler.add_sample(lerp_time, 3);
+            profiler.add_sample(others_time, 4);
+            profiler.add_sample(history_time, 5);
+        }
     }
 
     /// clear the world and transforms and reset the camera
     pub fn clear(&mut self) {
+        let deterministic = self.deterministic;
+        let history_enabled = self.history_enabled;
         *self = Self::new();
+        self.set_deterministic(deterministic);
+        self.set_history_enabled(history_enabled);
         // self.world.clear();
         // self.transforms = TransformSystem::new();
         // self.camera = Camera::from_transform(self.transforms.next().unwrap());
         // self.world.push((self.camera.transform,));
     }
 }
+/// components with the transform of their entity, in transform order when deterministic
+/// since legion iterates in an order depending on which archetypes entities were put in
+fn by_transform<T: storage::Component>(
+    world: &World,
+    deterministic: bool,
+) -> Vec<(&TransformID, &T)> {
+    let mut query = <(&TransformID, &T)>::query();
+    let mut components: Vec<_> = query.iter(world).collect();
+    if deterministic {
+        components.sort_by_key(|(id, _)| **id);
+    }
+    components
+}
+
+/// micros since the start of the lap, starting the next one, 0 if not timing
+fn lap(start: &mut Option<Instant>) -> u32 {
+    match start {
+        Some(start) => {
+            let micros = start.elapsed().as_micros() as u32;
+            *start = Instant::now();
+            micros
+        }
+        None => 0,
+    }
+}
+
+/// apply force generator components to the rigidbody on the same entity, skipping sleeping rigidbodies
+fn apply_forces<T: ForceGenerator + storage::Component>(
+    world: &World,
+    transforms: &TransformSystem,
+) {
+    let mut query = <(&Arc<RwLock<RigidBody>>, &T)>::query();
+    for (rigidbody, generator) in query.iter(world) {
+        let mut rigidbody = rigidbody.write().unwrap();
+        if !rigidbody.is_awake() {
+            continue;
+        }
+        let transform = transforms
+            .get_transform(&rigidbody.transform)
+            .unwrap()
+            .get_local_transform();
+        let (translation, rotation) = (*transform.translation, *transform.rotation);
+        generator.apply(&mut rigidbody, translation, rotation);
+    }
+}
+
 impl Default for GameWorld {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod game_world_tests {
+    use super::{GameWorld, CHARACTER_CAM_OFFSET};
+    use crate::{
+        game_objects::{
+            animation::{AnimationClip, Animator, Interpolation, LoopMode, Track},
+            transform::{TransformCreateInfo, TransformID},
+        },
+        physics::{CharacterController, Collider, ColliderRef, CollisionPhase, RigidBody},
+        LOGIC_PROFILER,
+    };
+    use cgmath::{InnerSpace, Matrix4, Vector3};
+    use legion::IntoQuery;
+    use std::sync::{Arc, RwLock};
+
+    /// index of the "Colliders" row of the logic profiler
+    const COLLIDERS_PROFILE: usize = 2;
+    /// samples averaged by the logic profiler
+    const PROFILER_SAMPLES: usize = 128;
+
+    /// columns of cuboids dropped onto a floor, each a little above the one below
+    fn stacked_cuboids(columns: i32, height: i32) -> GameWorld {
+        let mut world = GameWorld::new();
+        let floor =
+            world
+                .transforms
+                .add_transform(TransformCreateInfo::from([0., -0.5, 0.]).with_scale([
+                    columns as f32 * 2.,
+                    0.5,
+                    columns as f32 * 2.,
+                ]));
+        let collider = world
+            .colliders
+            .add(Collider::cuboid(floor, None), &mut world.transforms);
+        world.world.push((floor, collider));
+
+        for x in 0..columns {
+            for z in 0..columns {
+                for y in 0..height {
+                    let position = [
+                        (x * 2 - columns) as f32,
+                        0.6 + y as f32 * 1.1,
+                        (z * 2 - columns) as f32,
+                    ];
+                    let transform = world
+                        .transforms
+                        .add_transform(TransformCreateInfo::from(position).with_scale([0.5; 3]));
+                    let mut rigidbody = RigidBody::new(transform);
+                    rigidbody.set_moi_as_cuboid((0.5, 0.5, 0.5).into());
+                    let rigidbody = Arc::new(RwLock::new(rigidbody));
+                    let collider = world.colliders.add(
+                        Collider::cuboid(transform, Some(rigidbody.clone())),
+                        &mut world.transforms,
+                    );
+                    world.world.push((transform, rigidbody, collider));
+                }
+            }
+        }
+        world.colliders.rebuild_tree();
+        world
+    }
+
+    /// update the worlds side by side, checking they resolve the same contacts every update
+    fn same_contacts(single_threaded: &mut GameWorld, parallel: &mut GameWorld, updates: usize) {
+        for step in 0..updates {
+            single_threaded.update(0.02);
+            parallel.update(0.02);
+            assert_eq!(
+                single_threaded.colliders.get_last_contacts(),
+                parallel.colliders.get_last_contacts(),
+                "update {step}"
+            );
+        }
+    }
+
+    /// average of the profiler's colliders row over a profiler's worth of updates
+    fn colliders_micros(world: &mut GameWorld) -> u32 {
+        for _ in 0..PROFILER_SAMPLES {
+            world.update(0.02);
+            LOGIC_PROFILER.lock().unwrap().end_frame();
+        }
+        LOGIC_PROFILER.lock().unwrap().average(COLLIDERS_PROFILE)
+    }
+
+    /// the global models of every transform in the world
+    fn models(world: &mut GameWorld) -> Vec<Matrix4<f32>> {
+        let mut query = <&TransformID>::query();
+        let ids: Vec<_> = query.iter(&world.world).copied().collect();
+        ids.iter()
+            .map(|id| world.transforms.get_global_model(id).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parallel_narrow_phase_matches() {
+        let mut single_threaded = stacked_cuboids(3, 2);
+        single_threaded.colliders.set_single_threaded(true);
+        let mut parallel = stacked_cuboids(3, 2);
+        same_contacts(&mut single_threaded, &mut parallel, 50);
+        assert!(!parallel.colliders.get_last_contacts().is_empty());
+
+        // the threads find the same contacts, so the stacks end up the same
+        assert_eq!(models(&mut single_threaded), models(&mut parallel));
+    }
+
+    /// ignored so it runs on its own with `--ignored`, other tests updating worlds would add
+    /// their samples to the same profiler rows
+    #[test]
+    #[ignore]
+    fn parallel_narrow_phase_benchmark() {
+        let mut single_threaded = stacked_cuboids(10, 3);
+        single_threaded.colliders.set_single_threaded(true);
+        let mut parallel = stacked_cuboids(10, 3);
+        // warm up both and check they agree before timing
+        same_contacts(&mut single_threaded, &mut parallel, 10);
+
+        let single_micros = colliders_micros(&mut single_threaded);
+        let parallel_micros = colliders_micros(&mut parallel);
+        println!(
+            "[Benchmarking] colliders of 300 stacked cuboids: {single_micros} μs single threaded, {parallel_micros} μs on {} threads",
+            rayon::current_num_threads()
+        );
+
+        same_contacts(&mut single_threaded, &mut parallel, 1);
+        assert_eq!(models(&mut single_threaded), models(&mut parallel));
+    }
+
+    #[test]
+    fn history_opt_in() {
+        let mut world = stacked_cuboids(1, 1);
+        world.update(0.02);
+        assert!(world.history.is_empty());
+        assert!(!world.step_back());
+
+        world.set_history_enabled(true);
+        world.update(0.02);
+        world.update(0.02);
+        assert_eq!(world.history.len(), 2);
+        assert!(world.step_back());
+
+        world.set_history_enabled(false);
+        assert!(world.history.is_empty());
+        world.update(0.02);
+        assert!(world.history.is_empty());
+    }
+
+    #[test]
+    fn collision_events_after_update() {
+        let mut world = stacked_cuboids(1, 1);
+        let mut query = <(&TransformID, &Arc<RwLock<RigidBody>>)>::query();
+        let body = *query.iter(&world.world).next().unwrap().0;
+
+        let mut phases = vec![];
+        for _ in 0..30 {
+            world.update(0.02);
+            phases.extend(
+                world
+                    .drain_collision_events()
+                    .filter(|event| event.involves(&body))
+                    .map(|event| event.phase),
+            );
+        }
+        // lands on the floor and stays there
+        assert_eq!(phases.first(), Some(&CollisionPhase::Enter));
+        assert_eq!(phases.last(), Some(&CollisionPhase::Stay));
+        assert!(phases[1..]
+            .iter()
+            .all(|phase| *phase == CollisionPhase::Stay));
+
+        // undrained events are replaced by the next update's
+        world.update(0.02);
+        world.update(0.02);
+        assert_eq!(world.drain_collision_events().count(), 1);
+    }
+
+    #[test]
+    fn animated_trigger_events() {
+        let mut world = stacked_cuboids(1, 0);
+        let clip = AnimationClip::new().with_translation(Track::new(
+            [
+                (0., Vector3::new(0., 10., 0.)),
+                (1., Vector3::new(0., 0.5, 0.)),
+            ],
+            Interpolation::Linear,
+        ));
+        let transform = world.transforms.add_transform([0., 10., 0.]);
+        let collider = world.colliders.add(
+            Collider::cuboid(transform, None).with_trigger(true),
+            &mut world.transforms,
+        );
+        let animator = Animator::new(Arc::new(clip), LoopMode::Once);
+        world.world.push((transform, collider, animator));
+
+        // the trigger reaches the floor within the update that animates it there
+        world.update(1.);
+        let phases: Vec<_> = world
+            .drain_collision_events()
+            .filter(|event| event.involves(&transform))
+            .map(|event| event.phase)
+            .collect();
+        assert_eq!(phases, vec![CollisionPhase::Enter]);
+    }
+
+    #[test]
+    fn step_back_removes_transforms() {
+        let mut world = stacked_cuboids(1, 1);
+        world.set_history_enabled(true);
+        world.update(0.02);
+        let count = world.transforms.len();
+
+        let parent = world
+            .transforms
+            .add_transform(TransformCreateInfo::default());
+        let child = world
+            .transforms
+            .add_transform(TransformCreateInfo::default().with_parent(Some(parent)));
+        world.world.push((parent,));
+        world.update(0.02);
+        assert_eq!(world.transforms.len(), count + 2);
+
+        // the last snapshot was taken after adding them
+        assert!(world.step_back());
+        assert_eq!(world.transforms.len(), count + 2);
+        assert!(world.step_back());
+        assert_eq!(world.transforms.len(), count);
+        assert!(world.transforms.get_transform(&parent).is_none());
+        assert!(world.transforms.get_transform(&child).is_none());
+        let mut query = <&TransformID>::query();
+        assert!(query.iter(&world.world).all(|id| *id != parent));
+        world.update(0.02);
+    }
+
+    #[test]
+    fn despawn_removes_descendants() {
+        let mut world = stacked_cuboids(1, 2);
+        let mut query = <(
+            legion::Entity,
+            &TransformID,
+            &ColliderRef,
+            &Arc<RwLock<RigidBody>>,
+        )>::query();
+        let (entity, transform, collider) = query
+            .iter(&world.world)
+            .map(|(entity, transform, collider, _)| (*entity, *transform, *collider))
+            .next()
+            .unwrap();
+        let child = world
+            .transforms
+            .add_transform(TransformCreateInfo::default().with_parent(Some(transform)));
+        world.world.push((child,));
+        world.update(0.02);
+        let entities = query.iter(&world.world).count();
+
+        assert!(world.despawn(entity));
+        assert!(!world.despawn(entity));
+        let camera = world.world.push((world.camera.transform,));
+        assert!(!world.despawn(camera));
+        assert!(world.transforms.get_transform(&transform).is_none());
+        assert!(world.transforms.get_transform(&child).is_none());
+        assert_eq!(query.iter(&world.world).count(), entities - 1);
+        let mut query = <&TransformID>::query();
+        assert!(query.iter(&world.world).all(|id| *id != child));
+        assert!(world.history.is_empty());
+
+        // its collider is gone from the broad phase
+        assert!(world.colliders.remove(collider).is_err());
+        assert!(world
+            .colliders
+            .get_potential_overlaps()
+            .iter()
+            .all(|(a, b)| *a.get_transform() != transform && *b.get_transform() != transform));
+        world.update(0.02);
+    }
+
+    #[test]
+    fn despawn_keeps_camera() {
+        let mut world = stacked_cuboids(1, 1);
+        let parent = world
+            .transforms
+            .add_transform(TransformCreateInfo::default());
+        let entity = world.world.push((parent,));
+        let camera = world.camera.transform;
+        world.transforms.set_parent(&camera, Some(parent)).unwrap();
+
+        // the camera's ancestors stay
+        assert!(!world.despawn(entity));
+        assert!(world.world.contains(entity));
+        assert!(world.transforms.get_transform(&parent).is_some());
+        assert!(world.transforms.get_transform(&camera).is_some());
+
+        // once moved away its old parent can go
+        world.transforms.set_parent(&camera, None).unwrap();
+        assert!(world.despawn(entity));
+        assert!(world.transforms.get_transform(&camera).is_some());
+        world.update(0.02);
+    }
+
+    #[test]
+    fn camera_follows_target() {
+        let mut world = GameWorld::new();
+        let characters: Vec<_> = [[0., 0., 0.], [10., 0., 0.]]
+            .into_iter()
+            .map(|position| {
+                let transform = world.transforms.add_transform(position);
+                world
+                    .world
+                    .push((transform, CharacterController::new(0.5, 0.5)));
+                transform
+            })
+            .collect();
+
+        world.camera.target = Some(characters[1]);
+        world.update(0.02);
+
+        let translation = |world: &GameWorld, id| {
+            *world
+                .transforms
+                .get_transform(id)
+                .unwrap()
+                .get_local_transform()
+                .translation
+        };
+        let expected = translation(&world, &characters[1]) + CHARACTER_CAM_OFFSET;
+        let camera = translation(&world, &world.camera.transform);
+        assert!((camera - expected).magnitude() < 1e-5);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod animation;
 mod camera;
 mod game_world;
 pub mod light;
@@ -1,24 +1,49 @@
+#[cfg(test)]
+mod legacy;
+mod storage;
+
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashSet},
     fmt::Debug,
     time::Instant,
 };
 
-use cgmath::{Matrix4, One, Quaternion, SquareMatrix, Vector3, VectorSpace, Zero};
+use storage::TransformStorage;
+
+use cgmath::{
+    ElementWise, InnerSpace, Matrix3, Matrix4, One, Quaternion, Rotation, SquareMatrix, Vector3,
+    VectorSpace, Zero,
+};
 
 #[derive(Clone)]
 pub struct Transform {
     parent: Option<TransformID>,
-    children: HashSet<TransformID>,
+    children: BTreeSet<TransformID>,
     local_model: Option<Matrix4<f32>>,
+    /// none if the local values changed since it was calculated
     global_model: Option<Matrix4<f32>>,
+    /// incremented whenever the global model is calculated
+    version: u32,
+    /// version of the parent's global model the global model was calculated with
+    parent_version: u32,
+    /// number of ancestors
+    depth: u32,
     translation: Vector3<f32>,
     rotation: Quaternion<f32>,
     scale: Vector3<f32>,
-    last_model: Option<Matrix4<f32>>,
+    /// local values when the last model was stored, interpolated from
+    last_pose: Option<Pose>,
     pub phys_modified: bool,
 }
 
+/// local translation, rotation and scale of a transform
+#[derive(Clone, Copy, Debug)]
+struct Pose {
+    translation: Vector3<f32>,
+    rotation: Quaternion<f32>,
+    scale: Vector3<f32>,
+}
+
 pub struct TransformCreateInfo {
     pub parent: Option<TransformID>,
     pub translation: Vector3<f32>,
@@ -27,13 +52,29 @@ pub struct TransformCreateInfo {
 }
 
 pub struct TransformSystem {
-    root: HashSet<TransformID>,
-    transforms: HashMap<TransformID, Transform>,
+    root: BTreeSet<TransformID>,
+    transforms: TransformStorage,
     next_id: u32,
+    /// ids of removed transforms with their generation incremented, to be reused
+    free: Vec<TransformID>,
     last_fixed_time: Instant,
     interpolation: f32,
 }
 
+/// local values of every transform at one point, restored with `TransformSystem::restore`
+#[derive(Clone)]
+pub struct TransformSnapshot {
+    values: Vec<(TransformID, TransformValues)>,
+}
+
+#[derive(Clone)]
+struct TransformValues {
+    translation: Vector3<f32>,
+    rotation: Quaternion<f32>,
+    scale: Vector3<f32>,
+    last_pose: Option<Pose>,
+}
+
 #[derive(Debug)]
 pub struct TransformView<'a> {
     pub translation: &'a Vector3<f32>,
@@ -41,31 +82,56 @@ pub struct TransformView<'a> {
     pub scale: &'a Vector3<f32>,
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
-pub struct TransformID(u32);
+/// index of a transform and the number of times that index was reused,
+/// so the id of a removed transform doesn't refer to the one replacing it
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct TransformID {
+    index: u32,
+    generation: u32,
+}
 
 #[derive(Debug)]
 pub enum TransformError {
     IDNotFound,
+    /// a global model had no inverse, e.g. from a scale of zero
+    NotInvertible,
+}
+
+/// what happens to the children of a removed transform
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemoveMode {
+    /// remove all of its descendants too
+    Recursive,
+    /// children are given its parent, keeping their local values
+    ReparentChildren,
 }
 
 impl TransformID {
+    /// index of the transform, unique among transforms in the system at the same time but reused
+    /// by transforms added after it's removed, the generation tells them apart
     pub fn id(&self) -> u32 {
-        self.0
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
     }
 }
 
 impl Transform {
-    fn new(val: TransformCreateInfo) -> Self {
+    fn new(val: TransformCreateInfo, depth: u32) -> Self {
         Self {
             parent: val.parent,
-            children: HashSet::new(),
+            children: BTreeSet::new(),
             local_model: None,
             global_model: None,
+            version: 0,
+            parent_version: 0,
+            depth,
             translation: val.translation,
             rotation: val.rotation,
             scale: val.scale,
-            last_model: None,
+            last_pose: None,
             phys_modified: true,
         }
     }
@@ -75,9 +141,7 @@ impl Transform {
             Some(matrix) => matrix,
             None => {
                 // calc model and update
-                let model = Matrix4::from_translation(self.translation)
-                    * Matrix4::from(self.rotation)
-                    * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
+                let model = self.pose().model();
 
                 self.local_model = Some(model);
                 model
@@ -85,20 +149,27 @@ impl Transform {
         }
     }
 
-    /// update global model of self if needed and return global model
-    fn clean(&mut self, parent_global: &Matrix4<f32>) -> Matrix4<f32> {
+    /// update global model of self if it or its parent's changed and return global model
+    fn clean(&mut self, parent_global: &Matrix4<f32>, parent_version: u32) -> Matrix4<f32> {
         match self.global_model {
-            Some(model) => model,
-            None => {
+            Some(model) if self.parent_version == parent_version => model,
+            _ => {
                 let new_global = *parent_global * self.get_local_model();
                 self.global_model = Some(new_global);
+                self.version = self.version.wrapping_add(1);
+                self.parent_version = parent_version;
+                self.phys_modified = true;
                 new_global
             }
         }
     }
 
-    pub fn is_dirty(&self) -> bool {
-        self.global_model.is_none()
+    fn pose(&self) -> Pose {
+        Pose {
+            translation: self.translation,
+            rotation: self.rotation,
+            scale: self.scale,
+        }
     }
 
     /// modify translation, rotation and scale through a closure.
@@ -191,9 +262,10 @@ impl Default for TransformCreateInfo {
 impl TransformSystem {
     pub fn new() -> Self {
         Self {
-            root: HashSet::new(),
-            transforms: HashMap::new(),
+            root: BTreeSet::new(),
+            transforms: TransformStorage::default(),
             next_id: 0,
+            free: Vec::new(),
             last_fixed_time: Instant::now(),
             interpolation: 0.0,
         }
@@ -211,96 +283,247 @@ impl TransformSystem {
         self.interpolation
     }
 
+    /// global model of the transform, updating it and its ancestors if they changed
     pub fn get_global_model(&mut self, id: &TransformID) -> Result<Matrix4<f32>, TransformError> {
         let transform = self.transforms.get(id).ok_or(TransformError::IDNotFound)?;
+        if let (Some(model), false) = (transform.global_model, self.is_dirty(id)) {
+            return Ok(model);
+        }
+
+        // clean from the root down, each ancestor's model is only recalculated if it changed
+        let mut chain = vec![*id];
+        let mut parent = transform.parent;
+        while let Some(parent_id) = parent {
+            chain.push(parent_id);
+            parent = self.transforms.get(&parent_id).unwrap().parent;
+        }
+        let (mut model, mut version) = (Matrix4::identity(), 0);
+        for id in chain.iter().rev() {
+            let transform = self.transforms.get_mut(id).unwrap();
+            model = transform.clean(&model, version);
+            version = transform.version;
+        }
+        Ok(model)
+    }
 
-        Ok(transform.global_model.unwrap_or({
-            let parent_model = match transform.parent {
-                Some(parent_id) => self.get_global_model(&parent_id)?,
-                None => Matrix4::identity(),
+    /// the global model of the transform or one of its ancestors is out of date, walks up to the
+    /// first changed ancestor without updating anything
+    pub fn is_dirty(&self, id: &TransformID) -> bool {
+        let mut transform = self.transforms.get(id);
+        while let Some(current) = transform {
+            if current.global_model.is_none() {
+                return true;
+            }
+            let Some(parent_id) = current.parent else {
+                return false;
+            };
+            let parent = self.transforms.get(&parent_id);
+            if parent.is_some_and(|parent| parent.version != current.parent_version) {
+                return true;
+            }
+            transform = parent;
+        }
+        true
+    }
+
+    /// update the global model of every changed transform and their descendants in one pass,
+    /// flagging them as modified for physics
+    pub fn update_global_models(&mut self) {
+        self.transforms.update_global_models();
+    }
+    fn get_parent_model(&mut self, id: &TransformID) -> Result<Matrix4<f32>, TransformError> {
+        let transform = self.transforms.get(id).ok_or(TransformError::IDNotFound)?;
+        if let Some(id) = transform.parent {
+            self.get_global_model(&id)
+        } else {
+            Ok(Matrix4::identity())
+        }
+    }
+
+    pub fn get_global_position(
+        &mut self,
+        id: &TransformID,
+    ) -> Result<Vector3<f32>, TransformError> {
+        Ok(self.get_global_model(id)?.w.truncate())
+    }
+    pub fn get_global_rotation(
+        &mut self,
+        id: &TransformID,
+    ) -> Result<Quaternion<f32>, TransformError> {
+        Ok(decompose(&self.get_global_model(id)?).1)
+    }
+    /// world space scale along the transform's axes, skew from non-uniform scales of rotated
+    /// parents is lost
+    pub fn get_global_scale(&mut self, id: &TransformID) -> Result<Vector3<f32>, TransformError> {
+        Ok(decompose(&self.get_global_model(id)?).2)
+    }
+
+    /// move the transform to a world space position
+    pub fn set_global_position(
+        &mut self,
+        id: &TransformID,
+        position: impl Into<Vector3<f32>>,
+    ) -> Result<(), TransformError> {
+        let inv_parent = self.get_inverse_parent_model(id)?;
+        let translation = (inv_parent * position.into().extend(1.)).truncate();
+        self.get_transform_mut(id)
+            .unwrap()
+            .set_translation(translation);
+        Ok(())
+    }
+    /// rotate the transform to a world space rotation
+    pub fn set_global_rotation(
+        &mut self,
+        id: &TransformID,
+        rotation: impl Into<Quaternion<f32>>,
+    ) -> Result<(), TransformError> {
+        let parent_rotation = decompose(&self.get_parent_model(id)?).1;
+        let rotation = (parent_rotation.conjugate() * rotation.into()).normalize();
+        self.get_transform_mut(id).unwrap().set_rotation(rotation);
+        Ok(())
+    }
+    /// scale the transform to a world space scale along its own axes
+    pub fn set_global_scale(
+        &mut self,
+        id: &TransformID,
+        scale: impl Into<Vector3<f32>>,
+    ) -> Result<(), TransformError> {
+        let old_global = self.get_global_scale(id)?;
+        if old_global.x * old_global.y * old_global.z == 0. {
+            return Err(TransformError::NotInvertible);
+        }
+        let transform = self.get_transform_mut(id).unwrap();
+        let old_local = *transform.get_local_transform().scale;
+        // the parent's scale along each axis is the ratio between global and local scale
+        let scale = scale
+            .into()
+            .mul_element_wise(old_local)
+            .div_element_wise(old_global);
+        transform.set_scale(scale);
+        Ok(())
+    }
+
+    /// rotate the transform so its forward (-z) faces the target, keeping its y axis towards up
+    pub fn look_at(
+        &mut self,
+        id: &TransformID,
+        target: impl Into<Vector3<f32>>,
+        up: impl Into<Vector3<f32>>,
+    ) -> Result<(), TransformError> {
+        let direction = target.into() - self.get_global_position(id)?;
+        self.look_towards(id, direction, up)
+    }
+    /// rotate the transform so its forward (-z) points along the world space direction,
+    /// keeping its y axis towards up
+    ///
+    /// nothing happens for a zero direction, another up is picked if they are parallel
+    pub fn look_towards(
+        &mut self,
+        id: &TransformID,
+        direction: impl Into<Vector3<f32>>,
+        up: impl Into<Vector3<f32>>,
+    ) -> Result<(), TransformError> {
+        let direction = direction.into();
+        if direction.magnitude2() == 0. {
+            return match self.transforms.contains_key(id) {
+                true => Ok(()),
+                false => Err(TransformError::IDNotFound),
             };
+        }
+
+        let z = -direction.normalize();
+        let mut x = up.into().cross(z);
+        if x.magnitude2() < 1e-8 {
+            x = match z.x.abs() < 0.9 {
+                true => Vector3::unit_x(),
+                false => Vector3::unit_y(),
+            }
+            .cross(z);
+        }
+        let x = x.normalize();
+        let y = z.cross(x);
+        self.set_global_rotation(id, Matrix3::from_cols(x, y, z))
+    }
+
+    /// world space position of a point in the transform's local space
+    pub fn transform_point(
+        &mut self,
+        id: &TransformID,
+        point: impl Into<Vector3<f32>>,
+    ) -> Result<Vector3<f32>, TransformError> {
+        Ok((self.get_global_model(id)? * point.into().extend(1.)).truncate())
+    }
+    /// world space direction of a direction in the transform's local space, only rotated
+    pub fn transform_direction(
+        &mut self,
+        id: &TransformID,
+        direction: impl Into<Vector3<f32>>,
+    ) -> Result<Vector3<f32>, TransformError> {
+        Ok(self
+            .get_global_rotation(id)?
+            .rotate_vector(direction.into()))
+    }
+    /// local space position of a world space point
+    pub fn inverse_transform_point(
+        &mut self,
+        id: &TransformID,
+        point: impl Into<Vector3<f32>>,
+    ) -> Result<Vector3<f32>, TransformError> {
+        let inv_model = self
+            .get_global_model(id)?
+            .invert()
+            .ok_or(TransformError::NotInvertible)?;
+        Ok((inv_model * point.into().extend(1.)).truncate())
+    }
 
-            self.transforms.get_mut(id).unwrap().clean(&parent_model)
-        }))
+    fn get_inverse_parent_model(
+        &mut self,
+        id: &TransformID,
+    ) -> Result<Matrix4<f32>, TransformError> {
+        self.get_parent_model(id)?
+            .invert()
+            .ok_or(TransformError::NotInvertible)
     }
-    // pub fn get_parent_model(&mut self, id: &TransformID) -> Result<Matrix4<f32>, TransformError> {
-    //     let transform = self.transforms.get(id).ok_or(TransformError::IDNotFound)?;
-    //     if let Some(id) = transform.parent {
-    //         self.get_global_model(&id)
-    //     } else {
-    //         Ok(Matrix4::identity())
-    //     }
-    // }
 
+    /// store the local values of the transform to interpolate from until the next store
     pub fn store_last_model(&mut self, id: &TransformID) -> Result<(), TransformError> {
-        let model = self.get_global_model(id)?;
-        self.transforms.get_mut(id).unwrap().last_model = Some(model);
+        let transform = self
+            .transforms
+            .get_mut(id)
+            .ok_or(TransformError::IDNotFound)?;
+        transform.last_pose = Some(transform.pose());
         Ok(())
     }
     pub fn clear_last_model(&mut self, id: &TransformID) -> Result<(), TransformError> {
         self.transforms
             .get_mut(id)
             .ok_or(TransformError::IDNotFound)?
-            .last_model = None;
+            .last_pose = None;
         Ok(())
     }
-    pub fn get_lerp_model(&mut self, id: &TransformID) -> Result<Matrix4<f32>, TransformError> {
-        let now_model = self.get_global_model(id)?;
-        let last_model = self.transforms.get_mut(id).unwrap().last_model;
-        let model = match last_model {
-            None => now_model,
-            Some(last_model) => last_model.lerp(now_model, self.interpolation),
+    /// global model between the stored and current values by the interpolation amount
+    ///
+    /// translation and scale are lerped and rotation slerped in local space, then combined with
+    /// the parent's interpolated model so rotating parents move their children along arcs,
+    /// transforms without stored values use their current ones
+    pub fn get_lerp_model(&self, id: &TransformID) -> Result<Matrix4<f32>, TransformError> {
+        let transform = self.transforms.get(id).ok_or(TransformError::IDNotFound)?;
+        let pose = match transform.last_pose {
+            None => transform.pose(),
+            Some(last_pose) => last_pose.interpolate(&transform.pose(), self.interpolation),
+        };
+        let parent_model = match transform.parent {
+            Some(parent_id) => self.get_lerp_model(&parent_id)?,
+            None => Matrix4::identity(),
         };
 
-        Ok(model)
+        Ok(parent_model * pose.model())
+    }
 
-        //     if let Some(last_model) = last_model {
-        //         let old = last_model[3].truncate();
-        //         // let new = now_model[3].truncate() / now_model[3][3];
-        //         let lerp = model[3].truncate() / model[3][3];
-        //         println!(
-        //             "[debug] old: {:?}, lerp: {:?}, lerp_v: {:?}",
-        //             old.x, lerp.x, self.interpolation
-        //         );
-        //     }
-    }
-    // pub fn get_slerp_model(&mut self, id: &TransformID) -> Result<Matrix4<f32>, TransformError> {
-    //     let transform = self.transforms.get(id).ok_or(TransformError::IDNotFound)?;
-    //     let last_model = transform.last_model;
-    //     let parent_model = match transform.parent {
-    //         Some(parent_id) => self.get_global_model(&parent_id)?,
-    //         None => Matrix4::identity(),
-    //     };
-    //     let model = match last_model {
-    //         None => self
-    //             .transforms
-    //             .get_mut(id)
-    //             .ok_or(TransformError::IDNotFound)?
-    //             .clean(&parent_model),
-    //         Some(last_model) => {
-    //             let view = self.transforms.get(id).unwrap().get_local_transform();
-
-    //             let last_rot: Quaternion<f32> = Matrix3::from_cols(
-    //                 last_model[0].truncate(),
-    //                 last_model[1].truncate(),
-    //                 last_model[2].truncate(),
-    //             )
-    //             .into();
-    //             let last_pos = last_model[3].truncate() / last_model[3][3];
-
-    //             let lerp_rot = last_rot.slerp(*view.rotation, self.interpolation);
-    //             let lerp_pos = last_pos.lerp(*view.translation, self.interpolation);
-
-    //             parent_model * Matrix4::from_translation(lerp_pos) * Matrix4::from(lerp_rot)
-    //         }
-    //     };
-
-    //     Ok(model)
-    // }
-
-    /// Flag the global model of the corresponding transform and all its children as dirty
+    /// Flag the global model of the corresponding transform as dirty
     ///
-    /// Dirty models are recalculated when they are next retrived
+    /// Dirty models are recalculated when they are next retrived, along with their descendants'
+    /// as the version of their parent's model changes
     fn dirty(&mut self, id: &TransformID) -> Result<(), TransformError> {
         let transform = self
             .transforms
@@ -309,37 +532,137 @@ impl TransformSystem {
         transform.global_model = None;
         transform.phys_modified = true;
 
+        Ok(())
+    }
+
+    /// set the depth of the transform and its descendants from its new parent
+    fn update_depth(&mut self, id: &TransformID, depth: u32) {
+        let Some(transform) = self.transforms.get_mut(id) else {
+            return;
+        };
+        transform.depth = depth;
         for child in transform.children.clone() {
-            self.dirty(&child)?;
+            self.update_depth(&child, depth + 1);
         }
-
-        Ok(())
     }
 
     /// adds given transform to the system and returns its unique ID
     pub fn add_transform(&mut self, info: impl Into<TransformCreateInfo>) -> TransformID {
         let info = info.into();
 
-        // create id
-        let id = TransformID(self.next_id);
+        // create id, reusing the index of a removed transform if there is one
+        let id = self.free.pop().unwrap_or_else(|| {
+            self.next_id += 1;
+            TransformID {
+                index: self.next_id - 1,
+                generation: 0,
+            }
+        });
 
         // add to parent
-        match info.parent {
+        let depth = match info.parent {
+            Some(parent_id) => {
+                let parent = self.transforms.get_mut(&parent_id).unwrap();
+                parent.children.insert(id);
+                parent.depth + 1
+            }
+            None => {
+                self.root.insert(id);
+                0
+            }
+        };
+
+        // create transform
+        self.transforms.insert(id, Transform::new(info, depth));
+
+        id
+    }
+
+    /// remove the transform, returning the ids of every transform removed
+    ///
+    /// removed ids stop working, even once their index is reused
+    pub fn remove_transform(
+        &mut self,
+        id: &TransformID,
+        mode: RemoveMode,
+    ) -> Result<Vec<TransformID>, TransformError> {
+        let transform = self.transforms.get(id).ok_or(TransformError::IDNotFound)?;
+        let parent = transform.parent;
+        let children = transform.children.clone();
+
+        let mut removed = Vec::new();
+        match mode {
+            RemoveMode::Recursive => {
+                for child in children {
+                    self.remove_recursive(child, &mut removed);
+                }
+            }
+            RemoveMode::ReparentChildren => {
+                for child in children {
+                    self.set_parent(&child, parent)?;
+                }
+            }
+        }
+
+        match parent {
             Some(parent_id) => self
                 .transforms
                 .get_mut(&parent_id)
-                .unwrap()
+                .ok_or(TransformError::IDNotFound)?
                 .children
-                .insert(id),
-            None => self.root.insert(id),
+                .remove(id),
+            None => self.root.remove(id),
         };
+        self.transforms.remove(id);
+        self.free_id(*id);
+        removed.push(*id);
 
-        // create transform
-        self.transforms.insert(id, Transform::new(info));
-        self.next_id += 1;
+        Ok(removed)
+    }
 
-        id
+    /// remove transform and its descendants, without updating its parent
+    fn remove_recursive(&mut self, id: TransformID, removed: &mut Vec<TransformID>) {
+        let Some(transform) = self.transforms.remove(&id) else {
+            return;
+        };
+        for child in transform.children {
+            self.remove_recursive(child, removed);
+        }
+        self.free_id(id);
+        removed.push(id);
+    }
+
+    fn free_id(&mut self, id: TransformID) {
+        self.free.push(TransformID {
+            index: id.index,
+            generation: id.generation.wrapping_add(1),
+        });
+    }
+    /// swaps parent of child with given parent, changing its local values so it stays in place
+    pub fn set_parent_keep_global(
+        &mut self,
+        child: &TransformID,
+        parent: Option<TransformID>,
+    ) -> Result<(), TransformError> {
+        let global = self.get_global_model(child)?;
+        let inv_parent = match parent {
+            Some(parent) => self
+                .get_global_model(&parent)?
+                .invert()
+                .ok_or(TransformError::NotInvertible)?,
+            None => Matrix4::identity(),
+        };
+        self.set_parent(child, parent)?;
+
+        let (translation, rotation, scale) = decompose(&(inv_parent * global));
+        self.get_transform_mut(child).unwrap().mutate(|t, r, s| {
+            *t = translation;
+            *r = rotation;
+            *s = scale;
+        });
+        Ok(())
     }
+
     /// swaps parent of child with given parent, dirtying the child accordingly
     pub fn set_parent(
         &mut self,
@@ -367,35 +690,155 @@ impl TransformSystem {
         };
 
         // update new parent
-        match parent {
-            Some(parent_id) => self
-                .transforms
-                .get_mut(&parent_id)
-                .ok_or(TransformError::IDNotFound)?
-                .children
-                .insert(*child),
-            None => self.root.insert(*child),
+        let depth = match parent {
+            Some(parent_id) => {
+                let parent = self
+                    .transforms
+                    .get_mut(&parent_id)
+                    .ok_or(TransformError::IDNotFound)?;
+                parent.children.insert(*child);
+                parent.depth + 1
+            }
+            None => {
+                self.root.insert(*child);
+                0
+            }
         };
+        self.update_depth(child, depth);
+        self.transforms.mark_unsorted();
 
         Ok(())
     }
 
+    /// copy the local values of every transform, the hierachy isn't included
+    pub fn snapshot(&self) -> TransformSnapshot {
+        let values = self
+            .transforms
+            .iter()
+            .map(|(id, transform)| {
+                let values = TransformValues {
+                    translation: transform.translation,
+                    rotation: transform.rotation,
+                    scale: transform.scale,
+                    last_pose: transform.last_pose,
+                };
+                (*id, values)
+            })
+            .collect();
+        TransformSnapshot { values }
+    }
+
+    /// set transforms back to their values in the snapshot, dirtying them,
+    /// transforms added since are removed along with their descendants
+    pub fn restore(&mut self, snapshot: &TransformSnapshot) {
+        let kept: HashSet<_> = snapshot.values.iter().map(|(id, _)| *id).collect();
+        let added: Vec<_> = self
+            .transforms
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| !kept.contains(id))
+            .collect();
+        for id in added {
+            // already gone if it was a descendant of another added transform
+            let _ = self.remove_transform(&id, RemoveMode::Recursive);
+        }
+
+        for (id, values) in snapshot.values.iter() {
+            let Some(transform) = self.transforms.get_mut(id) else {
+                continue;
+            };
+            transform.mutate(|translation, rotation, scale| {
+                *translation = values.translation;
+                *rotation = values.rotation;
+                *scale = values.scale;
+            });
+            transform.last_pose = values.last_pose;
+            let _ = self.dirty(id);
+        }
+    }
+
+    /// number of transforms in the system
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the transform is the ancestor or one of its descendants
+    pub fn is_descendant(&self, id: &TransformID, ancestor: &TransformID) -> bool {
+        let mut current = Some(*id);
+        while let Some(id) = current {
+            if id == *ancestor {
+                return true;
+            }
+            current = self
+                .transforms
+                .get(&id)
+                .and_then(|transform| transform.parent);
+        }
+        false
+    }
+
     /// get an immutable view of local transform values
     pub fn get_transform(&self, id: &TransformID) -> Option<&Transform> {
         self.transforms.get(id)
     }
-    /// Get mutable reference to the corresponding transform, automatically sets transform and its children to dirty
+    /// Get mutable reference to the corresponding transform, automatically sets transform to dirty
     pub fn get_transform_mut(&mut self, id: &TransformID) -> Option<&mut Transform> {
         self.dirty(id).ok()?;
         self.transforms.get_mut(id)
     }
 
     pub fn reset_phys_modified(&mut self, id: &TransformID) {
-        if let Some(t) = self.get_transform_mut(id) {
+        if let Some(t) = self.transforms.get_mut(id) {
             t.phys_modified = false;
         }
     }
 }
+impl Pose {
+    fn model(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// lerp translation and scale and slerp rotation along the shortest arc
+    fn interpolate(&self, other: &Self, amount: f32) -> Self {
+        let other_rotation = match self.rotation.dot(other.rotation) < 0. {
+            true => -other.rotation,
+            false => other.rotation,
+        };
+        Self {
+            translation: self.translation.lerp(other.translation, amount),
+            rotation: self.rotation.slerp(other_rotation, amount).normalize(),
+            scale: self.scale.lerp(other.scale, amount),
+        }
+    }
+}
+
+/// translation, rotation and scale of a model without skew, a negative determinant is given to
+/// the x scale
+fn decompose(model: &Matrix4<f32>) -> (Vector3<f32>, Quaternion<f32>, Vector3<f32>) {
+    let mut axes = [model.x.truncate(), model.y.truncate(), model.z.truncate()];
+    let mut scale = Vector3::new(
+        axes[0].magnitude(),
+        axes[1].magnitude(),
+        axes[2].magnitude(),
+    );
+    if axes[0].cross(axes[1]).dot(axes[2]) < 0. {
+        scale.x = -scale.x;
+    }
+    for (axis, scale) in axes.iter_mut().zip(Into::<[f32; 3]>::into(scale)) {
+        if scale != 0. {
+            *axis /= scale;
+        }
+    }
+    let rotation = Quaternion::from(Matrix3::from_cols(axes[0], axes[1], axes[2])).normalize();
+    (model.w.truncate(), rotation, scale)
+}
+
 impl Iterator for TransformSystem {
     type Item = TransformID;
 
@@ -408,3 +851,454 @@ impl Default for TransformSystem {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod transform_tests {
+    use super::{
+        legacy::LegacyTransforms, RemoveMode, TransformCreateInfo, TransformID, TransformSystem,
+    };
+    use cgmath::{Deg, InnerSpace, Matrix4, Quaternion, Rotation, Rotation3, Vector3, Zero};
+    use std::time::Instant;
+
+    #[test]
+    fn remove_recursive() {
+        let mut transforms = TransformSystem::new();
+        let parent = transforms.add_transform(TransformCreateInfo::default());
+        let child =
+            transforms.add_transform(TransformCreateInfo::default().with_parent(Some(parent)));
+        let grandchild =
+            transforms.add_transform(TransformCreateInfo::default().with_parent(Some(child)));
+        let other = transforms.add_transform(TransformCreateInfo::default());
+
+        let mut removed = transforms
+            .remove_transform(&parent, RemoveMode::Recursive)
+            .unwrap();
+        removed.sort();
+        assert_eq!(removed, vec![parent, child, grandchild]);
+        assert!(transforms.get_transform(&grandchild).is_none());
+        assert!(transforms.get_transform(&other).is_some());
+        assert!(!transforms.root.contains(&parent));
+    }
+
+    #[test]
+    fn remove_reparent_children() {
+        let mut transforms = TransformSystem::new();
+        let grandparent = transforms.add_transform(TransformCreateInfo::from([1., 0., 0.]));
+        let parent = transforms
+            .add_transform(TransformCreateInfo::from([0., 1., 0.]).with_parent(Some(grandparent)));
+        let child = transforms
+            .add_transform(TransformCreateInfo::from([0., 0., 1.]).with_parent(Some(parent)));
+
+        let removed = transforms
+            .remove_transform(&parent, RemoveMode::ReparentChildren)
+            .unwrap();
+        assert_eq!(removed, vec![parent]);
+
+        // local values are kept under the new parent
+        let model = transforms.get_global_model(&child).unwrap();
+        assert_eq!(model.w.truncate(), Vector3::new(1., 0., 1.));
+        assert_eq!(
+            transforms.get_transform(&child).unwrap().parent,
+            Some(grandparent)
+        );
+        let grandparent = transforms.get_transform(&grandparent).unwrap();
+        assert!(grandparent.children.contains(&child));
+        assert!(!grandparent.children.contains(&parent));
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{a:?} != {b:?}");
+    }
+
+    /// a child of a moved, rotated and scaled parent
+    fn parented() -> (TransformSystem, TransformID, TransformID) {
+        let mut transforms = TransformSystem::new();
+        let parent = transforms.add_transform(
+            TransformCreateInfo::from([1., 2., 3.])
+                .set_rotation(Quaternion::from_angle_y(Deg(90.)))
+                .with_scale([2.; 3]),
+        );
+        let child = transforms
+            .add_transform(TransformCreateInfo::from([1., 0., 0.]).with_parent(Some(parent)));
+        (transforms, parent, child)
+    }
+
+    #[test]
+    fn global_getters() {
+        let (mut transforms, _, child) = parented();
+        assert_close(
+            transforms.get_global_position(&child).unwrap(),
+            Vector3::new(1., 2., 1.),
+        );
+        let rotation = transforms.get_global_rotation(&child).unwrap();
+        assert_close(
+            rotation.rotate_vector(Vector3::unit_x()),
+            -Vector3::unit_z(),
+        );
+        assert_close(
+            transforms.get_global_scale(&child).unwrap(),
+            Vector3::new(2., 2., 2.),
+        );
+    }
+
+    #[test]
+    fn global_setters() {
+        let (mut transforms, _, child) = parented();
+        transforms
+            .set_global_position(&child, [0., 0., 0.])
+            .unwrap();
+        assert_close(
+            transforms.get_global_position(&child).unwrap(),
+            Vector3::zero(),
+        );
+
+        let rotation = Quaternion::from_angle_x(Deg(30.));
+        transforms.set_global_rotation(&child, rotation).unwrap();
+        let global = transforms.get_global_rotation(&child).unwrap();
+        assert!(global.dot(rotation).abs() > 1. - 1e-5);
+
+        transforms.set_global_scale(&child, [1., 3., 1.]).unwrap();
+        assert_close(
+            transforms.get_global_scale(&child).unwrap(),
+            Vector3::new(1., 3., 1.),
+        );
+        assert_close(
+            transforms.get_global_position(&child).unwrap(),
+            Vector3::zero(),
+        );
+    }
+
+    #[test]
+    fn look_at_and_points() {
+        let (mut transforms, _, child) = parented();
+        transforms
+            .look_at(&child, [1., 2., 5.], Vector3::unit_y())
+            .unwrap();
+        assert_close(
+            transforms
+                .transform_direction(&child, -Vector3::unit_z())
+                .unwrap(),
+            Vector3::unit_z(),
+        );
+        assert_close(
+            transforms
+                .transform_direction(&child, Vector3::unit_y())
+                .unwrap(),
+            Vector3::unit_y(),
+        );
+
+        // straight up falls back to another up
+        transforms
+            .look_towards(&child, Vector3::unit_y(), Vector3::unit_y())
+            .unwrap();
+        assert_close(
+            transforms
+                .transform_direction(&child, -Vector3::unit_z())
+                .unwrap(),
+            Vector3::unit_y(),
+        );
+
+        let point = Vector3::new(0.5, -1., 2.);
+        let global = transforms.transform_point(&child, point).unwrap();
+        assert_close(
+            transforms.inverse_transform_point(&child, global).unwrap(),
+            point,
+        );
+    }
+
+    #[test]
+    fn reparent_keep_global() {
+        let (mut transforms, parent, child) = parented();
+        let other = transforms.add_transform(
+            TransformCreateInfo::from([-4., 0., 0.])
+                .set_rotation(Quaternion::from_angle_z(Deg(45.)))
+                .with_scale([0.5; 3]),
+        );
+        let point = Vector3::new(1., 1., 1.);
+        let before = transforms.transform_point(&child, point).unwrap();
+
+        transforms
+            .set_parent_keep_global(&child, Some(other))
+            .unwrap();
+        assert_close(transforms.transform_point(&child, point).unwrap(), before);
+        transforms.set_parent_keep_global(&child, None).unwrap();
+        assert_close(transforms.transform_point(&child, point).unwrap(), before);
+        assert!(!transforms
+            .get_transform(&parent)
+            .unwrap()
+            .children
+            .contains(&child));
+    }
+
+    /// columns of the rotation and scale part are unit length and perpendicular
+    fn assert_orthonormal(model: Matrix4<f32>) {
+        let axes = [model.x.truncate(), model.y.truncate(), model.z.truncate()];
+        for (i, axis) in axes.iter().enumerate() {
+            assert!((axis.magnitude() - 1.).abs() < 1e-4, "{model:?}");
+            for other in axes[i + 1..].iter() {
+                assert!(axis.dot(*other).abs() < 1e-4, "{model:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation_orthonormal() {
+        let mut transforms = TransformSystem::new();
+        let parent = transforms.add_transform(
+            TransformCreateInfo::default().set_rotation(Quaternion::from_angle_y(Deg(10.))),
+        );
+        let child = transforms.add_transform(
+            TransformCreateInfo::from([2., 0., 0.])
+                .set_rotation(Quaternion::from_angle_x(Deg(-20.)))
+                .with_parent(Some(parent)),
+        );
+        transforms.store_last_model(&parent).unwrap();
+        transforms.store_last_model(&child).unwrap();
+
+        // large rotations shrink a matrix lerp the most
+        transforms
+            .get_transform_mut(&parent)
+            .unwrap()
+            .set_rotation(Quaternion::from_angle_y(Deg(170.)));
+        transforms
+            .get_transform_mut(&child)
+            .unwrap()
+            .set_rotation(Quaternion::from_angle_z(Deg(150.)));
+
+        for step in 0..=10 {
+            transforms.interpolation = step as f32 / 10.;
+            assert_orthonormal(transforms.get_lerp_model(&parent).unwrap());
+            let model = transforms.get_lerp_model(&child).unwrap();
+            assert_orthonormal(model);
+            // the child orbits the parent instead of cutting across
+            assert!((model.w.truncate().magnitude() - 2.).abs() < 1e-4);
+        }
+
+        transforms.interpolation = 1.;
+        let now = transforms.get_global_model(&child).unwrap();
+        let lerp = transforms.get_lerp_model(&child).unwrap();
+        for (a, b) in Into::<[[f32; 4]; 4]>::into(now)
+            .iter()
+            .flatten()
+            .zip(Into::<[[f32; 4]; 4]>::into(lerp).iter().flatten())
+        {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn interpolation_shortest_arc() {
+        let mut transforms = TransformSystem::new();
+        let id = transforms.add_transform(
+            TransformCreateInfo::default().set_rotation(Quaternion::from_angle_y(Deg(10.))),
+        );
+        transforms.store_last_model(&id).unwrap();
+        // same rotation as -10 degrees from the other hemisphere
+        let rotation = -Quaternion::from_angle_y(Deg(-10.));
+        transforms
+            .get_transform_mut(&id)
+            .unwrap()
+            .set_rotation(rotation);
+
+        transforms.interpolation = 0.5;
+        let model = transforms.get_lerp_model(&id).unwrap();
+        assert_orthonormal(model);
+        assert_close(model.z.truncate(), Vector3::unit_z());
+    }
+
+    /// the 400 suzanne grid from `init_world` hung under a spinning root, each suzanne spinning too
+    fn suzanne_grid() -> (TransformSystem, TransformID, Vec<TransformID>) {
+        let mut transforms = TransformSystem::new();
+        let root = transforms.add_transform(TransformCreateInfo::default());
+        let mut suzannes = Vec::new();
+        for x in 0..20 {
+            for z in 0..20 {
+                suzannes.push(
+                    transforms.add_transform(
+                        TransformCreateInfo::from([(x * 3) as f32, 21.0, (z * 3) as f32])
+                            .with_parent(Some(root)),
+                    ),
+                );
+            }
+        }
+        (transforms, root, suzannes)
+    }
+
+    /// spin the root and every suzanne by the frame's angle and get their global models
+    fn spin_suzannes(
+        transforms: &mut TransformSystem,
+        root: TransformID,
+        suzannes: &[TransformID],
+        frame: u32,
+    ) {
+        let angle = Deg(frame as f32);
+        transforms
+            .get_transform_mut(&root)
+            .unwrap()
+            .set_rotation(Quaternion::from_angle_y(angle));
+        for id in suzannes {
+            transforms
+                .get_transform_mut(id)
+                .unwrap()
+                .set_rotation(Quaternion::from_angle_x(angle));
+        }
+        transforms.update_global_models();
+        for id in suzannes {
+            transforms.get_global_model(id).unwrap();
+        }
+    }
+
+    /// the same frame with the old hash map storage
+    fn spin_suzannes_legacy(
+        transforms: &mut LegacyTransforms,
+        root: TransformID,
+        suzannes: &[TransformID],
+        frame: u32,
+    ) {
+        let angle = Deg(frame as f32);
+        transforms
+            .get_transform_mut(&root)
+            .unwrap()
+            .set_rotation(Quaternion::from_angle_y(angle));
+        for id in suzannes {
+            transforms
+                .get_transform_mut(id)
+                .unwrap()
+                .set_rotation(Quaternion::from_angle_x(angle));
+        }
+        for id in suzannes {
+            transforms.get_global_model(id).unwrap();
+        }
+    }
+
+    #[test]
+    fn suzanne_grid_spinning() {
+        const FRAMES: u32 = 10;
+        let (mut transforms, root, suzannes) = suzanne_grid();
+        let mut legacy = LegacyTransforms::new(&transforms);
+        for frame in 0..FRAMES {
+            spin_suzannes(&mut transforms, root, &suzannes, frame);
+            spin_suzannes_legacy(&mut legacy, root, &suzannes, frame);
+        }
+
+        let angle = Deg((FRAMES - 1) as f32);
+        let model = transforms.get_global_model(&suzannes[21]).unwrap();
+        let expected = Matrix4::from_angle_y(angle)
+            * Matrix4::from_translation(Vector3::new(3., 21., 3.))
+            * Matrix4::from_angle_x(angle);
+        assert_close(model.w.truncate(), expected.w.truncate());
+        assert_close(model.z.truncate(), expected.z.truncate());
+        for id in &suzannes {
+            assert_eq!(
+                transforms.get_global_model(id).unwrap(),
+                legacy.get_global_model(id).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn suzanne_grid_benchmark() {
+        const FRAMES: u32 = 200;
+        let (mut transforms, root, suzannes) = suzanne_grid();
+        let mut legacy = LegacyTransforms::new(&transforms);
+
+        let start = Instant::now();
+        for frame in 0..FRAMES {
+            spin_suzannes_legacy(&mut legacy, root, &suzannes, frame);
+        }
+        let legacy_micros = start.elapsed().as_micros() as u32 / FRAMES;
+
+        let start = Instant::now();
+        for frame in 0..FRAMES {
+            spin_suzannes(&mut transforms, root, &suzannes, frame);
+        }
+        let micros = start.elapsed().as_micros() as u32 / FRAMES;
+        println!(
+            "[Benchmarking] 400 suzanne grid: {legacy_micros} μs per frame with the hash map, {micros} μs now"
+        );
+    }
+
+    #[test]
+    fn batched_update_out_of_order() {
+        let mut transforms = TransformSystem::new();
+        // children added before their new parents, so storage starts out of depth order
+        let leaf = transforms.add_transform(TransformCreateInfo::from([0., 0., 1.]));
+        let middle = transforms.add_transform(TransformCreateInfo::from([0., 1., 0.]));
+        let removed = transforms.add_transform(TransformCreateInfo::default());
+        let top = transforms.add_transform(TransformCreateInfo::from([1., 0., 0.]));
+        transforms.get_global_model(&leaf).unwrap();
+        transforms.set_parent(&leaf, Some(middle)).unwrap();
+        transforms.set_parent(&middle, Some(top)).unwrap();
+        transforms
+            .remove_transform(&removed, RemoveMode::Recursive)
+            .unwrap();
+
+        transforms.update_global_models();
+        let leaf_transform = transforms.get_transform(&leaf).unwrap();
+        assert_eq!(leaf_transform.depth, 2);
+        assert_close(
+            leaf_transform.global_model.unwrap().w.truncate(),
+            Vector3::new(1., 1., 1.),
+        );
+
+        // moving the top updates the leaf in the next pass
+        transforms
+            .get_transform_mut(&top)
+            .unwrap()
+            .set_translation([2., 0., 0.]);
+        transforms.reset_phys_modified(&leaf);
+        transforms.update_global_models();
+        let leaf_transform = transforms.get_transform(&leaf).unwrap();
+        assert!(leaf_transform.phys_modified);
+        assert_close(
+            leaf_transform.global_model.unwrap().w.truncate(),
+            Vector3::new(2., 1., 1.),
+        );
+    }
+
+    #[test]
+    fn descendants_of_moved_parent_dirty() {
+        let (mut transforms, parent, child) = parented();
+        let grandchild = transforms
+            .add_transform(TransformCreateInfo::from([0., 0., 1.]).with_parent(Some(child)));
+        let model = transforms.get_global_model(&grandchild).unwrap();
+        assert!(!transforms.is_dirty(&grandchild));
+        assert_eq!(transforms.get_global_model(&grandchild).unwrap(), model);
+
+        transforms
+            .get_transform_mut(&parent)
+            .unwrap()
+            .set_translation([5., 0., 0.]);
+        assert!(transforms.is_dirty(&parent));
+        assert!(transforms.is_dirty(&child));
+        assert!(transforms.is_dirty(&grandchild));
+
+        let moved = transforms.get_global_model(&grandchild).unwrap();
+        assert!(!transforms.is_dirty(&grandchild));
+        let child_model = transforms.get_global_model(&child).unwrap();
+        assert_eq!(
+            moved,
+            child_model * Matrix4::from_translation(Vector3::unit_z())
+        );
+        assert_ne!(moved, model);
+    }
+
+    #[test]
+    fn stale_id_after_reuse() {
+        let mut transforms = TransformSystem::new();
+        let old = transforms.add_transform(TransformCreateInfo::default());
+        transforms
+            .remove_transform(&old, RemoveMode::Recursive)
+            .unwrap();
+
+        let new = transforms.add_transform(TransformCreateInfo::default());
+        assert_eq!(new.id(), old.id());
+        assert_ne!(new, old);
+        assert!(transforms.get_transform(&old).is_none());
+        assert!(transforms
+            .remove_transform(&old, RemoveMode::Recursive)
+            .is_err());
+        assert!(transforms.get_transform(&new).is_some());
+    }
+}
@@ -0,0 +1,58 @@
+use super::{Transform, TransformError, TransformID, TransformSystem};
+use cgmath::{Matrix4, SquareMatrix};
+use std::collections::HashMap;
+
+/// the hash map storage transforms used before the dense storage, kept to benchmark against
+///
+/// moving a transform dirties all of its descendants and global models recurse to the root
+pub(super) struct LegacyTransforms {
+    transforms: HashMap<TransformID, Transform>,
+}
+
+impl LegacyTransforms {
+    /// copy of the transforms of a system
+    pub fn new(system: &TransformSystem) -> Self {
+        Self {
+            transforms: system
+                .transforms
+                .iter()
+                .map(|(id, transform)| (*id, transform.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn get_global_model(&mut self, id: &TransformID) -> Result<Matrix4<f32>, TransformError> {
+        let transform = self.transforms.get(id).ok_or(TransformError::IDNotFound)?;
+
+        Ok(transform.global_model.unwrap_or({
+            let parent_model = match transform.parent {
+                Some(parent_id) => self.get_global_model(&parent_id)?,
+                None => Matrix4::identity(),
+            };
+
+            // the parent version is ignored, a model is only recalculated once it's dirtied
+            self.transforms.get_mut(id).unwrap().clean(&parent_model, 0)
+        }))
+    }
+
+    pub fn get_transform_mut(&mut self, id: &TransformID) -> Option<&mut Transform> {
+        self.dirty(id).ok()?;
+        self.transforms.get_mut(id)
+    }
+
+    /// Flag the global model of the corresponding transform and all its children as dirty
+    fn dirty(&mut self, id: &TransformID) -> Result<(), TransformError> {
+        let transform = self
+            .transforms
+            .get_mut(id)
+            .ok_or(TransformError::IDNotFound)?;
+        transform.global_model = None;
+        transform.phys_modified = true;
+
+        for child in transform.children.clone() {
+            self.dirty(&child)?;
+        }
+
+        Ok(())
+    }
+}
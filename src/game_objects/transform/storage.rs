@@ -0,0 +1,128 @@
+use super::{Transform, TransformID};
+use cgmath::{Matrix4, SquareMatrix};
+
+/// transforms in dense arrays sorted by their depth in the hierachy, so parents always come
+/// before their children and every global model can be updated in one pass
+#[derive(Default)]
+pub(super) struct TransformStorage {
+    /// dense index of the transform using each id index
+    indices: Vec<Option<usize>>,
+    ids: Vec<TransformID>,
+    transforms: Vec<Transform>,
+    /// transforms were added or moved out of depth order since the last sort
+    unsorted: bool,
+}
+
+impl TransformStorage {
+    pub fn get(&self, id: &TransformID) -> Option<&Transform> {
+        self.index_of(id).map(|index| &self.transforms[index])
+    }
+
+    pub fn get_mut(&mut self, id: &TransformID) -> Option<&mut Transform> {
+        self.index_of(id).map(|index| &mut self.transforms[index])
+    }
+
+    pub fn contains_key(&self, id: &TransformID) -> bool {
+        self.index_of(id).is_some()
+    }
+
+    /// add the transform at the end, it is put in depth order by the next sort
+    pub fn insert(&mut self, id: TransformID, transform: Transform) {
+        let index = id.index as usize;
+        if self.indices.len() <= index {
+            self.indices.resize(index + 1, None);
+        }
+        if self
+            .transforms
+            .last()
+            .is_some_and(|last| last.depth > transform.depth)
+        {
+            self.unsorted = true;
+        }
+
+        self.indices[index] = Some(self.transforms.len());
+        self.ids.push(id);
+        self.transforms.push(transform);
+    }
+
+    /// remove the transform, filling its place with the last one
+    pub fn remove(&mut self, id: &TransformID) -> Option<Transform> {
+        let index = self.index_of(id)?;
+        self.indices[id.index as usize] = None;
+        self.ids.swap_remove(index);
+        let transform = self.transforms.swap_remove(index);
+
+        if let Some(moved) = self.ids.get(index) {
+            self.indices[moved.index as usize] = Some(index);
+            self.unsorted = true;
+        }
+        Some(transform)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    /// transforms in storage order
+    pub fn iter(&self) -> impl Iterator<Item = (&TransformID, &Transform)> {
+        self.ids.iter().zip(self.transforms.iter())
+    }
+
+    /// the depth of a transform was changed
+    pub fn mark_unsorted(&mut self) {
+        self.unsorted = true;
+    }
+
+    /// update the global model of every transform that changed or has a parent that changed,
+    /// parents are updated before their children so each is only calculated once
+    pub fn update_global_models(&mut self) {
+        self.sort();
+
+        for index in 0..self.transforms.len() {
+            let parent = self.transforms[index]
+                .parent
+                .and_then(|parent| self.index_of(&parent));
+            let (parents, transforms) = self.transforms.split_at_mut(index);
+            let (parent_model, parent_version) = match parent {
+                // sorting puts parents first
+                Some(parent) => (
+                    parents[parent].global_model.unwrap(),
+                    parents[parent].version,
+                ),
+                None => (Matrix4::identity(), 0),
+            };
+            transforms[0].clean(&parent_model, parent_version);
+        }
+    }
+
+    /// stable sort the transforms by depth if they are out of order
+    fn sort(&mut self) {
+        if !self.unsorted {
+            return;
+        }
+
+        let mut order: Vec<_> = (0..self.transforms.len()).collect();
+        order.sort_by_key(|index| self.transforms[*index].depth);
+
+        let mut transforms: Vec<_> = std::mem::take(&mut self.transforms)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let ids = std::mem::take(&mut self.ids);
+        for (dense, index) in order.into_iter().enumerate() {
+            let id = ids[index];
+            self.indices[id.index as usize] = Some(dense);
+            self.ids.push(id);
+            self.transforms.push(transforms[index].take().unwrap());
+        }
+        self.unsorted = false;
+    }
+
+    fn index_of(&self, id: &TransformID) -> Option<usize> {
+        self.indices
+            .get(id.index as usize)
+            .copied()
+            .flatten()
+            .filter(|index| self.ids[*index] == *id)
+    }
+}
@@ -9,7 +9,9 @@ pub mod ui;
 pub mod utilities {
     pub mod math;
     mod max_heap;
+    mod ring_buffer;
     pub use max_heap::MaxHeap;
+    pub use ring_buffer::RingBuffer;
 }
 mod vertex_data;
 pub mod vulkano_objects;
@@ -29,12 +31,13 @@ pub static RENDER_PROFILER: Mutex<Profiler<7, 128>> = Mutex::new(Profiler::new([
     "ComBuf building",
     "Execute",
 ]));
-pub static LOGIC_PROFILER: Mutex<Profiler<5, 128>> = Mutex::new(Profiler::new([
+pub static LOGIC_PROFILER: Mutex<Profiler<6, 128>> = Mutex::new(Profiler::new([
     "Lock wait",
     "Physics",
     "Colliders",
     "Interpolate",
     "Others",
+    "History",
 ]));
 
 #[cfg(test)]
@@ -1,6 +1,11 @@
+mod character;
 mod collider;
 mod contact;
+mod force;
 mod geo_alg;
+mod island;
+mod joint;
+mod material;
 // mod geo_alg_com;
 
 use crate::{
@@ -8,11 +13,20 @@ use crate::{
     utilities::math::skew,
 };
 use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3, Zero};
+pub use character::CharacterController;
 use collider::ContactIdPair;
-pub use collider::{ColliderSystem, CuboidCollider, LeafInHierachy};
+pub use collider::{
+    BroadPhaseKind, Collider, ColliderRef, ColliderShape, ColliderSnapshot, ColliderSystem,
+    CollisionEvent, CollisionPhase, ConvexHull, HeightField, QueryHit, TriangleMesh,
+};
+pub use contact::ContactSolver;
+pub use force::{AnchoredSpring, Buoyancy, ConstantForce, Drag, ForceGenerator, PointAttractor};
+pub use island::IslandBuilder;
+pub use joint::{solve_joints, Joint, JointKind, Motor, Spring};
+pub use material::{CombineRule, PhysicsMaterial};
 use std::{
     ops::ControlFlow,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{atomic::AtomicUsize, Arc, RwLock, Weak},
 };
 
 type Vector = Vector3<f32>;
@@ -28,6 +42,8 @@ const SLEEP_TIMER: u8 = 10;
 const WAKE_VEL_SQR: f32 = 0.1;
 /// min angular velocity needed to reset sleep timer
 const WAKE_BIVEL_SQR: f32 = 0.1;
+/// default linear and angular damping
+const DEFAULT_DAMPING: f32 = 0.05;
 
 /// Invert a othonormal model matrix that has no skew
 ///
@@ -53,6 +69,7 @@ pub fn matrix_truncate(model: &Matrix4<f32>) -> Matrix3<f32> {
     Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate())
 }
 
+#[derive(Clone)]
 pub struct RigidBody {
     pub transform: TransformID,
     pub velocity: Vector,
@@ -62,18 +79,35 @@ pub struct RigidBody {
     /// sqrt of masses at unit distance on principle axes
     pub principle_moi: Vector,
     pub gravity_multiplier: f32,
+    /// fraction of velocity lost per second, all of it is lost in updates shorter than its inverse
+    pub linear_damping: f32,
+    /// fraction of angular velocity lost per second, like `linear_damping`
+    pub angular_damping: f32,
+
+    /// forces added since the last update, cleared after integrating, adding them wakes a sleeping rb
+    pub force: Vector,
+    pub torque: Vector,
 
     /// heap index of contacts this rb is a part of
     pub contact_refs: Vec<Arc<AtomicUsize>>,
-    pub past_contacts: Vec<(u8, ContactIdPair)>,
+    /// age, id and accumulated impulse of contacts cached from past updates
+    pub past_contacts: Vec<(u8, ContactIdPair, Vector)>,
 
     pub old_velocity: Vector,
 
     /// were contacts cached here last frame
     pub caching_contacts: bool,
 
-    /// frames (updates) before rb starts sleeping, 0 means currently sleeping
+    /// frames (updates) before rb can sleep, 1 means ready to sleep once its island is,
+    /// 0 means currently sleeping
     pub sleep_timer: u8,
+    /// bodies in the same island when it fell asleep, shared by all of them, none while awake
+    pub sleeping_island: Option<Arc<[Weak<RwLock<RigidBody>>]>>,
+
+    /// continuous collision detection, stops fast rbs from passing through thin colliders
+    pub ccd: bool,
+    /// translation over the last update, only set with ccd enabled
+    pub ccd_motion: Option<Vector>,
 }
 impl RigidBody {
     pub fn new(transform: TransformID) -> Self {
@@ -85,6 +119,11 @@ impl RigidBody {
             inv_mass: 1.,
             principle_moi: (1., 1., 1.).into(),
             gravity_multiplier: 1.,
+            linear_damping: DEFAULT_DAMPING,
+            angular_damping: DEFAULT_DAMPING,
+
+            force: Vector::zero(),
+            torque: Vector::zero(),
 
             contact_refs: Vec::new(),
             past_contacts: Vec::new(),
@@ -94,30 +133,47 @@ impl RigidBody {
             caching_contacts: false,
 
             sleep_timer: SLEEP_TIMER,
+            sleeping_island: None,
+
+            ccd: false,
+            ccd_motion: None,
         }
     }
 
     pub fn update(&mut self, transform: &mut Transform, delta_secs: f32) {
+        self.ccd_motion = None;
         // no velocity updates if sleeping
         if self.is_awake() {
             if self.velocity.magnitude2() < WAKE_VEL_SQR
                 && self.bivelocity.magnitude2() < WAKE_BIVEL_SQR
             {
                 // println!("[Debug] RB ({:?}) sleep decrement", self.transform);
-                self.sleep_timer -= 1;
+                // only sleeps with the rest of its island
+                self.sleep_timer = (self.sleep_timer - 1).max(1);
             } else {
                 self.wake();
             }
 
-            self.velocity *= 1. - 0.05 * delta_secs;
-            self.bivelocity *= 1. - 0.05 * delta_secs;
+            // clamped so damping above 1 / delta_secs stops the body instead of reversing it
+            self.velocity *= (1. - self.linear_damping * delta_secs).max(0.);
+            self.bivelocity *= (1. - self.angular_damping * delta_secs).max(0.);
 
             self.velocity += GRAVITY * delta_secs * self.gravity_multiplier;
+            if !self.inv_mass.is_zero() {
+                let rotation = Matrix3::from(*transform.get_local_transform().rotation);
+                let inv_moi = Matrix3::from_diagonal(self.principle_moi.map(|c| 1. / c));
+                self.velocity += self.force * self.inv_mass * delta_secs;
+                self.bivelocity +=
+                    rotation * inv_moi * rotation.transpose() * self.torque * delta_secs;
+            }
 
             transform.mutate(|t, r, _| {
                 *t += self.velocity * delta_secs;
                 *r = geo_alg::bivec_exp((delta_secs / 2.) * self.bivelocity).into_quaternion() * *r;
             });
+            if self.ccd {
+                self.ccd_motion = Some(self.velocity * delta_secs);
+            }
         } else {
             self.velocity = Vector::zero();
             self.bivelocity = Vector::zero();
@@ -135,6 +191,9 @@ impl RigidBody {
         //     self.sleep_timer
         // );
 
+        self.force = Vector::zero();
+        self.torque = Vector::zero();
+
         self.contact_refs.clear();
         if self.caching_contacts {
             self.caching_contacts = false;
@@ -154,6 +213,19 @@ impl RigidBody {
         self.sleep_timer != 0
     }
 
+    /// rested long enough to sleep, once the rest of its island has too
+    pub fn is_ready_to_sleep(&self) -> bool {
+        self.sleep_timer == 1
+    }
+
+    /// stop updating rb until it or another body in its island is woken
+    pub fn sleep(&mut self, island: Arc<[Weak<RwLock<RigidBody>>]>) {
+        self.sleep_timer = 0;
+        self.velocity = Vector::zero();
+        self.bivelocity = Vector::zero();
+        self.sleeping_island = Some(island);
+    }
+
     pub fn apply_impulse(
         &mut self,
         point: Vector,
@@ -183,6 +255,35 @@ impl RigidBody {
         // );
     }
 
+    /// add force through the centre of mass, applied over the next update,
+    /// a sleeping rb is woken so the force isn't dropped
+    pub fn add_force(&mut self, force: Vector) {
+        self.force += force;
+        self.wake_for(force);
+    }
+
+    /// add torque, applied over the next update, a sleeping rb is woken so the torque isn't dropped
+    pub fn add_torque(&mut self, torque: Vector) {
+        self.torque += torque;
+        self.wake_for(torque);
+    }
+
+    /// add force at a point relative to the centre of mass (in world orientation),
+    /// applied over the next update, a sleeping rb is woken so the force isn't dropped
+    pub fn add_force_at_point(&mut self, force: Vector, point: Vector) {
+        self.force += force;
+        self.torque += point.cross(force);
+        self.wake_for(force);
+    }
+
+    /// wake if sleeping and the force or torque isn't zero,
+    /// awake bodies keep their sleep timer so constant forces don't stop them sleeping
+    fn wake_for(&mut self, added: Vector) {
+        if !self.is_awake() && !added.is_zero() {
+            self.wake();
+        }
+    }
+
     pub fn point_velocity(&self, point: Vector) -> Vector {
         self.velocity + self.bivelocity.cross(point)
     }
@@ -197,6 +298,41 @@ impl RigidBody {
         self.principle_moi = scale.map(|c| c * c) / (self.inv_mass * 12.);
     }
 
+    /// Set principle axis masses assuming object is a solid sphere of constant density
+    ///
+    /// Does nothing if inv_mass is zero (i.e infinite mass)
+    pub fn set_moi_as_sphere(&mut self, radius: f32) {
+        if self.inv_mass.is_zero() {
+            return;
+        }
+        let moi = 0.4 * radius * radius / self.inv_mass;
+        self.principle_moi = (moi, moi, moi).into();
+    }
+
+    /// Set principle axis masses assuming object is a solid capsule along the y-axis of constant density,
+    /// dimensions are in world scale
+    ///
+    /// Does nothing if inv_mass is zero (i.e infinite mass)
+    pub fn set_moi_as_capsule(&mut self, half_height: f32, radius: f32) {
+        if self.inv_mass.is_zero() {
+            return;
+        }
+        let height = 2. * half_height;
+        let r_sqr = radius * radius;
+
+        // split mass by volume between the cylinder and the hemispheres
+        let cylinder_vol = height * r_sqr;
+        let spheres_vol = 4. / 3. * radius * r_sqr;
+        let mass = 1. / self.inv_mass;
+        let cylinder_mass = mass * cylinder_vol / (cylinder_vol + spheres_vol);
+        let spheres_mass = mass - cylinder_mass;
+
+        let axial = cylinder_mass * r_sqr / 2. + spheres_mass * r_sqr * 0.4;
+        let perpendicular = cylinder_mass * (height * height / 12. + r_sqr / 4.)
+            + spheres_mass * (r_sqr * 0.4 + height * height / 2. + 0.375 * height * radius);
+        self.principle_moi = (perpendicular, axial, perpendicular).into();
+    }
+
     /// inverse moment of inertia about an axis (and other stuff), calculated via black magic
     pub fn angular_vel_per_impulse(
         &self,
@@ -255,8 +391,8 @@ impl RigidBody {
         self.old_velocity = self.velocity;
     }
 
-    /// search rb for matching contact id and remove it
-    pub fn remove_cached_contact(&mut self, id: &ContactIdPair) {
+    /// search rb for matching contact id and remove it, returning its accumulated impulse
+    pub fn remove_cached_contact(&mut self, id: &ContactIdPair) -> Option<Vector> {
         // let mut index = None;
         let index = self
             .past_contacts
@@ -276,8 +412,9 @@ impl RigidBody {
         //         break;
         //     }
         // }
-        if let ControlFlow::Break(i) = index {
-            self.past_contacts.remove(i);
+        match index {
+            ControlFlow::Break(i) => Some(self.past_contacts.remove(i).2),
+            ControlFlow::Continue(()) => None,
         }
     }
 }
@@ -286,7 +423,7 @@ impl RigidBody {
 mod physics_tests {
     use std::f32::consts::PI;
 
-    use cgmath::{Matrix3, One, Quaternion, Vector3};
+    use cgmath::{Matrix3, One, Quaternion, Vector3, Zero};
 
     use crate::game_objects::transform::TransformSystem;
     use crate::physics::RigidBody;
@@ -359,4 +496,53 @@ mod physics_tests {
                 }
         );
     }
+
+    #[test]
+    fn force_wakes_sleeping() {
+        let mut transforms = TransformSystem::new();
+        let transform = transforms.next().unwrap();
+        let mut rb = RigidBody::new(transform);
+        rb.gravity_multiplier = 0.;
+
+        // zero forces leave it asleep
+        rb.sleep(Vec::new().into());
+        rb.add_force(Vector3::new(0., 0., 0.));
+        rb.add_torque(Vector3::new(0., 0., 0.));
+        assert!(!rb.is_awake());
+
+        rb.add_force(Vector3::new(1., 0., 0.));
+        assert!(rb.is_awake());
+        rb.update(transforms.get_transform_mut(&transform).unwrap(), 0.1);
+        assert!(rb.velocity.x > 0.);
+
+        rb.sleep(Vec::new().into());
+        rb.add_force_at_point(Vector3::new(0., 0., 1.), Vector3::new(1., 0., 0.));
+        assert!(rb.is_awake());
+        rb.update(transforms.get_transform_mut(&transform).unwrap(), 0.1);
+        assert!(rb.velocity.z > 0.);
+        assert!(rb.bivelocity.y < 0.);
+
+        rb.sleep(Vec::new().into());
+        rb.add_torque(Vector3::new(0., 1., 0.));
+        assert!(rb.is_awake());
+        rb.update(transforms.get_transform_mut(&transform).unwrap(), 0.1);
+        assert!(rb.bivelocity.y > 0.);
+    }
+
+    #[test]
+    fn large_damping_stops() {
+        let mut transforms = TransformSystem::new();
+        let transform = transforms.next().unwrap();
+        let mut rb = RigidBody::new(transform);
+        rb.gravity_multiplier = 0.;
+        rb.linear_damping = 1000.;
+        rb.angular_damping = 1000.;
+        rb.velocity = Vector3::new(3., 0., -2.);
+        rb.bivelocity = Vector3::new(0., 5., 0.);
+
+        // far above 1 / delta time, so without a clamp it would flip and grow every update
+        rb.update(transforms.get_transform_mut(&transform).unwrap(), 0.02);
+        assert_eq!(rb.velocity, Vector3::zero());
+        assert_eq!(rb.bivelocity, Vector3::zero());
+    }
 }
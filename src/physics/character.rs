@@ -0,0 +1,491 @@
+//! Kinematic character moved with scene queries instead of by the contact solver
+
+use super::{Collider, ColliderSystem, QueryHit, Vector, GRAVITY};
+use crate::game_objects::transform::{TransformID, TransformSystem};
+use cgmath::{Angle, InnerSpace, Rad};
+use std::f32::consts::PI;
+
+/// gap kept between the character and surfaces it touches, so sweeps don't start overlapping
+const SKIN: f32 = 0.01;
+/// most surfaces slid along in one move
+const MAX_SLIDES: usize = 4;
+/// moves shorter than this are skipped
+const MIN_MOVE: f32 = 1e-4;
+
+/// Upright capsule that collides and slides through the world, steps up ledges and pushes rigidbodies
+///
+/// Contacts don't move the character, so give it a collider without a rigidbody on a layer outside its mask
+/// for rigidbodies to collide with
+#[derive(Clone)]
+pub struct CharacterController {
+    /// half length of the capsule's segment, along the y-axis
+    pub half_height: f32,
+    pub radius: f32,
+    /// horizontal speed at full input
+    pub speed: f32,
+    /// upwards speed when jumping
+    pub jump_speed: f32,
+    /// tallest ledge that can be walked onto
+    pub step_height: f32,
+    /// steepest ground that can be stood on, steeper ground is slid down
+    pub max_slope: Rad<f32>,
+    /// force on rigidbodies walked into head on
+    pub push_force: f32,
+    /// layers the character collides with
+    pub mask: u32,
+    vertical_speed: f32,
+    ground_normal: Option<Vector>,
+}
+
+impl CharacterController {
+    pub fn new(half_height: f32, radius: f32) -> Self {
+        Self {
+            half_height,
+            radius,
+            speed: 6.,
+            jump_speed: 5.,
+            step_height: 0.3,
+            max_slope: Rad(PI / 4.),
+            push_force: 500.,
+            mask: Collider::ALL_LAYERS,
+            vertical_speed: 0.,
+            ground_normal: None,
+        }
+    }
+
+    pub fn with_mask(mut self, mask: u32) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    pub fn with_max_slope(mut self, max_slope: impl Into<Rad<f32>>) -> Self {
+        self.max_slope = max_slope.into();
+        self
+    }
+
+    /// standing on walkable ground
+    pub fn is_grounded(&self) -> bool {
+        self.ground_normal.is_some()
+    }
+
+    /// normal of the ground stood on
+    pub fn ground_normal(&self) -> Option<Vector> {
+        self.ground_normal
+    }
+
+    pub fn vertical_speed(&self) -> f32 {
+        self.vertical_speed
+    }
+
+    /// move the character's transform for a time step
+    ///
+    /// direction is the horizontal direction to walk in, with a length of at most 1,
+    /// jumping only happens when grounded
+    pub fn update(
+        &mut self,
+        transform: &TransformID,
+        transforms: &mut TransformSystem,
+        colliders: &ColliderSystem,
+        direction: Vector,
+        jump: bool,
+        seconds_passed: f32,
+    ) {
+        let start = *transforms
+            .get_transform(transform)
+            .unwrap()
+            .get_local_transform()
+            .translation;
+        let mut position = self.depenetrate(colliders, transforms, start);
+
+        if jump && self.is_grounded() {
+            self.vertical_speed = self.jump_speed;
+            self.ground_normal = None;
+        }
+
+        let walk = Vector::new(direction.x, 0., direction.z) * self.speed * seconds_passed;
+        position = self.walk(colliders, transforms, position, walk);
+
+        if self.is_grounded() {
+            position = self.snap_to_ground(colliders, transforms, position);
+        } else {
+            self.vertical_speed += GRAVITY.y * seconds_passed;
+            let fall = Vector::new(0., self.vertical_speed * seconds_passed, 0.);
+            let (moved, hits) = self.slide(colliders, transforms, position, fall, false);
+            if let Some(ground) = hits.iter().find(|hit| self.walkable(hit.normal)) {
+                self.ground_normal = Some(ground.normal);
+                self.vertical_speed = 0.;
+            } else if !hits.is_empty() && seconds_passed > 0. {
+                // ceilings and steep slopes only allow the speed actually moved
+                self.vertical_speed = (moved.y - position.y) / seconds_passed;
+            }
+            position = moved;
+        }
+
+        if position != start {
+            transforms
+                .get_transform_mut(transform)
+                .unwrap()
+                .set_translation(position);
+        }
+    }
+
+    fn walkable(&self, normal: Vector) -> bool {
+        normal.y >= self.max_slope.cos()
+    }
+
+    /// first collider hit by the capsule centred at position moving along direction
+    fn sweep<'a>(
+        &self,
+        colliders: &'a ColliderSystem,
+        transforms: &mut TransformSystem,
+        position: Vector,
+        direction: Vector,
+        distance: f32,
+    ) -> Option<QueryHit<'a>> {
+        let offset = Vector::new(0., self.half_height, 0.);
+        colliders.sweep_capsule(
+            transforms,
+            position - offset,
+            position + offset,
+            self.radius,
+            direction,
+            distance,
+            self.mask,
+        )
+    }
+
+    /// move out of anything overlapping the capsule, e.g. rigidbodies that landed on it
+    fn depenetrate(
+        &self,
+        colliders: &ColliderSystem,
+        transforms: &mut TransformSystem,
+        position: Vector,
+    ) -> Vector {
+        let offset = Vector::new(0., self.half_height, 0.);
+        colliders
+            .capsule_penetrations(
+                transforms,
+                position - offset,
+                position + offset,
+                self.radius,
+                self.mask,
+            )
+            .into_iter()
+            .fold(position, |position, hit| {
+                position + hit.normal * (hit.distance + SKIN)
+            })
+    }
+
+    /// move as far as possible along the motion, sliding along surfaces hit,
+    /// returns the final position and the hits
+    ///
+    /// walls treats steep surfaces as vertical, so walking can't climb them
+    fn slide<'a>(
+        &self,
+        colliders: &'a ColliderSystem,
+        transforms: &mut TransformSystem,
+        mut position: Vector,
+        mut motion: Vector,
+        walls: bool,
+    ) -> (Vector, Vec<QueryHit<'a>>) {
+        let mut hits = Vec::new();
+        for _ in 0..MAX_SLIDES {
+            let distance = motion.magnitude();
+            if distance < MIN_MOVE {
+                break;
+            }
+            let direction = motion / distance;
+            let Some(hit) = self.sweep(colliders, transforms, position, direction, distance + SKIN)
+            else {
+                position += motion;
+                break;
+            };
+
+            let travel = (hit.distance - SKIN).clamp(0., distance);
+            position += direction * travel;
+            motion *= 1. - travel / distance;
+
+            let mut normal = hit.normal;
+            if walls && !self.walkable(normal) {
+                normal.y = 0.;
+                if normal.magnitude2() < MIN_MOVE {
+                    // flat ceiling
+                    hits.push(hit);
+                    break;
+                }
+                normal = normal.normalize();
+            }
+            // only remove the part of the motion going into the surface
+            motion -= normal * motion.dot(normal).min(0.);
+            hits.push(hit);
+        }
+        (position, hits)
+    }
+
+    /// horizontal movement, stepping over ledges and pushing rigidbodies that block it
+    fn walk(
+        &self,
+        colliders: &ColliderSystem,
+        transforms: &mut TransformSystem,
+        position: Vector,
+        motion: Vector,
+    ) -> Vector {
+        if motion.magnitude() < MIN_MOVE {
+            return position;
+        }
+
+        let (slid, hits) = self.slide(colliders, transforms, position, motion, true);
+        for hit in &hits {
+            self.push(hit, motion);
+        }
+        if !self.is_grounded() || hits.iter().all(|hit| self.walkable(hit.normal)) {
+            return slid;
+        }
+
+        // keep whichever of stepping up or sliding gets further
+        let progress =
+            |end: Vector| Vector::new(end.x - position.x, 0., end.z - position.z).magnitude();
+        match self.step_up(colliders, transforms, position, motion) {
+            Some(stepped) if progress(stepped) > progress(slid) + MIN_MOVE => stepped,
+            _ => slid,
+        }
+    }
+
+    /// move up by the step height, along the motion, then back down onto walkable ground
+    fn step_up(
+        &self,
+        colliders: &ColliderSystem,
+        transforms: &mut TransformSystem,
+        position: Vector,
+        motion: Vector,
+    ) -> Option<Vector> {
+        let up = Vector::new(0., self.step_height, 0.);
+        let (raised, _) = self.slide(colliders, transforms, position, up, false);
+        let (moved, _) = self.slide(colliders, transforms, raised, motion, true);
+
+        let drop = raised.y - position.y + SKIN;
+        let hit = self.sweep(colliders, transforms, moved, -Vector::unit_y(), drop + SKIN)?;
+        if !self.walkable(hit.normal) {
+            return None;
+        }
+        Some(moved - Vector::unit_y() * (hit.distance - SKIN).max(0.))
+    }
+
+    /// stay on the ground when walking down slopes and steps, otherwise start falling
+    fn snap_to_ground(
+        &mut self,
+        colliders: &ColliderSystem,
+        transforms: &mut TransformSystem,
+        position: Vector,
+    ) -> Vector {
+        let hit = self.sweep(
+            colliders,
+            transforms,
+            position,
+            -Vector::unit_y(),
+            self.step_height + SKIN,
+        );
+        match hit {
+            Some(hit) if self.walkable(hit.normal) => {
+                self.ground_normal = Some(hit.normal);
+                position - Vector::unit_y() * (hit.distance - SKIN).max(0.)
+            }
+            _ => {
+                self.ground_normal = None;
+                position
+            }
+        }
+    }
+
+    /// push a rigidbody hit by a horizontal move along the ground
+    fn push(&self, hit: &QueryHit, motion: Vector) {
+        let Some(rigidbody) = hit.collider.get_rigidbody() else {
+            return;
+        };
+        let mut direction = -hit.normal;
+        direction.y = 0.;
+        let head_on = direction.dot(motion.normalize());
+        if head_on <= 0. {
+            return;
+        }
+
+        let mut rigidbody = rigidbody.write().unwrap();
+        rigidbody.add_force(direction.normalize() * self.push_force * head_on);
+    }
+}
+
+#[cfg(test)]
+mod character_tests {
+    use super::CharacterController;
+    use crate::{
+        game_objects::transform::{TransformCreateInfo, TransformID, TransformSystem},
+        physics::{Collider, ColliderSystem, RigidBody, Vector},
+    };
+    use cgmath::{Quaternion, Rad, Rotation3, Zero};
+    use std::sync::{Arc, RwLock};
+
+    const STEP: f32 = 1. / 60.;
+
+    /// floor with its top at y = 0
+    fn floor() -> (TransformSystem, ColliderSystem) {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        let floor = transforms
+            .add_transform(TransformCreateInfo::from([0., -1., 0.]).with_scale([20., 1., 20.]));
+        colliders.add(Collider::cuboid(floor, None), &mut transforms);
+        (transforms, colliders)
+    }
+
+    fn run(
+        character: &mut CharacterController,
+        transform: TransformID,
+        transforms: &mut TransformSystem,
+        colliders: &ColliderSystem,
+        direction: Vector,
+        steps: usize,
+    ) -> Vector {
+        for _ in 0..steps {
+            character.update(&transform, transforms, colliders, direction, false, STEP);
+        }
+        *transforms
+            .get_transform(&transform)
+            .unwrap()
+            .get_local_transform()
+            .translation
+    }
+
+    #[test]
+    fn lands_and_jumps() {
+        let (mut transforms, colliders) = floor();
+        let transform = transforms.add_transform([0., 3., 0.]);
+        let mut character = CharacterController::new(0.5, 0.5);
+
+        let position = run(
+            &mut character,
+            transform,
+            &mut transforms,
+            &colliders,
+            Vector::zero(),
+            120,
+        );
+        assert!(character.is_grounded());
+        assert!((position.y - 1.).abs() < 0.05, "height: {}", position.y);
+
+        character.update(
+            &transform,
+            &mut transforms,
+            &colliders,
+            Vector::zero(),
+            true,
+            STEP,
+        );
+        assert!(!character.is_grounded());
+        let position = run(
+            &mut character,
+            transform,
+            &mut transforms,
+            &colliders,
+            Vector::zero(),
+            10,
+        );
+        assert!(position.y > 1.5, "height: {}", position.y);
+        run(
+            &mut character,
+            transform,
+            &mut transforms,
+            &colliders,
+            Vector::zero(),
+            120,
+        );
+        assert!(character.is_grounded());
+    }
+
+    #[test]
+    fn steps_up_low_ledges() {
+        for (height, climbs) in [(0.2, true), (0.6, false)] {
+            let (mut transforms, mut colliders) = floor();
+            let step = transforms.add_transform(
+                TransformCreateInfo::from([0., height / 2., -7.]).with_scale([2., height / 2., 5.]),
+            );
+            colliders.add(Collider::cuboid(step, None), &mut transforms);
+            let transform = transforms.add_transform([0., 1.02, 0.]);
+            let mut character = CharacterController::new(0.5, 0.5);
+
+            let position = run(
+                &mut character,
+                transform,
+                &mut transforms,
+                &colliders,
+                -Vector::unit_z(),
+                60,
+            );
+            assert_eq!(position.y > 1. + height / 2., climbs, "{:?}", position);
+            assert_eq!(position.z < -2.5, climbs, "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn max_slope() {
+        // the slopes from init_phys_test, rising towards -z
+        for (angle, climbs) in [(0.1, true), (0.2, false)] {
+            let mut transforms = TransformSystem::new();
+            let mut colliders = ColliderSystem::new();
+            let slope = transforms.add_transform(
+                TransformCreateInfo::from([0., -0.5, 0.])
+                    .with_scale([5., 0.5, 5.])
+                    .set_rotation(Quaternion::from_axis_angle(Vector::unit_x(), Rad(angle))),
+            );
+            colliders.add(Collider::cuboid(slope, None), &mut transforms);
+            let transform = transforms.add_transform([0., 1.2, 0.]);
+            let mut character = CharacterController::new(0.5, 0.5).with_max_slope(Rad(0.15));
+
+            let start = run(
+                &mut character,
+                transform,
+                &mut transforms,
+                &colliders,
+                Vector::zero(),
+                30,
+            );
+            assert_eq!(character.is_grounded(), climbs);
+            let end = run(
+                &mut character,
+                transform,
+                &mut transforms,
+                &colliders,
+                -Vector::unit_z(),
+                30,
+            );
+            assert_eq!(end.y > start.y + 0.1, climbs, "{:?} -> {:?}", start, end);
+        }
+    }
+
+    #[test]
+    fn pushes_rigidbodies() {
+        let (mut transforms, mut colliders) = floor();
+        let crate_transform = transforms
+            .add_transform(TransformCreateInfo::from([0., 0.5, -2.]).with_scale([0.5, 0.5, 0.5]));
+        let mut rigidbody = RigidBody::new(crate_transform);
+        rigidbody.set_moi_as_cuboid((0.5, 0.5, 0.5).into());
+        let rigidbody = Arc::new(RwLock::new(rigidbody));
+        colliders.add(
+            Collider::cuboid(crate_transform, Some(rigidbody.clone())),
+            &mut transforms,
+        );
+
+        let transform = transforms.add_transform([0., 1.02, 0.]);
+        let mut character = CharacterController::new(0.5, 0.5);
+        run(
+            &mut character,
+            transform,
+            &mut transforms,
+            &colliders,
+            -Vector::unit_z(),
+            30,
+        );
+
+        let rigidbody = rigidbody.read().unwrap();
+        assert!(rigidbody.is_awake());
+        assert!(rigidbody.force.z < 0., "force: {:?}", rigidbody.force);
+    }
+}
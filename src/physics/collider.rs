@@ -1,64 +1,118 @@
 // mod bounds_tree;
+mod broad_phase;
 mod bvh;
+mod ccd;
+mod convex_hull;
+mod event;
+mod gjk;
+mod heightfield;
+mod narrow_phase;
+mod query;
 mod ray;
+mod shape;
+mod sweep_and_prune;
+mod triangle_mesh;
 
-pub use self::bvh::LeafInHierachy;
+pub use self::broad_phase::{BroadPhaseKind, ColliderRef};
 use super::{
-    contact::{Contact, ContactResolver},
+    contact::{Contact, ContactResolver, ContactSolver},
+    island::IslandBuilder,
+    material::PhysicsMaterial,
     matrix_truncate, RigidBody, Vector,
 };
 use crate::game_objects::transform::{TransformID, TransformSystem};
-use bvh::{Bvh, DepthIter, LeafOutsideHierachy};
+use broad_phase::{BroadPhase, ColliderGuard};
 use cgmath::{InnerSpace, Matrix, Matrix4, MetricSpace, SquareMatrix, Zero};
+pub use convex_hull::ConvexHull;
 use core::f32;
+use event::CollisionTracker;
+pub use event::{CollisionEvent, CollisionPhase};
+pub use heightfield::HeightField;
+pub use query::QueryHit;
 use ray::Ray;
+use rayon::prelude::*;
+pub use shape::ColliderShape;
 use std::{
+    collections::BTreeSet,
     f32::EPSILON,
     fmt::Debug,
     sync::{Arc, RwLock, Weak},
 };
+pub use triangle_mesh::TriangleMesh;
 
 // const CROSS_INDICES: [[usize; 2]; 3] = [[1, 2], [2, 0], [0, 1]];
 /// allow resolving velocity of contacts close to penetrating, penetration resolution won't happen if it remains negative
 const CACHED_NEG_DEPTH: f32 = -0.2; // time step dependent
+/// broad phase bounds are enlarged by this much, so small movements don't need them updated
+const FAT_MARGIN: f32 = 0.2;
+/// fewest overlapping pairs given to a thread at once by the parallel narrow phase
+const NARROW_PHASE_CHUNK: usize = 8;
 
 #[derive(Clone, Copy, Debug)]
 pub struct BoundingBox {
     pub max: Vector,
     pub min: Vector,
 }
-/// cube with radius 1
-pub struct CuboidCollider {
+/// collider of any shape, sized by its transform
+#[derive(Clone)]
+pub struct Collider {
     transform: TransformID,
     rigidbody: Option<Arc<RwLock<RigidBody>>>,
+    shape: ColliderShape,
+    /// triggers report overlaps as events but aren't solid
+    trigger: bool,
+    /// layer bits this collider is in
+    layer: u32,
+    /// layer bits this collider collides with
+    mask: u32,
+    material: PhysicsMaterial,
     // bounding_box: BoundingBox,
 }
 
-/// Note: Probably a useless wrapper around the bvh
+/// Note: Probably a useless wrapper around the broad phase
 #[derive(Default)]
 pub struct ColliderSystem {
-    bounds_tree: Bvh,
+    broad_phase: BroadPhase,
     contact_resolver: ContactResolver,
+    events: CollisionTracker,
+    /// pairs of transforms whose colliders never collide
+    ignored_pairs: BTreeSet<(TransformID, TransformID)>,
+    /// rigidbodies connected by contacts since the islands were last taken
+    islands: IslandBuilder,
+    /// sort overlaps by transform so contacts don't depend on the layout of the broad phase
+    deterministic: bool,
+    /// run the narrow phase on the calling thread instead of rayon's thread pool
+    single_threaded: bool,
 }
 
-#[derive(Debug)]
+/// broad phase and touching pairs of a `ColliderSystem` at one point, restored with `ColliderSystem::restore`
+#[derive(Clone)]
+pub struct ColliderSnapshot {
+    broad_phase: BroadPhase,
+    events: CollisionTracker,
+}
+
+#[derive(Clone, Debug)]
 pub struct ContactIdentifier {
-    pub collider: Weak<CuboidCollider>,
-    element: CuboidElement,
+    pub collider: Weak<Collider>,
+    element: ColliderElement,
 }
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ContactIdPair(pub ContactIdentifier, pub ContactIdentifier);
 
 /// Vertex: bits 0, 1 and 2 correspond to the x, y and z components of the vertex where 0 => -1.0 and 1 => 1.0
 ///
 /// Face: value corresponds to normal axis of face, 0 => x-axis, 1 => y-axis, 2 => z-axis
-#[derive(PartialEq, Eq, Debug)]
-enum CuboidElement {
+///
+/// Surface: any point on a smooth shape (sphere, capsule, plane), these contacts are not cached
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum ColliderElement {
     Vertex(u8),
     Face(u8),
     Edge(u8),
+    Surface,
 }
-use CuboidElement::*;
+use ColliderElement::*;
 
 impl BoundingBox {
     pub fn new(min: impl Into<Vector>, max: impl Into<Vector>) -> Self {
@@ -126,6 +180,28 @@ impl BoundingBox {
         extends.x * extends.y * extends.z
     }
 
+    pub fn surface_area(&self) -> f32 {
+        let extends = self.max - self.min;
+        2. * (extends.x * extends.y + extends.y * extends.z + extends.z * extends.x)
+    }
+
+    /// true if other is entirely inside self
+    pub fn contains(&self, other: Self) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && self.min.z <= other.min.z
+            && self.max.x >= other.max.x
+            && self.max.y >= other.max.y
+            && self.max.z >= other.max.z
+    }
+
+    /// enlarge bounds by margin on every side
+    pub fn grow(&mut self, margin: f32) {
+        let margin = Vector::new(margin, margin, margin);
+        self.min -= margin;
+        self.max += margin;
+    }
+
     pub fn centre(&self) -> Vector {
         (self.min + self.max) / 2.
     }
@@ -194,21 +270,111 @@ const CUBE_VERTICES: [Vector; 8] = [
     },
 ];
 
-impl CuboidCollider {
+impl Collider {
+    pub const DEFAULT_LAYER: u32 = 1;
+    pub const ALL_LAYERS: u32 = u32::MAX;
+
     pub fn new(
         // transforms: &mut TransformSystem,
         transform: TransformID,
         rigidbody: Option<Arc<RwLock<RigidBody>>>,
+        shape: ColliderShape,
     ) -> Self {
-        let collider = CuboidCollider {
+        let collider = Collider {
             transform,
             rigidbody,
+            shape,
+            trigger: false,
+            layer: Self::DEFAULT_LAYER,
+            mask: Self::ALL_LAYERS,
+            material: PhysicsMaterial::default(),
             // bounding_box: BoundingBox::default(),
         };
         // collider.update_bounding(transforms);
         collider
     }
 
+    /// cube with radius 1
+    pub fn cuboid(transform: TransformID, rigidbody: Option<Arc<RwLock<RigidBody>>>) -> Self {
+        Self::new(transform, rigidbody, ColliderShape::Cuboid)
+    }
+
+    pub fn sphere(
+        transform: TransformID,
+        rigidbody: Option<Arc<RwLock<RigidBody>>>,
+        radius: f32,
+    ) -> Self {
+        Self::new(transform, rigidbody, ColliderShape::Sphere { radius })
+    }
+
+    pub fn capsule(
+        transform: TransformID,
+        rigidbody: Option<Arc<RwLock<RigidBody>>>,
+        half_height: f32,
+        radius: f32,
+    ) -> Self {
+        Self::new(
+            transform,
+            rigidbody,
+            ColliderShape::Capsule {
+                half_height,
+                radius,
+            },
+        )
+    }
+
+    /// static infinite plane, solid below the transform's local y-axis
+    pub fn plane(transform: TransformID) -> Self {
+        Self::new(transform, None, ColliderShape::Plane)
+    }
+
+    pub fn convex_hull(
+        transform: TransformID,
+        rigidbody: Option<Arc<RwLock<RigidBody>>>,
+        hull: Arc<ConvexHull>,
+    ) -> Self {
+        Self::new(transform, rigidbody, ColliderShape::ConvexHull(hull))
+    }
+
+    /// triangle meshes are static so don't take a rigidbody
+    pub fn triangle_mesh(transform: TransformID, mesh: Arc<TriangleMesh>) -> Self {
+        Self::new(transform, None, ColliderShape::TriangleMesh(mesh))
+    }
+
+    /// height fields are static so don't take a rigidbody
+    pub fn heightfield(transform: TransformID, field: Arc<HeightField>) -> Self {
+        Self::new(transform, None, ColliderShape::HeightField(field))
+    }
+
+    /// make the collider a trigger, so overlaps only create events without being resolved
+    pub fn with_trigger(mut self, trigger: bool) -> Self {
+        self.trigger = trigger;
+        self
+    }
+
+    /// set the layer bits the collider is in and the layers it collides with,
+    /// both colliders must be in the other's mask to collide
+    pub fn with_layers(mut self, layer: u32, mask: u32) -> Self {
+        self.layer = layer;
+        self.mask = mask;
+        self
+    }
+
+    pub fn with_material(mut self, material: PhysicsMaterial) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// change shape, modify through `ColliderSystem::get_mut` so the bounds are refit
+    pub fn set_shape(&mut self, shape: ColliderShape) {
+        self.shape = shape;
+    }
+
+    pub fn set_layers(&mut self, layer: u32, mask: u32) {
+        self.layer = layer;
+        self.mask = mask;
+    }
+
     pub fn calc_bounding(&self, transforms: &mut TransformSystem) -> BoundingBox {
         let global_model = transforms.get_global_model(&self.transform).unwrap();
         // let view = transforms
@@ -221,13 +387,22 @@ impl CuboidCollider {
         // self.bounding_box.min = pos.truncate() / pos.w;
         // self.bounding_box.max = self.bounding_box.min + view.scale;
 
-        let vertices = CUBE_VERTICES.map(|v| {
-            let v = global_model * v.extend(1.0);
-            v.truncate() / v.w
-        });
-
-        BoundingBox::from_vertices(&vertices)
+        let bounds = self.shape.to_global(&global_model).bounds();
         // self.bounding_box.translate(*view.translation);
+
+        // sweep bounds back over the last motion so the pairs passed through are still found
+        match self.ccd_motion() {
+            Some(motion) => {
+                let mut start = bounds;
+                start.translate(-motion);
+                bounds.join(start)
+            }
+            None => bounds,
+        }
+    }
+
+    fn ccd_motion(&self) -> Option<Vector> {
+        self.rigidbody.as_ref()?.read().unwrap().ccd_motion
     }
 
     // pub fn get_bounds(&self) -> &BoundingBox {
@@ -242,7 +417,32 @@ impl CuboidCollider {
         &self.rigidbody
     }
 
-    /// assuming inv_model is normalised, returned normal is not normalised
+    pub fn get_shape(&self) -> &ColliderShape {
+        &self.shape
+    }
+
+    pub fn is_trigger(&self) -> bool {
+        self.trigger
+    }
+
+    pub fn get_material(&self) -> &PhysicsMaterial {
+        &self.material
+    }
+
+    pub fn get_layer(&self) -> u32 {
+        self.layer
+    }
+
+    pub fn get_mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// check layers and masks of both colliders
+    pub fn can_collide(&self, other: &Self) -> bool {
+        self.layer & other.mask != 0 && other.layer & self.mask != 0
+    }
+
+    /// cuboid normal, assuming inv_model is normalised, returned normal is not normalised
     #[allow(clippy::collapsible_else_if)]
     pub fn point_normal(point: Vector, inv_model: &Matrix4<f32>) -> Vector {
         let point_local = matrix_truncate(inv_model) * (point + inv_model.w.truncate());
@@ -265,78 +465,249 @@ impl CuboidCollider {
         point_local[axis_index].signum() * inv_model.row(axis_index).truncate()
     }
 }
-impl Debug for CuboidCollider {
+impl Debug for Collider {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("Collider({})", self.transform.id()))
+        f.write_fmt(format_args!(
+            "Collider({}, gen {}, {:?})",
+            self.transform.id(),
+            self.transform.generation(),
+            self.shape
+        ))
     }
 }
 
 impl ColliderSystem {
     pub fn new() -> Self {
+        Self::with_broad_phase(BroadPhaseKind::default())
+    }
+
+    pub fn with_broad_phase(kind: BroadPhaseKind) -> Self {
         Self {
-            bounds_tree: Bvh::new(),
+            broad_phase: BroadPhase::new(kind),
             contact_resolver: ContactResolver::new(),
+            events: CollisionTracker::default(),
+            ignored_pairs: BTreeSet::new(),
+            islands: IslandBuilder::default(),
+            deterministic: false,
+            single_threaded: false,
         }
     }
 
+    pub fn get_broad_phase(&self) -> BroadPhaseKind {
+        self.broad_phase.kind()
+    }
+
+    pub fn set_contact_solver(&mut self, solver: ContactSolver) {
+        self.contact_resolver.solver = solver;
+    }
+
+    pub fn get_contact_solver(&self) -> ContactSolver {
+        self.contact_resolver.solver
+    }
+
+    /// find contacts in the same order every run, the same updates then give the same results
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// find contacts of overlapping pairs on one thread, the contacts found are the same either way
+    pub fn set_single_threaded(&mut self, single_threaded: bool) {
+        self.single_threaded = single_threaded;
+    }
+
+    /// take the islands connected by contacts since last taken, to add joints and update sleeping
+    pub fn take_islands(&mut self) -> IslandBuilder {
+        std::mem::take(&mut self.islands)
+    }
+
     pub fn tree_depth(&self) -> usize {
-        self.bounds_tree.depth()
+        self.broad_phase.depth()
     }
 
-    // removed update and reinsert given collider
-    pub fn update(&mut self, target: &mut LeafInHierachy, transforms: &mut TransformSystem) {
-        self.bounds_tree
+    /// update given collider in the broad phase if it has moved out of its enlarged bounds
+    pub fn update(&mut self, target: ColliderRef, transforms: &mut TransformSystem) {
+        self.broad_phase
             .recalculate_bounds(target, |collider| collider.calc_bounding(transforms))
             .unwrap();
     }
 
-    /// adds collider to bounds tree, returns a reference to its leaf node
-    pub fn add(
+    /// adds collider to the broad phase, returns a reference to it
+    pub fn add(&mut self, collider: Collider, transforms: &mut TransformSystem) -> ColliderRef {
+        self.broad_phase
+            .insert(collider.calc_bounding(transforms), Arc::new(collider))
+    }
+    /// adds many colliders at once, rebuilding the broad phase to fit them all
+    ///
+    /// returns references to them in the same order
+    pub fn add_many(
         &mut self,
-        collider: CuboidCollider,
+        colliders: impl IntoIterator<Item = Collider>,
         transforms: &mut TransformSystem,
-    ) -> LeafInHierachy {
-        self.bounds_tree.insert(Bvh::register_collider(
-            collider.calc_bounding(transforms),
-            Arc::new(collider),
-        ))
+    ) -> Vec<ColliderRef> {
+        let colliders = colliders
+            .into_iter()
+            .map(|collider| (collider.calc_bounding(transforms), Arc::new(collider)))
+            .collect();
+        self.broad_phase.insert_many(colliders)
     }
-    pub fn remove(
-        &mut self,
-        target: LeafInHierachy,
-    ) -> Result<LeafOutsideHierachy, LeafInHierachy> {
-        self.bounds_tree.remove(target)
+
+    /// rebuild the broad phase from scratch, best done after loading a level
+    pub fn rebuild_tree(&mut self) {
+        self.broad_phase.rebuild();
+    }
+
+    /// modify a collider, it's refit by `update_modified` once the guard is dropped
+    ///
+    /// none if the collider was removed or isn't from this system
+    pub fn get_mut(&mut self, target: ColliderRef) -> Option<ColliderGuard<'_>> {
+        self.broad_phase.get_mut(target)
+    }
+
+    /// refit colliders modified through `get_mut`, even if they haven't moved
+    pub fn update_modified(&mut self, transforms: &mut TransformSystem) {
+        self.broad_phase
+            .refit_modified(|collider| collider.calc_bounding(transforms));
     }
 
-    pub fn bounds_iter(&'_ self) -> DepthIter<'_> {
-        self.bounds_tree.iter()
+    /// copy the broad phase and the pairs touching, colliders are shared with the copy
+    pub fn snapshot(&self) -> ColliderSnapshot {
+        ColliderSnapshot {
+            broad_phase: self.broad_phase.clone(),
+            events: self.events.clone(),
+        }
     }
 
-    pub fn get_potential_overlaps(&self) -> Vec<(&Arc<CuboidCollider>, &Arc<CuboidCollider>)> {
-        self.bounds_tree.get_overlaps()
+    /// go back to a snapshot, references to colliders added since then stop working
+    /// and removed colliders come back
+    pub fn restore(&mut self, snapshot: &ColliderSnapshot) {
+        self.broad_phase = snapshot.broad_phase.clone();
+        self.events = snapshot.events.clone();
+    }
+
+    /// gives back the reference if it isn't from this system
+    pub fn remove(&mut self, target: ColliderRef) -> Result<Arc<Collider>, ColliderRef> {
+        self.broad_phase.remove(target)
+    }
+
+    /// broad phase bounds with their depth in the tree
+    pub fn bounds_iter(&'_ self) -> impl Iterator<Item = (BoundingBox, usize)> + '_ {
+        self.broad_phase.iter()
+    }
+
+    pub fn get_potential_overlaps(&self) -> Vec<(&Arc<Collider>, &Arc<Collider>)> {
+        self.broad_phase
+            .get_overlaps(&|coll_1, coll_2| self.pair_filter(coll_1, coll_2))
+    }
+
+    /// stop colliders of the 2 transforms from colliding, regardless of their layers
+    pub fn ignore_pair(&mut self, transform_1: TransformID, transform_2: TransformID) {
+        self.ignored_pairs
+            .insert(transform_pair(transform_1, transform_2));
+    }
+
+    /// undo `ignore_pair`, returns false if the pair wasn't ignored
+    pub fn restore_pair(&mut self, transform_1: TransformID, transform_2: TransformID) -> bool {
+        self.ignored_pairs
+            .remove(&transform_pair(transform_1, transform_2))
+    }
+
+    fn pair_filter(&self, coll_1: &Collider, coll_2: &Collider) -> bool {
+        coll_1.can_collide(coll_2)
+            && !self
+                .ignored_pairs
+                .contains(&transform_pair(coll_1.transform, coll_2.transform))
     }
     pub fn get_last_contacts(&self) -> &Vec<(Vector, Vector, u8, f32)> {
         &self.contact_resolver.past_contacts
     }
 
+    /// enter/stay/exit events of touching colliders from the last `get_contacts`
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, CollisionEvent> {
+        self.events.drain()
+    }
+
+    /// closest collider on a layer in the mask, ignores triggers
     pub fn raycast(
         &self,
         transforms: &mut TransformSystem,
         start: Vector,
         direction: Vector,
         distance: f32,
-    ) -> Option<(Vector, &Arc<CuboidCollider>)> {
+        mask: u32,
+    ) -> Option<(Vector, &Arc<Collider>)> {
         let ray = Ray::new(start, direction, distance);
-        let result = self.bounds_tree.raycast(&ray, transforms);
+        let result = self.broad_phase.raycast(&ray, transforms, &|collider| {
+            !collider.trigger && collider.layer & mask != 0
+        });
         result.map(|(d, c)| (ray.calc_point(d), c))
     }
 
+    /// move ccd rigidbodies back to where they first hit another collider during their last update,
+    /// bounds must be updated first and contacts found afterwards to stop them
+    ///
+    /// other colliders are treated as staying at their current position
+    pub fn resolve_ccd(&mut self, transforms: &mut TransformSystem) {
+        let mut overlaps = self.broad_phase.get_overlaps(&|coll_1, coll_2| {
+            !coll_1.trigger && !coll_2.trigger && self.pair_filter(coll_1, coll_2)
+        });
+        if self.deterministic {
+            sort_overlaps(&mut overlaps);
+        }
+
+        // earliest impact of each rigidbody
+        let mut impacts: Vec<(&Arc<RwLock<RigidBody>>, Vector, f32)> = Vec::new();
+        for (coll_1, coll_2) in overlaps {
+            for (moving, other) in [(coll_1, coll_2), (coll_2, coll_1)] {
+                let Some(rb) = &moving.rigidbody else {
+                    continue;
+                };
+                if let Some(other_rb) = &other.rigidbody {
+                    if Arc::ptr_eq(rb, other_rb) {
+                        continue;
+                    }
+                }
+                let Some(motion) = rb.read().unwrap().ccd_motion else {
+                    continue;
+                };
+
+                if let Some(toi) = ccd::time_of_impact(moving, motion, other, transforms) {
+                    match impacts.iter_mut().find(|(r, _, _)| Arc::ptr_eq(r, rb)) {
+                        Some(impact) => impact.2 = impact.2.min(toi),
+                        None => impacts.push((rb, motion, toi)),
+                    }
+                }
+            }
+        }
+
+        // motion is in world space, so rewind the global position in case of a parent
+        for (rb, motion, toi) in impacts {
+            let transform = rb.read().unwrap().transform;
+            let position = transforms.get_global_position(&transform).unwrap();
+            transforms
+                .set_global_position(&transform, position - (1. - toi) * motion)
+                .unwrap();
+        }
+    }
+
     #[allow(clippy::collapsible_else_if)]
     /// check overlaps in bvh for actual collisions
     ///
     /// TODO: point-face collisions can still have normals in the wrong direction
     pub fn get_contacts(&mut self, transforms: &mut TransformSystem) -> &mut ContactResolver {
-        for (mut coll_1, mut coll_2) in self.bounds_tree.get_overlaps() {
+        let mut overlaps = self
+            .broad_phase
+            .get_overlaps(&|coll_1, coll_2| self.pair_filter(coll_1, coll_2));
+        if self.deterministic {
+            sort_overlaps(&mut overlaps);
+        }
+        // pairs that need their contacts found and the models of both colliders
+        let mut pairs = Vec::with_capacity(overlaps.len());
+        for (mut coll_1, mut coll_2) in overlaps {
+            let trigger = coll_1.trigger || coll_2.trigger;
             if let Some(rb_1) = &coll_1.rigidbody {
                 if let Some(rb_2) = &coll_2.rigidbody {
                     if Arc::ptr_eq(rb_1, rb_2) {
@@ -349,12 +720,25 @@ impl ColliderSystem {
                 if coll_2.rigidbody.is_some() {
                     // ensure coll_1 always has rigidbody
                     std::mem::swap(&mut coll_1, &mut coll_2);
-                } else {
-                    // ignore contacts not involving rigidbodies
+                } else if !trigger {
+                    // ignore contacts not involving rigidbodies, triggers still fire for
+                    // colliders moved without one
                     continue;
                 }
             }
 
+            // sleeping islands keep their contacts from when they fell asleep
+            let awake = |collider: &Collider| {
+                collider
+                    .rigidbody
+                    .as_ref()
+                    .is_some_and(|rb| rb.read().unwrap().is_awake())
+            };
+            if coll_1.rigidbody.is_some() && !awake(coll_1) && !awake(coll_2) {
+                self.events.keep(coll_1.transform, coll_2.transform);
+                continue;
+            }
+
             let model_1 = transforms.get_global_model(&coll_1.transform).unwrap(); // impl func to try get model without mut
             let model_2 = transforms.get_global_model(&coll_2.transform).unwrap();
 
@@ -364,399 +748,72 @@ impl ColliderSystem {
                     model_1.w.w, model_2.w.w
                 );
             }
+            pairs.push((coll_1, coll_2, model_1, model_2));
+        }
 
-            // seperating axis
-            let dist_1_2 = (model_1.w - model_2.w).truncate(); // might need to normalise
+        // narrow phase, models are already calculated so pairs can be checked in parallel
+        let narrow_phase = |(coll_1, coll_2, model_1, model_2): &(
+            &Arc<Collider>,
+            &Arc<Collider>,
+            Matrix4<f32>,
+            Matrix4<f32>,
+        )| {
+            let shape_1 = coll_1.shape.to_global(model_1);
+            let shape_2 = coll_2.shape.to_global(model_2);
+            narrow_phase::contacts(&shape_1, &shape_2)
+        };
+        let pair_contacts: Vec<_> = if self.single_threaded {
+            pairs.iter().map(narrow_phase).collect()
+        } else {
+            pairs
+                .par_iter()
+                .with_min_len(NARROW_PHASE_CHUNK)
+                .map(narrow_phase)
+                .collect()
+        };
 
-            let axes = [
-                model_1.x, model_1.y, model_1.z, model_2.x, model_2.y, model_2.z,
-            ]
-            .map(|v| v.truncate());
-            let cross_axes = (0..3).flat_map(|i1| (3..6).map(move |i2| axes[i1].cross(axes[i2])));
-
-            let mut sep_axis_found = false;
-            for axis in axes.into_iter().chain(cross_axes) {
-                let proj_1 =
-                    axes[0].dot(axis).abs() + axes[1].dot(axis).abs() + axes[2].dot(axis).abs();
-                let proj_2 =
-                    axes[3].dot(axis).abs() + axes[4].dot(axis).abs() + axes[5].dot(axis).abs();
-
-                if dist_1_2.dot(axis).abs() > proj_1 + proj_2 {
-                    sep_axis_found = true;
-                    break;
-                }
+        // merged in the order of the pairs, so the result doesn't depend on the threads
+        for ((coll_1, coll_2, _, _), contacts) in pairs.into_iter().zip(pair_contacts) {
+            let trigger = coll_1.trigger || coll_2.trigger;
+            if !contacts.is_empty() {
+                self.events
+                    .touch(coll_1.transform, coll_2.transform, trigger);
             }
-            if sep_axis_found {
+            if trigger {
                 continue;
-            };
-
-            let inv_model_1 = model_1.invert().unwrap();
-            let inv_model_2 = model_2.invert().unwrap();
-            // let inv_axes_1: Matrix3<f32> = [
-            //     inv_model_1.x.truncate().into(),
-            //     inv_model_1.y.truncate().into(),
-            //     inv_model_1.z.truncate().into(),
-            // ]
-            // .into();
-            let axes_1_sqr = [
-                model_1.x.magnitude2(),
-                model_1.y.magnitude2(),
-                model_1.z.magnitude2(),
-            ];
-
-            let model_2_1 = inv_model_1 * model_2;
-            let model_1_2 = inv_model_2 * model_1;
-            // let axes_2_1 = [model_2_1.x, model_2_1.y, model_2_1.z].map(|v| v.truncate());
-            let axes_2_sqr = [0, 1, 2].map(|i| model_2[i].magnitude2());
-
-            let unit_bounds = BoundingBox {
-                min: CUBE_VERTICES[0],
-                max: CUBE_VERTICES[7],
-            };
-
-            let mut max_pen_pf_sqr = 0.;
-            let mut contact_point_pf = [0., 0., 0.].into();
-            let mut pen_axis: u8 = 0;
-            let mut pf_elems = (Vertex(0), Face(0));
-            // p-f contacts
-            let points_2_1 = CUBE_VERTICES.map(|v| (model_2_1 * v.extend(1.)).truncate());
-            for p2_i in 0..4 {
-                // opposite corners a and b forming the cubiod diagonal
-                let diagonal_2_min_1 = points_2_1[p2_i];
-                let diagonal_2_max_1 = points_2_1[7 - p2_i];
-                // unnormalised ray
-                let ray = Ray {
-                    origin: diagonal_2_min_1,
-                    direction: diagonal_2_max_1 - diagonal_2_min_1,
-                    distance: 1.,
-                };
-                let (close, far) = ray.box_intersection_raw(&unit_bounds);
-
-                // manual intersection check with distance 1
-                if close <= far && far >= 0. && close <= 1. {
-                    let min_depth = far; // p2 min point pen into far face
-                    let max_depth = 1. - close; // p2 max point pen into close face
-
-                    // use smaller depth
-                    if min_depth > max_depth {
-                        let p1_1 = ray.calc_point(close);
-                        let a1_i = CuboidElement::closest_face(p1_1);
-                        let d_1 = 1. - diagonal_2_max_1[a1_i as usize].abs(); // should be +ve i swear
-                        let depth_sqr = d_1 * d_1 * axes_1_sqr[a1_i as usize];
-
-                        if depth_sqr > max_pen_pf_sqr {
-                            max_pen_pf_sqr = depth_sqr;
-                            contact_point_pf = diagonal_2_max_1;
-                            pen_axis = a1_i;
-                            pf_elems = (Face(a1_i), Vertex(7 - p2_i as u8));
-                            // use max index for p2
-                        }
-                    } else {
-                        let p1_1 = ray.calc_point(far);
-                        let a1_i = CuboidElement::closest_face(p1_1);
-                        let d_1 = 1. - diagonal_2_min_1[a1_i as usize].abs(); // should be +ve i swear
-                        let depth_sqr = d_1 * d_1 * axes_1_sqr[a1_i as usize];
-
-                        if depth_sqr > max_pen_pf_sqr {
-                            max_pen_pf_sqr = depth_sqr;
-                            contact_point_pf = diagonal_2_min_1;
-                            pen_axis = a1_i;
-                            pf_elems = (Face(a1_i), Vertex(p2_i as u8));
-                        }
-                    }
-                }
             }
-            // f-p contacts
-            let points_1_2 = CUBE_VERTICES.map(|v| (model_1_2 * v.extend(1.)).truncate());
-            for p1_i in 0..4 {
-                let p1_min_2 = points_1_2[p1_i];
-                let p1_max_2 = points_1_2[7 - p1_i];
-                let ray = Ray {
-                    origin: p1_min_2,
-                    direction: p1_max_2 - p1_min_2,
-                    distance: 1.,
-                };
-                let (close, far) = ray.box_intersection_raw(&unit_bounds);
-
-                if close <= far && far >= 0. && close <= 1. {
-                    let min_depth = far; // min point pen into far face
-                    let max_depth = 1. - close; // max point pen into close face
-
-                    // use smaller depth
-                    if min_depth > max_depth {
-                        let p2_2 = ray.calc_point(close);
-                        let a2_i = CuboidElement::closest_face(p2_2);
-                        let d_2 = 1. - p1_max_2[a2_i as usize].abs(); // should be +ve i swear
-                        let depth_sqr = d_2 * d_2 * axes_2_sqr[a2_i as usize];
-
-                        if depth_sqr > max_pen_pf_sqr {
-                            max_pen_pf_sqr = depth_sqr;
-                            contact_point_pf = CUBE_VERTICES[7 - p1_i];
-                            pen_axis = a2_i + 3;
-                            pf_elems = (Vertex(7 - p1_i as u8), Face(a2_i));
-                            // use max index for p2
-                        }
-                    } else {
-                        let p2 = ray.calc_point(far);
-                        let a2_i = CuboidElement::closest_face(p2);
-                        let d_local = 1. - p1_min_2[a2_i as usize].abs(); // should be +ve i swear
-                        let depth_sqr = d_local * d_local * axes_2_sqr[a2_i as usize];
-
-                        if depth_sqr > max_pen_pf_sqr {
-                            max_pen_pf_sqr = depth_sqr;
-                            contact_point_pf = CUBE_VERTICES[p1_i];
-                            pen_axis = a2_i + 3;
-                            pf_elems = (Vertex(p1_i as u8), Face(a2_i));
-                        }
-                    }
-                }
+            // only trigger pairs can be without a rigidbody
+            let rb_1 = coll_1.rigidbody.as_ref().unwrap();
+            if !contacts.is_empty() {
+                self.islands.connect(rb_1, coll_2.rigidbody.as_ref());
             }
 
-            // e-e contacts
-            let points_1 = [1, 2, 4, 7].map(|i| (model_1 * CUBE_VERTICES[i].extend(1.)).truncate());
-            let points_2 = [1, 2, 4, 7].map(|i| (model_2 * CUBE_VERTICES[i].extend(1.)).truncate());
-            let a2_a1_precalc = [model_1.x, model_1.y, model_1.z].map(|a1| {
-                let a1_inv = a1 / a1.magnitude2();
-                [model_2.x, model_2.y, model_2.z].map(|a2| {
-                    let p2 = (a2 - a1.dot(a2) * a1_inv).truncate();
-                    (
-                        a2.truncate().cross(a1.truncate()).normalize(),
-                        p2 / p2.magnitude2(),
-                    )
-                })
-            });
-            // let axes_1 = model_1.in [1, 2, 3].map(|i| {(model_2 * CUBE_VERTICES[i].extend(1.)).truncate()});
-            let mut max_pen_ee_sqr = 0.;
-            let mut contact_point_ee = [0., 0., 0.].into();
-            let mut pen_axis_1 = 0;
-            let mut pen_axis_2 = 0;
-            let mut ee_elems = (Edge(0), Edge(0));
-            // for each unique axis point on 1
-            for (p1_i, p1) in points_1.iter().enumerate() {
-                for (p2_i, p2) in points_2.iter().enumerate() {
-                    let rough_d_1_2 = p1 - p2;
-                    for (e1_i, precalcs) in a2_a1_precalc.iter().enumerate() {
-                        for (e2_i, (cross, proj_a2)) in precalcs.iter().enumerate() {
-                            let d_1_2_mag = cross.dot(rough_d_1_2);
-                            let d_1_2 = d_1_2_mag * cross;
-
-                            // if d_1_2.dot((model_1.w - model_2.w).truncate())
-
-                            // check if new pen is larger than current max pen
-                            if d_1_2_mag * d_1_2_mag <= max_pen_ee_sqr {
-                                continue;
-                            }
-                            // check if closest on 2 is closer than closest on 1 to box 2 (is penetrating)
-                            let closest_2 =
-                                p2 + proj_a2.dot(rough_d_1_2) * model_2[e2_i].truncate();
-                            let closest_1 = closest_2 + d_1_2;
-                            if (closest_2 - model_2.w.truncate()).magnitude2()
-                                <= (closest_1 - model_2.w.truncate()).magnitude2()
-                            {
-                                continue;
-                            }
-                            // check if closest line points are outside the segment comprising their box edge
-                            if (closest_2 - model_2.w.truncate()).magnitude2()
-                                > (p2 - model_2.w.truncate()).magnitude2()
-                                || (closest_1 - model_1.w.truncate()).magnitude2()
-                                    > (p1 - model_1.w.truncate()).magnitude2()
-                            {
-                                continue;
-                            }
-                            // ensure closest point on 2 is inside box 1
-                            let t21 = inv_model_1 * closest_2.extend(1.);
-                            if t21.x.abs() > 1. || t21.y.abs() > 1. || t21.z.abs() > 1. {
-                                continue;
-                            }
-                            // ensure closest points are in the same quadrant of box 2 (prevents "penetration" of opposite edges)
-                            let t11 = inv_model_1 * closest_1.extend(1.);
-                            if t21.x.signum() != t11.x.signum()
-                                || t21.y.signum() != t11.y.signum()
-                                || t21.z.signum() != t11.z.signum()
-                            {
-                                continue;
-                            }
-
-                            // println!("[Debug] Closest in 1 local: {:?}", t1);
-                            // println!("[Debug] Closest in 2 local: {:?}", t2);
-
-                            max_pen_ee_sqr = d_1_2_mag * d_1_2_mag;
-                            contact_point_ee = closest_2;
-                            pen_axis_1 = e1_i;
-                            pen_axis_2 = e2_i + 3;
-                            ee_elems = (
-                                CuboidElement::from_vertex_axis(p1_i as u8, e1_i as u8),
-                                CuboidElement::from_vertex_axis(p2_i as u8, e2_i as u8),
-                            )
-                        }
-                    }
-                }
-            }
-            // for each unique axis point on 2
-            // for p2_i in [1, 2, 4, 7] {
-            //     let point_2_1 = points_2_1[p2_i as usize];
-            //     // for each edge from that point
-            //     for (a2_i, axis_2_1) in axes_2_1.iter().enumerate() {
-            //         // closest point on edge to 1's centre
-            //         let e2_c1_1 = point_2_1 - axis_2_1.dot(point_2_1) * axes_2_1_inv[a2_i];
-
-            //         // closest vertex on 1 to d (closest vertex to edge)
-            //         let point_1_1: Vector =
-            //             [e2_c1_1.x.signum(), e2_c1_1.y.signum(), e2_c1_1.z.signum()].into();
-            //         let p1_p2 = point_2_1 - point_1_1;
-            //         // let test = p1_p2.mul_element_wise(*a);
-
-            //         // let p1 = (model_1 * point_1_1.extend(1.)).truncate();
-            //         // let p2 = (model_1 * point_2_1.extend(1.)).truncate();
-            //         // let p1_p2 = p1 - p2;
-
-            //         // project edge onto each x,y,z plane
-            //         let a2_projs: [Vector; 3] = [
-            //             [0., axis_2_1.y, axis_2_1.z].into(),
-            //             [axis_2_1.x, 0., axis_2_1.z].into(),
-            //             [axis_2_1.x, axis_2_1.y, 0.].into(),
-            //         ];
-
-            //         // get closest point of edge to 3 edges of p1
-            //         let d2_per_edge = a2_projs
-            //             .map(|proj| point_2_1 - proj.dot(p1_p2) * axis_2_1 / proj.magnitude2());
-
-            //         let mut potential_pen = None;
-            //         let mut potential_a1_i = None;
-
-            //         // for each closest point
-            //         for a1_i in 0..3 {
-            //             // check if point is in 2
-            //             let d2_from_2 = d2_per_edge[a1_i] - model_2_1.w.truncate();
-            //             if d2_from_2.dot(axes_2_1_inv[a2_i]).abs() > 1. {
-            //                 continue;
-            //             }
-
-            //             // check if closest point on edge is in 2
-            //             // let mut d1_from_2 = d2_from_2;
-            //             // d1_from_2[a1_i] += p1[a1_i] - d2_per_edge[a1_i][a1_i];
-            //             let mut d1_from_2 = point_1_1;
-            //             d1_from_2[a1_i] = d2_per_edge[a1_i][a1_i];
-            //             d1_from_2 -= model_2_1.w.truncate();
-            //             if d1_from_2.dot(axes_2_1_inv[0]).abs() > 1.
-            //                 || d1_from_2.dot(axes_2_1_inv[1]).abs() > 1.
-            //                 || d1_from_2.dot(axes_2_1_inv[2]).abs() > 1.
-            //             {
-            //                 continue;
-            //             }
-
-            //             // check if point is in 1
-            //             let d_abs = [
-            //                 d2_per_edge[a1_i].x.abs(),
-            //                 d2_per_edge[a1_i].y.abs(),
-            //                 d2_per_edge[a1_i].z.abs(),
-            //             ];
-            //             if d_abs[0] > 1. || d_abs[1] > 1. || d_abs[2] > 1. {
-            //                 continue;
-            //             }
-
-            //             let [ci_1, ci_2] = CROSS_INDICES[a1_i];
-            //             let depth_1 = 1. - d_abs[ci_1];
-            //             let depth_2 = 1. - d_abs[ci_2];
-
-            //             let depth = depth_1 * depth_1 * model_1_sqr[ci_1]
-            //                 + depth_2 * depth_2 * model_1_sqr[ci_2];
-
-            //             if let Some(min_depth) = potential_pen {
-            //                 if depth < min_depth {
-            //                     potential_pen = Some(depth);
-            //                     potential_a1_i = Some(a1_i);
-            //                 }
-            //             } else {
-            //                 potential_pen = Some(depth);
-            //                 potential_a1_i = Some(a1_i);
-            //             }
-            //         }
-
-            //         if let Some(depth) = potential_pen {
-            //             if depth > max_pen_ee_sqr {
-            //                 let a1_i = potential_a1_i.unwrap();
-
-            //                 max_pen_ee_sqr = depth;
-            //                 contact_point_ee = d2_per_edge[a1_i];
-            //                 pen_axis_1 = a1_i;
-            //                 pen_axis_2 = a2_i + 3;
-            //                 ee_elems = (
-            //                     CuboidElement::from_vertex_axis(
-            //                         CuboidElement::closest_vertex(point_1_1),
-            //                         a1_i as u8,
-            //                     ),
-            //                     CuboidElement::from_vertex_axis(p2_i, a2_i as u8),
-            //                 )
-            //             }
-            //         }
-            //     }
-            // }
-
-            // compare p-f and e-e contacts
-            // returned normal points outward from coll_1
-            // should return max pen as well
-            if max_pen_pf_sqr == 0. && max_pen_ee_sqr == 0.0 {
-                // println!("CANT FIND CONTACT >:(");
-                continue;
-            }
-            if max_pen_pf_sqr >= max_pen_ee_sqr {
-                let normal = axes[pen_axis as usize];
-                // pen_axis.signum() as f32 * axes[pen_axis.unsigned_abs() as usize - 1];
-                // println!("pf collision normal: {:?}", normal);
-                let point = model_1 * contact_point_pf.extend(1.);
-
-                let contact_id = ContactIdPair(
-                    ContactIdentifier {
-                        collider: Arc::downgrade(coll_1),
-                        element: pf_elems.0,
-                    },
-                    ContactIdentifier {
-                        collider: Arc::downgrade(coll_2),
-                        element: pf_elems.1,
-                    },
-                );
-
-                let (index, contact) = Contact::new(
-                    transforms,
-                    point.truncate(),
-                    normal.normalize(),
-                    max_pen_pf_sqr.sqrt(),
-                    contact_id,
-                    0,
-                );
-                self.contact_resolver.add_contact(index, contact);
-            } else {
-                // println!("[e-e contact generation]\n\tpen_axis_1: {:?},\n\tpen_axis_2: {:?},\n\taxis 1: {:?},\n\taxis 2: {:?}",
-                //     pen_axis_1, pen_axis_2, axes[pen_axis_1], axes[pen_axis_2]
-                // );
-                let normal = axes[pen_axis_1].cross(axes[pen_axis_2]);
-                // println!("ee collision normal: {:?}", normal);
-                // let point = model_1 * contact_point_ee.extend(1.);
-
+            for contact in contacts {
                 let contact_id = ContactIdPair(
                     ContactIdentifier {
                         collider: Arc::downgrade(coll_1),
-                        element: ee_elems.0,
+                        element: contact.elements.0,
                     },
                     ContactIdentifier {
                         collider: Arc::downgrade(coll_2),
-                        element: ee_elems.1,
+                        element: contact.elements.1,
                     },
                 );
 
                 let (index, contact) = Contact::new(
                     transforms,
-                    contact_point_ee,
-                    normal.normalize(),
-                    max_pen_ee_sqr.sqrt(),
+                    contact.position,
+                    contact.normal,
+                    contact.penetration,
                     contact_id,
                     0,
+                    Vector::zero(),
                 );
                 self.contact_resolver.add_contact(index, contact);
             }
         }
 
+        self.events.finish_update();
         self.add_cached_contacts(transforms);
 
         &mut self.contact_resolver
@@ -769,23 +826,29 @@ impl ColliderSystem {
         for contact in contacts {
             let (rb_1, o_rb_2) = contact.get_rigidbodies();
 
-            for (age, contact) in rb_1.write().unwrap().past_contacts.drain(..) {
+            for (age, contact, impulse) in rb_1.write().unwrap().past_contacts.drain(..) {
                 // println!("[Uncaching contacts] age: {:?}", age);
+                if !contact.is_allowed(&self.ignored_pairs) {
+                    continue;
+                }
                 if let Some(res) = contact.into_contact(age, transform_sys) {
-                    cached_contacts.push(res);
+                    cached_contacts.push((res, impulse));
                 }
             }
             if let Some(rb_2) = o_rb_2 {
-                for (age, contact) in rb_2.write().unwrap().past_contacts.drain(..) {
+                for (age, contact, impulse) in rb_2.write().unwrap().past_contacts.drain(..) {
                     // println!("[Uncaching contacts] age: {:?}", age);
+                    if !contact.is_allowed(&self.ignored_pairs) {
+                        continue;
+                    }
                     if let Some(res) = contact.into_contact(age, transform_sys) {
-                        cached_contacts.push(res);
+                        cached_contacts.push((res, impulse));
                     }
                 }
             }
         }
 
-        for (position, normal, penetration, contact_id, age) in cached_contacts {
+        for ((position, normal, penetration, contact_id, age), impulse) in cached_contacts {
             let (index, contact) = Contact::new(
                 transform_sys,
                 position,
@@ -793,12 +856,36 @@ impl ColliderSystem {
                 penetration,
                 contact_id,
                 age,
+                impulse,
             );
             self.contact_resolver.add_contact(index, contact);
         }
     }
 }
 
+/// order overlaps by transform instead of by where they are in the broad phase
+fn sort_overlaps<'a>(overlaps: &mut [(&'a Arc<Collider>, &'a Arc<Collider>)]) {
+    for (coll_1, coll_2) in overlaps.iter_mut() {
+        if coll_2.transform < coll_1.transform {
+            std::mem::swap(coll_1, coll_2);
+        }
+    }
+    // stable, so colliders sharing transforms keep the broad phase's order
+    overlaps.sort_by_key(|(coll_1, coll_2)| (coll_1.transform, coll_2.transform));
+}
+
+/// pair ordered by id so it can be used as a key
+fn transform_pair(
+    transform_1: TransformID,
+    transform_2: TransformID,
+) -> (TransformID, TransformID) {
+    if transform_1 <= transform_2 {
+        (transform_1, transform_2)
+    } else {
+        (transform_2, transform_1)
+    }
+}
+
 #[derive(PartialEq)]
 enum ContactIdCompResult {
     SameVertex,
@@ -809,8 +896,8 @@ impl ContactIdentifier {
     fn compare(&self, other: &Self) -> ContactIdCompResult {
         if self == other {
             match self.element {
-                CuboidElement::Vertex(_) => ContactIdCompResult::SameVertex,
-                CuboidElement::Edge(_) => ContactIdCompResult::SameEdge,
+                Vertex(_) => ContactIdCompResult::SameVertex,
+                Edge(_) => ContactIdCompResult::SameEdge,
                 _ => ContactIdCompResult::None,
             }
         } else {
@@ -844,6 +931,16 @@ impl PartialEq for ContactIdPair {
 }
 
 impl ContactIdPair {
+    /// check the colliders still exist and can collide, the layers or ignored pairs may have changed
+    fn is_allowed(&self, ignored_pairs: &BTreeSet<(TransformID, TransformID)>) -> bool {
+        let (Some(coll_1), Some(coll_2)) = (self.0.collider.upgrade(), self.1.collider.upgrade())
+        else {
+            return false;
+        };
+        coll_1.can_collide(&coll_2)
+            && !ignored_pairs.contains(&transform_pair(coll_1.transform, coll_2.transform))
+    }
+
     fn into_contact(
         self,
         age: u8,
@@ -891,8 +988,8 @@ impl ContactIdPair {
                 )
             }
             (Edge(e1), Edge(e2)) => {
-                let (p1, a1_i) = CuboidElement::into_vertex_axis(*e1);
-                let (p2, a2_i) = CuboidElement::into_vertex_axis(*e2);
+                let (p1, a1_i) = ColliderElement::into_vertex_axis(*e1);
+                let (p2, a2_i) = ColliderElement::into_vertex_axis(*e2);
 
                 let p1 = (model_1 * p1.extend(1.)).truncate();
                 let p2 = (model_2 * p2.extend(1.)).truncate();
@@ -919,6 +1016,8 @@ impl ContactIdPair {
                     (c1, normal, depth)
                 }
             }
+            // smooth shapes don't have stable elements to track
+            (Surface, _) | (_, Surface) => return None,
             _ => {
                 panic!(
                     "Invalid contact id pair: {:?} {:?}",
@@ -927,11 +1026,14 @@ impl ContactIdPair {
             }
         };
 
+        // normal points away from 1 (assuming convex shape)
+        let normal = (position - model_1.w.truncate()).dot(normal).signum() * normal;
+
         Some((position, normal, penetration, self, age)) // todo: make contact create info struct
     }
 }
 
-impl CuboidElement {
+impl ColliderElement {
     fn from_vertex_axis(vertex: u8, axis: u8) -> Self {
         let axis_mask = 1 << axis;
         let axis_flags = axis << 3;
@@ -983,13 +1085,218 @@ impl CuboidElement {
 
 #[cfg(test)]
 mod coll_tests {
-    use super::CuboidElement;
+    use super::{
+        Collider, ColliderElement, ColliderSystem, CollisionEvent, CollisionPhase, RigidBody,
+        Vector,
+    };
+    use crate::game_objects::transform::{TransformCreateInfo, TransformSystem};
+    use cgmath::InnerSpace;
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn trigger_events() {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        let trigger = transforms.add_transform([0., 0., 0.]);
+        let body = transforms.add_transform([0., 1.5, 0.]);
+        let rb = Arc::new(RwLock::new(RigidBody::new(body)));
+        colliders.add(
+            Collider::cuboid(trigger, None).with_trigger(true),
+            &mut transforms,
+        );
+        colliders.add(Collider::cuboid(body, Some(rb)), &mut transforms);
+
+        // overlap is reported but not resolved
+        let resolver = colliders.get_contacts(&mut transforms);
+        assert_eq!(resolver.get_contacts().len(), 0);
+        let events: Vec<_> = colliders.drain_events().collect();
+        assert_eq!(
+            events,
+            [CollisionEvent {
+                phase: CollisionPhase::Enter,
+                transforms: (trigger, body),
+                trigger: true,
+            }]
+        );
+
+        transforms
+            .get_transform_mut(&body)
+            .unwrap()
+            .set_translation([0., 3., 0.]);
+        colliders.get_contacts(&mut transforms);
+        let events: Vec<_> = colliders.drain_events().map(|e| e.phase).collect();
+        assert_eq!(events, [CollisionPhase::Exit]);
+    }
+
+    #[test]
+    fn undrained_events_bounded() {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        let trigger = transforms.add_transform([0., 0., 0.]);
+        let body = transforms.add_transform([0., 1.5, 0.]);
+        colliders.add(
+            Collider::cuboid(trigger, None).with_trigger(true),
+            &mut transforms,
+        );
+        let rb = Arc::new(RwLock::new(RigidBody::new(body)));
+        colliders.add(Collider::cuboid(body, Some(rb)), &mut transforms);
+
+        // events from updates nobody drained don't pile up
+        for _ in 0..50 {
+            colliders.get_contacts(&mut transforms);
+        }
+        let events: Vec<_> = colliders.drain_events().map(|e| e.phase).collect();
+        assert_eq!(events, [CollisionPhase::Stay]);
+    }
+
+    #[test]
+    fn static_trigger_without_rigidbodies() {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        let trigger = transforms.add_transform([0., 0., 0.]);
+        let kinematic = transforms.add_transform([0., 5., 0.]);
+        colliders.add(
+            Collider::cuboid(trigger, None).with_trigger(true),
+            &mut transforms,
+        );
+        let leaf = colliders.add(Collider::cuboid(kinematic, None), &mut transforms);
+
+        // moved through the trigger without a rigidbody
+        let mut phases = vec![];
+        for y in [5., 1.5, 0.5, -5.] {
+            transforms
+                .get_transform_mut(&kinematic)
+                .unwrap()
+                .set_translation([0., y, 0.]);
+            colliders.update(leaf, &mut transforms);
+            colliders.get_contacts(&mut transforms);
+            phases.extend(colliders.drain_events().map(|event| {
+                assert!(event.trigger);
+                event.phase
+            }));
+        }
+        assert_eq!(
+            phases,
+            [
+                CollisionPhase::Enter,
+                CollisionPhase::Stay,
+                CollisionPhase::Exit
+            ]
+        );
+        // triggers don't create contacts
+        assert_eq!(
+            colliders.get_contacts(&mut transforms).get_contacts().len(),
+            0
+        );
+    }
+
+    #[test]
+    fn ccd_parented_body() {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        let floor = transforms
+            .add_transform(TransformCreateInfo::from([0., 0., 0.]).with_scale([5., 0.25, 5.]));
+        // scaled parent, so local and world offsets differ
+        let parent =
+            transforms.add_transform(TransformCreateInfo::from([0., 1., 0.]).with_scale([2.; 3]));
+        let body = transforms.add_transform(
+            TransformCreateInfo::from([0., -1.5, 0.])
+                .with_parent(Some(parent))
+                .with_scale([0.25; 3]),
+        );
+        colliders.add(Collider::cuboid(floor, None), &mut transforms);
+        let mut rb = RigidBody::new(body);
+        rb.ccd = true;
+        // ended up 2 below the floor after moving 4 straight down this update
+        rb.ccd_motion = Some((0., -4., 0.).into());
+        let rb = Arc::new(RwLock::new(rb));
+        colliders.add(Collider::cuboid(body, Some(rb.clone())), &mut transforms);
+        let start = transforms.get_global_position(&body).unwrap();
+        assert!((start - Vector::new(0., -2., 0.)).magnitude() < 1e-5);
+
+        colliders.resolve_ccd(&mut transforms);
+        let position = transforms.get_global_position(&body).unwrap();
+        assert!(position.x.abs() < 1e-4 && position.z.abs() < 1e-4);
+        assert!(position.y < 0.75 && position.y > 0.7, "y: {}", position.y);
+    }
+
+    #[test]
+    fn ccd_samples_capped() {
+        let calls = std::cell::Cell::new(0);
+        let hit = super::ccd::first_hit(1000., 1e-3, |t| {
+            calls.set(calls.get() + 1);
+            t > 0.5
+        });
+        assert!((hit.unwrap() - 0.5).abs() < 0.01);
+        assert!(calls.get() < 100, "{} samples", calls.get());
+    }
+
+    #[test]
+    fn ccd_stops_at_floor() {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        let floor = transforms
+            .add_transform(TransformCreateInfo::from([0., 0., 0.]).with_scale([5., 0.25, 5.]));
+        let body =
+            transforms.add_transform(TransformCreateInfo::from([0., 2., 0.]).with_scale([0.5; 3]));
+        let mut rb = RigidBody::new(body);
+        rb.velocity = (0., -200., 0.).into();
+        rb.ccd = true;
+        let rb = Arc::new(RwLock::new(rb));
+        colliders.add(Collider::cuboid(floor, None), &mut transforms);
+        let leaf = colliders.add(Collider::cuboid(body, Some(rb.clone())), &mut transforms);
+
+        // moves straight through the floor in one update
+        rb.write()
+            .unwrap()
+            .update(transforms.get_transform_mut(&body).unwrap(), 1. / 60.);
+        colliders.update(leaf, &mut transforms);
+        colliders.resolve_ccd(&mut transforms);
+
+        let y = transforms.get_global_model(&body).unwrap().w.y;
+        assert!(y < 0.75 && y > 0.7, "y: {}", y);
+    }
+
+    #[test]
+    fn layer_filtering() {
+        const DEBRIS: u32 = 2;
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        // stack of overlapping cubes, debris doesn't collide with itself
+        let floor = transforms.add_transform([0., 0., 0.]);
+        let debris_1 = transforms.add_transform([0., 1.5, 0.]);
+        let debris_2 = transforms.add_transform([0., 3., 0.]);
+        colliders.add(Collider::cuboid(floor, None), &mut transforms);
+        for debris in [debris_1, debris_2] {
+            colliders.add(
+                Collider::cuboid(debris, None).with_layers(DEBRIS, !DEBRIS),
+                &mut transforms,
+            );
+        }
+        assert_eq!(colliders.get_potential_overlaps().len(), 1);
+
+        colliders.ignore_pair(debris_1, floor);
+        assert!(colliders.get_potential_overlaps().is_empty());
+        assert!(colliders.restore_pair(floor, debris_1));
+        assert_eq!(colliders.get_potential_overlaps().len(), 1);
+
+        let start = (0., 10., 0.).into();
+        let down = (0., -1., 0.).into();
+        let (point, _) = colliders
+            .raycast(&mut transforms, start, down, 20., Collider::ALL_LAYERS)
+            .unwrap();
+        assert!((point.y - 4.).abs() < 1e-5);
+        let (point, _) = colliders
+            .raycast(&mut transforms, start, down, 20., !DEBRIS)
+            .unwrap();
+        assert!((point.y - 1.).abs() < 1e-5);
+    }
 
     #[test]
     fn bit_manips() {
-        let vertex = CuboidElement::closest_vertex((0.12, 1.2, -2.).into());
-        let edge = CuboidElement::from_vertex_axis(1, 1);
-        let (v, a) = CuboidElement::into_vertex_axis(0b01_010);
+        let vertex = ColliderElement::closest_vertex((0.12, 1.2, -2.).into());
+        let edge = ColliderElement::from_vertex_axis(1, 1);
+        let (v, a) = ColliderElement::into_vertex_axis(0b01_010);
 
         println!("c_v: {:?}", vertex);
         println!("e: {:?}", edge);
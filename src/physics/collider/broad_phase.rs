@@ -0,0 +1,413 @@
+use super::{
+    bvh::{Bvh, LeafInHierachy},
+    ray::Ray,
+    sweep_and_prune::{SweepAndPrune, SweepHandle},
+    BoundingBox, Collider,
+};
+use crate::game_objects::transform::TransformSystem;
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+/// which broad phase a `ColliderSystem` finds potential overlaps with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BroadPhaseKind {
+    /// bounding volume hierachy, good all round
+    #[default]
+    Bvh,
+    /// sort and sweep along one axis, good for colliders spread along a plane
+    SweepAndPrune,
+}
+
+#[derive(Clone)]
+pub enum BroadPhase {
+    Bvh(Bvh),
+    SweepAndPrune(SweepAndPrune),
+}
+
+/// reference to a collider in a `ColliderSystem`, needed to update and remove it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColliderRef {
+    Leaf(LeafInHierachy),
+    Sweep(SweepHandle),
+}
+
+impl BroadPhase {
+    pub fn new(kind: BroadPhaseKind) -> Self {
+        match kind {
+            BroadPhaseKind::Bvh => Self::Bvh(Bvh::new()),
+            BroadPhaseKind::SweepAndPrune => Self::SweepAndPrune(SweepAndPrune::new()),
+        }
+    }
+
+    pub fn kind(&self) -> BroadPhaseKind {
+        match self {
+            Self::Bvh(_) => BroadPhaseKind::Bvh,
+            Self::SweepAndPrune(_) => BroadPhaseKind::SweepAndPrune,
+        }
+    }
+
+    pub fn insert(&mut self, bounds: BoundingBox, collider: Arc<Collider>) -> ColliderRef {
+        match self {
+            Self::Bvh(tree) => {
+                ColliderRef::Leaf(tree.insert(Bvh::register_collider(bounds, collider)))
+            }
+            Self::SweepAndPrune(sap) => ColliderRef::Sweep(sap.insert(bounds, collider)),
+        }
+    }
+
+    pub fn insert_many(
+        &mut self,
+        colliders: Vec<(BoundingBox, Arc<Collider>)>,
+    ) -> Vec<ColliderRef> {
+        match self {
+            Self::Bvh(tree) => tree
+                .insert_many(
+                    colliders
+                        .into_iter()
+                        .map(|(bounds, collider)| Bvh::register_collider(bounds, collider)),
+                )
+                .into_iter()
+                .map(ColliderRef::Leaf)
+                .collect(),
+            Self::SweepAndPrune(sap) => sap
+                .insert_many(colliders)
+                .into_iter()
+                .map(ColliderRef::Sweep)
+                .collect(),
+        }
+    }
+
+    pub fn rebuild(&mut self) {
+        match self {
+            Self::Bvh(tree) => tree.rebuild(),
+            Self::SweepAndPrune(sap) => sap.rebuild(),
+        }
+    }
+
+    /// gives back the reference if it isn't from this broad phase
+    pub fn remove(&mut self, target: ColliderRef) -> Result<Arc<Collider>, ColliderRef> {
+        match (self, target) {
+            (Self::Bvh(tree), ColliderRef::Leaf(leaf)) => tree
+                .remove(leaf)
+                .map(|leaf| leaf.into_collider())
+                .map_err(ColliderRef::Leaf),
+            (Self::SweepAndPrune(sap), ColliderRef::Sweep(handle)) => {
+                sap.remove(handle).map_err(ColliderRef::Sweep)
+            }
+            (_, target) => Err(target),
+        }
+    }
+
+    pub fn recalculate_bounds<F>(&mut self, target: ColliderRef, calc_bounds: F) -> Result<(), ()>
+    where
+        F: FnOnce(&Collider) -> BoundingBox,
+    {
+        match (self, target) {
+            (Self::Bvh(tree), ColliderRef::Leaf(leaf)) => {
+                tree.recalculate_bounds(leaf, calc_bounds)
+            }
+            (Self::SweepAndPrune(sap), ColliderRef::Sweep(handle)) => {
+                sap.recalculate_bounds(handle, calc_bounds)
+            }
+            _ => Err(()),
+        }
+    }
+
+    pub fn get_mut(&mut self, target: ColliderRef) -> Option<ColliderGuard<'_>> {
+        match (self, target) {
+            (Self::Bvh(tree), ColliderRef::Leaf(leaf)) => tree.get_mut(leaf),
+            (Self::SweepAndPrune(sap), ColliderRef::Sweep(handle)) => sap.get_mut(handle),
+            _ => None,
+        }
+    }
+
+    pub fn refit_modified<F>(&mut self, calc_bounds: F)
+    where
+        F: FnMut(&Collider) -> BoundingBox,
+    {
+        match self {
+            Self::Bvh(tree) => tree.refit_modified(calc_bounds),
+            Self::SweepAndPrune(sap) => sap.refit_modified(calc_bounds),
+        }
+    }
+
+    pub fn get_overlaps(
+        &self,
+        filter: &dyn Fn(&Collider, &Collider) -> bool,
+    ) -> Vec<(&Arc<Collider>, &Arc<Collider>)> {
+        match self {
+            Self::Bvh(tree) => tree.get_overlaps(filter),
+            Self::SweepAndPrune(sap) => sap.get_overlaps(filter),
+        }
+    }
+
+    pub fn raycast(
+        &self,
+        ray: &Ray,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Option<(f32, &Arc<Collider>)> {
+        match self {
+            Self::Bvh(tree) => tree.raycast(ray, transforms, filter),
+            Self::SweepAndPrune(sap) => sap.raycast(ray, transforms, filter),
+        }
+    }
+
+    pub fn raycast_all(
+        &self,
+        ray: &Ray,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Vec<(f32, &Arc<Collider>)> {
+        match self {
+            Self::Bvh(tree) => tree.raycast_all(ray, transforms, filter),
+            Self::SweepAndPrune(sap) => sap.raycast_all(ray, transforms, filter),
+        }
+    }
+
+    pub fn query_bounds(
+        &self,
+        bounds: BoundingBox,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Vec<&Arc<Collider>> {
+        match self {
+            Self::Bvh(tree) => tree.query_bounds(bounds, filter),
+            Self::SweepAndPrune(sap) => sap.query_bounds(bounds, filter),
+        }
+    }
+
+    /// bounds with their depth in the tree, sort and sweep bounds all have a depth of 0
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (BoundingBox, usize)> + '_> {
+        match self {
+            Self::Bvh(tree) => Box::new(tree.iter()),
+            Self::SweepAndPrune(sap) => Box::new(sap.iter().map(|bounds| (bounds, 0))),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        match self {
+            Self::Bvh(tree) => tree.depth(),
+            Self::SweepAndPrune(_) => 0,
+        }
+    }
+}
+
+impl Default for BroadPhase {
+    fn default() -> Self {
+        Self::new(BroadPhaseKind::default())
+    }
+}
+
+/// mutable access to a collider in a broad phase, it's marked to be refit when the guard is dropped
+pub struct ColliderGuard<'a> {
+    collider: &'a mut Arc<Collider>,
+    modified: &'a mut Vec<usize>,
+    index: usize,
+}
+
+impl<'a> ColliderGuard<'a> {
+    /// index is pushed to modified on drop
+    pub(super) fn new(
+        collider: &'a mut Arc<Collider>,
+        modified: &'a mut Vec<usize>,
+        index: usize,
+    ) -> Self {
+        Self {
+            collider,
+            modified,
+            index,
+        }
+    }
+}
+
+impl Deref for ColliderGuard<'_> {
+    type Target = Collider;
+
+    fn deref(&self) -> &Self::Target {
+        self.collider
+    }
+}
+
+impl DerefMut for ColliderGuard<'_> {
+    /// clones the collider if it's shared elsewhere, weak references to it (e.g. in contacts) stop upgrading
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        Arc::make_mut(self.collider)
+    }
+}
+
+impl Drop for ColliderGuard<'_> {
+    fn drop(&mut self) {
+        self.modified.push(self.index);
+    }
+}
+
+#[cfg(test)]
+mod broad_phase_tests {
+    use super::BroadPhaseKind;
+    use crate::{
+        game_objects::transform::{TransformCreateInfo, TransformID, TransformSystem},
+        physics::{Collider, ColliderRef, ColliderSystem, Vector},
+    };
+    use cgmath::Matrix4;
+    use std::collections::BTreeSet;
+
+    const KINDS: [BroadPhaseKind; 2] = [BroadPhaseKind::Bvh, BroadPhaseKind::SweepAndPrune];
+
+    type Scene = (
+        TransformSystem,
+        ColliderSystem,
+        Vec<(TransformID, ColliderRef)>,
+    );
+
+    /// an empty system of each kind, filled in by the test
+    fn empty_scenes() -> [Scene; 2] {
+        KINDS.map(|kind| {
+            (
+                TransformSystem::new(),
+                ColliderSystem::with_broad_phase(kind),
+                vec![],
+            )
+        })
+    }
+
+    /// floor with a grid of boxes and spheres on it, some stacked, added both one at a time and all at once
+    fn fill(
+        transforms: &mut TransformSystem,
+        colliders: &mut ColliderSystem,
+        refs: &mut Vec<(TransformID, ColliderRef)>,
+    ) {
+        let floor = transforms
+            .add_transform(TransformCreateInfo::from([0., -1., 0.]).with_scale([40., 1., 40.]));
+        refs.push((
+            floor,
+            colliders.add(Collider::cuboid(floor, None), transforms),
+        ));
+
+        for i in 0..100 {
+            let position = [
+                (i % 10) as f32 * 2.1 - 10.,
+                ((i * 7) % 3) as f32 * 0.9 + 0.5,
+                (i / 10) as f32 * 2.3 - 10.,
+            ];
+            let transform = transforms.add_transform(position);
+            let collider = if i % 3 == 0 {
+                Collider::sphere(transform, None, 1.1)
+            } else {
+                Collider::cuboid(transform, None)
+            };
+            if i < 50 {
+                refs.push((transform, colliders.add(collider, transforms)));
+            } else {
+                let added = colliders.add_many([collider], transforms);
+                refs.extend(added.into_iter().map(|r| (transform, r)));
+            }
+        }
+    }
+
+    fn pairs(colliders: &ColliderSystem) -> BTreeSet<(u32, u32)> {
+        let overlaps = colliders.get_potential_overlaps();
+        let pairs: BTreeSet<_> = overlaps
+            .iter()
+            .map(|(a, b)| {
+                let (a, b) = (a.transform.id(), b.transform.id());
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        assert_eq!(pairs.len(), overlaps.len(), "Repeated overlap pairs");
+        pairs
+    }
+
+    fn move_by(transforms: &mut TransformSystem, transform: &TransformID, offset: Vector) {
+        transforms
+            .get_transform_mut(transform)
+            .unwrap()
+            .mutate(|t, _, _| *t += offset);
+    }
+
+    #[test]
+    fn identical_overlaps() {
+        let mut scenes = empty_scenes();
+        for (transforms, colliders, refs) in &mut scenes {
+            fill(transforms, colliders, refs);
+        }
+        let [expected, actual] = scenes.each_ref().map(|(_, colliders, _)| pairs(colliders));
+        assert!(!expected.is_empty());
+        assert_eq!(expected, actual);
+
+        // move some a little and some a lot
+        for (transforms, colliders, refs) in &mut scenes {
+            for (i, (transform, collider_ref)) in refs.iter_mut().enumerate().skip(1) {
+                let offset = match i % 4 {
+                    0 => Vector::new(0.1, 0., 0.),
+                    1 => Vector::new(-3., 0.5, 1.),
+                    2 => Vector::new(0., 0., 7. - i as f32 * 0.3),
+                    _ => Vector::new(0., 0., 0.),
+                };
+                move_by(transforms, transform, offset);
+                colliders.update(*collider_ref, transforms);
+            }
+        }
+        let [expected, actual] = scenes.each_ref().map(|(_, colliders, _)| pairs(colliders));
+        assert_eq!(expected, actual);
+
+        // remove every third one
+        for (_, colliders, refs) in &mut scenes {
+            for (_, collider_ref) in refs.drain(..).skip(1).step_by(3) {
+                colliders.remove(collider_ref).unwrap();
+            }
+            colliders.rebuild_tree();
+        }
+        let [expected, actual] = scenes.each_ref().map(|(_, colliders, _)| pairs(colliders));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn identical_queries() {
+        let mut scenes = empty_scenes();
+        for (transforms, colliders, refs) in &mut scenes {
+            fill(transforms, colliders, refs);
+        }
+        let rays = [
+            ([-12., 0.5, -10.], [1., 0., 0.]),
+            ([0., 10., 0.], [0., -1., 0.]),
+            ([-12., 3., -12.], [1., -0.2, 1.]),
+            ([5., 0.5, 30.], [0., 0., -1.]),
+        ];
+
+        let results = scenes.each_mut().map(|(transforms, colliders, _)| {
+            rays.map(|(start, direction)| {
+                let closest = colliders
+                    .raycast(transforms, start.into(), direction.into(), 40., u32::MAX)
+                    .map(|(point, collider)| (collider.transform.id(), point));
+                let all: BTreeSet<_> = colliders
+                    .raycast_all(transforms, start.into(), direction.into(), 40., u32::MAX)
+                    .iter()
+                    .map(|hit| hit.collider.transform.id())
+                    .collect();
+                let overlap: BTreeSet<_> = colliders
+                    .overlap_box(
+                        transforms,
+                        Matrix4::from_translation(start.into()) * Matrix4::from_scale(3.),
+                        u32::MAX,
+                    )
+                    .iter()
+                    .map(|collider| collider.transform.id())
+                    .collect();
+                (closest, all, overlap)
+            })
+        });
+
+        for ((expected, actual), ray) in results[0].iter().zip(&results[1]).zip(rays) {
+            assert_eq!(
+                expected.0.map(|hit| hit.0),
+                actual.0.map(|hit| hit.0),
+                "{ray:?}"
+            );
+            assert_eq!(expected.1, actual.1, "{ray:?}");
+            assert_eq!(expected.2, actual.2, "{ray:?}");
+        }
+        assert!(results[0].iter().all(|(closest, _, _)| closest.is_some()));
+    }
+}
@@ -1,584 +1,776 @@
-use super::{ray::Ray, BoundingBox, CuboidCollider};
-use crate::{game_objects::transform::TransformSystem, physics::quick_inverse};
+use super::{broad_phase::ColliderGuard, ray::Ray, BoundingBox, Collider, FAT_MARGIN};
+use crate::game_objects::transform::TransformSystem;
 use std::{
     fmt::Debug,
-    marker::PhantomData,
-    mem::{self, ManuallyDrop},
-    ptr::NonNull,
-    sync::Arc,
+    mem,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
 
-#[derive(Default)]
+/// leaf refits between each round of tree rotations
+const OPTIMISE_PERIOD: usize = 64;
+
+/// source of ids to tell which tree a leaf reference belongs to
+static NEXT_TREE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// bounding volume hierachy, nodes are stored in an arena and refer to each other by index
+///
+/// clones keep the id, so references to leaves work with a tree restored from a clone
+#[derive(Clone)]
 pub struct Bvh {
-    root: Option<NonNull<Node>>,
+    id: u32,
+    nodes: Vec<Node>,
+    /// indices of unused nodes
+    free: Vec<usize>,
+    root: Option<usize>,
     /// number of leafs (excluding those outside hierachy)
     size: usize,
+    /// leaf refits since the tree was last optimised
+    refits: usize,
+    /// leaves modified through a guard, to be refit
+    modified: Vec<usize>,
 }
 
+#[derive(Clone)]
 pub struct Node {
-    parent: Option<NonNull<BranchLinks>>,
+    parent: Option<usize>,
     right_child: bool,
     bounds: BoundingBox,
     depth: usize,
     content: NodeContent,
+    /// incremented when the node is freed, so references to an old leaf can't reach a new one
+    generation: u32,
 }
 
+#[derive(Clone)]
 enum NodeContent {
-    Branch(NonNull<BranchLinks>),
-    Leaf(Arc<CuboidCollider>),
-    /// only used for empty root
+    Branch {
+        left: usize,
+        right: usize,
+    },
+    Leaf(Arc<Collider>),
+    /// unused node
     None,
 }
-/// pointers to a set of children and their parent, which in turn have pointers back to the `BranchLink`
-struct BranchLinks {
-    node: NonNull<Node>,
-    left: NonNull<Node>,
-    right: NonNull<Node>,
-}
 
-/// unique external reference, return to bvh to remove the corresponding leaf node
-/// the NonNull node should only be dereferenced when provided with a &mut BVH matching the second element
+/// reference to a leaf in a tree, return to the tree to update or remove the leaf
+///
+/// using it after the leaf is removed or with another tree does nothing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct LeafInHierachy {
-    leaf: NonNull<Node>,
-    hierachy: *const Bvh,
+    tree: u32,
+    index: usize,
+    generation: u32,
 }
+/// leaf not in any tree, insert into a tree to get a reference to it
+#[derive(Debug)]
 pub struct LeafOutsideHierachy {
-    leaf: NonNull<Node>,
+    bounds: BoundingBox,
+    collider: Arc<Collider>,
 }
 
 /// Depth first iterator for `BoundsTree`, iterates over every node, returning its bounds and depth
 pub struct DepthIter<'a> {
-    current: Vec<NonNull<Node>>,
-    next: Vec<NonNull<Node>>,
-    lifetime: PhantomData<&'a Bvh>,
+    tree: &'a Bvh,
+    current: Vec<usize>,
+    next: Vec<usize>,
 }
 
 impl Bvh {
     pub fn new() -> Self {
         Bvh {
+            id: NEXT_TREE_ID.fetch_add(1, Ordering::Relaxed),
+            nodes: vec![],
+            free: vec![],
             root: None,
             size: 0,
+            refits: 0,
+            modified: vec![],
         }
     }
 
+    /// create a leaf for the collider, the bounds are enlarged by the fat margin
     pub fn register_collider(
-        bounds: BoundingBox,
-        collider: Arc<CuboidCollider>,
+        mut bounds: BoundingBox,
+        collider: Arc<Collider>,
     ) -> LeafOutsideHierachy {
-        LeafOutsideHierachy {
-            leaf: Node::new(bounds, collider),
-        }
+        bounds.grow(FAT_MARGIN);
+        LeafOutsideHierachy { bounds, collider }
     }
-    // pub fn deregister_collider(&self, leaf_ref: LeafReference<OutsideHierachy>) {
-    //     // properly drop a leaf node
-    //     unsafe {
-    //         Box::from_raw(leaf_ref.leaf.as_ptr());
-    //     }
-    // }
 
     /// traverse down tree to insert leaf before traversing back up tree to update depth and do balancing
     pub fn insert(&mut self, leaf_ref: LeafOutsideHierachy) -> LeafInHierachy {
-        if let Some(root) = self.root {
-            unsafe {
-                let leaf_bounds = leaf_ref.leaf.as_ref().bounds;
-
-                // find best closest leaf
-                let mut current = root;
-                let mut new_bounds = (*current.as_ptr()).bounds.join(leaf_bounds);
-
-                while let NodeContent::Branch(branch) = (*current.as_ptr()).content {
-                    (*current.as_ptr()).bounds = new_bounds;
-
-                    let left_raw = (*branch.as_ptr()).left.as_mut();
-                    let right_raw = (*branch.as_ptr()).right.as_mut();
-
-                    let new_left_bounds = left_raw.bounds.join(leaf_bounds);
-                    let new_right_bounds = right_raw.bounds.join(leaf_bounds);
-
-                    if new_left_bounds.volume() - left_raw.bounds.volume()
-                        <= new_right_bounds.volume() - right_raw.bounds.volume()
-                    {
-                        current = (*branch.as_ptr()).left;
-                        new_bounds = new_left_bounds;
-                    } else {
-                        current = (*branch.as_ptr()).right;
-                        new_bounds = new_right_bounds;
-                    }
-                }
+        let leaf_bounds = leaf_ref.bounds;
+        let leaf = self.allocate(leaf_ref);
 
-                // turn leaf to branch
-                let parent = (*current.as_ptr()).parent;
-                let right_child = (*current.as_ptr()).right_child;
-
-                let mut new_branch = NonNull::new_unchecked(Box::into_raw(Box::new(Node {
-                    parent,
-                    right_child,
-                    bounds: new_bounds,
-                    depth: 1,
-                    content: NodeContent::None,
-                })));
-                let branch_content = BranchLinks::new(new_branch, current, leaf_ref.leaf);
-                (*new_branch.as_ptr()).content = NodeContent::Branch(branch_content);
-
-                (*current.as_ptr()).parent = Some(branch_content);
-                (*current.as_ptr()).right_child = false;
-                (*leaf_ref.leaf.as_ptr()).parent = Some(branch_content);
-                (*leaf_ref.leaf.as_ptr()).right_child = true;
-
-                // update parent's content
-                if let Some(mut parent) = parent {
-                    if right_child {
-                        parent.as_mut().right = new_branch;
-                    } else {
-                        parent.as_mut().left = new_branch;
-                    }
+        if let Some(root) = self.root {
+            // find best closest leaf
+            let mut current = root;
+            let mut new_bounds = self.nodes[current].bounds.join(leaf_bounds);
+
+            while let NodeContent::Branch { left, right } = self.nodes[current].content {
+                self.nodes[current].bounds = new_bounds;
+
+                let left_bounds = self.nodes[left].bounds;
+                let right_bounds = self.nodes[right].bounds;
+                let new_left_bounds = left_bounds.join(leaf_bounds);
+                let new_right_bounds = right_bounds.join(leaf_bounds);
+
+                if new_left_bounds.surface_area() - left_bounds.surface_area()
+                    <= new_right_bounds.surface_area() - right_bounds.surface_area()
+                {
+                    current = left;
+                    new_bounds = new_left_bounds;
                 } else {
-                    self.root = Some(new_branch);
+                    current = right;
+                    new_bounds = new_right_bounds;
                 }
+            }
 
-                // update parent depths
-                let mut last_node = new_branch.as_mut();
-                let mut depth_changed = true;
-                // let mut bounds_changed = false;
-                while let Some(parent) = last_node.parent {
-                    // let parent_raw = parent.as_mut();
-                    let parent_node = (*parent.as_ptr()).node;
-
-                    if depth_changed {
-                        let old_depth = parent_node.as_ref().depth;
+            // turn leaf to branch
+            let parent = self.nodes[current].parent;
+            let right_child = self.nodes[current].right_child;
+            let new_branch = self.new_branch(current, leaf);
+            self.set_child(parent, right_child, new_branch);
 
-                        // rebalance tree if needed
-                        (*parent_node.as_ptr()).rebalance();
+            // update parent depths
+            let mut last_node = new_branch;
+            while let Some(parent) = self.nodes[last_node].parent {
+                let old_depth = self.nodes[parent].depth;
 
-                        // check if depth changed
-                        depth_changed = old_depth != parent_node.as_ref().depth;
-                    }
+                // rebalance tree if needed
+                self.rebalance(parent);
 
-                    if !depth_changed {
-                        break;
-                    }
-                    last_node = &mut *parent_node.as_ptr();
+                // check if depth changed
+                if old_depth == self.nodes[parent].depth {
+                    break;
                 }
+                last_node = parent;
             }
         } else {
-            self.root = Some(leaf_ref.leaf);
+            self.root = Some(leaf);
         }
 
         self.size += 1;
-        leaf_ref.convert(self)
+        self.leaf_ref(leaf)
     }
 
+    /// gives back the reference if it isn't a leaf in this tree
     pub fn remove(
         &mut self,
-        mut leaf_ref: LeafInHierachy,
+        leaf_ref: LeafInHierachy,
     ) -> Result<LeafOutsideHierachy, LeafInHierachy> {
-        if leaf_ref.hierachy != self {
+        let Some(leaf) = self.leaf_index(leaf_ref) else {
             // Leaf does not belong to this hierachy
             return Err(leaf_ref);
-        }
+        };
 
-        unsafe {
-            let leaf_node = leaf_ref.leaf.as_mut();
+        // convert parent branch to leaf
+        if let Some(parent) = self.nodes[leaf].parent {
+            let (left, right) = self.children(parent).unwrap();
+            let sibling = if self.nodes[leaf].right_child {
+                left
+            } else {
+                right
+            };
 
-            // convert parent branch to leaf
-            if let Some(parent_content) = leaf_node.parent.take() {
-                let raw_parent = parent_content.as_ref();
-                let mut sibling_leaf = if leaf_node.right_child {
-                    raw_parent.left
-                } else {
-                    raw_parent.right
-                };
-
-                let raw_sibling = sibling_leaf.as_mut();
-
-                // replace parent with sibling leaf in grandparent
-                let parent_node = &mut (*raw_parent.node.as_ptr());
-                if let Some(mut grandparent) = parent_node.parent {
-                    // replace grandparent content
-                    let raw_grandparent = grandparent.as_mut();
-                    if parent_node.right_child {
-                        raw_grandparent.right = sibling_leaf;
-                        raw_sibling.right_child = true;
-                    } else {
-                        raw_grandparent.left = sibling_leaf;
-                        raw_sibling.right_child = false;
-                    }
-                } else {
-                    // parent is root
-                    self.root = Some(sibling_leaf);
+            // replace parent with sibling in grandparent
+            let grandparent = self.nodes[parent].parent;
+            let parent_right = self.nodes[parent].right_child;
+            self.set_child(grandparent, parent_right, sibling);
+            self.free_node(parent);
+
+            // update parent depth and bounds
+            let mut last_node = sibling;
+            let mut depth_changed = true;
+            let mut bounds_changed = true;
+            while let Some(parent) = self.nodes[last_node].parent {
+                if depth_changed {
+                    let old_depth = self.nodes[parent].depth;
+
+                    // rebalance tree if needed
+                    self.rebalance(parent);
+
+                    // check if depth changed
+                    depth_changed = old_depth != self.nodes[parent].depth;
                 }
-                raw_sibling.parent = parent_node.parent;
-
-                // drop parent
-                let _ = Box::from_raw(parent_content.as_ref().node.as_ptr());
-                let _ = Box::from_raw(parent_content.as_ptr());
-
-                // update parent depth and bounds
-                let mut last_node = sibling_leaf;
-                let mut depth_changed = true;
-                let mut bounds_changed = true;
-                while let Some(parent) = last_node.as_ref().parent {
-                    let parent_node = parent.as_ref().node;
-
-                    if depth_changed {
-                        let old_depth = parent_node.as_ref().depth;
-
-                        // rebalance tree if needed
-                        (*parent_node.as_ptr()).rebalance();
-
-                        // check if depth changed
-                        depth_changed = old_depth != parent_node.as_ref().depth;
-                    }
 
-                    if bounds_changed {
-                        let new_bounds = parent
-                            .as_ref()
-                            .left
-                            .as_ref()
-                            .bounds
-                            .join(parent.as_ref().right.as_ref().bounds);
-
-                        bounds_changed = new_bounds != parent_node.as_ref().bounds;
-                        (*parent_node.as_ptr()).bounds = new_bounds;
-                    }
-
-                    if !depth_changed && !bounds_changed {
-                        break;
-                    }
+                if bounds_changed {
+                    let new_bounds = self.children_bounds(parent);
+                    bounds_changed = new_bounds != self.nodes[parent].bounds;
+                    self.nodes[parent].bounds = new_bounds;
+                }
 
-                    last_node = parent_node;
+                if !depth_changed && !bounds_changed {
+                    break;
                 }
-            } else {
-                // leaf on root
-                self.root = None;
+                last_node = parent;
             }
+        } else {
+            // leaf on root
+            self.root = None;
         }
 
         self.size -= 1;
-        Ok(leaf_ref.convert())
+        let bounds = self.nodes[leaf].bounds;
+        match self.free_node(leaf) {
+            NodeContent::Leaf(collider) => Ok(LeafOutsideHierachy { bounds, collider }),
+            _ => unreachable!(),
+        }
+    }
+
+    /// insert many leaves at once, rebuilding the whole tree with the surface area heuristic
+    ///
+    /// the returned references are in the same order as the given leaves
+    pub fn insert_many(
+        &mut self,
+        leaf_refs: impl IntoIterator<Item = LeafOutsideHierachy>,
+    ) -> Vec<LeafInHierachy> {
+        let mut leaves = self.take_leaves();
+        let new_leaves: Vec<_> = leaf_refs
+            .into_iter()
+            .map(|leaf_ref| self.allocate(leaf_ref))
+            .collect();
+        leaves.extend(&new_leaves);
+
+        self.size = leaves.len();
+        self.root = (!leaves.is_empty()).then(|| self.build_sah(&mut leaves));
+        self.refits = 0;
+        new_leaves
+            .into_iter()
+            .map(|leaf| self.leaf_ref(leaf))
+            .collect()
     }
 
-    /// remove and reinsert leaf with new bounds
+    /// rebuild the tree with the surface area heuristic, existing leaf references stay valid
+    pub fn rebuild(&mut self) {
+        self.insert_many([]);
+    }
+
+    /// update the leaf if its collider moved out of its bounds, refitting the bounds of its ancestors
+    ///
+    /// the tree is optimised with rotations after every few refits
     pub fn recalculate_bounds<F>(
         &mut self,
-        leaf_ref: &mut LeafInHierachy,
+        leaf_ref: LeafInHierachy,
         calc_bounds: F,
     ) -> Result<(), ()>
     where
-        F: FnOnce(&CuboidCollider) -> BoundingBox,
+        F: FnOnce(&Collider) -> BoundingBox,
     {
-        if leaf_ref.hierachy != self {
+        let Some(leaf) = self.leaf_index(leaf_ref) else {
             // Leaf does not belong to this hierachy
-            Err(())
-        } else {
-            unsafe {
-                // clone leaf (LEAF CLONE IS DANGEROUS DONT LET CLONE AND OG ESCAPE TOGETHER)
-                let leaf_clone = leaf_ref.clone();
-                // use clone for removal
-                let mut res = self.remove(leaf_clone).unwrap();
-                // modify collider safely
-
-                // update bounds
-                res.leaf.as_mut().bounds = calc_bounds(res.get_collider());
-                // re-insert leaf and reconcille clone and og (kill og)
-                *leaf_ref = self.insert(res);
-            }
-            Ok(())
+            return Err(());
+        };
+
+        let NodeContent::Leaf(collider) = &self.nodes[leaf].content else {
+            unreachable!()
+        };
+        let bounds = calc_bounds(collider);
+        if !self.nodes[leaf].bounds.contains(bounds) {
+            self.refit(leaf, bounds);
         }
+        Ok(())
     }
 
-    pub fn get_overlaps(&self) -> Vec<(&Arc<CuboidCollider>, &Arc<CuboidCollider>)> {
-        match self.root {
-            Some(root) => unsafe {
-                if let NodeContent::Branch(branch) = (*root.as_ptr()).content {
-                    let mut results = Vec::with_capacity(self.size);
-                    (*branch.as_ptr())
-                        .left
-                        .as_ref()
-                        .check_overlap((*branch.as_ptr()).right.as_ref(), &mut results);
-                    results
-                } else {
-                    vec![]
-                }
-            },
-            None => {
-                vec![]
+    /// access the leaf's collider, it will be refit by `refit_modified` after the guard is dropped
+    pub fn get_mut(&mut self, leaf_ref: LeafInHierachy) -> Option<ColliderGuard<'_>> {
+        let leaf = self.leaf_index(leaf_ref)?;
+        let NodeContent::Leaf(collider) = &mut self.nodes[leaf].content else {
+            unreachable!()
+        };
+        Some(ColliderGuard::new(collider, &mut self.modified, leaf))
+    }
+
+    /// refit every leaf modified through a guard, even if it's still inside its bounds
+    pub fn refit_modified<F>(&mut self, mut calc_bounds: F)
+    where
+        F: FnMut(&Collider) -> BoundingBox,
+    {
+        for leaf in mem::take(&mut self.modified) {
+            // leaf may have been removed since
+            if let NodeContent::Leaf(collider) = &self.nodes[leaf].content {
+                let bounds = calc_bounds(collider);
+                self.refit(leaf, bounds);
             }
         }
     }
 
+    /// improve the tree by rotating nodes where it reduces the surface area of branches,
+    /// refitting doesn't change the structure of the tree so it degrades as leaves move
+    pub fn optimise(&mut self) {
+        if let Some(root) = self.root {
+            self.optimise_node(root);
+        }
+        self.refits = 0;
+    }
+
+    /// overlapping leaves, only including pairs accepted by the filter, each pair is only given once
+    pub fn get_overlaps(
+        &self,
+        filter: &dyn Fn(&Collider, &Collider) -> bool,
+    ) -> Vec<(&Arc<Collider>, &Arc<Collider>)> {
+        let mut results = Vec::with_capacity(self.size);
+        if let Some(root) = self.root {
+            self.check_self_overlap(root, filter, &mut results);
+        }
+        results
+    }
+
+    /// closest collider accepted by the filter that the ray hits
     pub fn raycast(
         &self,
         ray: &Ray,
         transforms: &mut TransformSystem,
-    ) -> Option<(f32, &Arc<CuboidCollider>)> {
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Option<(f32, &Arc<Collider>)> {
         self.root
-            .and_then(|root_node| unsafe { root_node.as_ref().raycast(ray, transforms) })
+            .and_then(|root| self.raycast_node(root, ray, transforms, filter))
     }
 
-    pub unsafe fn get_root(&self) -> Option<NonNull<Node>> {
-        self.root
+    /// every collider accepted by the filter that the ray hits, unordered
+    pub fn raycast_all(
+        &self,
+        ray: &Ray,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Vec<(f32, &Arc<Collider>)> {
+        let mut results = Vec::new();
+        if let Some(root) = self.root {
+            self.raycast_all_node(root, ray, transforms, filter, &mut results);
+        }
+        results
     }
 
-    pub fn iter(&'_ self) -> DepthIter<'_> {
-        if let Some(root) = self.root {
-            DepthIter {
-                current: vec![root],
-                next: vec![],
-                lifetime: PhantomData,
+    /// colliders accepted by the filter with leaf bounds overlapping the bounds
+    pub fn query_bounds(
+        &self,
+        bounds: BoundingBox,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Vec<&Arc<Collider>> {
+        let mut results = Vec::new();
+        let mut stack: Vec<_> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.check_overlap(bounds) {
+                continue;
             }
-        } else {
-            DepthIter {
-                current: vec![],
-                next: vec![],
-                lifetime: PhantomData,
+            match &node.content {
+                NodeContent::Leaf(collider) => {
+                    if filter(collider) {
+                        results.push(collider);
+                    }
+                }
+                NodeContent::Branch { left, right } => {
+                    stack.push(*right);
+                    stack.push(*left);
+                }
+                NodeContent::None => {}
             }
         }
+        results
+    }
+
+    pub fn iter(&'_ self) -> DepthIter<'_> {
+        DepthIter {
+            tree: self,
+            current: self.root.into_iter().collect(),
+            next: vec![],
+        }
     }
 
     pub fn depth(&self) -> usize {
         match self.root {
-            Some(root) => unsafe { root.as_ref().depth },
+            Some(root) => self.nodes[root].depth,
             None => 0,
         }
     }
-}
-// impl Default for BVH {
-//     fn default() -> Self {
-
-//     }
-// }
-unsafe impl Send for Bvh {}
-unsafe impl Sync for Bvh {}
-
-impl Drop for Bvh {
-    fn drop(&mut self) {
-        // manually drop each node
-        if let Some(mut root) = self.root {
-            unsafe {
-                let mut current = root.as_mut();
-                let mut branch_stack: Vec<&mut Node> = Vec::with_capacity(current.depth);
-
-                while self.size > 0 {
-                    // check node contents before dropping
-                    if let NodeContent::Branch(branch_content) = current.content {
-                        // drop node
-                        let _ = Box::from_raw(current);
-
-                        // set right child as next
-                        branch_stack.push((*branch_content.as_ptr()).left.as_mut());
-                        current = (*branch_content.as_ptr()).right.as_mut();
-
-                        // drop branch content
-                        let _ = Box::from_raw(branch_content.as_ptr());
-                    } else {
-                        // drop node
-                        let _ = Box::from_raw(current);
-
-                        // get next node from stack
-                        self.size -= 1;
-                        if let Some(node) = branch_stack.pop() {
-                            current = node;
+
+    /// index of the referenced leaf if it's still in this tree
+    fn leaf_index(&self, leaf_ref: LeafInHierachy) -> Option<usize> {
+        let node = self.nodes.get(leaf_ref.index)?;
+        (leaf_ref.tree == self.id
+            && node.generation == leaf_ref.generation
+            && matches!(node.content, NodeContent::Leaf(_)))
+        .then_some(leaf_ref.index)
+    }
+
+    fn leaf_ref(&self, leaf: usize) -> LeafInHierachy {
+        LeafInHierachy {
+            tree: self.id,
+            index: leaf,
+            generation: self.nodes[leaf].generation,
+        }
+    }
+
+    /// put a parentless leaf into an unused node
+    fn allocate(&mut self, leaf_ref: LeafOutsideHierachy) -> usize {
+        self.allocate_node(leaf_ref.bounds, 0, NodeContent::Leaf(leaf_ref.collider))
+    }
+
+    fn allocate_node(&mut self, bounds: BoundingBox, depth: usize, content: NodeContent) -> usize {
+        let node = Node {
+            parent: None,
+            right_child: false,
+            bounds,
+            depth,
+            content,
+            generation: 0,
+        };
+        match self.free.pop() {
+            Some(index) => {
+                let generation = self.nodes[index].generation;
+                self.nodes[index] = Node { generation, ..node };
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// mark node as unused, returning its content
+    fn free_node(&mut self, index: usize) -> NodeContent {
+        let node = &mut self.nodes[index];
+        node.generation = node.generation.wrapping_add(1);
+        node.parent = None;
+        self.free.push(index);
+        mem::replace(&mut node.content, NodeContent::None)
+    }
+
+    fn children(&self, index: usize) -> Option<(usize, usize)> {
+        match self.nodes[index].content {
+            NodeContent::Branch { left, right } => Some((left, right)),
+            _ => None,
+        }
+    }
+
+    fn children_bounds(&self, index: usize) -> BoundingBox {
+        let (left, right) = self.children(index).unwrap();
+        self.nodes[left].bounds.join(self.nodes[right].bounds)
+    }
+
+    fn recalculate_depth(&mut self, index: usize) {
+        let (left, right) = self.children(index).unwrap();
+        self.nodes[index].depth = self.nodes[left].depth.max(self.nodes[right].depth) + 1;
+    }
+
+    /// make child the left or right child of parent, or the root if there is no parent
+    fn set_child(&mut self, parent: Option<usize>, right_child: bool, child: usize) {
+        match parent {
+            Some(parent) => {
+                match &mut self.nodes[parent].content {
+                    NodeContent::Branch { left, right } => {
+                        if right_child {
+                            *right = child;
                         } else {
-                            // check if any leaves left that we somehow missed
-                            if self.size > 0 {
-                                println!(
-                                    "Dropping BVH concluded when there are still leaves left to drop"
-                                );
-                            }
-                            break;
+                            *left = child;
                         }
-                    };
+                    }
+                    _ => panic!("Parent of node is not a branch"),
                 }
+                self.nodes[child].right_child = right_child;
+            }
+            None => {
+                self.root = Some(child);
+                self.nodes[child].right_child = false;
             }
         }
+        self.nodes[child].parent = parent;
     }
-}
 
-impl Node {
-    fn new(bounds: BoundingBox, collider: Arc<CuboidCollider>) -> NonNull<Self> {
-        unsafe {
-            NonNull::new_unchecked(Box::into_raw(Box::new(Node {
-                parent: None,
-                right_child: false,
-                bounds,
-                depth: 0,
-                content: NodeContent::Leaf(collider),
-            })))
+    /// branch with the given children, which must not have parents
+    fn new_branch(&mut self, left: usize, right: usize) -> usize {
+        let bounds = self.nodes[left].bounds.join(self.nodes[right].bounds);
+        let depth = self.nodes[left].depth.max(self.nodes[right].depth) + 1;
+        let branch = self.allocate_node(bounds, depth, NodeContent::Branch { left, right });
+        self.set_child(Some(branch), false, left);
+        self.set_child(Some(branch), true, right);
+        branch
+    }
+
+    /// detach every leaf and free all branches, leaving the tree empty
+    fn take_leaves(&mut self) -> Vec<usize> {
+        let mut leaves = Vec::with_capacity(self.size);
+        let mut stack: Vec<_> = self.root.take().into_iter().collect();
+        while let Some(index) = stack.pop() {
+            if let Some((left, right)) = self.children(index) {
+                stack.push(left);
+                stack.push(right);
+                self.free_node(index);
+            } else {
+                self.nodes[index].parent = None;
+                self.nodes[index].right_child = false;
+                leaves.push(index);
+            }
         }
+        self.size = 0;
+        leaves
     }
 
-    /// calculate depths and rebalances tree if needed
-    fn rebalance(&mut self) {
-        // println!("Right bigger: {}", right_bigger);
-        unsafe {
-            // determine larger child
-            let branch_content = match self.content {
-                NodeContent::Branch(branch) => branch,
-                NodeContent::Leaf(_) => return,
-                NodeContent::None => return,
-            };
+    /// set the bounds of a leaf enlarged by the fat margin, refitting the bounds of its ancestors
+    fn refit(&mut self, leaf: usize, mut bounds: BoundingBox) {
+        bounds.grow(FAT_MARGIN);
+        self.nodes[leaf].bounds = bounds;
 
-            let left_depth = branch_content.as_ref().left.as_ref().depth as i32;
-            let right_depth = branch_content.as_ref().right.as_ref().depth as i32;
+        let mut parent = self.nodes[leaf].parent;
+        while let Some(branch) = parent {
+            let new_bounds = self.children_bounds(branch);
+            if new_bounds == self.nodes[branch].bounds {
+                break;
+            }
+            self.nodes[branch].bounds = new_bounds;
+            parent = self.nodes[branch].parent;
+        }
 
-            let balance = left_depth - right_depth;
-            let larger_child = if balance > 1 {
-                branch_content.as_ref().left
-            } else if balance < -1 {
-                branch_content.as_ref().right
-            } else {
-                self.depth = (left_depth.max(right_depth) + 1) as usize;
-                return;
-            };
+        self.refits += 1;
+        if self.refits >= OPTIMISE_PERIOD {
+            self.optimise();
+        }
+    }
 
-            // determine larger grandchild in larger child
-            let child_content = if let NodeContent::Branch(branch) = larger_child.as_ref().content {
-                branch
-            } else {
-                panic!("During rebalancing, found larger node to have actual depth < 2");
-            };
+    /// build a subtree from parentless nodes, recursively splitting them along the axis and position
+    /// with the lowest surface area heuristic cost
+    ///
+    /// see: On fast Construction of SAH-based Bounding Volume Hierarchies (Wald)
+    fn build_sah(&mut self, nodes: &mut [usize]) -> usize {
+        let count = nodes.len();
+        if count == 1 {
+            return nodes[0];
+        }
 
-            let branch_raw = &mut *branch_content.as_ptr();
-            let child_raw = &mut *child_content.as_ptr();
+        let centre = |node: &usize, axis: usize| self.nodes[*node].bounds.centre()[axis];
+        // (cost, axis, number of nodes on the left)
+        let mut best = (f32::INFINITY, 0, count / 2);
+        let mut right_areas = vec![0.; count];
+        for axis in 0..3 {
+            nodes.sort_by(|a, b| centre(a, axis).total_cmp(&centre(b, axis)));
+
+            // areas of the bounds of every suffix
+            let mut bounds = self.nodes[nodes[count - 1]].bounds;
+            for i in (1..count).rev() {
+                bounds = bounds.join(self.nodes[nodes[i]].bounds);
+                right_areas[i] = bounds.surface_area();
+            }
 
-            let small_child = if larger_child.as_ref().right_child {
-                &mut branch_raw.left
-            } else {
-                &mut branch_raw.right
-            };
-            let large_grand = if child_raw.left.as_ref().depth < child_raw.right.as_ref().depth {
-                &mut child_raw.right
-            } else {
-                &mut child_raw.left
+            let mut bounds = self.nodes[nodes[0]].bounds;
+            for (i, right_area) in right_areas.iter().enumerate().skip(1) {
+                let cost = bounds.surface_area() * i as f32 + right_area * (count - i) as f32;
+                if cost < best.0 {
+                    best = (cost, axis, i);
+                }
+                bounds = bounds.join(self.nodes[nodes[i]].bounds);
+            }
+        }
+
+        let (_, axis, split) = best;
+        nodes.sort_by(|a, b| centre(a, axis).total_cmp(&centre(b, axis)));
+        let (left, right) = nodes.split_at_mut(split);
+        let left = self.build_sah(left);
+        let right = self.build_sah(right);
+        self.new_branch(left, right)
+    }
+
+    /// swap the positions of 2 nodes in the tree, recalculating the depth and bounds of their parents
+    ///
+    /// only used with a child and grandchild on opposite sides of a branch
+    fn swap_nodes(&mut self, child: usize, grandchild: usize) {
+        let child_parent = self.nodes[child].parent;
+        let child_side = self.nodes[child].right_child;
+        let grand_parent = self.nodes[grandchild].parent.unwrap();
+        let grand_side = self.nodes[grandchild].right_child;
+
+        self.set_child(Some(grand_parent), grand_side, child);
+        self.set_child(child_parent, child_side, grandchild);
+
+        self.recalculate_depth(grand_parent);
+        self.nodes[grand_parent].bounds = self.children_bounds(grand_parent);
+    }
+
+    /// optimise children first, then swap a child with a grandchild if it reduces the area of the other child
+    ///
+    /// see: Fast, Effective BVH Updates for Animated Scenes (Kopta et al.)
+    fn optimise_node(&mut self, index: usize) {
+        let Some((left, right)) = self.children(index) else {
+            return;
+        };
+        self.optimise_node(left);
+        self.optimise_node(right);
+
+        // (area reduction, other child, grandchild)
+        let mut best: Option<(f32, usize, usize)> = None;
+        for (child, other) in [(left, right), (right, left)] {
+            let Some((grand_left, grand_right)) = self.children(child) else {
+                continue;
             };
+            let area = self.nodes[child].bounds.surface_area();
+            for (grandchild, kept) in [(grand_left, grand_right), (grand_right, grand_left)] {
+                // the other child swaps places with the grandchild that isn't kept
+                let reduction = area
+                    - self.nodes[kept]
+                        .bounds
+                        .join(self.nodes[other].bounds)
+                        .surface_area();
+                if reduction > best.map_or(0., |(r, _, _)| r) {
+                    best = Some((reduction, other, grandchild));
+                }
+            }
+        }
 
-            // swap smaller child and larger grandchild's positions
-            mem::swap(small_child, large_grand);
-            mem::swap(
-                &mut small_child.as_mut().right_child,
-                &mut large_grand.as_mut().right_child,
-            );
-            mem::swap(
-                &mut small_child.as_mut().parent,
-                &mut large_grand.as_mut().parent,
-            );
+        if let Some((_, other, grandchild)) = best {
+            self.swap_nodes(other, grandchild);
+        }
+        self.recalculate_depth(index);
+    }
 
-            // update larger child's depth and bounds
-            child_raw.recalculate_depth();
-            child_raw.recalculate_bounds();
+    /// calculate depths and rebalances tree if needed
+    fn rebalance(&mut self, index: usize) {
+        // determine larger child
+        let Some((left, right)) = self.children(index) else {
+            return;
+        };
 
-            // update self depth (asumming an initial imbalance of 2)
-            self.depth = (left_depth as usize + right_depth as usize) / 2 + 1;
+        let left_depth = self.nodes[left].depth as i32;
+        let right_depth = self.nodes[right].depth as i32;
+
+        let balance = left_depth - right_depth;
+        let (larger_child, small_child) = if balance > 1 {
+            (left, right)
+        } else if balance < -1 {
+            (right, left)
+        } else {
+            self.nodes[index].depth = (left_depth.max(right_depth) + 1) as usize;
+            return;
+        };
+
+        // determine larger grandchild in larger child
+        let Some((grand_left, grand_right)) = self.children(larger_child) else {
+            panic!("During rebalancing, found larger node to have actual depth < 2");
+        };
+        let large_grand = if self.nodes[grand_left].depth < self.nodes[grand_right].depth {
+            grand_right
+        } else {
+            grand_left
+        };
+
+        // swap smaller child and larger grandchild's positions
+        self.swap_nodes(small_child, large_grand);
+
+        // update self depth
+        self.recalculate_depth(index);
+    }
+
+    /// overlapping pairs of leaves within this subtree
+    fn check_self_overlap<'a>(
+        &'a self,
+        index: usize,
+        filter: &dyn Fn(&Collider, &Collider) -> bool,
+        results: &mut Vec<(&'a Arc<Collider>, &'a Arc<Collider>)>,
+    ) {
+        if let Some((left, right)) = self.children(index) {
+            self.check_self_overlap(left, filter, results);
+            self.check_self_overlap(right, filter, results);
+            self.check_overlap(left, right, filter, results);
         }
     }
 
+    /// overlapping pairs of leaves with one leaf in each subtree
     fn check_overlap<'a>(
         &'a self,
-        other: &'a Node,
-        results: &mut Vec<(&'a Arc<CuboidCollider>, &'a Arc<CuboidCollider>)>,
+        index: usize,
+        other: usize,
+        filter: &dyn Fn(&Collider, &Collider) -> bool,
+        results: &mut Vec<(&'a Arc<Collider>, &'a Arc<Collider>)>,
     ) {
-        unsafe {
-            // recurse to children if any
-            if let NodeContent::Branch(branch) = self.content {
-                (*branch.as_ptr())
-                    .left
-                    .as_ref()
-                    .check_overlap((*branch.as_ptr()).right.as_ref(), results);
+        let (node, other_node) = (&self.nodes[index], &self.nodes[other]);
+        if !node.bounds.check_overlap(other_node.bounds) {
+            return;
+        }
+
+        match (&node.content, &other_node.content) {
+            (NodeContent::Branch { left, right }, NodeContent::Branch { .. })
+                if node.bounds.surface_area() > other_node.bounds.surface_area() =>
+            {
+                // split the larger branch
+                self.check_overlap(*left, other, filter, results);
+                self.check_overlap(*right, other, filter, results);
             }
-            if let NodeContent::Branch(other_branch) = other.content {
-                (*other_branch.as_ptr())
-                    .left
-                    .as_ref()
-                    .check_overlap((*other_branch.as_ptr()).right.as_ref(), results);
+            (NodeContent::Branch { left, right }, NodeContent::Leaf(_)) => {
+                // self is branch, other is leaf
+                self.check_overlap(*left, other, filter, results);
+                self.check_overlap(*right, other, filter, results);
+            }
+            (_, NodeContent::Branch { left, right }) => {
+                // other is the larger branch or self is leaf
+                self.check_overlap(index, *left, filter, results);
+                self.check_overlap(index, *right, filter, results);
+            }
+            (NodeContent::Leaf(coll), NodeContent::Leaf(other_coll)) => {
+                // both are leaves
+                if filter(coll, other_coll) {
+                    results.push((coll, other_coll))
+                }
+            }
+            (_, _) => {
+                println!("NONE NODE IN TREE???");
             }
+        }
+    }
 
-            if self.bounds.check_overlap(other.bounds) {
-                match (&self.content, &other.content) {
-                    (NodeContent::Branch(branch), NodeContent::Branch(other_branch)) => {
-                        // both are branches
-                        if self.bounds.volume() > other.bounds.volume() {
-                            (*branch.as_ptr())
-                                .left
-                                .as_ref()
-                                .check_overlap(other, results);
-                            (*branch.as_ptr())
-                                .right
-                                .as_ref()
-                                .check_overlap(other, results);
-                        } else {
-                            (*other_branch.as_ptr())
-                                .left
-                                .as_ref()
-                                .check_overlap(self, results);
-                            (*other_branch.as_ptr())
-                                .right
-                                .as_ref()
-                                .check_overlap(self, results);
-                        }
-                    }
-                    (NodeContent::Branch(branch), _) => {
-                        // self is branch, other is leaf
-                        (*branch.as_ptr())
-                            .left
-                            .as_ref()
-                            .check_overlap(other, results);
-                        (*branch.as_ptr())
-                            .right
-                            .as_ref()
-                            .check_overlap(other, results);
-                    }
-                    (_, NodeContent::Branch(other_branch)) => {
-                        // self is leaf, other is branch
-                        (*other_branch.as_ptr())
-                            .left
-                            .as_ref()
-                            .check_overlap(self, results);
-                        (*other_branch.as_ptr())
-                            .right
-                            .as_ref()
-                            .check_overlap(self, results);
-                    }
-                    (NodeContent::Leaf(coll), NodeContent::Leaf(other_coll)) => {
-                        // both are leaves
-                        results.push((coll, other_coll))
-                    }
-                    (_, _) => {
-                        println!("NONE NODE IN TREE???");
+    fn raycast_all_node<'a>(
+        &'a self,
+        index: usize,
+        ray: &Ray,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+        results: &mut Vec<(f32, &'a Arc<Collider>)>,
+    ) {
+        match &self.nodes[index].content {
+            NodeContent::Leaf(collider) => {
+                if let Some(distance) = Self::raycast_leaf(collider, ray, transforms, filter) {
+                    results.push((distance, collider));
+                }
+            }
+            NodeContent::Branch { left, right } => {
+                for child in [*left, *right] {
+                    if ray.box_intersection(&self.nodes[child].bounds).is_some() {
+                        self.raycast_all_node(child, ray, transforms, filter, results);
                     }
                 }
             }
+            NodeContent::None => {}
         }
     }
 
-    /// currently unused
-    fn raycast(
+    fn raycast_node(
         &self,
+        index: usize,
         ray: &Ray,
         transforms: &mut TransformSystem,
-    ) -> Option<(f32, &Arc<CuboidCollider>)> {
-        match &self.content {
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Option<(f32, &Arc<Collider>)> {
+        match &self.nodes[index].content {
             NodeContent::Leaf(collider) => {
-                let mut model = transforms.get_global_model(&collider.transform).unwrap();
-                quick_inverse(&mut model);
-                ray.cuboid_intersection(&model)
-                    .map(|depth| (depth, collider))
+                Self::raycast_leaf(collider, ray, transforms, filter).map(|depth| (depth, collider))
             }
-            NodeContent::Branch(branch) => unsafe {
-                let left_dist = ray.box_intersection(&branch.as_ref().left.as_ref().bounds);
-                let right_dist = ray.box_intersection(&branch.as_ref().right.as_ref().bounds);
+            NodeContent::Branch { left, right } => {
+                let left_dist = ray.box_intersection(&self.nodes[*left].bounds);
+                let right_dist = ray.box_intersection(&self.nodes[*right].bounds);
 
                 match (left_dist, right_dist) {
-                    (Some(left), Some(right)) => {
+                    (Some(left_dist), Some(right_dist)) => {
                         // check first intercepted child first
-                        let (close_child, far_child) = if left < right {
-                            ((*branch.as_ptr()).left, (*branch.as_ptr()).right)
+                        let (close_child, far_child) = if left_dist < right_dist {
+                            (*left, *right)
                         } else {
-                            ((*branch.as_ptr()).right, (*branch.as_ptr()).left)
+                            (*right, *left)
                         };
-                        let far_dist = left.max(right);
+                        let far_dist = left_dist.max(right_dist);
 
-                        let close_result = close_child.as_ref().raycast(ray, transforms);
+                        let close_result = self.raycast_node(close_child, ray, transforms, filter);
 
                         if let Some((close_dist, _)) = close_result {
                             // check if far bounds is entered before close result
@@ -586,7 +778,8 @@ impl Node {
                                 close_result
                             } else {
                                 // try raycast far
-                                let far_result = far_child.as_ref().raycast(ray, transforms);
+                                let far_result =
+                                    self.raycast_node(far_child, ray, transforms, filter);
                                 far_result.map_or(close_result, |(far_dist, _)| {
                                     if close_dist < far_dist {
                                         close_result
@@ -596,161 +789,104 @@ impl Node {
                                 })
                             }
                         } else {
-                            far_child.as_ref().raycast(ray, transforms)
+                            self.raycast_node(far_child, ray, transforms, filter)
                         }
                     }
-                    (Some(_), None) => branch.as_ref().left.as_ref().raycast(ray, transforms),
-                    (None, Some(_)) => branch.as_ref().right.as_ref().raycast(ray, transforms),
+                    (Some(_), None) => self.raycast_node(*left, ray, transforms, filter),
+                    (None, Some(_)) => self.raycast_node(*right, ray, transforms, filter),
                     _ => None,
                 }
-            },
+            }
             NodeContent::None => {
                 println!("NONE NODE IN TREE???");
                 None
             }
         }
     }
-}
 
-impl Debug for Node {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.content {
-            NodeContent::Branch(branch) => unsafe {
-                f.debug_struct("Branch")
-                    .field("parent", &self.parent)
-                    .field("right_child", &self.right_child)
-                    .field("depth", &self.depth)
-                    .field("left", branch.as_ref().left.as_ref())
-                    .field("right", branch.as_ref().right.as_ref())
-                    .finish()
-            },
-            NodeContent::Leaf(collider) => f
-                .debug_struct("Leaf")
-                .field("parent", &self.parent)
-                .field("right_child", &self.right_child)
-                .field("depth", &self.depth)
-                .field("collider", &collider)
-                .finish(),
-            NodeContent::None => f.debug_struct("None").finish(),
-        }
-    }
-}
-
-impl BranchLinks {
-    fn new(node: NonNull<Node>, left: NonNull<Node>, right: NonNull<Node>) -> NonNull<Self> {
-        unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(Self { node, left, right }))) }
-    }
-
-    fn recalculate_depth(&mut self) {
-        unsafe {
-            (*self.node.as_ptr()).depth =
-                self.left.as_ref().depth.max(self.right.as_ref().depth) + 1;
-        }
-    }
-
-    fn recalculate_bounds(&mut self) {
-        unsafe {
-            (*self.node.as_ptr()).bounds =
-                self.left.as_ref().bounds.join(self.right.as_ref().bounds);
+    fn raycast_leaf(
+        collider: &Collider,
+        ray: &Ray,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Option<f32> {
+        if !filter(collider) {
+            return None;
         }
+        let model = transforms.get_global_model(&collider.transform).unwrap();
+        collider.shape.to_global(&model).raycast(ray)
     }
 }
 
-// impl<T> LeafReference<T> {
-//     fn get_leaf(&self, hierachy: &BVH) -> Option<&Node> {
-//         if self.hierachy == hierachy {
-//             unsafe { Some(self.leaf.as_ref()) }
-//         } else {
-//             None
-//         }
-//     }
-//     fn get_leaf_mut(&mut self, hierachy: &mut BVH) -> Option<&mut Node> {
-//         if self.hierachy == hierachy {
-//             unsafe { Some(self.leaf.as_mut()) }
-//         } else {
-//             None
-//         }
-//     }
-// }
-impl LeafInHierachy {
-    fn convert(self) -> LeafOutsideHierachy {
-        LeafOutsideHierachy { leaf: self.leaf }
-    }
-
-    // probably nothing wrong with leaving this in
-    // pub fn get_collider(&self, hierachy: &BVH) -> Option<&CuboidCollider> {
-    //     if ptr::eq(self.hierachy, hierachy) {
-    //         unsafe {
-    //             if let NodeContent::Leaf(collider) = &self.leaf.as_ref().content {
-    //                 Some(collider)
-    //             } else {
-    //                 None
-    //             }
-    //         }
-    //     } else {
-    //         None
-    //     }
-    // }
-
-    /// Having 2 copies of a leaf reference and using one for removal leaves a LeafInHierachy that references a leaf outside hierachy
-    /// Not to mention dropping the newly converted LeafOutsideHierachy leaves dangling pointers
-    /// Note: This is used for quick removal and reinsertion without dropping the original LeafInHierachy, if we used a wrapper for the ECS this would not be needed
-    unsafe fn clone(&self) -> Self {
-        Self {
-            leaf: self.leaf,
-            hierachy: self.hierachy,
-        }
+impl Default for Bvh {
+    fn default() -> Self {
+        Self::new()
     }
 }
-impl LeafOutsideHierachy {
-    fn convert(self, hierachy: *const Bvh) -> LeafInHierachy {
-        let x = ManuallyDrop::new(self);
-        LeafInHierachy {
-            leaf: x.leaf,
-            hierachy,
-        }
-    }
 
-    pub fn get_collider(&self) -> &CuboidCollider {
-        unsafe {
-            if let NodeContent::Leaf(collider) = &self.leaf.as_ref().content {
-                collider
-            } else {
-                panic!("Leaf reference does not point to leaf")
+impl Debug for Bvh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.root {
+            Some(root) => NodeDebug {
+                tree: self,
+                index: root,
             }
+            .fmt(f),
+            None => f.write_str("Empty"),
         }
     }
+}
 
-    // pub fn get_collider_mut(&mut self) -> &mut CuboidCollider {
-    //     unsafe {
-    //         if let NodeContent::Leaf(collider) = &mut self.leaf.as_mut().content {
-    //             collider
-    //         } else {
-    //             panic!("Leaf reference does not point to leaf")
-    //         }
-    //     }
-    // }
+/// prints a node along with its children
+struct NodeDebug<'a> {
+    tree: &'a Bvh,
+    index: usize,
 }
 
-impl Debug for LeafInHierachy {
+impl Debug for NodeDebug<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Leaf does not belong to this hierachy")
+        let node = &self.tree.nodes[self.index];
+        match &node.content {
+            NodeContent::Branch { left, right } => f
+                .debug_struct("Branch")
+                .field("parent", &node.parent)
+                .field("right_child", &node.right_child)
+                .field("depth", &node.depth)
+                .field(
+                    "left",
+                    &NodeDebug {
+                        tree: self.tree,
+                        index: *left,
+                    },
+                )
+                .field(
+                    "right",
+                    &NodeDebug {
+                        tree: self.tree,
+                        index: *right,
+                    },
+                )
+                .finish(),
+            NodeContent::Leaf(collider) => f
+                .debug_struct("Leaf")
+                .field("parent", &node.parent)
+                .field("right_child", &node.right_child)
+                .field("depth", &node.depth)
+                .field("collider", &collider)
+                .finish(),
+            NodeContent::None => f.debug_struct("None").finish(),
+        }
     }
 }
-unsafe impl Send for LeafInHierachy {}
-unsafe impl Sync for LeafInHierachy {}
 
-impl Debug for LeafOutsideHierachy {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Leaf does not belong to this hierachy")
+impl LeafOutsideHierachy {
+    pub fn get_collider(&self) -> &Collider {
+        &self.collider
     }
-}
-// if a ref to a leaf outside the hierachy is dropped we drop the inner leaf node as well
-impl Drop for LeafOutsideHierachy {
-    fn drop(&mut self) {
-        unsafe {
-            let _ = Box::from_raw(self.leaf.as_ptr());
-        }
+
+    /// drop the leaf, keeping its collider
+    pub fn into_collider(self) -> Arc<Collider> {
+        self.collider
     }
 }
 
@@ -763,71 +899,50 @@ impl<'a> IntoIterator for &'a Bvh {
     }
 }
 
-impl<'a> Iterator for DepthIter<'a> {
+impl Iterator for DepthIter<'_> {
     type Item = (BoundingBox, usize);
 
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            match self.current.pop() {
-                Some(node) => {
-                    if let NodeContent::Branch(branch) = node.as_ref().content {
-                        self.next.push(branch.as_ref().left);
-                        self.next.push(branch.as_ref().right);
-                    }
-                    Some((node.as_ref().bounds, node.as_ref().depth))
-                }
-                None => {
-                    // swap current and next
-                    std::mem::swap(&mut self.current, &mut self.next);
-
-                    // try pop current again
-                    if let Some(node) = self.current.pop() {
-                        if let NodeContent::Branch(branch) = node.as_ref().content {
-                            self.next.push(branch.as_ref().left);
-                            self.next.push(branch.as_ref().right);
-                        }
-                        Some((node.as_ref().bounds, node.as_ref().depth))
-                    } else {
-                        // both vecs empty
-                        None
-                    }
-                }
-            }
+        if self.current.is_empty() {
+            // swap current and next
+            mem::swap(&mut self.current, &mut self.next);
+        }
+
+        // both vecs empty if none
+        let node = &self.tree.nodes[self.current.pop()?];
+        if let NodeContent::Branch { left, right } = node.content {
+            self.next.push(left);
+            self.next.push(right);
         }
+        Some((node.bounds, node.depth))
     }
 }
 
 #[cfg(test)]
 mod tree_tests {
-    use std::{
-        ptr::{self, addr_of, NonNull},
-        sync::Arc,
-    };
+    use std::{collections::BTreeSet, sync::Arc, time::Instant};
 
     use cgmath::Matrix4;
 
-    use crate::{game_objects::transform::TransformSystem, physics::collider::CuboidCollider};
+    use crate::{game_objects::transform::TransformSystem, physics::collider::Collider};
 
-    use super::{BranchLinks, Bvh, Node, NodeContent};
+    use super::{BoundingBox, Bvh, NodeContent, FAT_MARGIN, OPTIMISE_PERIOD};
 
     fn validate_tree(
-        child: &Node,
-        parent: Option<&NonNull<BranchLinks>>,
+        tree: &Bvh,
+        index: usize,
+        parent: Option<usize>,
         right_child: bool,
     ) -> Result<(), String> {
         // error format is {expected} vs {actual}
+        let child = &tree.nodes[index];
 
         // parent check
-        if let Some(actual_parent) = child.parent {
-            if let Some(expected_parent) = parent {
-                if !ptr::eq(expected_parent.as_ptr(), actual_parent.as_ptr()) {
-                    return Err(format!("Mismatching parent"));
-                }
-            } else {
-                return Err(format!("Parent present when None was expected"));
-            }
-        } else if parent.is_some() {
-            return Err(format!("Child parent incorrectly set to None"));
+        if child.parent != parent {
+            return Err(format!(
+                "Mismatching parent: {:?} vs {:?}",
+                parent, child.parent
+            ));
         }
 
         // side check
@@ -839,18 +954,9 @@ mod tree_tests {
         }
 
         match &child.content {
-            NodeContent::Branch(branch) => unsafe {
-                // check node
-                if !ptr::eq(child, branch.as_ref().node.as_ptr()) {
-                    return Err(format!(
-                        "Incorrect Node in BranchContent: {:?} vs {:?}",
-                        addr_of!(child),
-                        branch.as_ref().node.as_ptr()
-                    ));
-                }
-
-                let left_raw = branch.as_ref().left.as_ref();
-                let right_raw = branch.as_ref().right.as_ref();
+            NodeContent::Branch { left, right } => {
+                let left_raw = &tree.nodes[*left];
+                let right_raw = &tree.nodes[*right];
 
                 // bounds check
                 if child.bounds != left_raw.bounds.join(right_raw.bounds) {
@@ -867,8 +973,7 @@ mod tree_tests {
                         "Incorrect depth: {:?} vs {:?}",
                         left_raw.depth.max(right_raw.depth) + 1,
                         child.depth
-                    )
-                    .to_string());
+                    ));
                 }
 
                 // // balance check
@@ -876,41 +981,36 @@ mod tree_tests {
                 //     return Err(format!("Unbalanced tree"));
                 // }
 
-                validate_tree(left_raw, Some(branch), false)?;
-                validate_tree(right_raw, Some(branch), true)?;
-            },
+                validate_tree(tree, *left, Some(index), false)?;
+                validate_tree(tree, *right, Some(index), true)?;
+            }
             NodeContent::Leaf(_) => {
-                // // bounds check
-                // if child.bounds != collider.bounding_box {
-                //     return Err(format!(
-                //         "Incorrect Bounds: {:?} vs {:?}",
-                //         collider.bounding_box, child.bounds
-                //     ));
-                // }
-
                 // depth check
                 if child.depth != 0 {
-                    return Err(format!("Incorrect depth: 0 vs {:?}", child.depth).to_string());
+                    return Err(format!("Incorrect depth: 0 vs {:?}", child.depth));
                 }
             }
             NodeContent::None => {
-                return Err(format!("Found unitialised node"));
+                return Err("Found unused node in tree".to_string());
             }
         }
 
         Ok(())
     }
 
-    fn assert_valid_tree(root: &Node) {
-        let validation = validate_tree(&root, None, false);
+    fn assert_valid_tree(tree: &Bvh) {
+        let validation = validate_tree(tree, tree.root.unwrap(), None, false);
 
         assert!(
             validation.is_ok(),
             "Err: {:?}, \nTree: {:#?}",
             validation,
-            root
+            tree
         );
-        // assert_eq!("ALL", "GOOD", "Tree: \n{:#?}", root);
+
+        // every used node is in the tree
+        let used = tree.nodes.len() - tree.free.len();
+        assert_eq!(tree.iter().count(), used, "Unreachable nodes");
     }
 
     #[test]
@@ -940,33 +1040,22 @@ mod tree_tests {
 
         let a = Bvh::register_collider(
             crap_box,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let b = Bvh::register_collider(
             box_2,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let c = Bvh::register_collider(
             box_2,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
 
         let _a = tree.insert(a);
         let _b = tree.insert(b);
         let _c = tree.insert(c);
 
-        unsafe {
-            assert_valid_tree(&tree.root.unwrap().as_ref());
-        }
+        assert_valid_tree(&tree);
     }
     #[test]
     fn remove_test() {
@@ -984,24 +1073,15 @@ mod tree_tests {
 
         let a = Bvh::register_collider(
             crap_box,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let b = Bvh::register_collider(
             box_2,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let c = Bvh::register_collider(
             box_2,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
 
         let _a = tree.insert(a);
@@ -1010,9 +1090,7 @@ mod tree_tests {
 
         tree.remove(b).expect("Incorrect hierachy for removal");
 
-        unsafe {
-            assert_valid_tree(&tree.root.unwrap().as_ref());
-        }
+        assert_valid_tree(&tree);
     }
     #[test]
     fn big_tree() {
@@ -1049,19 +1127,14 @@ mod tree_tests {
         ] {
             let leaf = Bvh::register_collider(
                 bounding_box,
-                Arc::new(CuboidCollider {
-                    transform: trans.next().unwrap(),
-                    rigidbody: None,
-                }),
+                Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
             );
             tree.insert(leaf);
         }
 
         println!("Yes?");
 
-        unsafe {
-            assert_valid_tree(&tree.root.unwrap().as_ref());
-        }
+        assert_valid_tree(&tree);
     }
     #[test]
     fn big_remove() {
@@ -1095,10 +1168,7 @@ mod tree_tests {
 
         let leaf = Bvh::register_collider(
             box_6,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let a = tree.insert(leaf);
 
@@ -1107,10 +1177,7 @@ mod tree_tests {
         ] {
             let leaf = Bvh::register_collider(
                 bounding_box,
-                Arc::new(CuboidCollider {
-                    transform: trans.next().unwrap(),
-                    rigidbody: None,
-                }),
+                Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
             );
             tree.insert(leaf);
             // unsafe {
@@ -1120,19 +1187,14 @@ mod tree_tests {
 
         let leaf = Bvh::register_collider(
             box_2,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let b = tree.insert(leaf);
 
         tree.remove(a).unwrap();
         tree.remove(b).unwrap();
 
-        unsafe {
-            assert_valid_tree(&tree.root.unwrap().as_ref());
-        }
+        assert_valid_tree(&tree);
     }
     #[test]
     fn remove_branch_root() {
@@ -1150,25 +1212,17 @@ mod tree_tests {
 
         let a = Bvh::register_collider(
             crap_box,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         tree.insert(a);
         let b = Bvh::register_collider(
             box_2,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let b = tree.insert(b);
 
         tree.remove(b).unwrap();
-        unsafe {
-            assert_valid_tree(&tree.root.unwrap().as_ref());
-        }
+        assert_valid_tree(&tree);
     }
     #[test]
     fn remove_leaf_root() {
@@ -1181,10 +1235,7 @@ mod tree_tests {
         };
         let remove = Bvh::register_collider(
             crap_box,
-            Arc::new(CuboidCollider {
-                transform: trans.next().unwrap(),
-                rigidbody: None,
-            }),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
         );
         let remove = tree.insert(remove);
 
@@ -1224,18 +1275,18 @@ mod tree_tests {
     //     };
 
     //     for bounding_box in [crap_box, box_2, box_5, box_6, box_2, box_4, box_6] {
-    //         tree1.insert_new(CuboidCollider {
+    //         tree1.insert_new(Collider {
     //             transform: trans.next().unwrap(),
     //             bounding_box,
     //         });
     //     }
     //     for bounding_box in [box_5, box_2, box_3] {
-    //         tree2.insert_new(CuboidCollider {
+    //         tree2.insert_new(Collider {
     //             transform: trans.next().unwrap(),
     //             bounding_box,
     //         });
     //     }
-    //     let uwu = tree2.insert_new(CuboidCollider {
+    //     let uwu = tree2.insert_new(Collider {
     //         transform: trans.next().unwrap(),
     //         bounding_box: crap_box,
     //     });
@@ -1248,4 +1299,222 @@ mod tree_tests {
     // }
     #[test]
     fn removal_balance() {}
+
+    /// sum of branch surface areas, proportional to the expected cost of a query
+    fn sah_cost(tree: &Bvh, index: usize) -> f32 {
+        let node = &tree.nodes[index];
+        match node.content {
+            NodeContent::Branch { left, right } => {
+                node.bounds.surface_area() + sah_cost(tree, left) + sah_cost(tree, right)
+            }
+            _ => 0.,
+        }
+    }
+
+    /// number of overlapping pairs, asserting that none are repeated
+    fn unique_overlaps(tree: &Bvh) -> usize {
+        let overlaps = tree.get_overlaps(&|_, _| true);
+        let unique: BTreeSet<_> = overlaps
+            .iter()
+            .map(|(a, b)| {
+                let (a, b) = (Arc::as_ptr(a), Arc::as_ptr(b));
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        assert_eq!(unique.len(), overlaps.len(), "Repeated overlap pairs");
+        unique.len()
+    }
+
+    fn moved(mut bounds: BoundingBox, translation: (f32, f32, f32)) -> BoundingBox {
+        bounds.translate(translation.into());
+        bounds
+    }
+
+    /// bounds of the 20x20 grid of objects from `init_world`
+    fn grid_bounds() -> Vec<BoundingBox> {
+        (0..400)
+            .map(|i| {
+                let centre = ((i / 20) as f32 * 3., 21., (i % 20) as f32 * 3.);
+                moved(
+                    BoundingBox::new((-1.6, -1.6, -1.6), (1.6, 1.6, 1.6)),
+                    centre,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grid_tree() {
+        let mut trans = TransformSystem::new();
+        let bounds = grid_bounds();
+        let mut fat_bounds = bounds.clone();
+        for b in &mut fat_bounds {
+            b.grow(FAT_MARGIN);
+        }
+        let mut brute_pairs = 0;
+        for (i, a) in fat_bounds.iter().enumerate() {
+            brute_pairs += fat_bounds[i + 1..]
+                .iter()
+                .filter(|b| a.check_overlap(**b))
+                .count();
+        }
+
+        let mut incremental = Bvh::new();
+        let mut bulk = Bvh::new();
+        let mut leaves = vec![];
+        let mut bulk_leaves = vec![];
+        for b in &bounds {
+            let collider = Arc::new(Collider::cuboid(trans.next().unwrap(), None));
+            leaves.push(incremental.insert(Bvh::register_collider(*b, collider.clone())));
+            bulk_leaves.push(Bvh::register_collider(*b, collider));
+        }
+        let bulk_leaves = bulk.insert_many(bulk_leaves);
+
+        for (name, tree) in [("incremental", &incremental), ("bulk", &bulk)] {
+            assert_valid_tree(tree);
+            assert_eq!(tree.size, 400);
+            assert_eq!(
+                unique_overlaps(tree),
+                brute_pairs,
+                "{name} tree missed overlaps"
+            );
+        }
+        assert!(
+            sah_cost(&bulk, bulk.root.unwrap())
+                <= sah_cost(&incremental, incremental.root.unwrap())
+        );
+
+        // small movements stay inside the fat bounds
+        for (leaf, b) in bulk_leaves.iter().zip(&bounds) {
+            let new_bounds = moved(*b, (0.1, 0., -0.1));
+            bulk.recalculate_bounds(*leaf, |_| new_bounds).unwrap();
+        }
+        assert_eq!(bulk.refits, 0);
+
+        // large movements refit and optimise the tree
+        let mut moved_bounds = vec![];
+        for (i, (leaf, b)) in bulk_leaves.iter().zip(&bounds).enumerate() {
+            let new_bounds = moved(*b, (0., (i % 7) as f32, 0.));
+            bulk.recalculate_bounds(*leaf, |_| new_bounds).unwrap();
+            moved_bounds.push(new_bounds);
+        }
+        assert!(bulk.refits < OPTIMISE_PERIOD);
+        assert_valid_tree(&bulk);
+
+        let moved_pairs = unique_overlaps(&bulk);
+        let mut brute_pairs = 0;
+        for (i, leaf) in bulk_leaves.iter().enumerate() {
+            let a = bulk.nodes[leaf.index].bounds;
+            assert!(a.contains(moved_bounds[i]));
+            brute_pairs += bulk_leaves[i + 1..]
+                .iter()
+                .filter(|b| a.check_overlap(bulk.nodes[b.index].bounds))
+                .count();
+        }
+        assert_eq!(moved_pairs, brute_pairs);
+
+        for leaf in bulk_leaves {
+            bulk.remove(leaf).expect("Incorrect hierachy for removal");
+        }
+        assert!(bulk.root.is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn grid_benchmark() {
+        let mut trans = TransformSystem::new();
+        let mut incremental = Bvh::new();
+        let mut bulk_leaves = vec![];
+        for b in grid_bounds() {
+            let collider = Arc::new(Collider::cuboid(trans.next().unwrap(), None));
+            incremental.insert(Bvh::register_collider(b, collider.clone()));
+            bulk_leaves.push(Bvh::register_collider(b, collider));
+        }
+        let mut bulk = Bvh::new();
+        bulk.insert_many(bulk_leaves);
+
+        let query = BoundingBox::new((10., 18., 10.), (20., 24., 20.));
+        for (name, tree) in [("incremental", &incremental), ("bulk", &bulk)] {
+            let start = Instant::now();
+            let pairs = unique_overlaps(tree);
+            let overlap_time = start.elapsed();
+
+            let start = Instant::now();
+            let hits = tree.query_bounds(query, &|_| true).len();
+            let query_time = start.elapsed();
+            println!(
+                "[Benchmarking] {name}: {pairs} pairs in {overlap_time:?}, {hits} query hits in {query_time:?}, depth {}, cost {}",
+                tree.depth(),
+                sah_cost(tree, tree.root.unwrap()),
+            );
+        }
+    }
+
+    #[test]
+    fn stale_references() {
+        let mut trans = TransformSystem::new();
+        let mut tree = Bvh::new();
+        let mut other_tree = Bvh::new();
+        let bounds = BoundingBox::new((0., 0., 0.), (1., 1., 1.));
+
+        let leaves: Vec<_> = (0..4)
+            .map(|_| {
+                let collider = Arc::new(Collider::cuboid(trans.next().unwrap(), None));
+                tree.insert(Bvh::register_collider(bounds, collider))
+            })
+            .collect();
+        let other = other_tree.insert(Bvh::register_collider(
+            bounds,
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
+        ));
+
+        // references stay valid when the tree moves
+        let mut tree = Box::new(tree);
+        tree.remove(leaves[1]).unwrap();
+
+        // the removed leaf's node gets reused, but the old reference can't reach it
+        let reused = tree.insert(Bvh::register_collider(
+            bounds,
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
+        ));
+        assert_eq!(tree.remove(leaves[1]).unwrap_err(), leaves[1]);
+        assert!(tree.recalculate_bounds(leaves[1], |_| bounds).is_err());
+        assert!(tree.get_mut(leaves[1]).is_none());
+        assert!(tree.remove(other).is_err());
+        assert_valid_tree(&tree);
+
+        tree.remove(reused).unwrap();
+        assert_eq!(tree.size, 3);
+        assert_valid_tree(&tree);
+    }
+
+    #[test]
+    fn guard_refits() {
+        let mut trans = TransformSystem::new();
+        let mut tree = Bvh::new();
+        let bounds = BoundingBox::new((0., 0., 0.), (1., 1., 1.));
+        let leaf = tree.insert(Bvh::register_collider(
+            bounds,
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
+        ));
+        tree.insert(Bvh::register_collider(
+            moved(bounds, (3., 0., 0.)),
+            Arc::new(Collider::cuboid(trans.next().unwrap(), None)),
+        ));
+
+        // still inside its fat bounds, so only refit because it was modified
+        tree.get_mut(leaf).unwrap().set_layers(2, 2);
+        assert_eq!(tree.modified, vec![leaf.index]);
+        let smaller = BoundingBox::new((0.1, 0.1, 0.1), (0.9, 0.9, 0.9));
+        tree.refit_modified(|collider| {
+            assert_eq!(collider.get_layer(), 2);
+            smaller
+        });
+        assert!(tree.modified.is_empty());
+
+        let mut expected = smaller;
+        expected.grow(FAT_MARGIN);
+        assert_eq!(tree.nodes[leaf.index].bounds, expected);
+        assert_valid_tree(&tree);
+    }
 }
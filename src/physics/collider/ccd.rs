@@ -0,0 +1,72 @@
+use super::{narrow_phase, Collider};
+use crate::{game_objects::transform::TransformSystem, physics::Vector};
+use cgmath::{InnerSpace, Matrix4};
+
+/// min cos between a contact normal and the motion for the contact to block it,
+/// so sliding along a surface isn't counted as an impact
+const MIN_APPROACH: f32 = 0.2;
+/// most samples taken along a motion before bisecting, so thin or fast colliders can't stall
+/// an update
+const MAX_SAMPLES: u32 = 32;
+const BISECTION_ITERS: u32 = 8;
+
+/// Fraction of the motion the moving collider makes before hitting the other, None if it doesn't hit
+///
+/// The moving collider is at the end of its motion and keeps its current rotation while swept.
+/// Samples are spaced by its smallest half extent, so other colliders can't be skipped over
+/// unless the motion needs more than `MAX_SAMPLES`.
+pub fn time_of_impact(
+    moving: &Collider,
+    motion: Vector,
+    other: &Collider,
+    transforms: &mut TransformSystem,
+) -> Option<f32> {
+    let end_model = transforms.get_global_model(&moving.transform).unwrap();
+    let other_model = transforms.get_global_model(&other.transform).unwrap();
+    let other_shape = other.shape.to_global(&other_model);
+
+    let extents = moving.shape.to_global(&end_model).bounds().extents();
+    let step = extents.x.min(extents.y).min(extents.z);
+    let distance = motion.magnitude();
+    if distance <= step {
+        // too slow to tunnel, left to the regular contacts
+        return None;
+    }
+    let direction = motion / distance;
+
+    let hits = |t: f32| {
+        let model = Matrix4::from_translation((t - 1.) * motion) * end_model;
+        narrow_phase::contacts(&moving.shape.to_global(&model), &other_shape)
+            .iter()
+            .any(|contact| contact.normal.dot(direction) > MIN_APPROACH)
+    };
+
+    first_hit(distance, step, hits)
+}
+
+/// Fraction of a motion where `hits` first returns true, None if it never does
+///
+/// The motion is sampled every step along its distance, spaced further apart past
+/// `MAX_SAMPLES`, then refined by bisection. The start of the motion isn't checked.
+pub fn first_hit(distance: f32, step: f32, hits: impl Fn(f32) -> bool) -> Option<f32> {
+    let samples = ((distance / step).ceil() as u32).clamp(1, MAX_SAMPLES);
+    let mut free = 0.;
+    for i in 1..=samples {
+        let t = i as f32 / samples as f32;
+        if hits(t) {
+            // refine between the last free sample and the hit
+            let mut hit = t;
+            for _ in 0..BISECTION_ITERS {
+                let mid = (free + hit) / 2.;
+                if hits(mid) {
+                    hit = mid;
+                } else {
+                    free = mid;
+                }
+            }
+            return Some(hit);
+        }
+        free = t;
+    }
+    None
+}
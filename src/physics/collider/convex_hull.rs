@@ -0,0 +1,246 @@
+use super::ray::Ray;
+use crate::physics::Vector;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix};
+use std::{collections::BTreeSet, fmt::Debug};
+
+/// min distance of a point from a face for it to be considered outside the hull
+const HULL_EPSILON: f32 = 1e-5;
+
+/// Convex hull of a set of points in the local space of its collider
+pub struct ConvexHull {
+    vertices: Vec<Vector>,
+    /// outward normal and distance from origin of each face
+    planes: Vec<(Vector, f32)>,
+    /// vertices of each face, wound anti-clockwise from outside
+    faces: Vec<[Vector; 3]>,
+}
+
+impl ConvexHull {
+    /// Builds the hull of the given points by adding them one by one to an initial tetrahedron
+    ///
+    /// Returns `None` if the points are all coplanar
+    pub fn new(points: impl IntoIterator<Item = impl Into<Vector>>) -> Option<Self> {
+        let points: Vec<Vector> = points.into_iter().map(|p| p.into()).collect();
+        let mut faces = initial_tetrahedron(&points)?;
+
+        for (i, point) in points.iter().enumerate() {
+            let (visible, hidden): (Vec<_>, Vec<_>) = faces
+                .into_iter()
+                .partition(|face| face_height(&points, face, *point) > HULL_EPSILON);
+            faces = hidden;
+            if visible.is_empty() {
+                continue;
+            }
+
+            // edges on the border of the visible region get joined to the new point,
+            // ordered so the same points always give the same faces
+            let visible_edges: BTreeSet<(usize, usize)> = visible
+                .iter()
+                .flat_map(|[a, b, c]| [(*a, *b), (*b, *c), (*c, *a)])
+                .collect();
+            for (a, b) in visible_edges.iter() {
+                if !visible_edges.contains(&(*b, *a)) {
+                    faces.push([*a, *b, i]);
+                }
+            }
+        }
+
+        // only keep vertices used by faces
+        let used: BTreeSet<usize> = faces.iter().flatten().copied().collect();
+        let vertices = used.into_iter().map(|i| points[i]).collect();
+        let (planes, faces) = faces
+            .iter()
+            .filter_map(|face| {
+                let normal = face_normal(&points, face);
+                let length = normal.magnitude();
+                if length < HULL_EPSILON {
+                    return None;
+                }
+                let normal = normal / length;
+                Some((
+                    (normal, normal.dot(points[face[0]])),
+                    face.map(|i| points[i]),
+                ))
+            })
+            .unzip();
+
+        Some(Self {
+            vertices,
+            planes,
+            faces,
+        })
+    }
+
+    pub fn vertices(&self) -> &[Vector] {
+        &self.vertices
+    }
+
+    pub fn faces(&self) -> &[[Vector; 3]] {
+        &self.faces
+    }
+
+    /// furthest vertex in the given direction
+    pub fn support(&self, direction: Vector) -> Vector {
+        *self
+            .vertices
+            .iter()
+            .max_by(|a, b| a.dot(direction).total_cmp(&b.dot(direction)))
+            .unwrap()
+    }
+
+    /// point in local space is on or inside the hull
+    pub fn contains(&self, point: Vector) -> bool {
+        self.planes
+            .iter()
+            .all(|(normal, offset)| normal.dot(point) - offset <= HULL_EPSILON)
+    }
+
+    /// distance along ray to the first intersection with the hull transformed by model, 0 if ray starts inside
+    pub fn raycast(&self, ray: &Ray, model: &Matrix4<f32>) -> Option<f32> {
+        let inv_model = model.invert().unwrap();
+        let origin = (inv_model * ray.origin.extend(1.)).truncate();
+        let direction = (inv_model * ray.direction.extend(0.)).truncate();
+
+        // clip ray by each face
+        let mut close = 0f32;
+        let mut far = ray.distance;
+        for (normal, offset) in self.planes.iter() {
+            let height = normal.dot(origin) - offset;
+            let speed = normal.dot(direction);
+            if speed.abs() < f32::EPSILON {
+                if height > 0. {
+                    // parallel and outside
+                    return None;
+                }
+                continue;
+            }
+
+            let t = -height / speed;
+            if speed < 0. {
+                close = close.max(t);
+            } else {
+                far = far.min(t);
+            }
+            if close > far {
+                return None;
+            }
+        }
+
+        Some(close)
+    }
+}
+impl Debug for ConvexHull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("ConvexHull({} vertices)", self.vertices.len()))
+    }
+}
+
+/// unnormalised outward normal of a face with anti-clockwise winding
+fn face_normal(points: &[Vector], [a, b, c]: &[usize; 3]) -> Vector {
+    (points[*b] - points[*a]).cross(points[*c] - points[*a])
+}
+
+fn face_height(points: &[Vector], face: &[usize; 3], point: Vector) -> f32 {
+    let normal = face_normal(points, face);
+    let length = normal.magnitude();
+    if length < HULL_EPSILON {
+        return f32::NEG_INFINITY;
+    }
+    (point - points[face[0]]).dot(normal) / length
+}
+
+/// faces of the largest tetrahedron found from the extremes of the points, wound to face outward
+fn initial_tetrahedron(points: &[Vector]) -> Option<Vec<[usize; 3]>> {
+    let furthest_by = |key: &dyn Fn(Vector) -> f32| {
+        (0..points.len()).max_by(|a, b| key(points[*a]).total_cmp(&key(points[*b])))
+    };
+
+    let p0 = 0;
+    let p1 = furthest_by(&|p| (p - points[p0]).magnitude2())?;
+    let line = points[p1] - points[p0];
+    let p2 = furthest_by(&|p| line.cross(p - points[p0]).magnitude2())?;
+    let normal = line.cross(points[p2] - points[p0]);
+    let p3 = furthest_by(&|p| normal.dot(p - points[p0]).abs())?;
+
+    if line.magnitude2() < HULL_EPSILON
+        || normal.magnitude2() < HULL_EPSILON
+        || normal.dot(points[p3] - points[p0]).abs() < HULL_EPSILON
+    {
+        return None;
+    }
+
+    let mut faces = vec![[p0, p1, p2], [p0, p3, p1], [p1, p3, p2], [p2, p3, p0]];
+    // flip winding if p3 is in front of the first face
+    if normal.dot(points[p3] - points[p0]) > 0. {
+        for face in faces.iter_mut() {
+            face.swap(1, 2);
+        }
+    }
+    Some(faces)
+}
+
+#[cfg(test)]
+mod hull_tests {
+    use super::ConvexHull;
+    use crate::physics::{
+        collider::{ray::Ray, CUBE_VERTICES},
+        Vector,
+    };
+    use cgmath::{InnerSpace, Matrix4, SquareMatrix};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn cube_with_interior_points() {
+        let points = CUBE_VERTICES
+            .into_iter()
+            .chain([(0., 0., 0.).into(), (0.5, -0.2, 0.1).into()]);
+        let hull = ConvexHull::new(points).unwrap();
+
+        assert_eq!(hull.vertices().len(), 8);
+        assert_eq!(hull.support((1., 2., -3.).into()), (1., 1., -1.).into());
+    }
+
+    #[test]
+    fn sphere_points() {
+        // fibonacci sphere, every point is on the hull
+        let count = 500;
+        let points: Vec<Vector> = (0..count)
+            .map(|i| {
+                let y = 1. - 2. * (i as f32 + 0.5) / count as f32;
+                let r = (1. - y * y).sqrt();
+                let theta = PI * (3. - 5f32.sqrt()) * i as f32;
+                (r * theta.cos(), y, r * theta.sin()).into()
+            })
+            .collect();
+        let hull = ConvexHull::new(points.clone()).unwrap();
+
+        assert_eq!(hull.vertices().len(), count);
+        for direction in points.iter().map(|p| p + Vector::new(0.1, -0.2, 0.3)) {
+            let brute_force = points
+                .iter()
+                .map(|p| p.dot(direction))
+                .fold(f32::NEG_INFINITY, f32::max);
+            assert!((hull.support(direction).dot(direction) - brute_force).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn flat_points() {
+        let points = [(0., 0., 0.), (1., 0., 0.), (0., 0., 1.), (1., 0., 1.)];
+        assert!(ConvexHull::new(points).is_none());
+    }
+
+    #[test]
+    fn hull_raycast() {
+        let hull = ConvexHull::new(CUBE_VERTICES).unwrap();
+        let model = Matrix4::identity();
+
+        let hit = Ray::new((-5., 0.5, 0.).into(), (1., 0., 0.).into(), 10.);
+        let miss = Ray::new((-5., 2., 0.).into(), (1., 0., 0.).into(), 10.);
+        let inside = Ray::new((0., 0., 0.).into(), (1., 0., 0.).into(), 10.);
+
+        assert!((hull.raycast(&hit, &model).unwrap() - 4.).abs() < 1e-5);
+        assert!(hull.raycast(&miss, &model).is_none());
+        assert_eq!(hull.raycast(&inside, &model), Some(0.));
+    }
+}
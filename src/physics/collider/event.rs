@@ -0,0 +1,140 @@
+use super::transform_pair;
+use crate::game_objects::transform::TransformID;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CollisionPhase {
+    /// first update the colliders touched
+    Enter,
+    /// colliders were already touching last update
+    Stay,
+    /// colliders stopped touching (or one was removed)
+    Exit,
+}
+
+/// Change in the touching state of 2 colliders, identified by their transforms
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CollisionEvent {
+    pub phase: CollisionPhase,
+    /// ordered by id
+    pub transforms: (TransformID, TransformID),
+    /// one of the colliders is a trigger so no contacts were resolved
+    pub trigger: bool,
+}
+
+/// Turns the pairs touching each update into enter/stay/exit events
+#[derive(Clone, Default)]
+pub struct CollisionTracker {
+    /// pairs touching last update and whether they involve a trigger
+    touching: BTreeMap<(TransformID, TransformID), bool>,
+    current: BTreeMap<(TransformID, TransformID), bool>,
+    /// events of the last update only
+    events: Vec<CollisionEvent>,
+}
+
+impl CollisionEvent {
+    pub fn involves(&self, transform: &TransformID) -> bool {
+        self.transforms.0 == *transform || self.transforms.1 == *transform
+    }
+
+    /// the transform paired with the given one, None if the event doesn't involve it
+    pub fn other(&self, transform: &TransformID) -> Option<TransformID> {
+        if self.transforms.0 == *transform {
+            Some(self.transforms.1)
+        } else if self.transforms.1 == *transform {
+            Some(self.transforms.0)
+        } else {
+            None
+        }
+    }
+}
+
+impl CollisionTracker {
+    /// mark pair as touching this update
+    pub fn touch(&mut self, transform_1: TransformID, transform_2: TransformID, trigger: bool) {
+        let pair = transform_pair(transform_1, transform_2);
+        *self.current.entry(pair).or_default() |= trigger;
+    }
+
+    /// keep pair touching if it was last update, without checking for contacts
+    pub fn keep(&mut self, transform_1: TransformID, transform_2: TransformID) {
+        let pair = transform_pair(transform_1, transform_2);
+        if let Some(&trigger) = self.touching.get(&pair) {
+            self.current.insert(pair, trigger);
+        }
+    }
+
+    /// compare touching pairs with the last update to create events, replacing the last update's
+    pub fn finish_update(&mut self) {
+        self.events.clear();
+        for (&transforms, &trigger) in &self.current {
+            let phase = match self.touching.remove(&transforms) {
+                Some(_) => CollisionPhase::Stay,
+                None => CollisionPhase::Enter,
+            };
+            self.events.push(CollisionEvent {
+                phase,
+                transforms,
+                trigger,
+            });
+        }
+        for (&transforms, &trigger) in &self.touching {
+            self.events.push(CollisionEvent {
+                phase: CollisionPhase::Exit,
+                transforms,
+                trigger,
+            });
+        }
+
+        self.touching = std::mem::take(&mut self.current);
+    }
+
+    /// events of the last update, they are dropped by the next one if not drained
+    pub fn drain(&mut self) -> std::vec::Drain<'_, CollisionEvent> {
+        self.events.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod event_tests {
+    use super::{CollisionPhase::*, CollisionTracker};
+    use crate::game_objects::transform::TransformSystem;
+
+    #[test]
+    fn enter_stay_exit() {
+        let mut transforms = TransformSystem::new();
+        let a = transforms.next().unwrap();
+        let b = transforms.next().unwrap();
+        let c = transforms.next().unwrap();
+        let mut tracker = CollisionTracker::default();
+
+        let events = |tracker: &mut CollisionTracker| -> Vec<_> {
+            tracker.finish_update();
+            tracker.drain().map(|e| (e.phase, e.transforms)).collect()
+        };
+
+        tracker.touch(b, a, false);
+        assert_eq!(events(&mut tracker), [(Enter, (a, b))]);
+        tracker.touch(a, b, false);
+        tracker.touch(a, c, true);
+        assert_eq!(events(&mut tracker), [(Stay, (a, b)), (Enter, (a, c))]);
+        tracker.touch(a, c, true);
+        assert_eq!(events(&mut tracker), [(Stay, (a, c)), (Exit, (a, b))]);
+        assert_eq!(tracker.drain().count(), 0);
+    }
+
+    #[test]
+    fn undrained_events_bounded() {
+        let mut transforms = TransformSystem::new();
+        let a = transforms.next().unwrap();
+        let b = transforms.next().unwrap();
+        let mut tracker = CollisionTracker::default();
+
+        for _ in 0..100 {
+            tracker.touch(a, b, false);
+            tracker.finish_update();
+        }
+        let events: Vec<_> = tracker.drain().map(|e| e.phase).collect();
+        assert_eq!(events, [Stay]);
+    }
+}
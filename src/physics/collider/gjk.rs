@@ -0,0 +1,355 @@
+//! Intersection test and penetration depth of convex shapes using only their support points
+//!
+//! see: https://winter.dev/articles/gjk-algorithm and https://winter.dev/articles/epa-algorithm
+
+use super::{
+    narrow_phase::ContactData,
+    shape::{barycentric, GlobalShape},
+    ColliderElement::Surface,
+};
+use crate::physics::Vector;
+use cgmath::InnerSpace;
+
+const GJK_ITER_LIMIT: u32 = 64;
+const EPA_ITER_LIMIT: u32 = 64;
+/// accepted difference between the closest face and the furthest support point to end epa
+const EPA_TOLERANCE: f32 = 1e-4;
+
+/// point of the minkowski difference (shape 1 - shape 2) and the points on each shape that made it
+#[derive(Clone, Copy)]
+struct SupportPoint {
+    point: Vector,
+    on_1: Vector,
+    on_2: Vector,
+}
+
+impl SupportPoint {
+    /// None if either shape has no support point
+    fn new(shape_1: &GlobalShape, shape_2: &GlobalShape, direction: Vector) -> Option<Self> {
+        let on_1 = shape_1.support(direction)?;
+        let on_2 = shape_2.support(-direction)?;
+        Some(Self {
+            point: on_1 - on_2,
+            on_1,
+            on_2,
+        })
+    }
+}
+
+/// contact of 2 penetrating convex shapes, normal points from 1 to 2,
+/// None for shapes without support points
+pub fn contact(shape_1: &GlobalShape, shape_2: &GlobalShape) -> Option<ContactData> {
+    let simplex = gjk(shape_1, shape_2)?;
+    epa(shape_1, shape_2, simplex)
+}
+
+/// returns simplex enclosing the origin if the shapes overlap, newest point first
+fn gjk(shape_1: &GlobalShape, shape_2: &GlobalShape) -> Option<Vec<SupportPoint>> {
+    let mut direction = shape_2.centre() - shape_1.centre();
+    if direction.magnitude2() < f32::EPSILON {
+        direction = Vector::unit_x();
+    }
+
+    let first = SupportPoint::new(shape_1, shape_2, direction)?;
+    let mut simplex = vec![first];
+    direction = -first.point;
+
+    for _ in 0..GJK_ITER_LIMIT {
+        if direction.magnitude2() < f32::EPSILON {
+            // origin on the simplex
+            return Some(simplex);
+        }
+
+        let new_point = SupportPoint::new(shape_1, shape_2, direction)?;
+        if new_point.point.dot(direction) <= 0. {
+            // origin not reachable
+            return None;
+        }
+        simplex.insert(0, new_point);
+
+        match next_simplex(&mut simplex) {
+            Some(new_direction) => direction = new_direction,
+            None => return Some(simplex),
+        }
+    }
+
+    None
+}
+
+/// reduce simplex to the part closest to the origin, returns None if the simplex contains the origin
+fn next_simplex(simplex: &mut Vec<SupportPoint>) -> Option<Vector> {
+    match simplex.len() {
+        2 => Some(line(simplex)),
+        3 => Some(triangle(simplex)),
+        4 => tetrahedron(simplex),
+        _ => unreachable!("simplex has {} points", simplex.len()),
+    }
+}
+
+fn line(simplex: &mut Vec<SupportPoint>) -> Vector {
+    let a = simplex[0].point;
+    let ab = simplex[1].point - a;
+    let ao = -a;
+
+    if ab.dot(ao) > 0. {
+        ab.cross(ao).cross(ab)
+    } else {
+        simplex.truncate(1);
+        ao
+    }
+}
+
+fn triangle(simplex: &mut Vec<SupportPoint>) -> Vector {
+    let a = simplex[0].point;
+    let ab = simplex[1].point - a;
+    let ac = simplex[2].point - a;
+    let ao = -a;
+    let abc = ab.cross(ac);
+
+    if abc.cross(ac).dot(ao) > 0. {
+        if ac.dot(ao) > 0. {
+            simplex.remove(1);
+            ac.cross(ao).cross(ac)
+        } else {
+            simplex.truncate(2);
+            line(simplex)
+        }
+    } else if ab.cross(abc).dot(ao) > 0. {
+        simplex.truncate(2);
+        line(simplex)
+    } else if abc.dot(ao) > 0. {
+        abc
+    } else {
+        simplex.swap(1, 2);
+        -abc
+    }
+}
+
+fn tetrahedron(simplex: &mut Vec<SupportPoint>) -> Option<Vector> {
+    let a = simplex[0].point;
+    let ab = simplex[1].point - a;
+    let ac = simplex[2].point - a;
+    let ad = simplex[3].point - a;
+    let ao = -a;
+
+    if ab.cross(ac).dot(ao) > 0. {
+        simplex.truncate(3);
+        Some(triangle(simplex))
+    } else if ac.cross(ad).dot(ao) > 0. {
+        simplex.remove(1);
+        Some(triangle(simplex))
+    } else if ad.cross(ab).dot(ao) > 0. {
+        *simplex = vec![simplex[0], simplex[3], simplex[1]];
+        Some(triangle(simplex))
+    } else {
+        None
+    }
+}
+
+/// expand the gjk simplex to find the face of the minkowski difference closest to the origin
+fn epa(
+    shape_1: &GlobalShape,
+    shape_2: &GlobalShape,
+    mut polytope: Vec<SupportPoint>,
+) -> Option<ContactData> {
+    fill_simplex(shape_1, shape_2, &mut polytope)?;
+
+    let mut faces = vec![[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]];
+    let mut normals: Vec<(Vector, f32)> = faces.iter().map(|f| face_normal(&polytope, f)).collect();
+
+    for _ in 0..EPA_ITER_LIMIT {
+        let (closest, (normal, distance)) = normals
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))?;
+
+        let support = SupportPoint::new(shape_1, shape_2, normal)?;
+        if support.point.dot(normal) - distance < EPA_TOLERANCE {
+            return Some(contact_from_face(
+                &polytope,
+                &faces[closest],
+                normal,
+                distance,
+            ));
+        }
+
+        // remove faces that can see the new point, keeping their outer edges
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        let mut i = 0;
+        while i < faces.len() {
+            let [a, b, c] = faces[i];
+            if normals[i].0.dot(support.point - polytope[a].point) > 0. {
+                for edge in [(a, b), (b, c), (c, a)] {
+                    if let Some(reverse) = edges.iter().position(|e| *e == (edge.1, edge.0)) {
+                        edges.swap_remove(reverse);
+                    } else {
+                        edges.push(edge);
+                    }
+                }
+                faces.swap_remove(i);
+                normals.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        let new_index = polytope.len();
+        polytope.push(support);
+        for (a, b) in edges {
+            let face = [a, b, new_index];
+            normals.push(face_normal(&polytope, &face));
+            faces.push(face);
+        }
+    }
+
+    // use best guess
+    let (closest, (normal, distance)) = normals
+        .iter()
+        .copied()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))?;
+    Some(contact_from_face(
+        &polytope,
+        &faces[closest],
+        normal,
+        distance,
+    ))
+}
+
+/// gjk may finish early with the origin on an edge or face, grow it back into a tetrahedron
+fn fill_simplex(
+    shape_1: &GlobalShape,
+    shape_2: &GlobalShape,
+    simplex: &mut Vec<SupportPoint>,
+) -> Option<()> {
+    let directions = [
+        Vector::unit_x(),
+        Vector::unit_y(),
+        Vector::unit_z(),
+        -Vector::unit_x(),
+        -Vector::unit_y(),
+        -Vector::unit_z(),
+    ];
+
+    while simplex.len() < 4 {
+        let extra_directions = if simplex.len() == 3 {
+            let normal =
+                (simplex[1].point - simplex[0].point).cross(simplex[2].point - simplex[0].point);
+            vec![normal, -normal]
+        } else {
+            Vec::new()
+        };
+
+        let new_point = extra_directions
+            .into_iter()
+            .chain(directions)
+            .filter_map(|d| SupportPoint::new(shape_1, shape_2, d))
+            .find(|p| adds_dimension(simplex, p.point))?;
+        simplex.push(new_point);
+    }
+
+    // wind first face so the last point is behind it
+    let normal = (simplex[1].point - simplex[0].point).cross(simplex[2].point - simplex[0].point);
+    if normal.dot(simplex[3].point - simplex[0].point) > 0. {
+        simplex.swap(1, 2);
+    }
+
+    Some(())
+}
+
+fn adds_dimension(simplex: &[SupportPoint], point: Vector) -> bool {
+    let offset = point - simplex[0].point;
+    let error = match simplex.len() {
+        1 => offset.magnitude2(),
+        2 => (simplex[1].point - simplex[0].point)
+            .normalize()
+            .cross(offset)
+            .magnitude2(),
+        _ => {
+            let normal = (simplex[1].point - simplex[0].point)
+                .cross(simplex[2].point - simplex[0].point)
+                .normalize();
+            normal.dot(offset).powi(2)
+        }
+    };
+    error > EPA_TOLERANCE * EPA_TOLERANCE
+}
+
+/// outward normal and distance to origin of face
+fn face_normal(polytope: &[SupportPoint], [a, b, c]: &[usize; 3]) -> (Vector, f32) {
+    let a = polytope[*a].point;
+    let normal = (polytope[*b].point - a)
+        .cross(polytope[*c].point - a)
+        .normalize();
+    let distance = normal.dot(a);
+    if distance.is_nan() {
+        // degenerate face
+        (Vector::unit_x(), f32::INFINITY)
+    } else {
+        (normal, distance)
+    }
+}
+
+fn contact_from_face(
+    polytope: &[SupportPoint],
+    [a, b, c]: &[usize; 3],
+    normal: Vector,
+    distance: f32,
+) -> ContactData {
+    let (a, b, c) = (polytope[*a], polytope[*b], polytope[*c]);
+    let [u, v, w] = barycentric(normal * distance, a.point, b.point, c.point);
+    let on_1 = u * a.on_1 + v * b.on_1 + w * c.on_1;
+    let on_2 = u * a.on_2 + v * b.on_2 + w * c.on_2;
+
+    ContactData {
+        position: (on_1 + on_2) / 2.,
+        normal,
+        penetration: distance,
+        elements: (Surface, Surface),
+    }
+}
+
+#[cfg(test)]
+mod gjk_tests {
+    use super::contact;
+    use crate::physics::{collider::shape::GlobalShape, Vector};
+    use cgmath::{InnerSpace, Matrix4};
+
+    #[test]
+    fn overlapping_cuboids() {
+        let cuboid_1 = GlobalShape::Cuboid(Matrix4::from_scale(1.));
+        let cuboid_2 = GlobalShape::Cuboid(Matrix4::from_translation((0., 1.8, 0.).into()));
+
+        let result = contact(&cuboid_1, &cuboid_2).unwrap();
+        assert!((result.penetration - 0.2).abs() < 1e-3);
+        assert!((result.normal - Vector::unit_y()).magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn seperate_spheres() {
+        let sphere_1 = GlobalShape::Sphere {
+            centre: (0., 0., 0.).into(),
+            radius: 1.,
+        };
+        let sphere_2 = GlobalShape::Sphere {
+            centre: (2.1, 0., 0.).into(),
+            radius: 1.,
+        };
+
+        assert!(contact(&sphere_1, &sphere_2).is_none());
+    }
+
+    #[test]
+    fn sphere_in_cuboid() {
+        let cuboid = GlobalShape::Cuboid(Matrix4::from_scale(1.));
+        let sphere = GlobalShape::Sphere {
+            centre: (1.2, 0., 0.).into(),
+            radius: 0.5,
+        };
+
+        let result = contact(&cuboid, &sphere).unwrap();
+        assert!((result.penetration - 0.3).abs() < 1e-2);
+        assert!((result.normal - Vector::unit_x()).magnitude() < 1e-2);
+    }
+}
@@ -0,0 +1,250 @@
+use super::{ray::Ray, BoundingBox};
+use crate::physics::Vector;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix};
+use std::fmt::Debug;
+
+/// Static grid of heights in the local space of its collider
+///
+/// Samples are spread evenly from -1 to 1 on the x and z axes with heights along the y-axis,
+/// so the transform's scale gives the size of the terrain.
+/// Each cell of the grid is split into 2 triangles facing up
+pub struct HeightField {
+    /// samples along the x-axis
+    width: usize,
+    /// samples along the z-axis
+    depth: usize,
+    /// row major, rows go along the x-axis
+    heights: Vec<f32>,
+    min_height: f32,
+    max_height: f32,
+}
+
+impl HeightField {
+    /// Panics if there are less than 2 samples on either axis or the number of heights doesn't match
+    pub fn new(width: usize, depth: usize, heights: Vec<f32>) -> Self {
+        assert!(
+            width >= 2 && depth >= 2,
+            "Height field needs at least 2x2 samples"
+        );
+        assert_eq!(heights.len(), width * depth, "Height field size mismatch");
+
+        let min_height = heights.iter().copied().fold(f32::INFINITY, f32::min);
+        let max_height = heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        Self {
+            width,
+            depth,
+            heights,
+            min_height,
+            max_height,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// local position of the sample
+    pub fn position(&self, x: usize, z: usize) -> Vector {
+        Vector::new(
+            2. * x as f32 / (self.width - 1) as f32 - 1.,
+            self.heights[z * self.width + x],
+            2. * z as f32 / (self.depth - 1) as f32 - 1.,
+        )
+    }
+
+    /// local normal of the sample from the neighbouring samples
+    pub fn normal(&self, x: usize, z: usize) -> Vector {
+        let left = self.position(x.saturating_sub(1), z);
+        let right = self.position((x + 1).min(self.width - 1), z);
+        let back = self.position(x, z.saturating_sub(1));
+        let front = self.position(x, (z + 1).min(self.depth - 1));
+        (front - back).cross(right - left).normalize()
+    }
+
+    /// local bounds of the whole field
+    pub fn bounds(&self) -> BoundingBox {
+        BoundingBox::new((-1., self.min_height, -1.), (1., self.max_height, 1.))
+    }
+
+    /// indices of the triangles making up the grid, wound anti-clockwise from above
+    pub fn indices(&self) -> Vec<u32> {
+        (0..self.triangle_count())
+            .flat_map(|t| {
+                self.triangle_samples(t)
+                    .map(|(x, z)| (z * self.width + x) as u32)
+            })
+            .collect()
+    }
+
+    pub fn triangle(&self, index: usize) -> [Vector; 3] {
+        self.triangle_samples(index)
+            .map(|(x, z)| self.position(x, z))
+    }
+
+    /// indices of triangles whose bounds overlap the local bounds
+    pub fn query(&self, bounds: BoundingBox) -> Vec<usize> {
+        if bounds.min.y > self.max_height || bounds.max.y < self.min_height {
+            return Vec::new();
+        }
+
+        let cells_x = self.width - 1;
+        let cells_z = self.depth - 1;
+        let Some((min_x, max_x)) = cell_range(bounds.min.x, bounds.max.x, cells_x) else {
+            return Vec::new();
+        };
+        let Some((min_z, max_z)) = cell_range(bounds.min.z, bounds.max.z, cells_z) else {
+            return Vec::new();
+        };
+
+        (min_z..=max_z)
+            .flat_map(|z| (min_x..=max_x).map(move |x| 2 * (z * cells_x + x)))
+            .flat_map(|t| [t, t + 1])
+            .filter(|t| BoundingBox::from_vertices(&self.triangle(*t)).check_overlap(bounds))
+            .collect()
+    }
+
+    /// distance along ray to the closest triangle of the field transformed by model
+    pub fn raycast(&self, ray: &Ray, model: &Matrix4<f32>) -> Option<f32> {
+        let inv_model = model.invert().unwrap();
+        // unnormalised local ray, distances stay the same
+        let local_ray = Ray {
+            origin: (inv_model * ray.origin.extend(1.)).truncate(),
+            direction: (inv_model * ray.direction.extend(0.)).truncate(),
+            distance: ray.distance,
+        };
+
+        // only check the cells under the part of the ray inside the field
+        let (enter, exit) = local_ray.box_intersection_raw(&self.bounds());
+        let enter = enter.max(0.);
+        let exit = exit.min(ray.distance);
+        if enter > exit {
+            return None;
+        }
+        let start = local_ray.origin + enter * local_ray.direction;
+        let end = local_ray.origin + exit * local_ray.direction;
+        // padded so axis aligned rays still overlap the triangles they touch
+        let bounds = BoundingBox::new(
+            start.zip(end, f32::min).map(|c| c - f32::EPSILON),
+            start.zip(end, f32::max).map(|c| c + f32::EPSILON),
+        );
+
+        self.query(bounds)
+            .into_iter()
+            .filter_map(|t| {
+                let [a, b, c] = self.triangle(t);
+                local_ray.triangle_intersection(a, b, c)
+            })
+            .min_by(f32::total_cmp)
+    }
+
+    fn triangle_count(&self) -> usize {
+        2 * (self.width - 1) * (self.depth - 1)
+    }
+
+    /// samples of the triangle, each cell is split along the diagonal from (x + 1, z) to (x, z + 1)
+    fn triangle_samples(&self, index: usize) -> [(usize, usize); 3] {
+        let cell = index / 2;
+        let x = cell % (self.width - 1);
+        let z = cell / (self.width - 1);
+        if index.is_multiple_of(2) {
+            [(x, z), (x, z + 1), (x + 1, z)]
+        } else {
+            [(x + 1, z), (x, z + 1), (x + 1, z + 1)]
+        }
+    }
+}
+impl Debug for HeightField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("HeightField({}x{})", self.width, self.depth))
+    }
+}
+
+/// range of cells covered by the local coordinates along an axis, None if completely outside
+fn cell_range(min: f32, max: f32, cells: usize) -> Option<(usize, usize)> {
+    if max < -1. || min > 1. {
+        return None;
+    }
+    let to_grid = |c: f32| (c + 1.) / 2. * cells as f32;
+    // coordinates on the border of 2 cells are in both
+    let low = (to_grid(min).ceil() - 1.).max(0.) as usize;
+    let high = (to_grid(max).floor() as usize).min(cells - 1);
+    Some((low.min(high), high))
+}
+
+#[cfg(test)]
+mod heightfield_tests {
+    use super::HeightField;
+    use crate::physics::{
+        collider::{ray::Ray, BoundingBox},
+        Vector,
+    };
+    use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, SquareMatrix, Transform};
+
+    /// slope rising along the x-axis from 0 to 1
+    fn slope() -> HeightField {
+        let heights = (0..5).flat_map(|_| (0..5).map(|x| x as f32 / 4.)).collect();
+        HeightField::new(5, 5, heights)
+    }
+
+    #[test]
+    fn triangles_face_up() {
+        let field = slope();
+        let expected = Vector::new(-1., 2., 0.).normalize();
+        for t in 0..field.triangle_count() {
+            let [a, b, c] = field.triangle(t);
+            let normal = (b - a).cross(c - a).normalize();
+            assert!((normal - expected).magnitude() < 1e-5);
+        }
+        assert!((field.normal(2, 2) - expected).magnitude() < 1e-5);
+    }
+
+    /// the mesh keeps local normals, the renderer's inverse transpose model applies the scale
+    #[test]
+    fn scaled_normals() {
+        let field = slope();
+        let scale = Matrix4::from_nonuniform_scale(40., 10., 40.);
+        let normal_matrix = scale.invert().unwrap().transpose();
+
+        let [a, b, c] = field
+            .triangle(0)
+            .map(|v| scale.transform_point(Point3::from_vec(v)));
+        let expected = (b - a).cross(c - a).normalize();
+        let normal = normal_matrix
+            .transform_vector(field.normal(2, 2))
+            .normalize();
+        assert!((normal - expected).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn query_field() {
+        let field = slope();
+
+        let found = field.query(BoundingBox::new((-0.1, -1., -0.1), (0.1, 2., 0.1)));
+        assert_eq!(found.len(), 8);
+        // above the slope
+        let found = field.query(BoundingBox::new((-1., 0.6, -1.), (-0.5, 1., 1.)));
+        assert!(found.is_empty());
+        let found = field.query(BoundingBox::new((1.5, -1., -1.), (2., 2., 1.)));
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn raycast_field() {
+        let field = slope();
+        let model = Matrix4::from_nonuniform_scale(10., 4., 10.);
+
+        let down = Ray::new((0., 10., 3.).into(), (0., -1., 0.).into(), 20.);
+        let across = Ray::new((-20., 1.2, 3.).into(), (1., 0., 0.).into(), 40.);
+        let outside = Ray::new((12., 10., 0.).into(), (0., -1., 0.).into(), 20.);
+
+        // height of 2 halfway up the slope
+        assert!((field.raycast(&down, &model).unwrap() - 8.).abs() < 1e-4);
+        // hits the slope at a height of 1.2 at x = -4
+        assert!((field.raycast(&across, &model).unwrap() - 16.).abs() < 1e-4);
+        assert!(field.raycast(&outside, &model).is_none());
+    }
+}
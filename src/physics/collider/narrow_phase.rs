@@ -0,0 +1,798 @@
+use super::{
+    gjk,
+    ray::Ray,
+    shape::{
+        barycentric, closest_point_on_segment, closest_points_between_segments, transform_bounds,
+        GlobalShape,
+    },
+    BoundingBox,
+    ColliderElement::{self, *},
+    CUBE_VERTICES,
+};
+use crate::physics::Vector;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix};
+
+/// Iterations used to find the closest point of a capsule segment to a cuboid
+const CAPSULE_CUBOID_ITER: u8 = 4;
+/// min dot product between a contact normal and triangle normal to use the vertices of a shape as contacts
+const TRIANGLE_FACE_ALIGN: f32 = 0.95;
+
+/// Contact info between 2 shapes in global space
+#[derive(Debug)]
+pub struct ContactData {
+    /// midway between the penetrating surfaces
+    pub position: Vector,
+    /// normalised, points from shape 1 to shape 2
+    pub normal: Vector,
+    pub penetration: f32,
+    pub elements: (ColliderElement, ColliderElement),
+}
+
+impl ContactData {
+    /// contact from shape 2's pov
+    fn flipped(self) -> Self {
+        Self {
+            position: self.position,
+            normal: -self.normal,
+            penetration: self.penetration,
+            elements: (self.elements.1, self.elements.0),
+        }
+    }
+}
+
+/// find contacts between 2 shapes, returns nothing if they aren't touching
+pub fn contacts(shape_1: &GlobalShape, shape_2: &GlobalShape) -> Vec<ContactData> {
+    ordered_contacts(shape_1, shape_2, false)
+}
+
+/// contacts for the shapes in this order, tries the other order if it isn't handled and
+/// returns nothing if neither is
+fn ordered_contacts(
+    shape_1: &GlobalShape,
+    shape_2: &GlobalShape,
+    swapped: bool,
+) -> Vec<ContactData> {
+    use GlobalShape as S;
+
+    match (shape_1, shape_2) {
+        // static shapes don't collide with each other
+        (
+            S::Plane { .. } | S::TriangleMesh { .. } | S::HeightField { .. },
+            S::Plane { .. } | S::TriangleMesh { .. } | S::HeightField { .. },
+        ) => Vec::new(),
+        (_, S::TriangleMesh { model, mesh }) => {
+            let bounds = local_bounds(shape_1, model);
+            triangles(
+                shape_1,
+                model,
+                mesh.query(bounds).into_iter().map(|i| mesh.triangle(i)),
+            )
+        }
+        (_, S::HeightField { model, field }) => {
+            let bounds = local_bounds(shape_1, model);
+            triangles(
+                shape_1,
+                model,
+                field.query(bounds).into_iter().map(|i| field.triangle(i)),
+            )
+        }
+        (S::Cuboid(model_1), S::Cuboid(model_2)) => {
+            cuboid_cuboid(model_1, model_2).into_iter().collect()
+        }
+        (S::Sphere { centre, radius }, S::Cuboid(model)) => {
+            sphere_cuboid(*centre, *radius, model).into_iter().collect()
+        }
+        (
+            S::Sphere {
+                centre: centre_1,
+                radius: radius_1,
+            },
+            S::Sphere {
+                centre: centre_2,
+                radius: radius_2,
+            },
+        ) => sphere_sphere(*centre_1, *radius_1, *centre_2, *radius_2)
+            .into_iter()
+            .collect(),
+        (
+            S::Sphere { centre, radius },
+            S::Capsule {
+                start,
+                end,
+                radius: cap_radius,
+            },
+        ) => {
+            let closest = closest_point_on_segment(*centre, *start, *end);
+            sphere_sphere(*centre, *radius, closest, *cap_radius)
+                .into_iter()
+                .collect()
+        }
+        (S::Sphere { centre, radius }, S::Plane { point, normal }) => {
+            sphere_plane(*centre, *radius, *point, *normal)
+                .into_iter()
+                .collect()
+        }
+        (S::Capsule { start, end, radius }, S::Cuboid(model)) => {
+            capsule_cuboid(*start, *end, *radius, model)
+        }
+        (
+            S::Capsule {
+                start: start_1,
+                end: end_1,
+                radius: radius_1,
+            },
+            S::Capsule {
+                start: start_2,
+                end: end_2,
+                radius: radius_2,
+            },
+        ) => {
+            let (closest_1, closest_2) =
+                closest_points_between_segments(*start_1, *end_1, *start_2, *end_2);
+            sphere_sphere(closest_1, *radius_1, closest_2, *radius_2)
+                .into_iter()
+                .collect()
+        }
+        (S::Capsule { start, end, radius }, S::Plane { point, normal }) => [*start, *end]
+            .into_iter()
+            .filter_map(|centre| sphere_plane(centre, *radius, *point, *normal))
+            .collect(),
+        (S::Cuboid(model), S::Plane { point, normal }) => cuboid_plane(model, *point, *normal),
+        (S::ConvexHull { model, hull }, S::Plane { point, normal }) => {
+            vertices_plane(hull.vertices(), model, *point, *normal, |_| Surface)
+        }
+        (S::ConvexHull { .. }, _) => gjk::contact(shape_1, shape_2).into_iter().collect(),
+        // swapped versions of the above
+        _ if !swapped => ordered_contacts(shape_2, shape_1, true)
+            .into_iter()
+            .map(ContactData::flipped)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn sphere_sphere(
+    centre_1: Vector,
+    radius_1: f32,
+    centre_2: Vector,
+    radius_2: f32,
+) -> Option<ContactData> {
+    let offset = centre_2 - centre_1;
+    let distance = offset.magnitude();
+    let penetration = radius_1 + radius_2 - distance;
+    if penetration <= 0. {
+        return None;
+    }
+
+    // pick any normal if centres overlap
+    let normal = if distance > f32::EPSILON {
+        offset / distance
+    } else {
+        Vector::unit_y()
+    };
+    let surface_1 = centre_1 + radius_1 * normal;
+    let surface_2 = centre_2 - radius_2 * normal;
+
+    Some(ContactData {
+        position: (surface_1 + surface_2) / 2.,
+        normal,
+        penetration,
+        elements: (Surface, Surface),
+    })
+}
+
+fn sphere_plane(centre: Vector, radius: f32, point: Vector, normal: Vector) -> Option<ContactData> {
+    let height = (centre - point).dot(normal);
+    let penetration = radius - height;
+    if penetration <= 0. {
+        return None;
+    }
+
+    let surface_1 = centre - radius * normal;
+    let surface_2 = centre - height * normal;
+
+    Some(ContactData {
+        position: (surface_1 + surface_2) / 2.,
+        normal: -normal,
+        penetration,
+        elements: (Surface, Surface),
+    })
+}
+
+fn sphere_cuboid(centre: Vector, radius: f32, model: &Matrix4<f32>) -> Option<ContactData> {
+    let inv_model = model.invert().unwrap();
+    let local = (inv_model * centre.extend(1.)).truncate();
+    let clamped = local.map(|c| c.clamp(-1., 1.));
+
+    if clamped == local {
+        // centre inside cuboid, push out through closest face
+        let axes_mag = [0, 1, 2].map(|i| model[i].truncate().magnitude());
+        let depths = [0, 1, 2].map(|i| (1. - local[i].abs()) * axes_mag[i]);
+        let mut axis = 0;
+        for i in [1, 2] {
+            if depths[i] < depths[axis] {
+                axis = i;
+            }
+        }
+
+        let outward = local[axis].signum() * model[axis].truncate() / axes_mag[axis];
+        let surface_1 = centre - radius * outward;
+        let surface_2 = centre + depths[axis] * outward;
+
+        return Some(ContactData {
+            position: (surface_1 + surface_2) / 2.,
+            normal: -outward,
+            penetration: radius + depths[axis],
+            elements: (Surface, Surface),
+        });
+    }
+
+    let closest = (model * clamped.extend(1.)).truncate();
+    let offset = closest - centre;
+    let distance = offset.magnitude();
+    let penetration = radius - distance;
+    if penetration <= 0. {
+        return None;
+    }
+
+    let normal = offset / distance;
+    let surface_1 = centre + radius * normal;
+
+    Some(ContactData {
+        position: (surface_1 + closest) / 2.,
+        normal,
+        penetration,
+        elements: (Surface, Surface),
+    })
+}
+
+/// contacts of each end of the capsule, or the closest point of the capsule if neither end touches
+fn capsule_cuboid(
+    start: Vector,
+    end: Vector,
+    radius: f32,
+    model: &Matrix4<f32>,
+) -> Vec<ContactData> {
+    let result: Vec<_> = [start, end]
+        .into_iter()
+        .filter_map(|centre| sphere_cuboid(centre, radius, model))
+        .collect();
+    if !result.is_empty() {
+        return result;
+    }
+
+    // alternate closest points between the segment and the cuboid
+    let inv_model = model.invert().unwrap();
+    let mut closest = closest_point_on_segment(model.w.truncate(), start, end);
+    for _ in 0..CAPSULE_CUBOID_ITER {
+        let local = (inv_model * closest.extend(1.)).truncate();
+        let on_cuboid = (model * local.map(|c| c.clamp(-1., 1.)).extend(1.)).truncate();
+        closest = closest_point_on_segment(on_cuboid, start, end);
+    }
+
+    sphere_cuboid(closest, radius, model).into_iter().collect()
+}
+
+fn cuboid_plane(model: &Matrix4<f32>, point: Vector, normal: Vector) -> Vec<ContactData> {
+    vertices_plane(&CUBE_VERTICES, model, point, normal, |i| Vertex(i as u8))
+}
+
+/// contact for each local vertex under the plane, element gives the contact element of each vertex index
+fn vertices_plane(
+    vertices: &[Vector],
+    model: &Matrix4<f32>,
+    point: Vector,
+    normal: Vector,
+    element: impl Fn(usize) -> ColliderElement,
+) -> Vec<ContactData> {
+    vertices
+        .iter()
+        .enumerate()
+        .filter_map(|(i, v)| {
+            let vertex = (model * v.extend(1.)).truncate();
+            let height = (vertex - point).dot(normal);
+            if height >= 0. {
+                return None;
+            }
+
+            Some(ContactData {
+                position: vertex - (height / 2.) * normal,
+                normal: -normal,
+                penetration: -height,
+                elements: (element(i), Surface),
+            })
+        })
+        .collect()
+}
+
+/// bounds of the shape in the local space of model
+fn local_bounds(shape: &GlobalShape, model: &Matrix4<f32>) -> BoundingBox {
+    transform_bounds(shape.bounds(), &model.invert().unwrap())
+}
+
+/// contacts with each of the local triangles transformed by model
+fn triangles(
+    shape: &GlobalShape,
+    model: &Matrix4<f32>,
+    local_triangles: impl Iterator<Item = [Vector; 3]>,
+) -> Vec<ContactData> {
+    local_triangles
+        .flat_map(|triangle| {
+            let triangle = triangle.map(|v| (model * v.extend(1.)).truncate());
+            shape_triangle(shape, triangle)
+        })
+        .collect()
+}
+
+/// gjk contact with the triangle, if the contact is on the triangle's face then shapes with vertices use each penetrating vertex instead
+fn shape_triangle(shape: &GlobalShape, [a, b, c]: [Vector; 3]) -> Vec<ContactData> {
+    let Some(contact) = gjk::contact(shape, &GlobalShape::Triangle([a, b, c])) else {
+        return Vec::new();
+    };
+
+    let normal = (b - a).cross(c - a).normalize();
+    let alignment = normal.dot(contact.normal);
+    if alignment.abs() < TRIANGLE_FACE_ALIGN {
+        return vec![contact];
+    }
+    // normal into the triangle from the shape's side
+    let normal = alignment.signum() * normal;
+
+    let vertices: Vec<Vector> = match shape {
+        GlobalShape::Cuboid(model) => CUBE_VERTICES
+            .iter()
+            .map(|v| (model * v.extend(1.)).truncate())
+            .collect(),
+        GlobalShape::ConvexHull { model, hull } => hull
+            .vertices()
+            .iter()
+            .map(|v| (model * v.extend(1.)).truncate())
+            .collect(),
+        _ => return vec![contact],
+    };
+
+    let result: Vec<ContactData> = vertices
+        .into_iter()
+        .filter_map(|vertex| {
+            let depth = (vertex - a).dot(normal);
+            // ignore vertices past the triangle's other side
+            if depth <= 0. || depth > contact.penetration + f32::EPSILON {
+                return None;
+            }
+            let projected = vertex - depth * normal;
+            if barycentric(projected, a, b, c).iter().any(|w| *w < 0.) {
+                return None;
+            }
+
+            Some(ContactData {
+                position: vertex - (depth / 2.) * normal,
+                normal,
+                penetration: depth,
+                elements: (Surface, Surface),
+            })
+        })
+        .collect();
+
+    if result.is_empty() {
+        vec![contact]
+    } else {
+        result
+    }
+}
+
+/// seperating axis test followed by point-face and edge-edge contact generation
+fn cuboid_cuboid(model_1: &Matrix4<f32>, model_2: &Matrix4<f32>) -> Option<ContactData> {
+    // seperating axis
+    let dist_1_2 = (model_1.w - model_2.w).truncate(); // might need to normalise
+
+    let axes = [
+        model_1.x, model_1.y, model_1.z, model_2.x, model_2.y, model_2.z,
+    ]
+    .map(|v| v.truncate());
+    let cross_axes = (0..3).flat_map(|i1| (3..6).map(move |i2| axes[i1].cross(axes[i2])));
+
+    for axis in axes.into_iter().chain(cross_axes) {
+        let proj_1 = axes[0].dot(axis).abs() + axes[1].dot(axis).abs() + axes[2].dot(axis).abs();
+        let proj_2 = axes[3].dot(axis).abs() + axes[4].dot(axis).abs() + axes[5].dot(axis).abs();
+
+        if dist_1_2.dot(axis).abs() > proj_1 + proj_2 {
+            return None;
+        }
+    }
+
+    let inv_model_1 = model_1.invert().unwrap();
+    let inv_model_2 = model_2.invert().unwrap();
+    let axes_1_sqr = [
+        model_1.x.magnitude2(),
+        model_1.y.magnitude2(),
+        model_1.z.magnitude2(),
+    ];
+
+    let model_2_1 = inv_model_1 * model_2;
+    let model_1_2 = inv_model_2 * model_1;
+    let axes_2_sqr = [0, 1, 2].map(|i| model_2[i].magnitude2());
+
+    let unit_bounds = BoundingBox {
+        min: CUBE_VERTICES[0],
+        max: CUBE_VERTICES[7],
+    };
+
+    let mut max_pen_pf_sqr = 0.;
+    let mut contact_point_pf = [0., 0., 0.].into();
+    let mut pen_axis: u8 = 0;
+    let mut pf_elems = (Vertex(0), Face(0));
+    // p-f contacts
+    let points_2_1 = CUBE_VERTICES.map(|v| (model_2_1 * v.extend(1.)).truncate());
+    for p2_i in 0..4 {
+        // opposite corners a and b forming the cubiod diagonal
+        let diagonal_2_min_1 = points_2_1[p2_i];
+        let diagonal_2_max_1 = points_2_1[7 - p2_i];
+        // unnormalised ray
+        let ray = Ray {
+            origin: diagonal_2_min_1,
+            direction: diagonal_2_max_1 - diagonal_2_min_1,
+            distance: 1.,
+        };
+        let (close, far) = ray.box_intersection_raw(&unit_bounds);
+
+        // manual intersection check with distance 1
+        if close <= far && far >= 0. && close <= 1. {
+            let min_depth = far; // p2 min point pen into far face
+            let max_depth = 1. - close; // p2 max point pen into close face
+
+            // use smaller depth
+            if min_depth > max_depth {
+                let p1_1 = ray.calc_point(close);
+                let a1_i = ColliderElement::closest_face(p1_1);
+                let d_1 = 1. - diagonal_2_max_1[a1_i as usize].abs(); // should be +ve i swear
+                let depth_sqr = d_1 * d_1 * axes_1_sqr[a1_i as usize];
+
+                if depth_sqr > max_pen_pf_sqr {
+                    max_pen_pf_sqr = depth_sqr;
+                    contact_point_pf = diagonal_2_max_1;
+                    pen_axis = a1_i;
+                    pf_elems = (Face(a1_i), Vertex(7 - p2_i as u8));
+                    // use max index for p2
+                }
+            } else {
+                let p1_1 = ray.calc_point(far);
+                let a1_i = ColliderElement::closest_face(p1_1);
+                let d_1 = 1. - diagonal_2_min_1[a1_i as usize].abs(); // should be +ve i swear
+                let depth_sqr = d_1 * d_1 * axes_1_sqr[a1_i as usize];
+
+                if depth_sqr > max_pen_pf_sqr {
+                    max_pen_pf_sqr = depth_sqr;
+                    contact_point_pf = diagonal_2_min_1;
+                    pen_axis = a1_i;
+                    pf_elems = (Face(a1_i), Vertex(p2_i as u8));
+                }
+            }
+        }
+    }
+    // f-p contacts
+    let points_1_2 = CUBE_VERTICES.map(|v| (model_1_2 * v.extend(1.)).truncate());
+    for p1_i in 0..4 {
+        let p1_min_2 = points_1_2[p1_i];
+        let p1_max_2 = points_1_2[7 - p1_i];
+        let ray = Ray {
+            origin: p1_min_2,
+            direction: p1_max_2 - p1_min_2,
+            distance: 1.,
+        };
+        let (close, far) = ray.box_intersection_raw(&unit_bounds);
+
+        if close <= far && far >= 0. && close <= 1. {
+            let min_depth = far; // min point pen into far face
+            let max_depth = 1. - close; // max point pen into close face
+
+            // use smaller depth
+            if min_depth > max_depth {
+                let p2_2 = ray.calc_point(close);
+                let a2_i = ColliderElement::closest_face(p2_2);
+                let d_2 = 1. - p1_max_2[a2_i as usize].abs(); // should be +ve i swear
+                let depth_sqr = d_2 * d_2 * axes_2_sqr[a2_i as usize];
+
+                if depth_sqr > max_pen_pf_sqr {
+                    max_pen_pf_sqr = depth_sqr;
+                    contact_point_pf = CUBE_VERTICES[7 - p1_i];
+                    pen_axis = a2_i + 3;
+                    pf_elems = (Vertex(7 - p1_i as u8), Face(a2_i));
+                    // use max index for p2
+                }
+            } else {
+                let p2 = ray.calc_point(far);
+                let a2_i = ColliderElement::closest_face(p2);
+                let d_local = 1. - p1_min_2[a2_i as usize].abs(); // should be +ve i swear
+                let depth_sqr = d_local * d_local * axes_2_sqr[a2_i as usize];
+
+                if depth_sqr > max_pen_pf_sqr {
+                    max_pen_pf_sqr = depth_sqr;
+                    contact_point_pf = CUBE_VERTICES[p1_i];
+                    pen_axis = a2_i + 3;
+                    pf_elems = (Vertex(p1_i as u8), Face(a2_i));
+                }
+            }
+        }
+    }
+
+    // e-e contacts
+    let points_1 = [1, 2, 4, 7].map(|i| (model_1 * CUBE_VERTICES[i].extend(1.)).truncate());
+    let points_2 = [1, 2, 4, 7].map(|i| (model_2 * CUBE_VERTICES[i].extend(1.)).truncate());
+    let a2_a1_precalc = [model_1.x, model_1.y, model_1.z].map(|a1| {
+        let a1_inv = a1 / a1.magnitude2();
+        [model_2.x, model_2.y, model_2.z].map(|a2| {
+            let p2 = (a2 - a1.dot(a2) * a1_inv).truncate();
+            (
+                a2.truncate().cross(a1.truncate()).normalize(),
+                p2 / p2.magnitude2(),
+            )
+        })
+    });
+    let mut max_pen_ee_sqr = 0.;
+    let mut contact_point_ee = [0., 0., 0.].into();
+    let mut pen_axis_1 = 0;
+    let mut pen_axis_2 = 0;
+    let mut ee_elems = (Edge(0), Edge(0));
+    // for each unique axis point on 1
+    for (p1_i, p1) in points_1.iter().enumerate() {
+        for (p2_i, p2) in points_2.iter().enumerate() {
+            let rough_d_1_2 = p1 - p2;
+            for (e1_i, precalcs) in a2_a1_precalc.iter().enumerate() {
+                for (e2_i, (cross, proj_a2)) in precalcs.iter().enumerate() {
+                    let d_1_2_mag = cross.dot(rough_d_1_2);
+                    let d_1_2 = d_1_2_mag * cross;
+
+                    // check if new pen is larger than current max pen
+                    if d_1_2_mag * d_1_2_mag <= max_pen_ee_sqr {
+                        continue;
+                    }
+                    // check if closest on 2 is closer than closest on 1 to box 2 (is penetrating)
+                    let closest_2 = p2 + proj_a2.dot(rough_d_1_2) * model_2[e2_i].truncate();
+                    let closest_1 = closest_2 + d_1_2;
+                    if (closest_2 - model_2.w.truncate()).magnitude2()
+                        <= (closest_1 - model_2.w.truncate()).magnitude2()
+                    {
+                        continue;
+                    }
+                    // check if closest line points are outside the segment comprising their box edge
+                    if (closest_2 - model_2.w.truncate()).magnitude2()
+                        > (p2 - model_2.w.truncate()).magnitude2()
+                        || (closest_1 - model_1.w.truncate()).magnitude2()
+                            > (p1 - model_1.w.truncate()).magnitude2()
+                    {
+                        continue;
+                    }
+                    // ensure closest point on 2 is inside box 1
+                    let t21 = inv_model_1 * closest_2.extend(1.);
+                    if t21.x.abs() > 1. || t21.y.abs() > 1. || t21.z.abs() > 1. {
+                        continue;
+                    }
+                    // ensure closest points are in the same quadrant of box 2 (prevents "penetration" of opposite edges)
+                    let t11 = inv_model_1 * closest_1.extend(1.);
+                    if t21.x.signum() != t11.x.signum()
+                        || t21.y.signum() != t11.y.signum()
+                        || t21.z.signum() != t11.z.signum()
+                    {
+                        continue;
+                    }
+
+                    max_pen_ee_sqr = d_1_2_mag * d_1_2_mag;
+                    contact_point_ee = closest_2;
+                    pen_axis_1 = e1_i;
+                    pen_axis_2 = e2_i + 3;
+                    ee_elems = (
+                        ColliderElement::from_vertex_axis(p1_i as u8, e1_i as u8),
+                        ColliderElement::from_vertex_axis(p2_i as u8, e2_i as u8),
+                    )
+                }
+            }
+        }
+    }
+
+    // compare p-f and e-e contacts
+    if max_pen_pf_sqr == 0. && max_pen_ee_sqr == 0.0 {
+        return None;
+    }
+    let (position, normal, penetration, elements) = if max_pen_pf_sqr >= max_pen_ee_sqr {
+        let point = model_1 * contact_point_pf.extend(1.);
+        (
+            point.truncate(),
+            axes[pen_axis as usize].normalize(),
+            max_pen_pf_sqr.sqrt(),
+            pf_elems,
+        )
+    } else {
+        (
+            contact_point_ee,
+            axes[pen_axis_1].cross(axes[pen_axis_2]).normalize(),
+            max_pen_ee_sqr.sqrt(),
+            ee_elems,
+        )
+    };
+
+    // normal points away from 1 (assuming convex shape)
+    let normal = (position - model_1.w.truncate()).dot(normal).signum() * normal;
+
+    Some(ContactData {
+        position,
+        normal,
+        penetration,
+        elements,
+    })
+}
+
+#[cfg(test)]
+mod narrow_phase_tests {
+    use super::{contacts, GlobalShape, CUBE_VERTICES};
+    use crate::physics::{
+        collider::{HeightField, TriangleMesh},
+        ConvexHull, Vector,
+    };
+    use cgmath::{InnerSpace, Matrix4};
+
+    #[test]
+    fn sphere_on_plane() {
+        let sphere = GlobalShape::Sphere {
+            centre: (0., 0.9, 0.).into(),
+            radius: 1.,
+        };
+        let plane = GlobalShape::Plane {
+            point: (0., 0., 0.).into(),
+            normal: Vector::unit_y(),
+        };
+
+        let result = contacts(&sphere, &plane);
+        assert_eq!(result.len(), 1);
+        assert!((result[0].penetration - 0.1).abs() < 1e-5);
+        assert!((result[0].normal + Vector::unit_y()).magnitude() < 1e-5);
+
+        // swapped order flips normal
+        let result = contacts(&plane, &sphere);
+        assert!((result[0].normal - Vector::unit_y()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn sphere_cuboid_inside_and_outside() {
+        let cuboid = GlobalShape::Cuboid(Matrix4::from_nonuniform_scale(2., 1., 2.));
+        let touching = GlobalShape::Sphere {
+            centre: (0., 1.5, 0.).into(),
+            radius: 0.6,
+        };
+        let separate = GlobalShape::Sphere {
+            centre: (3., 0., 0.).into(),
+            radius: 0.5,
+        };
+        let inside = GlobalShape::Sphere {
+            centre: (0., 0.5, 0.).into(),
+            radius: 0.1,
+        };
+
+        let result = contacts(&touching, &cuboid);
+        assert_eq!(result.len(), 1);
+        assert!((result[0].penetration - 0.1).abs() < 1e-5);
+        assert!((result[0].normal + Vector::unit_y()).magnitude() < 1e-5);
+
+        assert!(contacts(&separate, &cuboid).is_empty());
+
+        let result = contacts(&inside, &cuboid);
+        assert!((result[0].penetration - 0.6).abs() < 1e-5);
+        assert!((result[0].normal + Vector::unit_y()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn capsule_across_cuboid() {
+        // lying across the top of a cube with both ends hanging off
+        let capsule = GlobalShape::Capsule {
+            start: (-3., 1.4, 0.).into(),
+            end: (3., 1.4, 0.).into(),
+            radius: 0.5,
+        };
+        let cuboid = GlobalShape::Cuboid(Matrix4::from_scale(1.));
+
+        let result = contacts(&capsule, &cuboid);
+        assert_eq!(result.len(), 1);
+        assert!((result[0].penetration - 0.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn every_shape_pair() {
+        let hull = ConvexHull::new(CUBE_VERTICES).unwrap();
+        let vertices = [(-5., 0., -5.), (-5., 0., 5.), (5., 0., 5.), (5., 0., -5.)];
+        let mesh = TriangleMesh::new(vertices, &[0, 1, 2, 0, 2, 3]);
+        let field = HeightField::new(3, 3, vec![0., 0., 0., 0., 1., 0., 0., 0., 0.]);
+        // all overlapping around the origin
+        let shapes = [
+            GlobalShape::Cuboid(Matrix4::from_translation((0., 0.5, 0.).into())),
+            GlobalShape::Sphere {
+                centre: (0.2, 0.5, 0.).into(),
+                radius: 1.,
+            },
+            GlobalShape::Capsule {
+                start: (-1., 0.5, 0.).into(),
+                end: (1., 0.5, 0.3).into(),
+                radius: 0.5,
+            },
+            GlobalShape::Plane {
+                point: (0., 0., 0.).into(),
+                normal: Vector::unit_y(),
+            },
+            GlobalShape::ConvexHull {
+                model: Matrix4::from_translation((0.3, 0.2, 0.).into()),
+                hull: &hull,
+            },
+            GlobalShape::TriangleMesh {
+                model: Matrix4::from_scale(1.),
+                mesh: &mesh,
+            },
+            GlobalShape::HeightField {
+                model: Matrix4::from_nonuniform_scale(5., 1., 5.),
+                field: &field,
+            },
+            GlobalShape::Triangle([
+                (-2., 0.2, -2.).into(),
+                (-2., 0.2, 2.).into(),
+                (2., 0.2, 0.).into(),
+            ]),
+        ];
+
+        // every combination returns instead of recursing or panicking
+        for shape_1 in shapes.iter() {
+            for shape_2 in shapes.iter() {
+                for contact in contacts(shape_1, shape_2) {
+                    assert!(contact.penetration.is_finite());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cuboid_on_triangle_mesh() {
+        // square split into 2 triangles facing up
+        let vertices = [(-5., 0., -5.), (-5., 0., 5.), (5., 0., 5.), (5., 0., -5.)];
+        let mesh = TriangleMesh::new(vertices, &[0, 1, 2, 0, 2, 3]);
+        let mesh_shape = GlobalShape::TriangleMesh {
+            model: Matrix4::from_scale(1.),
+            mesh: &mesh,
+        };
+        // no corners over the diagonal
+        let cuboid = GlobalShape::Cuboid(Matrix4::from_translation((2., 0.9, 0.5).into()));
+
+        let result = contacts(&cuboid, &mesh_shape);
+        assert_eq!(result.len(), 4);
+        for contact in result.iter() {
+            assert!((contact.penetration - 0.1).abs() < 1e-3);
+            assert!((contact.normal + Vector::unit_y()).magnitude() < 1e-3);
+        }
+
+        let above = GlobalShape::Cuboid(Matrix4::from_translation((2., 1.1, 0.5).into()));
+        assert!(contacts(&above, &mesh_shape).is_empty());
+    }
+
+    #[test]
+    fn sphere_on_heightfield() {
+        let field = HeightField::new(3, 3, vec![0., 0., 0., 0., 1., 0., 0., 0., 0.]);
+        let field_shape = GlobalShape::HeightField {
+            model: Matrix4::from_nonuniform_scale(10., 2., 10.),
+            field: &field,
+        };
+        // on the flat corner
+        let sphere = GlobalShape::Sphere {
+            centre: (-8., 0.9, -8.).into(),
+            radius: 1.,
+        };
+
+        let result = contacts(&field_shape, &sphere);
+        assert!(!result.is_empty());
+        for contact in result.iter() {
+            assert!((contact.penetration - 0.1).abs() < 1e-3);
+            assert!((contact.normal - Vector::unit_y()).magnitude() < 1e-3);
+        }
+
+        // above the peak
+        let sphere = GlobalShape::Sphere {
+            centre: (0., 3.1, 0.).into(),
+            radius: 1.,
+        };
+        assert!(contacts(&sphere, &field_shape).is_empty());
+    }
+}
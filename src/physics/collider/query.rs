@@ -0,0 +1,404 @@
+//! Scene queries answered from the bvh, all ignore triggers and colliders outside the mask
+
+use super::{
+    ccd, narrow_phase, ray::Ray, shape::GlobalShape, BoundingBox, Collider, ColliderSystem,
+};
+use crate::{game_objects::transform::TransformSystem, physics::Vector};
+use cgmath::{InnerSpace, Matrix4};
+use std::sync::Arc;
+
+/// Collider hit by a ray or sweep
+#[derive(Clone, Copy, Debug)]
+pub struct QueryHit<'a> {
+    pub collider: &'a Arc<Collider>,
+    /// distance travelled along the query before the hit, 0 if it started overlapping
+    pub distance: f32,
+    /// point on the surface of the collider that was hit
+    pub point: Vector,
+    /// normalised surface normal of the collider at the point, facing against the query
+    pub normal: Vector,
+}
+
+impl ColliderSystem {
+    fn query_filter(mask: u32) -> impl Fn(&Collider) -> bool {
+        move |collider| !collider.trigger && collider.layer & mask != 0
+    }
+
+    /// every collider on a layer in the mask along the ray, closest first
+    pub fn raycast_all(
+        &self,
+        transforms: &mut TransformSystem,
+        start: Vector,
+        direction: Vector,
+        distance: f32,
+        mask: u32,
+    ) -> Vec<QueryHit<'_>> {
+        let ray = Ray::new(start, direction, distance);
+        let mut hits: Vec<_> = self
+            .broad_phase
+            .raycast_all(&ray, transforms, &Self::query_filter(mask))
+            .into_iter()
+            .map(|(distance, collider)| {
+                let model = transforms.get_global_model(&collider.transform).unwrap();
+                let shape = collider.shape.to_global(&model);
+                let point = ray.calc_point(distance);
+                let mut normal = shape.normal(point);
+                if normal.dot(ray.direction) > 0. && two_sided(&shape) {
+                    normal = -normal;
+                }
+                QueryHit {
+                    collider,
+                    distance,
+                    point,
+                    normal,
+                }
+            })
+            .collect();
+        hits.sort_by(|hit_1, hit_2| hit_1.distance.total_cmp(&hit_2.distance));
+        hits
+    }
+
+    /// first collider on a layer in the mask hit by a sphere moving along a direction
+    pub fn sweep_sphere(
+        &self,
+        transforms: &mut TransformSystem,
+        centre: Vector,
+        radius: f32,
+        direction: Vector,
+        distance: f32,
+        mask: u32,
+    ) -> Option<QueryHit<'_>> {
+        let motion = direction.normalize() * distance;
+        self.sweep(transforms, motion, radius, mask, |t| GlobalShape::Sphere {
+            centre: centre + t * motion,
+            radius,
+        })
+    }
+
+    /// first collider on a layer in the mask hit by a box moving along a direction,
+    /// the box is the unit cube transformed by the model like cuboid colliders
+    pub fn sweep_box(
+        &self,
+        transforms: &mut TransformSystem,
+        model: Matrix4<f32>,
+        direction: Vector,
+        distance: f32,
+        mask: u32,
+    ) -> Option<QueryHit<'_>> {
+        let motion = direction.normalize() * distance;
+        let step = [model.x, model.y, model.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(f32::INFINITY, f32::min);
+        self.sweep(transforms, motion, step, mask, |t| {
+            GlobalShape::Cuboid(Matrix4::from_translation(t * motion) * model)
+        })
+    }
+
+    /// first collider on a layer in the mask hit by a capsule moving along a direction,
+    /// the capsule is the segment from start to end swept by the radius
+    #[allow(clippy::too_many_arguments)]
+    pub fn sweep_capsule(
+        &self,
+        transforms: &mut TransformSystem,
+        start: Vector,
+        end: Vector,
+        radius: f32,
+        direction: Vector,
+        distance: f32,
+        mask: u32,
+    ) -> Option<QueryHit<'_>> {
+        let motion = direction.normalize() * distance;
+        self.sweep(transforms, motion, radius, mask, |t| GlobalShape::Capsule {
+            start: start + t * motion,
+            end: end + t * motion,
+            radius,
+        })
+    }
+
+    /// shape_at gives the swept shape at a fraction of the motion,
+    /// step is the furthest it can move between samples without skipping over colliders
+    fn sweep<'a>(
+        &self,
+        transforms: &mut TransformSystem,
+        motion: Vector,
+        step: f32,
+        mask: u32,
+        shape_at: impl Fn(f32) -> GlobalShape<'a>,
+    ) -> Option<QueryHit<'_>> {
+        let bounds = shape_at(0.).bounds().join(shape_at(1.).bounds());
+        let candidates = self
+            .broad_phase
+            .query_bounds(bounds, &Self::query_filter(mask));
+
+        let mut closest: Option<(f32, &Arc<Collider>)> = None;
+        for collider in candidates {
+            let model = transforms.get_global_model(&collider.transform).unwrap();
+            let shape = collider.shape.to_global(&model);
+            let hits = |t: f32| !narrow_phase::contacts(&shape_at(t), &shape).is_empty();
+
+            let toi = if hits(0.) {
+                Some(0.)
+            } else {
+                ccd::first_hit(motion.magnitude(), step, hits)
+            };
+            if let Some(toi) = toi {
+                if closest.is_none_or(|(closest_toi, _)| toi < closest_toi) {
+                    closest = Some((toi, collider));
+                }
+            }
+        }
+
+        let (toi, collider) = closest?;
+        let model = transforms.get_global_model(&collider.transform).unwrap();
+        let shape = collider.shape.to_global(&model);
+        // deepest contact at the hit, its normal points from the swept shape into the collider
+        let contact = narrow_phase::contacts(&shape_at(toi), &shape)
+            .into_iter()
+            .max_by(|c_1, c_2| c_1.penetration.total_cmp(&c_2.penetration))?;
+        Some(QueryHit {
+            collider,
+            distance: toi * motion.magnitude(),
+            point: contact.position,
+            normal: -contact.normal,
+        })
+    }
+
+    /// colliders on a layer in the mask overlapping the box,
+    /// the box is the unit cube transformed by the model like cuboid colliders
+    pub fn overlap_box(
+        &self,
+        transforms: &mut TransformSystem,
+        model: Matrix4<f32>,
+        mask: u32,
+    ) -> Vec<&Arc<Collider>> {
+        self.overlap(transforms, GlobalShape::Cuboid(model), mask)
+    }
+
+    /// colliders on a layer in the mask within the radius of the centre
+    pub fn overlap_sphere(
+        &self,
+        transforms: &mut TransformSystem,
+        centre: Vector,
+        radius: f32,
+        mask: u32,
+    ) -> Vec<&Arc<Collider>> {
+        self.overlap(transforms, GlobalShape::Sphere { centre, radius }, mask)
+    }
+
+    /// colliders on a layer in the mask overlapping the capsule, as hits with the normal pointing out of the collider
+    ///
+    /// the hit distance is the depth of the deepest contact, how far the capsule must move along the normal to separate
+    pub fn capsule_penetrations(
+        &self,
+        transforms: &mut TransformSystem,
+        start: Vector,
+        end: Vector,
+        radius: f32,
+        mask: u32,
+    ) -> Vec<QueryHit<'_>> {
+        let shape = GlobalShape::Capsule { start, end, radius };
+        self.broad_phase
+            .query_bounds(shape.bounds(), &Self::query_filter(mask))
+            .into_iter()
+            .filter_map(|collider| {
+                let model = transforms.get_global_model(&collider.transform).unwrap();
+                let contact = narrow_phase::contacts(&shape, &collider.shape.to_global(&model))
+                    .into_iter()
+                    .max_by(|c_1, c_2| c_1.penetration.total_cmp(&c_2.penetration))?;
+                Some(QueryHit {
+                    collider,
+                    distance: contact.penetration,
+                    point: contact.position,
+                    normal: -contact.normal,
+                })
+            })
+            .collect()
+    }
+
+    fn overlap(
+        &self,
+        transforms: &mut TransformSystem,
+        shape: GlobalShape,
+        mask: u32,
+    ) -> Vec<&Arc<Collider>> {
+        self.broad_phase
+            .query_bounds(shape.bounds(), &Self::query_filter(mask))
+            .into_iter()
+            .filter(|collider| {
+                let model = transforms.get_global_model(&collider.transform).unwrap();
+                !narrow_phase::contacts(&shape, &collider.shape.to_global(&model)).is_empty()
+            })
+            .collect()
+    }
+
+    /// closest point on a collider on a layer in the mask within max_distance of the point,
+    /// the point itself if it is inside a collider
+    pub fn closest_point(
+        &self,
+        transforms: &mut TransformSystem,
+        point: Vector,
+        max_distance: f32,
+        mask: u32,
+    ) -> Option<(Vector, &Arc<Collider>)> {
+        let extents = Vector::new(max_distance, max_distance, max_distance);
+        let bounds = BoundingBox::new(point - extents, point + extents);
+        self.broad_phase
+            .query_bounds(bounds, &Self::query_filter(mask))
+            .into_iter()
+            .filter_map(|collider| {
+                let model = transforms.get_global_model(&collider.transform).unwrap();
+                let closest = collider
+                    .shape
+                    .to_global(&model)
+                    .closest_point(point, max_distance)?;
+                Some((closest, collider))
+            })
+            .min_by(|(p_1, _), (p_2, _)| {
+                (p_1 - point)
+                    .magnitude2()
+                    .total_cmp(&(p_2 - point).magnitude2())
+            })
+    }
+}
+
+/// triangles have no inside, so either side can be hit
+fn two_sided(shape: &GlobalShape) -> bool {
+    matches!(
+        shape,
+        GlobalShape::TriangleMesh { .. }
+            | GlobalShape::HeightField { .. }
+            | GlobalShape::Triangle(_)
+    )
+}
+
+#[cfg(test)]
+mod query_tests {
+    use crate::{
+        game_objects::transform::{TransformCreateInfo, TransformSystem},
+        physics::{Collider, ColliderSystem, Vector},
+    };
+    use cgmath::{InnerSpace, Matrix4};
+
+    /// floor with its top at y = 0 and 2 spheres above it along x
+    fn scene() -> (TransformSystem, ColliderSystem) {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        let floor = transforms
+            .add_transform(TransformCreateInfo::from([0., -1., 0.]).with_scale([10., 1., 10.]));
+        colliders.add(Collider::cuboid(floor, None), &mut transforms);
+        for x in [2., 5.] {
+            let sphere = transforms.add_transform([x, 1., 0.]);
+            colliders.add(Collider::sphere(sphere, None, 0.5), &mut transforms);
+        }
+        (transforms, colliders)
+    }
+
+    fn assert_near(a: Vector, b: Vector) {
+        assert!((a - b).magnitude() < 0.05, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn raycast_all_sorted() {
+        let (mut transforms, colliders) = scene();
+        let hits = colliders.raycast_all(
+            &mut transforms,
+            (0., 1., 0.).into(),
+            (1., 0., 0.).into(),
+            10.,
+            u32::MAX,
+        );
+        let distances: Vec<_> = hits.iter().map(|hit| hit.distance).collect();
+        assert_eq!(hits.len(), 2, "{:?}", distances);
+        assert!((distances[0] - 1.5).abs() < 1e-4 && (distances[1] - 4.5).abs() < 1e-4);
+        assert_near(hits[0].normal, (-1., 0., 0.).into());
+
+        let hits = colliders.raycast_all(
+            &mut transforms,
+            (7., 3., 0.).into(),
+            (0., -1., 0.).into(),
+            10.,
+            u32::MAX,
+        );
+        assert_eq!(hits.len(), 1);
+        assert_near(hits[0].point, (7., 0., 0.).into());
+        assert_near(hits[0].normal, (0., 1., 0.).into());
+    }
+
+    #[test]
+    fn sweeps() {
+        let (mut transforms, colliders) = scene();
+        let hit = colliders
+            .sweep_sphere(
+                &mut transforms,
+                (0., 1., 0.).into(),
+                0.5,
+                (1., 0., 0.).into(),
+                10.,
+                u32::MAX,
+            )
+            .unwrap();
+        assert!(
+            (hit.distance - 1.).abs() < 0.05,
+            "distance: {}",
+            hit.distance
+        );
+        assert_near(hit.point, (1.5, 1., 0.).into());
+        assert_near(hit.normal, (-1., 0., 0.).into());
+
+        // box dropped next to the spheres lands on the floor
+        let model = Matrix4::from_translation((8., 4., 0.).into()) * Matrix4::from_scale(0.5);
+        let hit = colliders
+            .sweep_box(&mut transforms, model, (0., -1., 0.).into(), 10., u32::MAX)
+            .unwrap();
+        assert!(
+            (hit.distance - 3.5).abs() < 0.05,
+            "distance: {}",
+            hit.distance
+        );
+        assert_near(hit.normal, (0., 1., 0.).into());
+
+        // starting overlapped
+        let hit = colliders
+            .sweep_sphere(
+                &mut transforms,
+                (2., 1., 0.).into(),
+                0.1,
+                (0., 1., 0.).into(),
+                1.,
+                u32::MAX,
+            )
+            .unwrap();
+        assert_eq!(hit.distance, 0.);
+    }
+
+    #[test]
+    fn overlaps_and_closest_point() {
+        let (mut transforms, colliders) = scene();
+        let overlaps =
+            colliders.overlap_sphere(&mut transforms, (3.5, 1.3, 0.).into(), 1.2, u32::MAX);
+        assert_eq!(overlaps.len(), 2);
+        let overlaps =
+            colliders.overlap_sphere(&mut transforms, (3.5, 2., 0.).into(), 1.2, u32::MAX);
+        assert!(overlaps.is_empty());
+        let model = Matrix4::from_translation((2., 0.5, 0.).into()) * Matrix4::from_scale(0.6);
+        assert_eq!(
+            colliders
+                .overlap_box(&mut transforms, model, u32::MAX)
+                .len(),
+            2
+        );
+
+        let (point, _) = colliders
+            .closest_point(&mut transforms, (-12., 3., 0.).into(), 10., u32::MAX)
+            .unwrap();
+        assert_near(point, (-10., 0., 0.).into());
+        let (point, _) = colliders
+            .closest_point(&mut transforms, (5., 2.5, 0.).into(), 10., u32::MAX)
+            .unwrap();
+        assert_near(point, (5., 1.5, 0.).into());
+        assert!(colliders
+            .closest_point(&mut transforms, (0., 20., 0.).into(), 5., u32::MAX)
+            .is_none());
+    }
+}
@@ -119,6 +119,133 @@ impl Ray {
         }
     }
 
+    /// Gives distance to intercept point on sphere, 0 if ray starts inside the sphere
+    ///
+    /// assumes ray direction is normalised
+    pub fn sphere_intersection(&self, centre: Vector, radius: f32) -> Option<f32> {
+        let offset = self.origin - centre;
+        let c = offset.magnitude2() - radius * radius;
+        if c <= 0. {
+            // ray origin in sphere
+            return Some(0.);
+        }
+
+        let b = offset.dot(self.direction);
+        let discriminant = b * b - c;
+        if b > 0. || discriminant < 0. {
+            // pointing away or missing
+            return None;
+        }
+
+        let close = -b - discriminant.sqrt();
+        if close <= self.distance {
+            Some(close)
+        } else {
+            None
+        }
+    }
+
+    /// Gives distance to intercept point on capsule, 0 if ray starts inside the capsule
+    ///
+    /// assumes ray direction is normalised
+    pub fn capsule_intersection(&self, start: Vector, end: Vector, radius: f32) -> Option<f32> {
+        let axis = end - start;
+        let length_sqr = axis.magnitude2();
+        if length_sqr.is_zero() {
+            return self.sphere_intersection(start, radius);
+        }
+
+        // infinite cylinder around the axis, clipped to the segment
+        let offset = self.origin - start;
+        let dir_perp = self.direction - (self.direction.dot(axis) / length_sqr) * axis;
+        let offset_perp = offset - (offset.dot(axis) / length_sqr) * axis;
+
+        let a = dir_perp.magnitude2();
+        let b = offset_perp.dot(dir_perp);
+        let c = offset_perp.magnitude2() - radius * radius;
+
+        let mut closest: Option<f32> = None;
+        if c <= 0. {
+            let t = offset.dot(axis) / length_sqr;
+            if (0. ..=1.).contains(&t) {
+                // ray origin in cylinder section
+                return Some(0.);
+            }
+        } else if !a.is_zero() {
+            let discriminant = b * b - a * c;
+            if discriminant >= 0. {
+                let close = (-b - discriminant.sqrt()) / a;
+                let t = (offset + close * self.direction).dot(axis) / length_sqr;
+                if close >= 0. && (0. ..=1.).contains(&t) {
+                    closest = Some(close);
+                }
+            }
+        }
+
+        // end caps
+        for centre in [start, end] {
+            if let Some(d) = self.sphere_intersection(centre, radius) {
+                closest = Some(closest.map_or(d, |c| c.min(d)));
+            }
+        }
+
+        closest.filter(|d| *d <= self.distance)
+    }
+
+    /// Gives distance to intercept point on plane, 0 if ray starts below the plane
+    pub fn plane_intersection(&self, point: Vector, normal: Vector) -> Option<f32> {
+        let height = (self.origin - point).dot(normal);
+        if height <= 0. {
+            // ray origin under plane
+            return Some(0.);
+        }
+
+        let speed = self.direction.dot(normal);
+        if speed >= 0. {
+            return None;
+        }
+
+        let distance = -height / speed;
+        if distance <= self.distance {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    /// Gives distance to intercept point on either side of a triangle
+    ///
+    /// see: Möller–Trumbore intersection algorithm
+    pub fn triangle_intersection(&self, a: Vector, b: Vector, c: Vector) -> Option<f32> {
+        let ab = b - a;
+        let ac = c - a;
+        let p = self.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < f32::EPSILON {
+            // parallel to triangle
+            return None;
+        }
+
+        let inv_det = 1. / det;
+        let offset = self.origin - a;
+        let u = offset.dot(p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = offset.cross(ab);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+
+        let distance = ac.dot(q) * inv_det;
+        if (0. ..=self.distance).contains(&distance) {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
     /// self.distance is unused here
     pub fn box_intersection_raw(&self, bounds: &BoundingBox) -> (f32, f32) {
         let mut closest = f32::NEG_INFINITY;
@@ -126,6 +253,10 @@ impl Ray {
 
         for i in 0..3 {
             if self.direction[i].is_zero() {
+                if self.origin[i] < bounds.min[i] || self.origin[i] > bounds.max[i] {
+                    // parallel to and outside this slab
+                    return (f32::INFINITY, f32::NEG_INFINITY);
+                }
                 continue;
             }
 
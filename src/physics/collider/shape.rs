@@ -0,0 +1,513 @@
+use super::{
+    convex_hull::ConvexHull, heightfield::HeightField, ray::Ray, triangle_mesh::TriangleMesh,
+    BoundingBox, Collider, CUBE_VERTICES,
+};
+use crate::physics::{matrix_truncate, quick_inverse, Vector};
+use cgmath::{InnerSpace, Matrix, Matrix4, SquareMatrix};
+use std::sync::Arc;
+
+/// Half size of the bounds given to planes in the bvh, planes are infinite so this just needs to cover the level
+const PLANE_BOUNDS_EXTENT: f32 = 1000.;
+/// distance around a surface point searched for the triangle it lies on
+const SURFACE_TOLERANCE: f32 = 1e-3;
+
+/// Shape of a collider in the local space of its transform
+#[derive(Clone, Debug)]
+pub enum ColliderShape {
+    /// cube with radius 1, scaled by its transform
+    Cuboid,
+    /// sphere around the transform origin, radius is scaled by the largest axis of the transform
+    Sphere { radius: f32 },
+    /// line segment along the local y-axis from -half_height to half_height swept by a sphere
+    ///
+    /// The segment is scaled by the transform, radius is scaled by the larger of the x and z axes
+    Capsule { half_height: f32, radius: f32 },
+    /// infinite plane through the transform origin with the local y-axis as its normal
+    ///
+    /// Everything below the plane is treated as solid, planes are meant to be static and should not have rigidbodies
+    Plane,
+    /// convex hull of a set of points, scaled by its transform
+    ConvexHull(Arc<ConvexHull>),
+    /// static triangle mesh, scaled by its transform, should not have a rigidbody
+    TriangleMesh(Arc<TriangleMesh>),
+    /// static grid of heights, scaled by its transform, should not have a rigidbody
+    HeightField(Arc<HeightField>),
+}
+
+/// Collider shape transformed into global space
+#[derive(Clone, Copy, Debug)]
+pub enum GlobalShape<'a> {
+    Cuboid(Matrix4<f32>),
+    Sphere {
+        centre: Vector,
+        radius: f32,
+    },
+    Capsule {
+        start: Vector,
+        end: Vector,
+        radius: f32,
+    },
+    Plane {
+        point: Vector,
+        normal: Vector,
+    },
+    ConvexHull {
+        model: Matrix4<f32>,
+        hull: &'a ConvexHull,
+    },
+    TriangleMesh {
+        model: Matrix4<f32>,
+        mesh: &'a TriangleMesh,
+    },
+    HeightField {
+        model: Matrix4<f32>,
+        field: &'a HeightField,
+    },
+    /// single triangle of a triangle mesh
+    Triangle([Vector; 3]),
+}
+
+impl ColliderShape {
+    pub fn to_global(&self, model: &Matrix4<f32>) -> GlobalShape<'_> {
+        match self {
+            ColliderShape::Cuboid => GlobalShape::Cuboid(*model),
+            &ColliderShape::Sphere { radius } => {
+                let scale = model
+                    .x
+                    .magnitude()
+                    .max(model.y.magnitude())
+                    .max(model.z.magnitude());
+                GlobalShape::Sphere {
+                    centre: model.w.truncate(),
+                    radius: radius * scale,
+                }
+            }
+            &ColliderShape::Capsule {
+                half_height,
+                radius,
+            } => {
+                let scale = model.x.magnitude().max(model.z.magnitude());
+                let half_axis = model.y.truncate() * half_height;
+                GlobalShape::Capsule {
+                    start: model.w.truncate() - half_axis,
+                    end: model.w.truncate() + half_axis,
+                    radius: radius * scale,
+                }
+            }
+            ColliderShape::Plane => GlobalShape::Plane {
+                point: model.w.truncate(),
+                // cross of the tangent axes so skewed models still give the right normal
+                normal: model.z.truncate().cross(model.x.truncate()).normalize(),
+            },
+            ColliderShape::ConvexHull(hull) => GlobalShape::ConvexHull {
+                model: *model,
+                hull,
+            },
+            ColliderShape::TriangleMesh(mesh) => GlobalShape::TriangleMesh {
+                model: *model,
+                mesh,
+            },
+            ColliderShape::HeightField(field) => GlobalShape::HeightField {
+                model: *model,
+                field,
+            },
+        }
+    }
+}
+
+impl GlobalShape<'_> {
+    pub fn bounds(&self) -> BoundingBox {
+        match self {
+            GlobalShape::Cuboid(model) => {
+                let vertices = CUBE_VERTICES.map(|v| {
+                    let v = model * v.extend(1.0);
+                    v.truncate() / v.w
+                });
+                BoundingBox::from_vertices(&vertices)
+            }
+            GlobalShape::Sphere { centre, radius } => {
+                let extents = Vector::new(*radius, *radius, *radius);
+                BoundingBox::new(centre - extents, centre + extents)
+            }
+            GlobalShape::Capsule { start, end, radius } => {
+                let extents = Vector::new(*radius, *radius, *radius);
+                BoundingBox::new(start - extents, start + extents)
+                    .join(BoundingBox::new(end - extents, end + extents))
+            }
+            GlobalShape::Plane { point, normal } => {
+                let mut min = point.map(|c| c - PLANE_BOUNDS_EXTENT);
+                let mut max = point.map(|c| c + PLANE_BOUNDS_EXTENT);
+                // axis aligned planes only extend to one side of their normal axis
+                for i in 0..3 {
+                    if normal[i] >= 1. - f32::EPSILON {
+                        max[i] = point[i];
+                    } else if normal[i] <= f32::EPSILON - 1. {
+                        min[i] = point[i];
+                    }
+                }
+                BoundingBox::new(min, max)
+            }
+            GlobalShape::ConvexHull { model, hull } => {
+                let vertices: Vec<_> = hull
+                    .vertices()
+                    .iter()
+                    .map(|v| (model * v.extend(1.)).truncate())
+                    .collect();
+                BoundingBox::from_vertices(&vertices)
+            }
+            GlobalShape::TriangleMesh { model, mesh } => transform_bounds(mesh.bounds(), model),
+            GlobalShape::HeightField { model, field } => transform_bounds(field.bounds(), model),
+            GlobalShape::Triangle(vertices) => BoundingBox::from_vertices(vertices),
+        }
+    }
+
+    /// distance along ray to the first intersection, returns 0 if the ray starts inside the shape
+    pub fn raycast(&self, ray: &Ray) -> Option<f32> {
+        match self {
+            GlobalShape::Cuboid(model) => {
+                let mut inv_model = *model;
+                quick_inverse(&mut inv_model);
+                ray.cuboid_intersection(&inv_model)
+            }
+            GlobalShape::Sphere { centre, radius } => ray.sphere_intersection(*centre, *radius),
+            GlobalShape::Capsule { start, end, radius } => {
+                ray.capsule_intersection(*start, *end, *radius)
+            }
+            GlobalShape::Plane { point, normal } => ray.plane_intersection(*point, *normal),
+            GlobalShape::ConvexHull { model, hull } => hull.raycast(ray, model),
+            GlobalShape::TriangleMesh { model, mesh } => mesh.raycast(ray, model),
+            GlobalShape::HeightField { model, field } => field.raycast(ray, model),
+            GlobalShape::Triangle([a, b, c]) => ray.triangle_intersection(*a, *b, *c),
+        }
+    }
+
+    /// furthest point of the shape in the given direction, used by gjk
+    ///
+    /// None for planes, triangle meshes and height fields as they have no support point
+    pub fn support(&self, direction: Vector) -> Option<Vector> {
+        let support = match self {
+            GlobalShape::Cuboid(model) => {
+                let local_dir = matrix_truncate(model).transpose() * direction;
+                let vertex = local_dir.map(|c| if c >= 0. { 1. } else { -1. });
+                (model * vertex.extend(1.)).truncate()
+            }
+            GlobalShape::Sphere { centre, radius } => centre + *radius * direction.normalize(),
+            GlobalShape::Capsule { start, end, radius } => {
+                let furthest = if start.dot(direction) > end.dot(direction) {
+                    start
+                } else {
+                    end
+                };
+                furthest + *radius * direction.normalize()
+            }
+            GlobalShape::Plane { .. }
+            | GlobalShape::TriangleMesh { .. }
+            | GlobalShape::HeightField { .. } => return None,
+            GlobalShape::ConvexHull { model, hull } => {
+                let local_dir = matrix_truncate(model).transpose() * direction;
+                (model * hull.support(local_dir).extend(1.)).truncate()
+            }
+            GlobalShape::Triangle(vertices) => *vertices
+                .iter()
+                .max_by(|a, b| a.dot(direction).total_cmp(&b.dot(direction)))
+                .unwrap(),
+        };
+        Some(support)
+    }
+
+    /// closest point on or in the shape, the point itself if it is inside,
+    /// None if the closest point is further than max_distance
+    ///
+    /// triangle meshes and height fields have no inside
+    pub fn closest_point(&self, point: Vector, max_distance: f32) -> Option<Vector> {
+        let closest = match self {
+            GlobalShape::Cuboid(model) => {
+                let centre = model.w.truncate();
+                let offset = point - centre;
+                [model.x, model.y, model.z]
+                    .into_iter()
+                    .fold(centre, |closest, axis| {
+                        let axis = axis.truncate();
+                        let extent = axis.magnitude();
+                        let direction = axis / extent;
+                        closest + offset.dot(direction).clamp(-extent, extent) * direction
+                    })
+            }
+            GlobalShape::Sphere { centre, radius } => {
+                closest_point_on_sphere(point, *centre, *radius)
+            }
+            GlobalShape::Capsule { start, end, radius } => {
+                let centre = closest_point_on_segment(point, *start, *end);
+                closest_point_on_sphere(point, centre, *radius)
+            }
+            GlobalShape::Plane {
+                point: plane_point,
+                normal,
+            } => {
+                let height = (point - plane_point).dot(*normal);
+                point - height.max(0.) * normal
+            }
+            GlobalShape::ConvexHull { model, hull } => {
+                let local_point = (model.invert().unwrap() * point.extend(1.)).truncate();
+                if hull.contains(local_point) {
+                    point
+                } else {
+                    closest_point_on_triangles(point, self.triangles(point, max_distance))?
+                }
+            }
+            GlobalShape::TriangleMesh { .. }
+            | GlobalShape::HeightField { .. }
+            | GlobalShape::Triangle(_) => {
+                closest_point_on_triangles(point, self.triangles(point, max_distance))?
+            }
+        };
+
+        if (closest - point).magnitude2() <= max_distance * max_distance {
+            Some(closest)
+        } else {
+            None
+        }
+    }
+
+    /// normalised outward normal at a point on the surface of the shape
+    ///
+    /// triangles of meshes and height fields are 2 sided, so their normals can face either way
+    pub fn normal(&self, point: Vector) -> Vector {
+        match self {
+            GlobalShape::Cuboid(model) => {
+                let mut inv_model = *model;
+                quick_inverse(&mut inv_model);
+                Collider::point_normal(point, &inv_model).normalize()
+            }
+            GlobalShape::Sphere { centre, .. } => (point - centre).normalize(),
+            GlobalShape::Capsule { start, end, .. } => {
+                (point - closest_point_on_segment(point, *start, *end)).normalize()
+            }
+            GlobalShape::Plane { normal, .. } => *normal,
+            GlobalShape::ConvexHull { .. }
+            | GlobalShape::TriangleMesh { .. }
+            | GlobalShape::HeightField { .. }
+            | GlobalShape::Triangle(_) => {
+                let closest =
+                    self.triangles(point, SURFACE_TOLERANCE)
+                        .into_iter()
+                        .min_by(|t_1, t_2| {
+                            let distance = |[a, b, c]: &[Vector; 3]| {
+                                (closest_point_on_triangle(point, *a, *b, *c) - point).magnitude2()
+                            };
+                            distance(t_1).total_cmp(&distance(t_2))
+                        });
+                match closest {
+                    Some([a, b, c]) => (b - a).cross(c - a).normalize(),
+                    None => (point - self.centre()).normalize(),
+                }
+            }
+        }
+    }
+
+    /// triangles in global space of shapes made of triangles, meshes and height fields only give
+    /// triangles within distance of the point
+    fn triangles(&self, point: Vector, distance: f32) -> Vec<[Vector; 3]> {
+        let to_global = |model: &Matrix4<f32>, triangle: [Vector; 3]| {
+            triangle.map(|v| (model * v.extend(1.)).truncate())
+        };
+        let local_bounds = |model: &Matrix4<f32>| {
+            let extents = Vector::new(distance, distance, distance);
+            let bounds = BoundingBox::new(point - extents, point + extents);
+            transform_bounds(bounds, &model.invert().unwrap())
+        };
+
+        match self {
+            GlobalShape::ConvexHull { model, hull } => hull
+                .faces()
+                .iter()
+                .map(|face| to_global(model, *face))
+                .collect(),
+            GlobalShape::TriangleMesh { model, mesh } => mesh
+                .query(local_bounds(model))
+                .into_iter()
+                .map(|i| to_global(model, mesh.triangle(i)))
+                .collect(),
+            GlobalShape::HeightField { model, field } => field
+                .query(local_bounds(model))
+                .into_iter()
+                .map(|i| to_global(model, field.triangle(i)))
+                .collect(),
+            GlobalShape::Triangle(triangle) => vec![*triangle],
+            _ => Vec::new(),
+        }
+    }
+
+    /// rough centre of the shape
+    pub fn centre(&self) -> Vector {
+        match self {
+            GlobalShape::Cuboid(model)
+            | GlobalShape::ConvexHull { model, .. }
+            | GlobalShape::TriangleMesh { model, .. }
+            | GlobalShape::HeightField { model, .. } => model.w.truncate(),
+            GlobalShape::Sphere { centre, .. } => *centre,
+            GlobalShape::Capsule { start, end, .. } => (start + end) / 2.,
+            GlobalShape::Plane { point, .. } => *point,
+            GlobalShape::Triangle([a, b, c]) => (a + b + c) / 3.,
+        }
+    }
+}
+
+/// bounds of the given bounds after being transformed by model
+pub fn transform_bounds(bounds: BoundingBox, model: &Matrix4<f32>) -> BoundingBox {
+    let centre = bounds.centre();
+    let extents = bounds.extents();
+    let corners = CUBE_VERTICES.map(|v| {
+        let corner = centre + Vector::new(v.x * extents.x, v.y * extents.y, v.z * extents.z);
+        (model * corner.extend(1.)).truncate()
+    });
+    BoundingBox::from_vertices(&corners)
+}
+
+/// see: Real-Time Collision Detection (Ericson), 3.4
+pub fn barycentric(point: Vector, a: Vector, b: Vector, c: Vector) -> [f32; 3] {
+    let v0 = b - a;
+    let v1 = c - a;
+    let v2 = point - a;
+    let d00 = v0.dot(v0);
+    let d01 = v0.dot(v1);
+    let d11 = v1.dot(v1);
+    let d20 = v2.dot(v0);
+    let d21 = v2.dot(v1);
+    let denom = d00 * d11 - d01 * d01;
+    if denom.abs() < f32::EPSILON {
+        return [1., 0., 0.];
+    }
+
+    let v = (d11 * d20 - d01 * d21) / denom;
+    let w = (d00 * d21 - d01 * d20) / denom;
+    [1. - v - w, v, w]
+}
+
+/// closest point to `point` on the line segment from `start` to `end`
+pub fn closest_point_on_segment(point: Vector, start: Vector, end: Vector) -> Vector {
+    let axis = end - start;
+    let length_sqr = axis.magnitude2();
+    if length_sqr <= f32::EPSILON {
+        return start;
+    }
+
+    let t = ((point - start).dot(axis) / length_sqr).clamp(0., 1.);
+    start + t * axis
+}
+
+fn closest_point_on_sphere(point: Vector, centre: Vector, radius: f32) -> Vector {
+    let offset = point - centre;
+    if offset.magnitude2() <= radius * radius {
+        point
+    } else {
+        centre + radius * offset.normalize()
+    }
+}
+
+fn closest_point_on_triangles(
+    point: Vector,
+    triangles: impl IntoIterator<Item = [Vector; 3]>,
+) -> Option<Vector> {
+    triangles
+        .into_iter()
+        .map(|[a, b, c]| closest_point_on_triangle(point, a, b, c))
+        .min_by(|p_1, p_2| {
+            (p_1 - point)
+                .magnitude2()
+                .total_cmp(&(p_2 - point).magnitude2())
+        })
+}
+
+/// closest point to `point` on the triangle abc
+///
+/// see: Real-Time Collision Detection (Ericson), 5.1.5
+pub fn closest_point_on_triangle(point: Vector, a: Vector, b: Vector, c: Vector) -> Vector {
+    let ab = b - a;
+    let ac = c - a;
+
+    // vertex regions
+    let ap = point - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0. && d2 <= 0. {
+        return a;
+    }
+    let bp = point - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0. && d4 <= d3 {
+        return b;
+    }
+    let cp = point - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0. && d5 <= d6 {
+        return c;
+    }
+
+    // edge regions
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0. && d1 >= 0. && d3 <= 0. {
+        return a + d1 / (d1 - d3) * ab;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0. && d2 >= 0. && d6 <= 0. {
+        return a + d2 / (d2 - d6) * ac;
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0. && d4 - d3 >= 0. && d5 - d6 >= 0. {
+        return b + (d4 - d3) / ((d4 - d3) + (d5 - d6)) * (c - b);
+    }
+
+    // face region
+    let denom = 1. / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+/// closest pair of points between 2 line segments, first point is on segment 1
+///
+/// see: Real-Time Collision Detection (Ericson), 5.1.9
+pub fn closest_points_between_segments(
+    start_1: Vector,
+    end_1: Vector,
+    start_2: Vector,
+    end_2: Vector,
+) -> (Vector, Vector) {
+    let axis_1 = end_1 - start_1;
+    let axis_2 = end_2 - start_2;
+    let offset = start_1 - start_2;
+    let length_sqr_1 = axis_1.magnitude2();
+    let length_sqr_2 = axis_2.magnitude2();
+    let f = axis_2.dot(offset);
+
+    // degenerate segments
+    if length_sqr_1 <= f32::EPSILON {
+        return (start_1, closest_point_on_segment(start_1, start_2, end_2));
+    }
+    let c = axis_1.dot(offset);
+    if length_sqr_2 <= f32::EPSILON {
+        return (closest_point_on_segment(start_2, start_1, end_1), start_2);
+    }
+
+    let b = axis_1.dot(axis_2);
+    let denom = length_sqr_1 * length_sqr_2 - b * b;
+
+    // pick arbitrary s for parallel segments
+    let mut s = if denom > f32::EPSILON {
+        ((b * f - c * length_sqr_2) / denom).clamp(0., 1.)
+    } else {
+        0.
+    };
+    let mut t = (b * s + f) / length_sqr_2;
+
+    if t < 0. {
+        t = 0.;
+        s = (-c / length_sqr_1).clamp(0., 1.);
+    } else if t > 1. {
+        t = 1.;
+        s = ((b - c) / length_sqr_1).clamp(0., 1.);
+    }
+
+    (start_1 + s * axis_1, start_2 + t * axis_2)
+}
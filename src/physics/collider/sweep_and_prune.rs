@@ -0,0 +1,358 @@
+use super::{broad_phase::ColliderGuard, ray::Ray, BoundingBox, Collider, FAT_MARGIN};
+use crate::{game_objects::transform::TransformSystem, physics::Vector};
+use cgmath::{ElementWise, Zero};
+use std::sync::Arc;
+
+/// sort and sweep broad phase, proxies are kept sorted by the minimum of their bounds along one axis
+///
+/// the order barely changes between frames so moved proxies are shifted into place incrementally,
+/// suited for scenes spread along a plane where a tree's bounds overlap a lot
+#[derive(Clone, Default)]
+pub struct SweepAndPrune {
+    proxies: Vec<Proxy>,
+    /// unused proxy slots
+    free: Vec<usize>,
+    /// indices of used proxies, sorted by the minimum of their bounds along the axis
+    order: Vec<usize>,
+    axis: usize,
+    /// proxies modified through a guard, to be refit
+    modified: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct Proxy {
+    /// enlarged by the fat margin
+    bounds: BoundingBox,
+    collider: Option<Arc<Collider>>,
+    /// incremented when the slot is freed, so old handles can't be used with its next proxy
+    generation: u32,
+    /// index in the sorted order
+    position: usize,
+}
+
+/// reference to a proxy in the sweep and prune broad phase, does nothing once the proxy is removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SweepHandle {
+    index: usize,
+    generation: u32,
+}
+
+impl SweepAndPrune {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add collider, the bounds are enlarged by the fat margin
+    pub fn insert(&mut self, bounds: BoundingBox, collider: Arc<Collider>) -> SweepHandle {
+        let handle = self.allocate(bounds, collider);
+
+        // insert into sorted position
+        let min = self.proxies[handle.index].bounds.min[self.axis];
+        let position = self
+            .order
+            .partition_point(|i| self.proxies[*i].bounds.min[self.axis] <= min);
+        self.order.insert(position, handle.index);
+        self.update_positions(position..self.order.len());
+        handle
+    }
+
+    /// add many colliders at once, resorting along the best axis afterwards
+    ///
+    /// the returned handles are in the same order as the given colliders
+    pub fn insert_many(
+        &mut self,
+        colliders: impl IntoIterator<Item = (BoundingBox, Arc<Collider>)>,
+    ) -> Vec<SweepHandle> {
+        let handles = colliders
+            .into_iter()
+            .map(|(bounds, collider)| {
+                let handle = self.allocate(bounds, collider);
+                self.order.push(handle.index);
+                handle
+            })
+            .collect();
+        self.rebuild();
+        handles
+    }
+
+    /// sort along the axis with the largest spread of colliders, existing handles stay valid
+    pub fn rebuild(&mut self) {
+        if !self.order.is_empty() {
+            let count = self.order.len() as f32;
+            let mean = self
+                .order
+                .iter()
+                .map(|i| self.proxies[*i].bounds.centre())
+                .fold(Vector::zero(), |sum, c| sum + c)
+                / count;
+            let variance = self
+                .order
+                .iter()
+                .map(|i| {
+                    let offset = self.proxies[*i].bounds.centre() - mean;
+                    offset.mul_element_wise(offset)
+                })
+                .fold(Vector::zero(), |sum, c| sum + c);
+
+            self.axis = if variance.x >= variance.y && variance.x >= variance.z {
+                0
+            } else if variance.y >= variance.z {
+                1
+            } else {
+                2
+            };
+        }
+
+        let (proxies, axis) = (&self.proxies, self.axis);
+        self.order
+            .sort_by(|a, b| proxies[*a].bounds.min[axis].total_cmp(&proxies[*b].bounds.min[axis]));
+        self.update_positions(0..self.order.len());
+    }
+
+    /// remove collider, gives back the handle if it isn't from this broad phase
+    pub fn remove(&mut self, handle: SweepHandle) -> Result<Arc<Collider>, SweepHandle> {
+        if !self.is_valid(&handle) {
+            return Err(handle);
+        }
+
+        let proxy = &mut self.proxies[handle.index];
+        let collider = proxy.collider.take().unwrap();
+        proxy.generation = proxy.generation.wrapping_add(1);
+        let position = proxy.position;
+
+        self.order.remove(position);
+        self.update_positions(position..self.order.len());
+        self.free.push(handle.index);
+        Ok(collider)
+    }
+
+    /// update the proxy if its collider moved out of its bounds, shifting it to its new place in the order
+    pub fn recalculate_bounds<F>(&mut self, handle: SweepHandle, calc_bounds: F) -> Result<(), ()>
+    where
+        F: FnOnce(&Collider) -> BoundingBox,
+    {
+        if !self.is_valid(&handle) {
+            return Err(());
+        }
+
+        let proxy = &self.proxies[handle.index];
+        let bounds = calc_bounds(proxy.collider.as_ref().unwrap());
+        if !proxy.bounds.contains(bounds) {
+            self.refit(handle.index, bounds);
+        }
+        Ok(())
+    }
+
+    /// access the proxy's collider, it will be refit by `refit_modified` after the guard is dropped
+    pub fn get_mut(&mut self, handle: SweepHandle) -> Option<ColliderGuard<'_>> {
+        if !self.is_valid(&handle) {
+            return None;
+        }
+        let collider = self.proxies[handle.index].collider.as_mut().unwrap();
+        Some(ColliderGuard::new(
+            collider,
+            &mut self.modified,
+            handle.index,
+        ))
+    }
+
+    /// refit every proxy modified through a guard, even if it's still inside its bounds
+    pub fn refit_modified<F>(&mut self, mut calc_bounds: F)
+    where
+        F: FnMut(&Collider) -> BoundingBox,
+    {
+        for index in std::mem::take(&mut self.modified) {
+            // proxy may have been removed since
+            if let Some(collider) = &self.proxies[index].collider {
+                let bounds = calc_bounds(collider);
+                self.refit(index, bounds);
+            }
+        }
+    }
+
+    /// overlapping proxies, only including pairs accepted by the filter, each pair is only given once
+    pub fn get_overlaps(
+        &self,
+        filter: &dyn Fn(&Collider, &Collider) -> bool,
+    ) -> Vec<(&Arc<Collider>, &Arc<Collider>)> {
+        let mut results = Vec::with_capacity(self.order.len());
+        for (position, index) in self.order.iter().enumerate() {
+            let proxy = &self.proxies[*index];
+            let max = proxy.bounds.max[self.axis];
+            let collider = proxy.collider.as_ref().unwrap();
+
+            // sweep until proxies start after this one ends
+            for other in self.order[position + 1..]
+                .iter()
+                .map(|i| &self.proxies[*i])
+                .take_while(|other| other.bounds.min[self.axis] <= max)
+            {
+                let other_collider = other.collider.as_ref().unwrap();
+                if proxy.bounds.check_overlap(other.bounds) && filter(collider, other_collider) {
+                    results.push((collider, other_collider));
+                }
+            }
+        }
+        results
+    }
+
+    /// closest collider accepted by the filter that the ray hits
+    pub fn raycast(
+        &self,
+        ray: &Ray,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Option<(f32, &Arc<Collider>)> {
+        let mut candidates: Vec<_> = self
+            .candidates(ray)
+            .filter_map(|(collider, bounds)| {
+                ray.box_intersection(&bounds)
+                    .map(|distance| (distance, collider))
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut closest: Option<(f32, &Arc<Collider>)> = None;
+        for (bounds_distance, collider) in candidates {
+            if closest.is_some_and(|(distance, _)| distance < bounds_distance) {
+                // remaining bounds are entered after the closest hit
+                break;
+            }
+            if let Some(distance) = Self::narrow_raycast(ray, collider, transforms, filter) {
+                if closest.is_none_or(|(closest, _)| distance < closest) {
+                    closest = Some((distance, collider));
+                }
+            }
+        }
+        closest
+    }
+
+    /// every collider accepted by the filter that the ray hits, unordered
+    pub fn raycast_all(
+        &self,
+        ray: &Ray,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Vec<(f32, &Arc<Collider>)> {
+        let candidates: Vec<_> = self.candidates(ray).collect();
+        candidates
+            .into_iter()
+            .filter(|(_, bounds)| ray.box_intersection(bounds).is_some())
+            .filter_map(|(collider, _)| {
+                Self::narrow_raycast(ray, collider, transforms, filter)
+                    .map(|distance| (distance, collider))
+            })
+            .collect()
+    }
+
+    /// colliders accepted by the filter with proxy bounds overlapping the bounds
+    pub fn query_bounds(
+        &self,
+        bounds: BoundingBox,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Vec<&Arc<Collider>> {
+        self.order
+            .iter()
+            .map(|i| &self.proxies[*i])
+            .take_while(|proxy| proxy.bounds.min[self.axis] <= bounds.max[self.axis])
+            .filter(|proxy| proxy.bounds.check_overlap(bounds))
+            .map(|proxy| proxy.collider.as_ref().unwrap())
+            .filter(|collider| filter(collider))
+            .collect()
+    }
+
+    /// bounds of every proxy in sorted order
+    pub fn iter(&self) -> impl Iterator<Item = BoundingBox> + '_ {
+        self.order.iter().map(|i| self.proxies[*i].bounds)
+    }
+
+    /// set the bounds of a proxy enlarged by the fat margin, insertion sorting it to its new place
+    fn refit(&mut self, index: usize, mut bounds: BoundingBox) {
+        bounds.grow(FAT_MARGIN);
+        let proxy = &mut self.proxies[index];
+        proxy.bounds = bounds;
+
+        let min = bounds.min[self.axis];
+        let old_position = proxy.position;
+        let mut position = old_position;
+        while position > 0 && self.min_at(position - 1) > min {
+            self.order.swap(position - 1, position);
+            position -= 1;
+        }
+        while position + 1 < self.order.len() && self.min_at(position + 1) < min {
+            self.order.swap(position, position + 1);
+            position += 1;
+        }
+        self.update_positions(position.min(old_position)..position.max(old_position) + 1);
+    }
+
+    fn allocate(&mut self, mut bounds: BoundingBox, collider: Arc<Collider>) -> SweepHandle {
+        bounds.grow(FAT_MARGIN);
+        match self.free.pop() {
+            Some(index) => {
+                let proxy = &mut self.proxies[index];
+                proxy.bounds = bounds;
+                proxy.collider = Some(collider);
+                SweepHandle {
+                    index,
+                    generation: proxy.generation,
+                }
+            }
+            None => {
+                self.proxies.push(Proxy {
+                    bounds,
+                    collider: Some(collider),
+                    generation: 0,
+                    position: 0,
+                });
+                SweepHandle {
+                    index: self.proxies.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    fn is_valid(&self, handle: &SweepHandle) -> bool {
+        self.proxies
+            .get(handle.index)
+            .is_some_and(|proxy| proxy.collider.is_some() && proxy.generation == handle.generation)
+    }
+
+    fn min_at(&self, position: usize) -> f32 {
+        self.proxies[self.order[position]].bounds.min[self.axis]
+    }
+
+    fn update_positions(&mut self, positions: std::ops::Range<usize>) {
+        for position in positions {
+            self.proxies[self.order[position]].position = position;
+        }
+    }
+
+    /// proxies that could be hit by the ray going by their extent along the axis
+    fn candidates<'a>(
+        &'a self,
+        ray: &Ray,
+    ) -> impl Iterator<Item = (&'a Arc<Collider>, BoundingBox)> + 'a {
+        let end = ray.calc_point(ray.distance)[self.axis];
+        let ray_max = ray.origin[self.axis].max(end);
+        self.order
+            .iter()
+            .map(|i| &self.proxies[*i])
+            .take_while(move |proxy| proxy.bounds.min[self.axis] <= ray_max)
+            .map(|proxy| (proxy.collider.as_ref().unwrap(), proxy.bounds))
+    }
+
+    fn narrow_raycast(
+        ray: &Ray,
+        collider: &Collider,
+        transforms: &mut TransformSystem,
+        filter: &dyn Fn(&Collider) -> bool,
+    ) -> Option<f32> {
+        if !filter(collider) {
+            return None;
+        }
+        let model = transforms.get_global_model(&collider.transform).unwrap();
+        collider.shape.to_global(&model).raycast(ray)
+    }
+}
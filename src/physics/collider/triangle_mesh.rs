@@ -0,0 +1,220 @@
+use super::{ray::Ray, BoundingBox};
+use crate::physics::Vector;
+use cgmath::{Matrix4, SquareMatrix};
+use std::fmt::Debug;
+
+/// max triangles stored in a leaf of the mesh bvh
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// Static triangle mesh in the local space of its collider, with its own bvh over the triangles
+///
+/// Triangles are one sided in the sense that their normal follows the anti-clockwise winding,
+/// but contacts are generated from either side
+pub struct TriangleMesh {
+    vertices: Vec<Vector>,
+    /// sorted to match the leaves of the bvh
+    triangles: Vec<[usize; 3]>,
+    /// flattened bvh, a branch's left child directly follows it
+    nodes: Vec<MeshNode>,
+}
+
+struct MeshNode {
+    bounds: BoundingBox,
+    content: MeshNodeContent,
+}
+
+enum MeshNodeContent {
+    /// range of triangles in the leaf
+    Leaf { start: usize, count: usize },
+    /// index of the right child
+    Branch { right: usize },
+}
+
+impl TriangleMesh {
+    /// Creates a mesh from vertex positions and triangle indices, leftover indices are ignored
+    ///
+    /// Panics if there are no triangles
+    pub fn new(vertices: impl IntoIterator<Item = impl Into<Vector>>, indices: &[u32]) -> Self {
+        let vertices: Vec<Vector> = vertices.into_iter().map(|v| v.into()).collect();
+        let mut triangles: Vec<[usize; 3]> = indices
+            .chunks_exact(3)
+            .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+            .collect();
+        assert!(!triangles.is_empty(), "Triangle mesh has no triangles");
+
+        let mut nodes = Vec::with_capacity(2 * triangles.len() / MAX_LEAF_TRIANGLES + 1);
+        build_node(&vertices, &mut triangles, 0, &mut nodes);
+
+        Self {
+            vertices,
+            triangles,
+            nodes,
+        }
+    }
+
+    /// local bounds of the whole mesh
+    pub fn bounds(&self) -> BoundingBox {
+        self.nodes[0].bounds
+    }
+
+    pub fn triangle(&self, index: usize) -> [Vector; 3] {
+        self.triangles[index].map(|i| self.vertices[i])
+    }
+
+    /// indices of triangles whose bounds overlap the local bounds
+    pub fn query(&self, bounds: BoundingBox) -> Vec<usize> {
+        let mut result = Vec::new();
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.check_overlap(bounds) {
+                continue;
+            }
+            match node.content {
+                MeshNodeContent::Leaf { start, count } => {
+                    result.extend((start..start + count).filter(|i| {
+                        BoundingBox::from_vertices(&self.triangle(*i)).check_overlap(bounds)
+                    }))
+                }
+                MeshNodeContent::Branch { right } => stack.extend([index + 1, right]),
+            }
+        }
+        result
+    }
+
+    /// distance along ray to the closest triangle of the mesh transformed by model
+    pub fn raycast(&self, ray: &Ray, model: &Matrix4<f32>) -> Option<f32> {
+        let inv_model = model.invert().unwrap();
+        // unnormalised local ray, distances stay the same
+        let mut local_ray = Ray {
+            origin: (inv_model * ray.origin.extend(1.)).truncate(),
+            direction: (inv_model * ray.direction.extend(0.)).truncate(),
+            distance: ray.distance,
+        };
+
+        let mut closest = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if local_ray.box_intersection(&node.bounds).is_none() {
+                continue;
+            }
+            match node.content {
+                MeshNodeContent::Leaf { start, count } => {
+                    for triangle in start..start + count {
+                        let [a, b, c] = self.triangle(triangle);
+                        if let Some(distance) = local_ray.triangle_intersection(a, b, c) {
+                            // only look for closer hits
+                            local_ray.distance = distance;
+                            closest = Some(distance);
+                        }
+                    }
+                }
+                MeshNodeContent::Branch { right } => stack.extend([index + 1, right]),
+            }
+        }
+        closest
+    }
+}
+impl Debug for TriangleMesh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "TriangleMesh({} triangles)",
+            self.triangles.len()
+        ))
+    }
+}
+
+/// recursively split triangles by the median of their centres along the longest axis
+fn build_node(
+    vertices: &[Vector],
+    triangles: &mut [[usize; 3]],
+    offset: usize,
+    nodes: &mut Vec<MeshNode>,
+) {
+    let bounds = BoundingBox::from_vertices(triangles.iter().flatten().map(|i| &vertices[*i]));
+    let index = nodes.len();
+
+    if triangles.len() <= MAX_LEAF_TRIANGLES {
+        nodes.push(MeshNode {
+            bounds,
+            content: MeshNodeContent::Leaf {
+                start: offset,
+                count: triangles.len(),
+            },
+        });
+        return;
+    }
+
+    let extents = bounds.extents();
+    let axis = if extents.x >= extents.y && extents.x >= extents.z {
+        0
+    } else if extents.y >= extents.z {
+        1
+    } else {
+        2
+    };
+    let centre =
+        |t: &[usize; 3]| vertices[t[0]][axis] + vertices[t[1]][axis] + vertices[t[2]][axis];
+    triangles.sort_unstable_by(|a, b| centre(a).total_cmp(&centre(b)));
+
+    // right index is set once the left side is built
+    nodes.push(MeshNode {
+        bounds,
+        content: MeshNodeContent::Branch { right: 0 },
+    });
+    let middle = triangles.len() / 2;
+    let (left, right) = triangles.split_at_mut(middle);
+    build_node(vertices, left, offset, nodes);
+    let right_index = nodes.len();
+    build_node(vertices, right, offset + middle, nodes);
+    nodes[index].content = MeshNodeContent::Branch { right: right_index };
+}
+
+#[cfg(test)]
+mod triangle_mesh_tests {
+    use super::TriangleMesh;
+    use crate::physics::collider::{ray::Ray, BoundingBox};
+    use cgmath::{Matrix4, SquareMatrix};
+
+    /// flat grid on the xz-plane of size x size squares
+    fn grid(size: u32) -> TriangleMesh {
+        let vertices = (0..=size).flat_map(|x| (0..=size).map(move |z| (x as f32, 0., z as f32)));
+        let indices: Vec<u32> = (0..size)
+            .flat_map(|x| (0..size).map(move |z| (x, z)))
+            .flat_map(|(x, z)| {
+                let i = x * (size + 1) + z;
+                [i, i + 1, i + size + 1, i + 1, i + size + 2, i + size + 1]
+            })
+            .collect();
+        TriangleMesh::new(vertices, &indices)
+    }
+
+    #[test]
+    fn query_grid() {
+        let mesh = grid(10);
+        let found = mesh.query(BoundingBox::new((2.2, -1., 2.2), (2.8, 1., 2.8)));
+
+        // both triangles of the square must be found
+        assert!(found.len() >= 2);
+        for t in found.iter() {
+            let [a, b, c] = mesh.triangle(*t);
+            let bounds = BoundingBox::from_vertices([&a, &b, &c]);
+            assert!(bounds.max.x >= 2.2 && bounds.min.x <= 2.8);
+        }
+    }
+
+    #[test]
+    fn raycast_grid() {
+        let mesh = grid(10);
+        let model = Matrix4::identity();
+
+        let down = Ray::new((3.3, 5., 7.1).into(), (0., -1., 0.).into(), 10.);
+        let short = Ray::new((3.3, 5., 7.1).into(), (0., -1., 0.).into(), 4.);
+        let outside = Ray::new((-3., 5., 7.1).into(), (0., -1., 0.).into(), 10.);
+
+        assert!((mesh.raycast(&down, &model).unwrap() - 5.).abs() < 1e-5);
+        assert!(mesh.raycast(&short, &model).is_none());
+        assert!(mesh.raycast(&outside, &model).is_none());
+    }
+}
@@ -1,25 +1,38 @@
-use super::{collider::ContactIdPair, geo_alg::bivec_exp, RigidBody, Vector};
+use super::{
+    collider::ContactIdPair, geo_alg::bivec_exp, material::ContactMaterial, RigidBody, Vector,
+};
 use crate::{game_objects::transform::TransformSystem, utilities::MaxHeap};
 use cgmath::{InnerSpace, Matrix3, One, SquareMatrix};
 use std::sync::{atomic::AtomicUsize, Arc, RwLock};
 
+mod sequential_impulse;
+
 const PEN_RESTITUTION: f32 = 1.; // useless for now
 const MIN_BOUNCE_VEL: f32 = 0.2; // time step dependent
 const MIN_CONTACT_VEL: f32 = 0.005; // time step dependent
 const ANGULAR_MOVE_LIMIT_RAD: f32 = 0.5;
 const MAX_CONTACT_AGE: u8 = 3;
 const VELOCITY_ITER_LIMIT: u32 = 500;
-const STATIC_FRICTION_COEFF: f32 = 2.;
-const DYNAMIC_FRICTION_COEFF: f32 = 3.;
 
 #[derive(PartialEq, Clone, Copy)]
 struct OrdF32(pub f32);
 
+/// Method used to resolve contacts
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ContactSolver {
+    /// one contact at a time, deepest penetration then fastest closing velocity first
+    #[default]
+    Heap,
+    /// all contacts together over a fixed number of iterations, warm started from cached contacts
+    SequentialImpulse,
+}
+
 pub struct ContactResolver {
     pending_contacts: MaxHeap<OrdF32, Contact>,
     settled_contacts: Vec<(Arc<AtomicUsize>, Contact)>,
 
     pub past_contacts: Vec<(Vector, Vector, u8, f32)>,
+    pub solver: ContactSolver,
 }
 
 pub struct Contact {
@@ -37,6 +50,10 @@ pub struct Contact {
     target_delta_velocity: Vector,
 
     contact_id: ContactIdPair,
+    /// combined materials of both colliders
+    material: ContactMaterial,
+    /// total impulse applied to rb_2 by the sequential impulse solver, cached for warm starting
+    impulse: Vector,
 
     age: u8,
 }
@@ -63,6 +80,7 @@ impl ContactResolver {
             pending_contacts: MaxHeap::new(),
             settled_contacts: Vec::new(),
             past_contacts: Vec::new(),
+            solver: ContactSolver::default(),
         }
     }
 
@@ -76,6 +94,15 @@ impl ContactResolver {
     }
 
     pub fn resolve(&mut self, transform_system: &mut TransformSystem, delta_seconds: f32) {
+        if self.solver == ContactSolver::SequentialImpulse {
+            while let Some(contact) = self.pending_contacts.extract_min() {
+                self.settled_contacts.push(contact);
+            }
+            sequential_impulse::solve(&mut self.settled_contacts, transform_system);
+            self.clear();
+            return;
+        }
+
         println!("-----Resolve Start-----");
         self.resolve_penetration(transform_system);
 
@@ -182,7 +209,7 @@ impl ContactResolver {
             let impulse_r2 = impulse_r * impulse_r;
             // if target tangent impulse > max static fric impulse then use dynamic fric instead
             let impulse = if impulse.magnitude2() - impulse_r2
-                > STATIC_FRICTION_COEFF * STATIC_FRICTION_COEFF * impulse_r2
+                > contact.material.static_friction * contact.material.static_friction * impulse_r2
             {
                 // required friction too high
 
@@ -192,7 +219,7 @@ impl ContactResolver {
 
                 let tangent_vel =
                     (contact.target_delta_velocity.magnitude2() - (normal_vel * normal_vel)).sqrt();
-                let coeff = DYNAMIC_FRICTION_COEFF * tangent_vel * delta_seconds;
+                let coeff = contact.material.dynamic_friction * tangent_vel * delta_seconds;
 
                 // get normal and tangent components of static impulse
                 let static_normal = impulse.dot(contact.normal);
@@ -214,7 +241,7 @@ impl ContactResolver {
                 // };
                 // let v_f = velocity_diff - velocity_diff.dot(contact.normal) * contact.normal;
                 // impulse_r * contact.normal
-                //     + v_f * DYNAMIC_FRICTION_COEFF * impulse_r.abs() * delta_seconds
+                //     + v_f * contact.material.dynamic_friction * impulse_r.abs() * delta_seconds
             } else {
                 impulse
             };
@@ -302,7 +329,7 @@ impl ContactResolver {
             let mut rb_1 = contact.rb_1.rigidbody.write().unwrap();
             if contact.age + 1 < MAX_CONTACT_AGE {
                 rb_1.past_contacts
-                    .push((contact.age + 1, contact.contact_id));
+                    .push((contact.age + 1, contact.contact_id, contact.impulse));
                 rb_1.caching_contacts = true;
             }
         }
@@ -317,7 +344,11 @@ impl Default for ContactResolver {
 impl Contact {
     /// create new contact, automatically adding itself to the respective rigidbodies' contact_refs
     ///
-    /// normal should be normalised but either pointing towards or away from 1 is fine.
+    /// normal should be normalised and point away from 1.
+    ///
+    /// impulse is only used by the sequential impulse solver,
+    /// new contacts (age 0) take it from the matching cached contact if there is one
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         transform_sys: &TransformSystem,
         position: Vector,
@@ -327,23 +358,13 @@ impl Contact {
         // rb_2: Option<Arc<RwLock<RigidBody>>>,
         contact_id: ContactIdPair,
         age: u8,
+        mut impulse: Vector,
     ) -> (Arc<AtomicUsize>, Self) {
-        let rb_1 = contact_id
-            .0
-            .collider
-            .upgrade()
-            .unwrap()
-            .get_rigidbody()
-            .as_ref()
-            .unwrap()
-            .clone();
-        let rb_2 = contact_id
-            .1
-            .collider
-            .upgrade()
-            .unwrap()
-            .get_rigidbody()
-            .clone();
+        let collider_1 = contact_id.0.collider.upgrade().unwrap();
+        let collider_2 = contact_id.1.collider.upgrade().unwrap();
+        let rb_1 = collider_1.get_rigidbody().as_ref().unwrap().clone();
+        let rb_2 = collider_2.get_rigidbody().clone();
+        let material = collider_1.get_material().combine(collider_2.get_material());
 
         let heap_index = Arc::new(AtomicUsize::new(usize::MAX));
 
@@ -358,9 +379,6 @@ impl Contact {
         let point_vel_1 = rb_guard_1.point_velocity(relative_pos);
         let old_vel_1 = rb_guard_1.old_velocity;
 
-        // normal points away from contact point 1 (assuming convex shape) TODO: generalise to any shape
-        let normal = relative_pos.dot(normal).signum() * normal;
-
         let linear_inertia = rb_guard_1.inv_mass;
         // n x r
         let torque_per_impulse = -normal.cross(relative_pos);
@@ -374,7 +392,9 @@ impl Contact {
         let index = rb_guard_1.contact_refs.len();
         rb_guard_1.contact_refs.push(heap_index.clone());
         if age == 0 {
-            rb_guard_1.remove_cached_contact(&contact_id);
+            if let Some(cached) = rb_guard_1.remove_cached_contact(&contact_id) {
+                impulse = cached;
+            }
         }
 
         let (total_inertia, target_delta_velocity, rb_2) = if let Some(rb_2) = rb_2 {
@@ -388,15 +408,6 @@ impl Contact {
             let point_vel_2 = rb_guard_2.point_velocity(relative_pos);
             let old_vel_2 = rb_guard_2.old_velocity;
 
-            // wake any rb if needed
-            if rb_guard_1.is_awake() {
-                if !rb_guard_2.is_awake() {
-                    rb_guard_2.wake();
-                }
-            } else if rb_guard_2.is_awake() {
-                rb_guard_1.wake();
-            }
-
             let inv_mass = rb_guard_2.inv_mass;
             // n x r
             let torque_per_impulse = normal.cross(relative_pos);
@@ -410,7 +421,9 @@ impl Contact {
             let index = rb_guard_2.contact_refs.len();
             rb_guard_2.contact_refs.push(heap_index.clone());
             if age == 0 {
-                rb_guard_2.remove_cached_contact(&contact_id);
+                if let Some(cached) = rb_guard_2.remove_cached_contact(&contact_id) {
+                    impulse = cached;
+                }
             }
             drop(rb_guard_2);
             let rb_2 = RigidBodyRef {
@@ -429,7 +442,7 @@ impl Contact {
             let restituition = if closing_velocity.dot(normal) < MIN_BOUNCE_VEL {
                 0.0
             } else {
-                0.5
+                material.restitution
             };
             // tdv(r) = v(r) + res * (v(r) - dv(r))
             //        = v(r) + res * ov(r)
@@ -451,7 +464,7 @@ impl Contact {
             let restituition = if point_vel_1.dot(normal) < MIN_BOUNCE_VEL {
                 0.0
             } else {
-                0.5
+                material.restitution
             };
             let old_normal_velocity = old_vel_1.dot(normal) * normal;
             // let delta_velocity = point_vel_1 - old_vel_1;
@@ -491,6 +504,8 @@ impl Contact {
                 target_delta_velocity,
 
                 contact_id,
+                material,
+                impulse,
                 age,
             },
         )
@@ -0,0 +1,451 @@
+//! Sequential impulse (projected Gauss-Seidel) contact solver
+//!
+//! Impulses are accumulated and clamped over all iterations instead of per contact,
+//! starting from the impulses cached with the contact ids last update.
+//! Penetration is removed afterwards with split impulses, which only move the transforms
+//! so they don't add energy to the velocities
+
+use super::{Contact, RigidBodyRef, MIN_BOUNCE_VEL};
+use crate::{
+    game_objects::transform::TransformSystem,
+    physics::{geo_alg::bivec_exp, RigidBody, Vector},
+    utilities::math::skew,
+};
+use cgmath::{InnerSpace, Matrix, Matrix3, One, SquareMatrix, Zero};
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicUsize, Arc, RwLock},
+};
+
+const VELOCITY_ITERS: u32 = 20;
+const POSITION_ITERS: u32 = 4;
+/// penetration left uncorrected so contacts are still found next update
+const PENETRATION_SLOP: f32 = 0.01;
+/// fraction of the remaining penetration removed each position iteration
+const SPLIT_IMPULSE_FACTOR: f32 = 0.5;
+/// max penetration removed by a single position iteration
+const MAX_CORRECTION: f32 = 0.2;
+
+/// state of a rigidbody shared between its contacts
+struct SolverBody {
+    rigidbody: Arc<RwLock<RigidBody>>,
+    velocity: Vector,
+    bivelocity: Vector,
+    /// velocity before the last update, without this update's acceleration
+    old_velocity: Vector,
+    inv_mass: f32,
+    /// global inverse moment of inertia, zero for infinite mass
+    inv_inertia: Matrix3<f32>,
+    /// movement from split impulses, applied to the transform after solving
+    translation: Vector,
+    rotation: Vector,
+}
+
+struct SolverContact {
+    body_1: usize,
+    body_2: Option<usize>,
+    relative_1: Vector,
+    relative_2: Vector,
+    /// points from body 1 to body 2
+    normal: Vector,
+    tangents: [Vector; 2],
+    /// impulse per unit of velocity change along the normal and tangents
+    normal_mass: f32,
+    tangent_masses: [f32; 2],
+    /// friction limit while the contact sticks
+    static_friction: f32,
+    /// friction limit while the contact slides, never above the static limit
+    dynamic_friction: f32,
+    /// min separating velocity, above zero to bounce
+    target_velocity: f32,
+    penetration: f32,
+
+    normal_impulse: f32,
+    tangent_impulses: [f32; 2],
+}
+
+/// resolve velocities then penetration of all contacts, storing the final impulses for warm starting
+pub fn solve(contacts: &mut [(Arc<AtomicUsize>, Contact)], transforms: &mut TransformSystem) {
+    let mut bodies: Vec<SolverBody> = Vec::new();
+    let mut body_indices = HashMap::new();
+    let mut solver_contacts: Vec<SolverContact> = Vec::new();
+    // solver contacts of each pair of bodies
+    let mut pair_contacts: HashMap<(usize, Option<usize>), Vec<usize>> = HashMap::new();
+    // narrow phase can find the same point more than once, these share a solver contact
+    // so the point isn't pushed out multiple times
+    let solver_indices: Vec<usize> = contacts
+        .iter()
+        .map(|(_, contact)| {
+            let body_1 = body_index(&mut bodies, &mut body_indices, &contact.rb_1);
+            let body_2 = contact
+                .rb_2
+                .as_ref()
+                .map(|rb_2| body_index(&mut bodies, &mut body_indices, rb_2));
+            let pair = pair_contacts.entry((body_1, body_2)).or_default();
+            let duplicate = pair.iter().copied().find(|other| {
+                (solver_contacts[*other].relative_1 - contact.rb_1.relative_pos).magnitude2()
+                    < f32::EPSILON
+            });
+            duplicate.unwrap_or_else(|| {
+                solver_contacts.push(SolverContact::new(contact, &bodies, body_1, body_2));
+                pair.push(solver_contacts.len() - 1);
+                solver_contacts.len() - 1
+            })
+        })
+        .collect();
+
+    // warm start
+    for contact in solver_contacts.iter() {
+        let impulse = contact.normal_impulse * contact.normal
+            + contact.tangent_impulses[0] * contact.tangents[0]
+            + contact.tangent_impulses[1] * contact.tangents[1];
+        contact.apply_impulse(&mut bodies, impulse);
+    }
+
+    for _ in 0..VELOCITY_ITERS {
+        for contact in solver_contacts.iter_mut() {
+            contact.solve_velocity(&mut bodies);
+        }
+    }
+
+    for _ in 0..POSITION_ITERS {
+        for contact in solver_contacts.iter() {
+            contact.solve_position(&mut bodies);
+        }
+    }
+
+    for ((_, contact), &index) in contacts.iter_mut().zip(solver_indices.iter()) {
+        let solved = &solver_contacts[index];
+        contact.impulse = solved.normal_impulse * solved.normal
+            + solved.tangent_impulses[0] * solved.tangents[0]
+            + solved.tangent_impulses[1] * solved.tangents[1];
+    }
+
+    for body in bodies {
+        let mut rigidbody = body.rigidbody.write().unwrap();
+        rigidbody.velocity = body.velocity;
+        rigidbody.bivelocity = body.bivelocity;
+
+        if body.translation.is_zero() && body.rotation.is_zero() {
+            continue;
+        }
+        transforms
+            .get_transform_mut(&rigidbody.transform)
+            .unwrap()
+            .mutate(|translation, rotation, _| {
+                *translation += body.translation;
+                *rotation = bivec_exp(body.rotation * 0.5).into_quaternion() * *rotation;
+            });
+    }
+}
+
+/// index of the rigidbody in bodies, adding it if it isn't there yet
+fn body_index(
+    bodies: &mut Vec<SolverBody>,
+    indices: &mut HashMap<*const RwLock<RigidBody>, usize>,
+    rb_ref: &RigidBodyRef,
+) -> usize {
+    let index = *indices
+        .entry(Arc::as_ptr(&rb_ref.rigidbody))
+        .or_insert(bodies.len());
+    if index < bodies.len() {
+        return index;
+    }
+
+    let guard = rb_ref.rigidbody.read().unwrap();
+    let inv_inertia = if guard.inv_mass.is_zero() {
+        Matrix3::zero()
+    } else {
+        let inv_moi = Matrix3::from_diagonal(guard.principle_moi.map(|c| 1. / c));
+        rb_ref.rotation * inv_moi * rb_ref.rotation.transpose()
+    };
+    bodies.push(SolverBody {
+        rigidbody: rb_ref.rigidbody.clone(),
+        velocity: guard.velocity,
+        bivelocity: guard.bivelocity,
+        old_velocity: guard.old_velocity,
+        inv_mass: guard.inv_mass,
+        inv_inertia,
+        translation: Vector::zero(),
+        rotation: Vector::zero(),
+    });
+    bodies.len() - 1
+}
+
+impl SolverBody {
+    /// change in velocity at the point per impulse
+    fn point_inertia(&self, relative_pos: Vector) -> Matrix3<f32> {
+        let skew = skew(relative_pos);
+        self.inv_mass * Matrix3::one() - skew * self.inv_inertia * skew
+    }
+
+    fn point_velocity(&self, relative_pos: Vector) -> Vector {
+        self.velocity + self.bivelocity.cross(relative_pos)
+    }
+}
+
+impl SolverContact {
+    fn new(contact: &Contact, bodies: &[SolverBody], body_1: usize, body_2: Option<usize>) -> Self {
+        let normal = contact.normal;
+        let relative_1 = contact.rb_1.relative_pos;
+        let relative_2 = contact
+            .rb_2
+            .as_ref()
+            .map_or(Vector::zero(), |rb_2| rb_2.relative_pos);
+
+        let mut inertia = bodies[body_1].point_inertia(relative_1);
+        if let Some(body_2) = body_2 {
+            inertia += bodies[body_2].point_inertia(relative_2);
+        }
+        let mass = |direction: Vector| direction.dot(inertia * direction).recip();
+
+        let tangent = if normal.x.abs() < 0.9 {
+            Vector::unit_x()
+        } else {
+            Vector::unit_y()
+        };
+        let tangent = tangent.cross(normal).normalize();
+        let tangents = [tangent, normal.cross(tangent)];
+
+        let mut solver_contact = Self {
+            body_1,
+            body_2,
+            relative_1,
+            relative_2,
+            normal,
+            tangents,
+            normal_mass: mass(normal),
+            tangent_masses: tangents.map(mass),
+            static_friction: contact.material.static_friction,
+            // used as a coulomb coefficient here, sliding never grips harder than sticking
+            dynamic_friction: contact
+                .material
+                .dynamic_friction
+                .min(contact.material.static_friction),
+            target_velocity: 0.,
+            penetration: contact.penetration,
+            normal_impulse: 0.,
+            tangent_impulses: [0.; 2],
+        };
+
+        // bounce off the velocity before warm starting, using only the old velocity
+        // so resting contacts don't bounce from gravity
+        let normal_velocity = normal.dot(solver_contact.relative_velocity(bodies));
+        if -normal_velocity > MIN_BOUNCE_VEL {
+            let old_velocity_2 = body_2.map_or(Vector::zero(), |b| bodies[b].old_velocity);
+            let old_normal_velocity = normal.dot(old_velocity_2 - bodies[body_1].old_velocity);
+            solver_contact.target_velocity =
+                -contact.material.restitution * old_normal_velocity.min(0.);
+        }
+
+        // cached impulse, projected onto the new basis and clamped
+        solver_contact.normal_impulse = normal.dot(contact.impulse).max(0.);
+        let max_friction = solver_contact.static_friction * solver_contact.normal_impulse;
+        solver_contact.tangent_impulses =
+            tangents.map(|t| t.dot(contact.impulse).clamp(-max_friction, max_friction));
+
+        solver_contact
+    }
+
+    /// velocity of the contact point on body 2 relative to body 1
+    fn relative_velocity(&self, bodies: &[SolverBody]) -> Vector {
+        let velocity_2 = self.body_2.map_or(Vector::zero(), |b| {
+            bodies[b].point_velocity(self.relative_2)
+        });
+        velocity_2 - bodies[self.body_1].point_velocity(self.relative_1)
+    }
+
+    /// apply impulse to body 2 and the opposite to body 1
+    fn apply_impulse(&self, bodies: &mut [SolverBody], impulse: Vector) {
+        let body_1 = &mut bodies[self.body_1];
+        body_1.velocity -= body_1.inv_mass * impulse;
+        body_1.bivelocity -= body_1.inv_inertia * self.relative_1.cross(impulse);
+        if let Some(body_2) = self.body_2 {
+            let body_2 = &mut bodies[body_2];
+            body_2.velocity += body_2.inv_mass * impulse;
+            body_2.bivelocity += body_2.inv_inertia * self.relative_2.cross(impulse);
+        }
+    }
+
+    fn solve_velocity(&mut self, bodies: &mut [SolverBody]) {
+        // friction, limited by the normal impulse from the last iteration, the static limit
+        // applies if it can stop the contact sliding and the dynamic one otherwise
+        let velocity = self.relative_velocity(bodies);
+        let stopping: [f32; 2] = std::array::from_fn(|i| {
+            self.tangent_impulses[i] - self.tangents[i].dot(velocity) * self.tangent_masses[i]
+        });
+        let static_max = self.static_friction * self.normal_impulse;
+        let max_friction = match stopping[0].hypot(stopping[1]) <= static_max {
+            true => static_max,
+            false => self.dynamic_friction * self.normal_impulse,
+        };
+        for i in 0..2 {
+            let speed = self.tangents[i].dot(self.relative_velocity(bodies));
+            let total = (self.tangent_impulses[i] - speed * self.tangent_masses[i])
+                .clamp(-max_friction, max_friction);
+            self.apply_impulse(
+                bodies,
+                (total - self.tangent_impulses[i]) * self.tangents[i],
+            );
+            self.tangent_impulses[i] = total;
+        }
+
+        // contacts can only push
+        let speed = self.normal.dot(self.relative_velocity(bodies));
+        let total =
+            (self.normal_impulse + (self.target_velocity - speed) * self.normal_mass).max(0.);
+        self.apply_impulse(bodies, (total - self.normal_impulse) * self.normal);
+        self.normal_impulse = total;
+    }
+
+    fn solve_position(&self, bodies: &mut [SolverBody]) {
+        // penetration after the movement from previous iterations
+        let body_1 = &bodies[self.body_1];
+        let mut separation =
+            -(body_1.translation + body_1.rotation.cross(self.relative_1)).dot(self.normal);
+        if let Some(body_2) = self.body_2 {
+            let body_2 = &bodies[body_2];
+            separation +=
+                (body_2.translation + body_2.rotation.cross(self.relative_2)).dot(self.normal);
+        }
+        let penetration = self.penetration - separation;
+
+        let correction =
+            (SPLIT_IMPULSE_FACTOR * (penetration - PENETRATION_SLOP)).clamp(0., MAX_CORRECTION);
+        if correction.is_zero() {
+            return;
+        }
+        let impulse = correction * self.normal_mass * self.normal;
+
+        let body_1 = &mut bodies[self.body_1];
+        body_1.translation -= body_1.inv_mass * impulse;
+        body_1.rotation -= body_1.inv_inertia * self.relative_1.cross(impulse);
+        if let Some(body_2) = self.body_2 {
+            let body_2 = &mut bodies[body_2];
+            body_2.translation += body_2.inv_mass * impulse;
+            body_2.rotation += body_2.inv_inertia * self.relative_2.cross(impulse);
+        }
+    }
+}
+
+#[cfg(test)]
+mod sequential_impulse_tests {
+    use crate::{
+        game_objects::transform::{TransformCreateInfo, TransformSystem},
+        physics::{Collider, ColliderSystem, ContactSolver, PhysicsMaterial, RigidBody},
+    };
+    use cgmath::{InnerSpace, Vector3};
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn stack_stays_upright() {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        colliders.set_contact_solver(ContactSolver::SequentialImpulse);
+
+        let floor = transforms
+            .add_transform(TransformCreateInfo::from([0., -0.5, 0.]).with_scale([5., 0.5, 5.]));
+        colliders.add(Collider::cuboid(floor, None), &mut transforms);
+
+        let mut boxes = Vec::new();
+        for i in 0..3 {
+            let transform = transforms.add_transform(
+                TransformCreateInfo::from([0., 0.5 + i as f32, 0.]).with_scale([0.5; 3]),
+            );
+            let mut rigidbody = RigidBody::new(transform);
+            rigidbody.set_moi_as_cuboid((1., 1., 1.).into());
+            let rigidbody = Arc::new(RwLock::new(rigidbody));
+            let leaf = colliders.add(
+                Collider::cuboid(transform, Some(rigidbody.clone())),
+                &mut transforms,
+            );
+            boxes.push((transform, rigidbody, leaf));
+        }
+
+        for step in 0..200 {
+            for (transform, rigidbody, leaf) in boxes.iter_mut() {
+                rigidbody
+                    .write()
+                    .unwrap()
+                    .update(transforms.get_transform_mut(transform).unwrap(), 0.02);
+                colliders.update(*leaf, &mut transforms);
+            }
+            colliders
+                .get_contacts(&mut transforms)
+                .resolve(&mut transforms, 0.02);
+            for (_, rigidbody, _) in boxes.iter() {
+                rigidbody.write().unwrap().set_old_velocity();
+            }
+
+            // heap solver topples this stack, boxes should only sink into the slop and jitter
+            for (i, (transform, _, _)) in boxes.iter().enumerate() {
+                let position = transforms.get_global_model(transform).unwrap().w.truncate();
+                let expected = Vector3::new(0., 0.5 + i as f32, 0.);
+                assert!(
+                    (position - expected).magnitude() < 0.15,
+                    "box {} at step {}: {:?}",
+                    i,
+                    step,
+                    position
+                );
+            }
+        }
+    }
+
+    /// distance a box pushed along a floor slides, both using the material
+    fn slide_distance(material: PhysicsMaterial) -> f32 {
+        let mut transforms = TransformSystem::new();
+        let mut colliders = ColliderSystem::new();
+        colliders.set_contact_solver(ContactSolver::SequentialImpulse);
+
+        let floor = transforms
+            .add_transform(TransformCreateInfo::from([0., -0.5, 0.]).with_scale([50., 0.5, 5.]));
+        colliders.add(
+            Collider::cuboid(floor, None).with_material(material),
+            &mut transforms,
+        );
+
+        let transform =
+            transforms.add_transform(TransformCreateInfo::from([0., 0.5, 0.]).with_scale([0.5; 3]));
+        let mut rigidbody = RigidBody::new(transform);
+        rigidbody.set_moi_as_cuboid((1., 1., 1.).into());
+        rigidbody.velocity.x = 4.;
+        let rigidbody = Arc::new(RwLock::new(rigidbody));
+        let leaf = colliders.add(
+            Collider::cuboid(transform, Some(rigidbody.clone())).with_material(material),
+            &mut transforms,
+        );
+
+        for _ in 0..100 {
+            rigidbody
+                .write()
+                .unwrap()
+                .update(transforms.get_transform_mut(&transform).unwrap(), 0.02);
+            colliders.update(leaf, &mut transforms);
+            colliders
+                .get_contacts(&mut transforms)
+                .resolve(&mut transforms, 0.02);
+            rigidbody.write().unwrap().set_old_velocity();
+        }
+        transforms.get_global_model(&transform).unwrap().w.x
+    }
+
+    #[test]
+    fn dynamic_friction_while_sliding() {
+        // same static friction, so only the sliding friction differs
+        let slippery = slide_distance(PhysicsMaterial::new(0.8, 0.1, 0.));
+        let rough = slide_distance(PhysicsMaterial::new(0.8, 0.6, 0.));
+        assert!(slippery > rough + 0.5, "{slippery} vs {rough}");
+        assert!(rough > 0.);
+
+        // the default material's dynamic friction is above its static, so it slides like
+        // sticking friction would
+        let concrete = slide_distance(PhysicsMaterial::default());
+        let sticking = slide_distance(PhysicsMaterial::new(2., 2., 0.5));
+        assert!(
+            (concrete - sticking).abs() < 1e-4,
+            "{concrete} vs {sticking}"
+        );
+        assert!(concrete > 0.);
+    }
+}
@@ -0,0 +1,211 @@
+//! Components adding forces to the rigidbody on the same entity every update
+
+use super::{RigidBody, Vector, GRAVITY};
+use cgmath::{InnerSpace, Quaternion, Rotation, Zero};
+
+/// closest distance used by attractors, stops the force blowing up near the centre
+const MIN_ATTRACTOR_DISTANCE: f32 = 0.5;
+
+pub trait ForceGenerator {
+    /// add forces for the next update, translation and rotation are of the rigidbody's transform
+    fn apply(&self, rigidbody: &mut RigidBody, translation: Vector, rotation: Quaternion<f32>);
+}
+
+/// Same force every update, either in world space or rotating with the rigidbody (e.g. a thruster)
+pub struct ConstantForce {
+    pub force: Vector,
+    /// point the force acts on relative to the centre of mass, in local space
+    pub point: Vector,
+    /// force is in local space
+    pub local: bool,
+}
+
+/// Pulls rigidbodies towards a point, weakening with the square of distance
+pub struct PointAttractor {
+    pub position: Vector,
+    /// force at unit distance
+    pub strength: f32,
+}
+
+/// Spring between a point on the rigidbody and a fixed point in the world
+pub struct AnchoredSpring {
+    pub anchor: Vector,
+    /// point the spring is attached to relative to the centre of mass, in local space
+    pub point: Vector,
+    pub rest_length: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+}
+
+/// Force against velocity, linear and quadratic in speed
+pub struct Drag {
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+/// Force up from a horizontal water plane, scaled by how submerged the centre of buoyancy is
+pub struct Buoyancy {
+    pub water_height: f32,
+    /// depth where the object is fully submerged, measured from half submerged
+    pub max_depth: f32,
+    /// volume displaced when fully submerged
+    pub volume: f32,
+    pub liquid_density: f32,
+    /// centre of buoyancy relative to the centre of mass, in local space
+    pub point: Vector,
+}
+
+impl ConstantForce {
+    pub fn new(force: Vector) -> Self {
+        Self {
+            force,
+            point: Vector::zero(),
+            local: false,
+        }
+    }
+
+    /// force in local space acting on a point of the rigidbody
+    pub fn thruster(force: Vector, point: Vector) -> Self {
+        Self {
+            force,
+            point,
+            local: true,
+        }
+    }
+}
+
+impl Buoyancy {
+    pub fn new(water_height: f32, max_depth: f32, volume: f32, liquid_density: f32) -> Self {
+        Self {
+            water_height,
+            max_depth,
+            volume,
+            liquid_density,
+            point: Vector::zero(),
+        }
+    }
+
+    pub fn with_point(mut self, point: Vector) -> Self {
+        self.point = point;
+        self
+    }
+}
+
+impl ForceGenerator for ConstantForce {
+    fn apply(&self, rigidbody: &mut RigidBody, _: Vector, rotation: Quaternion<f32>) {
+        let force = if self.local {
+            rotation.rotate_vector(self.force)
+        } else {
+            self.force
+        };
+        rigidbody.add_force_at_point(force, rotation.rotate_vector(self.point));
+    }
+}
+
+impl ForceGenerator for PointAttractor {
+    fn apply(&self, rigidbody: &mut RigidBody, translation: Vector, _: Quaternion<f32>) {
+        let offset = self.position - translation;
+        let distance = offset.magnitude();
+        if distance.is_zero() {
+            return;
+        }
+        let falloff = distance.max(MIN_ATTRACTOR_DISTANCE).powi(2);
+        rigidbody.add_force(offset / distance * self.strength / falloff);
+    }
+}
+
+impl ForceGenerator for AnchoredSpring {
+    fn apply(&self, rigidbody: &mut RigidBody, translation: Vector, rotation: Quaternion<f32>) {
+        let point = rotation.rotate_vector(self.point);
+        let offset = translation + point - self.anchor;
+        let length = offset.magnitude();
+        if length.is_zero() {
+            return;
+        }
+        let direction = offset / length;
+
+        let speed = rigidbody.point_velocity(point).dot(direction);
+        let magnitude = self.stiffness * (length - self.rest_length) + self.damping * speed;
+        rigidbody.add_force_at_point(-magnitude * direction, point);
+    }
+}
+
+impl ForceGenerator for Drag {
+    fn apply(&self, rigidbody: &mut RigidBody, _: Vector, _: Quaternion<f32>) {
+        let velocity = rigidbody.velocity;
+        let coefficient = self.linear + self.quadratic * velocity.magnitude();
+        rigidbody.add_force(-coefficient * velocity);
+    }
+}
+
+impl ForceGenerator for Buoyancy {
+    fn apply(&self, rigidbody: &mut RigidBody, translation: Vector, rotation: Quaternion<f32>) {
+        let point = rotation.rotate_vector(self.point);
+        let depth = self.water_height - (translation + point).y;
+        // 0 just out of the water, 1 fully submerged
+        let submerged = ((depth + self.max_depth) / (2. * self.max_depth)).clamp(0., 1.);
+        if submerged.is_zero() {
+            return;
+        }
+
+        let force = -GRAVITY * self.liquid_density * self.volume * submerged;
+        rigidbody.add_force_at_point(force, point);
+    }
+}
+
+#[cfg(test)]
+mod force_tests {
+    use super::{Buoyancy, Drag, ForceGenerator};
+    use crate::{
+        game_objects::transform::TransformSystem,
+        physics::{RigidBody, Vector},
+    };
+    use cgmath::{InnerSpace, One, Quaternion, Zero};
+
+    #[test]
+    fn force_at_point() {
+        let mut transforms = TransformSystem::new();
+        let transform = transforms.add_transform([0., 0., 0.]);
+        let mut rigidbody = RigidBody::new(transform);
+        rigidbody.gravity_multiplier = 0.;
+
+        // push the +x side forward, turning about +y
+        rigidbody.add_force_at_point((0., 0., -1.).into(), (1., 0., 0.).into());
+        rigidbody.update(transforms.get_transform_mut(&transform).unwrap(), 0.1);
+
+        assert!(rigidbody.velocity.z < 0.);
+        assert!(rigidbody.bivelocity.y > 0.);
+        assert!(rigidbody.bivelocity.x.abs() < 1e-6 && rigidbody.bivelocity.z.abs() < 1e-6);
+        assert!(rigidbody.force.is_zero() && rigidbody.torque.is_zero());
+    }
+
+    #[test]
+    fn buoyancy_floats() {
+        let mut transforms = TransformSystem::new();
+        let transform = transforms.add_transform([0., 5., 0.]);
+        let mut rigidbody = RigidBody::new(transform);
+        // half as dense as the liquid, so floats half submerged
+        let buoyancy = Buoyancy::new(1., 0.5, 1., 2.);
+        let drag = Drag {
+            linear: 1.,
+            quadratic: 0.,
+        };
+
+        for _ in 0..1000 {
+            let translation = *transforms
+                .get_transform(&transform)
+                .unwrap()
+                .get_local_transform()
+                .translation;
+            for generator in [&buoyancy as &dyn ForceGenerator, &drag] {
+                generator.apply(&mut rigidbody, translation, Quaternion::one());
+            }
+            rigidbody.update(transforms.get_transform_mut(&transform).unwrap(), 0.02);
+        }
+
+        let height = transforms.get_global_model(&transform).unwrap().w.y;
+        assert!((height - 1.).abs() < 0.01, "height: {}", height);
+        assert!(rigidbody.velocity.magnitude() < 0.01);
+        assert_eq!(rigidbody.bivelocity, Vector::zero());
+    }
+}
@@ -0,0 +1,195 @@
+//! Groups of rigidbodies connected by contacts or joints
+//!
+//! Bodies in an island sleep and wake together, so a pile doesn't partially fall asleep.
+//! Sleeping islands are stored in their bodies since they have no contacts to connect them.
+
+use super::RigidBody;
+use cgmath::Zero;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock, Weak},
+};
+
+/// Union find over the rigidbodies connected in an update
+#[derive(Default)]
+pub struct IslandBuilder {
+    bodies: Vec<Arc<RwLock<RigidBody>>>,
+    parents: Vec<usize>,
+    indices: HashMap<*const RwLock<RigidBody>, usize>,
+    /// sleeping islands already added, so each is only walked once
+    sleeping_islands: HashSet<*const [Weak<RwLock<RigidBody>>]>,
+}
+
+impl IslandBuilder {
+    /// add rigidbody as its own island, returning its index
+    pub fn add(&mut self, rigidbody: &Arc<RwLock<RigidBody>>) -> usize {
+        if let Some(&index) = self.indices.get(&Arc::as_ptr(rigidbody)) {
+            return index;
+        }
+
+        let index = self.bodies.len();
+        self.indices.insert(Arc::as_ptr(rigidbody), index);
+        self.bodies.push(rigidbody.clone());
+        self.parents.push(index);
+
+        // members of its sleeping island are connected to it
+        let Some(island) = rigidbody.read().unwrap().sleeping_island.clone() else {
+            return index;
+        };
+        if !self.sleeping_islands.insert(Arc::as_ptr(&island)) {
+            return index;
+        }
+        for other in island.iter().filter_map(Weak::upgrade) {
+            let other = self.add(&other);
+            self.union(index, other);
+        }
+        index
+    }
+
+    /// put both rigidbodies in the same island,
+    /// infinite mass bodies are left out so they don't join everything they touch
+    pub fn connect(
+        &mut self,
+        rigidbody_1: &Arc<RwLock<RigidBody>>,
+        rigidbody_2: Option<&Arc<RwLock<RigidBody>>>,
+    ) {
+        let index_1 = self.add(rigidbody_1);
+        let Some(rigidbody_2) = rigidbody_2 else {
+            return;
+        };
+        let index_2 = self.add(rigidbody_2);
+
+        let dynamic =
+            |rigidbody: &Arc<RwLock<RigidBody>>| !rigidbody.read().unwrap().inv_mass.is_zero();
+        if dynamic(rigidbody_1) && dynamic(rigidbody_2) {
+            self.union(index_1, index_2);
+        }
+    }
+
+    fn find(&mut self, mut index: usize) -> usize {
+        while self.parents[index] != index {
+            self.parents[index] = self.parents[self.parents[index]];
+            index = self.parents[index];
+        }
+        index
+    }
+
+    fn union(&mut self, index_1: usize, index_2: usize) {
+        let root_1 = self.find(index_1);
+        let root_2 = self.find(index_2);
+        self.parents[root_2] = root_1;
+    }
+
+    /// rigidbodies grouped by island
+    pub fn islands(&mut self) -> Vec<Vec<Arc<RwLock<RigidBody>>>> {
+        let mut islands: Vec<Vec<_>> = Vec::new();
+        let mut root_islands = HashMap::new();
+        for index in 0..self.bodies.len() {
+            let root = self.find(index);
+            let island = *root_islands.entry(root).or_insert_with(|| {
+                islands.push(Vec::new());
+                islands.len() - 1
+            });
+            islands[island].push(self.bodies[index].clone());
+        }
+        islands
+    }
+
+    /// Sleep islands where every body is at rest, wake the rest
+    ///
+    /// An awake body that isn't at rest wakes its whole island, including bodies that were sleeping
+    pub fn update_sleep(mut self) {
+        for island in self.islands() {
+            let at_rest = island.iter().all(|rigidbody| {
+                let guard = rigidbody.read().unwrap();
+                !guard.is_awake() || guard.is_ready_to_sleep()
+            });
+
+            if at_rest {
+                let members: Arc<[_]> = island.iter().map(Arc::downgrade).collect();
+                for rigidbody in island.iter() {
+                    rigidbody.write().unwrap().sleep(members.clone());
+                }
+            } else {
+                for rigidbody in island.iter() {
+                    let mut guard = rigidbody.write().unwrap();
+                    if !guard.is_awake() {
+                        guard.wake();
+                    }
+                    guard.sleeping_island = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod island_tests {
+    use super::IslandBuilder;
+    use crate::{game_objects::transform::TransformSystem, physics::RigidBody};
+    use cgmath::Zero;
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn sleep_and_wake_together() {
+        let mut transforms = TransformSystem::new();
+        let mut new_body = || {
+            let mut rigidbody = RigidBody::new(transforms.next().unwrap());
+            rigidbody.gravity_multiplier = 0.;
+            Arc::new(RwLock::new(rigidbody))
+        };
+        let (a, b, c) = (new_body(), new_body(), new_body());
+        let mut floor = RigidBody::new(transforms.next().unwrap());
+        floor.inv_mass = 0.;
+        floor.gravity_multiplier = 0.;
+        let floor = Arc::new(RwLock::new(floor));
+
+        let step = |transforms: &mut TransformSystem, moving: Option<&Arc<RwLock<RigidBody>>>| {
+            for rigidbody in [&a, &b, &c, &floor] {
+                let mut guard = rigidbody.write().unwrap();
+                if moving.is_some_and(|m| Arc::ptr_eq(m, rigidbody)) {
+                    guard.velocity.x = 1.;
+                    guard.wake();
+                }
+                let transform = guard.transform;
+                guard.update(transforms.get_transform_mut(&transform).unwrap(), 0.02);
+            }
+
+            // a and b are stacked on the floor, c only touches the floor
+            let mut islands = IslandBuilder::default();
+            if a.read().unwrap().is_awake() || b.read().unwrap().is_awake() {
+                islands.connect(&a, Some(&b));
+            }
+            islands.connect(&a, Some(&floor));
+            islands.connect(&c, Some(&floor));
+            for rigidbody in [&a, &b, &c, &floor] {
+                islands.add(rigidbody);
+            }
+            islands.update_sleep();
+        };
+
+        // b keeps moving so a can't sleep, c sleeps on its own
+        for _ in 0..20 {
+            step(&mut transforms, Some(&b));
+        }
+        assert!(a.read().unwrap().is_awake());
+        assert!(!c.read().unwrap().is_awake());
+
+        for _ in 0..20 {
+            b.write().unwrap().velocity.set_zero();
+            step(&mut transforms, None);
+        }
+        assert!(!a.read().unwrap().is_awake());
+        assert!(!b.read().unwrap().is_awake());
+        // the island is stored once and shared by its members
+        let island_a = a.read().unwrap().sleeping_island.clone().unwrap();
+        let island_b = b.read().unwrap().sleeping_island.clone().unwrap();
+        assert!(Arc::ptr_eq(&island_a, &island_b));
+        assert_eq!(island_a.len(), 2);
+
+        // waking b wakes a without a contact between them
+        step(&mut transforms, Some(&b));
+        assert!(a.read().unwrap().is_awake());
+        assert!(!c.read().unwrap().is_awake());
+    }
+}
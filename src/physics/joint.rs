@@ -0,0 +1,955 @@
+//! Constraints between 2 rigidbodies, or a rigidbody and the world
+//!
+//! Joints are solved after contacts by applying impulses to the rigidbodies' velocities,
+//! followed by moving the transforms directly to remove any remaining error
+
+use super::{geo_alg::bivec_exp, RigidBody, Vector};
+use crate::{
+    game_objects::transform::{TransformID, TransformSystem},
+    utilities::math::skew,
+};
+use cgmath::{InnerSpace, Matrix, Matrix3, One, Quaternion, Rotation, SquareMatrix, Zero};
+use std::sync::{Arc, RwLock};
+
+const VELOCITY_ITERS: u32 = 10;
+const POSITION_ITERS: u32 = 4;
+/// positional error left uncorrected to avoid jitter
+const LINEAR_SLOP: f32 = 0.005;
+/// rotational error (rad) left uncorrected to avoid jitter
+const ANGULAR_SLOP: f32 = 0.01;
+/// max distance moved by a single position correction
+const MAX_LINEAR_CORRECTION: f32 = 0.2;
+
+/// drives a hinge towards a target angular velocity
+#[derive(Clone, Copy, Debug)]
+pub struct Motor {
+    /// rad/s of body 2 relative to body 1 around the hinge axis
+    pub target_velocity: f32,
+    pub max_torque: f32,
+}
+
+/// makes a distance joint soft
+#[derive(Clone, Copy, Debug)]
+pub struct Spring {
+    pub stiffness: f32,
+    pub damping: f32,
+}
+
+/// Joint types, axes and rotations are stored in the local space of each body
+#[derive(Clone, Copy, Debug)]
+pub enum JointKind {
+    /// anchors are kept together, rotation is free
+    BallSocket,
+    /// anchors are kept together, rotation is only allowed around the axis
+    Hinge {
+        axis_1: Vector,
+        axis_2: Vector,
+        /// perpendicular to the axis, used to measure the hinge angle
+        reference_1: Vector,
+        reference_2: Vector,
+        /// min and max angle (rad)
+        limits: Option<(f32, f32)>,
+        motor: Option<Motor>,
+    },
+    /// anchors are kept together and the relative rotation is locked
+    Fixed { rotation: Quaternion<f32> },
+    /// anchors can only move apart along the axis of body 1, the relative rotation is locked
+    Slider {
+        axis_1: Vector,
+        rotation: Quaternion<f32>,
+        /// min and max distance along the axis
+        limits: Option<(f32, f32)>,
+    },
+    /// anchors are kept at a fixed distance, or pulled towards it by a spring
+    Distance { length: f32, spring: Option<Spring> },
+}
+
+/// Constraint between 2 rigidbodies, add as a component to have it solved by the game world
+///
+/// If there is no second rigidbody the joint is attached to the world and anchor 2 is in global space.
+/// Rigidbody transforms are assumed to have no parent, same as contacts
+pub struct Joint {
+    rb_1: Arc<RwLock<RigidBody>>,
+    rb_2: Option<Arc<RwLock<RigidBody>>>,
+    anchor_1: Vector,
+    anchor_2: Vector,
+    kind: JointKind,
+}
+
+impl Joint {
+    pub fn ball_socket(
+        transforms: &TransformSystem,
+        rb_1: Arc<RwLock<RigidBody>>,
+        rb_2: Option<Arc<RwLock<RigidBody>>>,
+        anchor: Vector,
+    ) -> Self {
+        Self::new(transforms, rb_1, rb_2, anchor, |_, _| JointKind::BallSocket)
+    }
+
+    /// hinge around the global axis through the global anchor, starting at an angle of 0
+    pub fn hinge(
+        transforms: &TransformSystem,
+        rb_1: Arc<RwLock<RigidBody>>,
+        rb_2: Option<Arc<RwLock<RigidBody>>>,
+        anchor: Vector,
+        axis: Vector,
+    ) -> Self {
+        let axis = axis.normalize();
+        let reference = perpendicular(axis);
+        Self::new(transforms, rb_1, rb_2, anchor, |q_1, q_2| {
+            JointKind::Hinge {
+                axis_1: q_1.invert().rotate_vector(axis),
+                axis_2: q_2.invert().rotate_vector(axis),
+                reference_1: q_1.invert().rotate_vector(reference),
+                reference_2: q_2.invert().rotate_vector(reference),
+                limits: None,
+                motor: None,
+            }
+        })
+    }
+
+    pub fn fixed(
+        transforms: &TransformSystem,
+        rb_1: Arc<RwLock<RigidBody>>,
+        rb_2: Option<Arc<RwLock<RigidBody>>>,
+        anchor: Vector,
+    ) -> Self {
+        Self::new(transforms, rb_1, rb_2, anchor, |q_1, q_2| {
+            JointKind::Fixed {
+                rotation: q_1.invert() * q_2,
+            }
+        })
+    }
+
+    /// slider along the global axis through the global anchor
+    pub fn slider(
+        transforms: &TransformSystem,
+        rb_1: Arc<RwLock<RigidBody>>,
+        rb_2: Option<Arc<RwLock<RigidBody>>>,
+        anchor: Vector,
+        axis: Vector,
+    ) -> Self {
+        let axis = axis.normalize();
+        Self::new(transforms, rb_1, rb_2, anchor, |q_1, q_2| {
+            JointKind::Slider {
+                axis_1: q_1.invert().rotate_vector(axis),
+                rotation: q_1.invert() * q_2,
+                limits: None,
+            }
+        })
+    }
+
+    /// keeps the global anchors at their current distance
+    pub fn distance(
+        transforms: &TransformSystem,
+        rb_1: Arc<RwLock<RigidBody>>,
+        rb_2: Option<Arc<RwLock<RigidBody>>>,
+        anchor_1: Vector,
+        anchor_2: Vector,
+    ) -> Self {
+        let mut joint = Self::new(transforms, rb_1, rb_2, anchor_1, |_, _| {
+            JointKind::Distance {
+                length: (anchor_2 - anchor_1).magnitude(),
+                spring: None,
+            }
+        });
+        joint.anchor_2 = match &joint.rb_2 {
+            Some(rb_2) => {
+                let (translation, rotation) = body_transform(transforms, rb_2);
+                rotation.invert().rotate_vector(anchor_2 - translation)
+            }
+            None => anchor_2,
+        };
+        joint
+    }
+
+    /// Limit the angle of a hinge (rad) or the distance along a slider's axis
+    ///
+    /// Panics for other joints
+    pub fn with_limits(mut self, min: f32, max: f32) -> Self {
+        match &mut self.kind {
+            JointKind::Hinge { limits, .. } | JointKind::Slider { limits, .. } => {
+                *limits = Some((min, max))
+            }
+            _ => panic!("Only hinge and slider joints have limits"),
+        }
+        self
+    }
+
+    /// Panics if the joint isn't a hinge
+    pub fn with_motor(mut self, target_velocity: f32, max_torque: f32) -> Self {
+        match &mut self.kind {
+            JointKind::Hinge { motor, .. } => {
+                *motor = Some(Motor {
+                    target_velocity,
+                    max_torque,
+                })
+            }
+            _ => panic!("Only hinge joints have motors"),
+        }
+        self
+    }
+
+    /// Set the rest length of a distance joint
+    ///
+    /// Panics if the joint isn't a distance joint
+    pub fn with_length(mut self, rest_length: f32) -> Self {
+        match &mut self.kind {
+            JointKind::Distance { length, .. } => *length = rest_length,
+            _ => panic!("Only distance joints have a length"),
+        }
+        self
+    }
+
+    /// Panics if the joint isn't a distance joint
+    pub fn with_spring(mut self, stiffness: f32, damping: f32) -> Self {
+        match &mut self.kind {
+            JointKind::Distance { spring, .. } => *spring = Some(Spring { stiffness, damping }),
+            _ => panic!("Only distance joints have springs"),
+        }
+        self
+    }
+
+    pub fn get_kind(&self) -> &JointKind {
+        &self.kind
+    }
+
+    pub fn get_motor_mut(&mut self) -> Option<&mut Motor> {
+        match &mut self.kind {
+            JointKind::Hinge { motor, .. } => motor.as_mut(),
+            _ => None,
+        }
+    }
+
+    pub fn get_rigidbodies(&self) -> (&Arc<RwLock<RigidBody>>, Option<&Arc<RwLock<RigidBody>>>) {
+        (&self.rb_1, self.rb_2.as_ref())
+    }
+
+    /// relative angle of a hinge (rad), None for other joints
+    pub fn hinge_angle(&self, transforms: &TransformSystem) -> Option<f32> {
+        let frame = JointFrame::new(self, transforms);
+        match self.kind {
+            JointKind::Hinge {
+                axis_1,
+                reference_1,
+                reference_2,
+                ..
+            } => Some(frame.angle(axis_1, reference_1, reference_2)),
+            _ => None,
+        }
+    }
+
+    fn new(
+        transforms: &TransformSystem,
+        rb_1: Arc<RwLock<RigidBody>>,
+        rb_2: Option<Arc<RwLock<RigidBody>>>,
+        anchor: Vector,
+        kind: impl FnOnce(Quaternion<f32>, Quaternion<f32>) -> JointKind,
+    ) -> Self {
+        if let Some(rb_2) = &rb_2 {
+            assert!(
+                !Arc::ptr_eq(&rb_1, rb_2),
+                "Joint needs 2 different rigidbodies"
+            );
+        }
+
+        let (translation_1, rotation_1) = body_transform(transforms, &rb_1);
+        let (anchor_2, rotation_2) = match &rb_2 {
+            Some(rb_2) => {
+                let (translation_2, rotation_2) = body_transform(transforms, rb_2);
+                (
+                    rotation_2.invert().rotate_vector(anchor - translation_2),
+                    rotation_2,
+                )
+            }
+            None => (anchor, Quaternion::one()),
+        };
+
+        Self {
+            anchor_1: rotation_1.invert().rotate_vector(anchor - translation_1),
+            anchor_2,
+            kind: kind(rotation_1, rotation_2),
+            rb_1,
+            rb_2,
+        }
+    }
+}
+
+/// Solve joints for this time step, should be called after contacts are resolved
+pub fn solve_joints<'a>(
+    joints: impl IntoIterator<Item = &'a Joint>,
+    transforms: &mut TransformSystem,
+    delta_seconds: f32,
+) {
+    // skip joints where both bodies are sleeping
+    let joints: Vec<&Joint> = joints.into_iter().filter(|j| j.wake()).collect();
+    if joints.is_empty() {
+        return;
+    }
+
+    // velocity
+    let frames: Vec<JointFrame> = joints
+        .iter()
+        .map(|joint| JointFrame::new(joint, transforms))
+        .collect();
+    let mut accumulated = vec![AccumulatedImpulse::default(); joints.len()];
+    for (joint, frame) in joints.iter().zip(frames.iter()) {
+        joint.apply_spring(frame, delta_seconds);
+    }
+    for _ in 0..VELOCITY_ITERS {
+        for ((joint, frame), accumulated) in
+            joints.iter().zip(frames.iter()).zip(accumulated.iter_mut())
+        {
+            joint.solve_velocity(frame, accumulated, delta_seconds);
+        }
+    }
+
+    // position
+    for _ in 0..POSITION_ITERS {
+        for joint in joints.iter() {
+            let frame = JointFrame::new(joint, transforms);
+            joint.solve_position(&frame, transforms);
+        }
+    }
+}
+
+/// impulses that are clamped over the whole time step instead of per iteration
+#[derive(Clone, Copy, Default)]
+struct AccumulatedImpulse {
+    motor: f32,
+    limit: f32,
+}
+
+/// global space state of a body at the start of solving
+#[derive(Clone, Copy)]
+struct BodyFrame {
+    translation: Vector,
+    rotation: Quaternion<f32>,
+    inv_mass: f32,
+    /// global inverse moment of inertia, zero for static bodies
+    inv_inertia: Matrix3<f32>,
+}
+
+/// global space state of a joint
+struct JointFrame {
+    body_1: BodyFrame,
+    body_2: BodyFrame,
+    /// global offset of the anchors from their bodies
+    relative_1: Vector,
+    relative_2: Vector,
+}
+
+impl Joint {
+    /// wakes both bodies if either is awake, returns false if both are sleeping
+    fn wake(&self) -> bool {
+        let motor_running = matches!(
+            self.kind,
+            JointKind::Hinge { motor: Some(motor), .. } if !motor.target_velocity.is_zero()
+        );
+
+        let mut guard_1 = self.rb_1.write().unwrap();
+        let Some(rb_2) = &self.rb_2 else {
+            if motor_running {
+                guard_1.wake();
+            }
+            return guard_1.is_awake();
+        };
+        let mut guard_2 = rb_2.write().unwrap();
+
+        if guard_1.is_awake() || guard_2.is_awake() || motor_running {
+            guard_1.wake();
+            guard_2.wake();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn apply_spring(&self, frame: &JointFrame, delta_seconds: f32) {
+        let JointKind::Distance {
+            length,
+            spring: Some(spring),
+        } = self.kind
+        else {
+            return;
+        };
+
+        let offset = frame.anchor_2() - frame.anchor_1();
+        let distance = offset.magnitude();
+        if distance < f32::EPSILON {
+            return;
+        }
+        let normal = offset / distance;
+        let speed = normal.dot(self.relative_velocity(frame));
+
+        let force = -spring.stiffness * (distance - length) - spring.damping * speed;
+        self.apply_impulse(frame, force * delta_seconds * normal);
+    }
+
+    fn solve_velocity(
+        &self,
+        frame: &JointFrame,
+        accumulated: &mut AccumulatedImpulse,
+        delta_seconds: f32,
+    ) {
+        match self.kind {
+            JointKind::BallSocket | JointKind::Fixed { .. } | JointKind::Hinge { .. } => {
+                let velocity = self.relative_velocity(frame);
+                if let Some(inv_inertia) = frame.point_inertia().invert() {
+                    self.apply_impulse(frame, -(inv_inertia * velocity));
+                }
+            }
+            JointKind::Slider { axis_1, .. } => {
+                let axis = frame.body_1.rotation.rotate_vector(axis_1);
+                let velocity = self.relative_velocity(frame);
+                let impulse = solve_perpendicular(frame.point_inertia(), axis, -velocity);
+                self.apply_impulse(frame, impulse);
+            }
+            JointKind::Distance { spring: None, .. } => {
+                let offset = frame.anchor_2() - frame.anchor_1();
+                if offset.magnitude2() > f32::EPSILON {
+                    let normal = offset.normalize();
+                    let speed = normal.dot(self.relative_velocity(frame));
+                    let inertia = normal.dot(frame.point_inertia() * normal);
+                    self.apply_impulse(frame, -(speed / inertia) * normal);
+                }
+            }
+            JointKind::Distance { .. } => (),
+        }
+
+        match self.kind {
+            JointKind::Fixed { .. } | JointKind::Slider { .. } => {
+                let angular_velocity = self.relative_angular_velocity();
+                if let Some(inv_inertia) = frame.angular_inertia().invert() {
+                    self.apply_angular_impulse(frame, -(inv_inertia * angular_velocity));
+                }
+            }
+            JointKind::Hinge {
+                axis_1,
+                reference_1,
+                reference_2,
+                limits,
+                motor,
+                ..
+            } => {
+                let axis = frame.body_1.rotation.rotate_vector(axis_1);
+                let inertia = frame.angular_inertia();
+
+                // lock rotation off the axis
+                let angular_velocity = self.relative_angular_velocity();
+                let impulse = solve_perpendicular(inertia, axis, -angular_velocity);
+                self.apply_angular_impulse(frame, impulse);
+
+                let axis_inertia = axis.dot(inertia * axis);
+                if axis_inertia < f32::EPSILON {
+                    return;
+                }
+
+                if let Some(motor) = motor {
+                    let speed = axis.dot(self.relative_angular_velocity());
+                    let max_impulse = motor.max_torque * delta_seconds;
+                    let impulse = (motor.target_velocity - speed) / axis_inertia;
+                    let total = (accumulated.motor + impulse).clamp(-max_impulse, max_impulse);
+                    self.apply_angular_impulse(frame, (total - accumulated.motor) * axis);
+                    accumulated.motor = total;
+                }
+
+                if let Some((min, max)) = limits {
+                    let angle = frame.angle(axis_1, reference_1, reference_2);
+                    let speed = axis.dot(self.relative_angular_velocity());
+                    let impulse = -speed / axis_inertia;
+                    // limits can only push away from themselves
+                    let total = if angle <= min {
+                        (accumulated.limit + impulse).max(0.)
+                    } else if angle >= max {
+                        (accumulated.limit + impulse).min(0.)
+                    } else {
+                        return;
+                    };
+                    self.apply_angular_impulse(frame, (total - accumulated.limit) * axis);
+                    accumulated.limit = total;
+                }
+            }
+            _ => (),
+        }
+
+        if let JointKind::Slider {
+            axis_1,
+            limits: Some((min, max)),
+            ..
+        } = self.kind
+        {
+            let axis = frame.body_1.rotation.rotate_vector(axis_1);
+            let position = axis.dot(frame.anchor_2() - frame.anchor_1());
+            let speed = axis.dot(self.relative_velocity(frame));
+            let impulse = -speed / axis.dot(frame.point_inertia() * axis);
+            let total = if position <= min {
+                (accumulated.limit + impulse).max(0.)
+            } else if position >= max {
+                (accumulated.limit + impulse).min(0.)
+            } else {
+                return;
+            };
+            self.apply_impulse(frame, (total - accumulated.limit) * axis);
+            accumulated.limit = total;
+        }
+    }
+
+    fn solve_position(&self, frame: &JointFrame, transforms: &mut TransformSystem) {
+        let error = frame.anchor_2() - frame.anchor_1();
+
+        // linear
+        let correction = match self.kind {
+            JointKind::BallSocket | JointKind::Fixed { .. } | JointKind::Hinge { .. } => {
+                if error.magnitude() < LINEAR_SLOP {
+                    None
+                } else {
+                    frame.point_inertia().invert().map(|inv| -(inv * error))
+                }
+            }
+            JointKind::Slider { axis_1, limits, .. } => {
+                let axis = frame.body_1.rotation.rotate_vector(axis_1);
+                let position = axis.dot(error);
+                let mut target = error - position * axis;
+                if let Some((min, max)) = limits {
+                    target += (position - position.clamp(min, max)) * axis;
+                }
+
+                if target.magnitude() < LINEAR_SLOP {
+                    None
+                } else {
+                    frame.point_inertia().invert().map(|inv| -(inv * target))
+                }
+            }
+            JointKind::Distance {
+                length,
+                spring: None,
+            } => {
+                let distance = error.magnitude();
+                if (distance - length).abs() < LINEAR_SLOP || distance < f32::EPSILON {
+                    None
+                } else {
+                    let normal = error / distance;
+                    let inertia = normal.dot(frame.point_inertia() * normal);
+                    Some(-((distance - length) / inertia) * normal)
+                }
+            }
+            JointKind::Distance { .. } => None,
+        };
+        if let Some(impulse) = correction {
+            self.move_bodies(frame, transforms, impulse, Vector::zero());
+        }
+
+        // angular
+        let target = match self.kind {
+            JointKind::Fixed { rotation } | JointKind::Slider { rotation, .. } => {
+                // rotation from the target to current
+                let error = frame.body_2.rotation * (frame.body_1.rotation * rotation).invert();
+                let error = 2. * error.s.signum() * error.v;
+                (error.magnitude() > ANGULAR_SLOP).then_some(-error)
+            }
+            JointKind::Hinge {
+                axis_1,
+                axis_2,
+                reference_1,
+                reference_2,
+                limits,
+                ..
+            } => {
+                let world_axis_1 = frame.body_1.rotation.rotate_vector(axis_1);
+                let world_axis_2 = frame.body_2.rotation.rotate_vector(axis_2);
+                let mut target = world_axis_2.cross(world_axis_1);
+
+                if let Some((min, max)) = limits {
+                    let angle = frame.angle(axis_1, reference_1, reference_2);
+                    target += (angle.clamp(min, max) - angle) * world_axis_1;
+                }
+                (target.magnitude() > ANGULAR_SLOP).then_some(target)
+            }
+            _ => None,
+        };
+        if let Some(target) = target {
+            let frame = JointFrame::new(self, transforms);
+            if let Some(inv_inertia) = frame.angular_inertia().invert() {
+                self.move_bodies(&frame, transforms, Vector::zero(), inv_inertia * target);
+            }
+        }
+    }
+
+    /// velocity of anchor 2 relative to anchor 1
+    fn relative_velocity(&self, frame: &JointFrame) -> Vector {
+        let velocity_1 = self.rb_1.read().unwrap().point_velocity(frame.relative_1);
+        let velocity_2 = self.rb_2.as_ref().map_or(Vector::zero(), |rb| {
+            rb.read().unwrap().point_velocity(frame.relative_2)
+        });
+        velocity_2 - velocity_1
+    }
+
+    /// angular velocity of body 2 relative to body 1
+    fn relative_angular_velocity(&self) -> Vector {
+        let bivelocity_1 = self.rb_1.read().unwrap().bivelocity;
+        let bivelocity_2 = self
+            .rb_2
+            .as_ref()
+            .map_or(Vector::zero(), |rb| rb.read().unwrap().bivelocity);
+        bivelocity_2 - bivelocity_1
+    }
+
+    /// apply impulse at anchor 2, and the opposite at anchor 1
+    fn apply_impulse(&self, frame: &JointFrame, impulse: Vector) {
+        let mut guard_1 = self.rb_1.write().unwrap();
+        guard_1.velocity -= frame.body_1.inv_mass * impulse;
+        guard_1.bivelocity -= frame.body_1.inv_inertia * frame.relative_1.cross(impulse);
+        if let Some(rb_2) = &self.rb_2 {
+            let mut guard_2 = rb_2.write().unwrap();
+            guard_2.velocity += frame.body_2.inv_mass * impulse;
+            guard_2.bivelocity += frame.body_2.inv_inertia * frame.relative_2.cross(impulse);
+        }
+    }
+
+    /// apply angular impulse to body 2, and the opposite to body 1
+    fn apply_angular_impulse(&self, frame: &JointFrame, impulse: Vector) {
+        self.rb_1.write().unwrap().bivelocity -= frame.body_1.inv_inertia * impulse;
+        if let Some(rb_2) = &self.rb_2 {
+            rb_2.write().unwrap().bivelocity += frame.body_2.inv_inertia * impulse;
+        }
+    }
+
+    /// move bodies as if the impulses were applied to body 2 (and the opposite to body 1) for a second
+    fn move_bodies(
+        &self,
+        frame: &JointFrame,
+        transforms: &mut TransformSystem,
+        impulse: Vector,
+        angular_impulse: Vector,
+    ) {
+        let mut bodies = vec![(&self.rb_1, &frame.body_1, frame.relative_1, -1.)];
+        if let Some(rb_2) = &self.rb_2 {
+            bodies.push((rb_2, &frame.body_2, frame.relative_2, 1.));
+        }
+
+        for (rigidbody, body, relative_pos, sign) in bodies {
+            let mut linear_move = sign * body.inv_mass * impulse;
+            if linear_move.magnitude() > MAX_LINEAR_CORRECTION {
+                linear_move = linear_move.normalize_to(MAX_LINEAR_CORRECTION);
+            }
+            let angular_move =
+                sign * (body.inv_inertia * (relative_pos.cross(impulse) + angular_impulse));
+
+            let transform_id = rigidbody.read().unwrap().transform;
+            transforms.get_transform_mut(&transform_id).unwrap().mutate(
+                |translation, rotation, _| {
+                    *translation += linear_move;
+                    *rotation = bivec_exp(angular_move * 0.5).into_quaternion() * *rotation;
+                },
+            );
+        }
+    }
+}
+
+impl BodyFrame {
+    fn new(transforms: &TransformSystem, rigidbody: &Arc<RwLock<RigidBody>>) -> Self {
+        let (translation, rotation) = body_transform(transforms, rigidbody);
+        let guard = rigidbody.read().unwrap();
+
+        // infinite mass bodies don't rotate either
+        let inv_inertia = if guard.inv_mass.is_zero() {
+            Matrix3::zero()
+        } else {
+            let rotation = Matrix3::from(rotation);
+            let inv_moi = Matrix3::from_diagonal(guard.principle_moi.map(|c| 1. / c));
+            rotation * inv_moi * rotation.transpose()
+        };
+
+        Self {
+            translation,
+            rotation,
+            inv_mass: guard.inv_mass,
+            inv_inertia,
+        }
+    }
+
+    /// the world, anchors and axes are already in global space
+    fn world() -> Self {
+        Self {
+            translation: Vector::zero(),
+            rotation: Quaternion::one(),
+            inv_mass: 0.,
+            inv_inertia: Matrix3::zero(),
+        }
+    }
+
+    /// change in velocity at the point per impulse
+    fn point_inertia(&self, relative_pos: Vector) -> Matrix3<f32> {
+        let skew = skew(relative_pos);
+        self.inv_mass * Matrix3::one() - skew * self.inv_inertia * skew
+    }
+}
+
+impl JointFrame {
+    fn new(joint: &Joint, transforms: &TransformSystem) -> Self {
+        let body_1 = BodyFrame::new(transforms, &joint.rb_1);
+        let body_2 = joint
+            .rb_2
+            .as_ref()
+            .map_or(BodyFrame::world(), |rb| BodyFrame::new(transforms, rb));
+
+        Self {
+            relative_1: body_1.rotation.rotate_vector(joint.anchor_1),
+            relative_2: body_2.rotation.rotate_vector(joint.anchor_2),
+            body_1,
+            body_2,
+        }
+    }
+
+    fn anchor_1(&self) -> Vector {
+        self.body_1.translation + self.relative_1
+    }
+
+    fn anchor_2(&self) -> Vector {
+        self.body_2.translation + self.relative_2
+    }
+
+    /// change in relative velocity of the anchors per impulse
+    fn point_inertia(&self) -> Matrix3<f32> {
+        self.body_1.point_inertia(self.relative_1) + self.body_2.point_inertia(self.relative_2)
+    }
+
+    /// change in relative angular velocity per angular impulse
+    fn angular_inertia(&self) -> Matrix3<f32> {
+        self.body_1.inv_inertia + self.body_2.inv_inertia
+    }
+
+    /// angle of body 2's reference around the axis from body 1's reference
+    fn angle(&self, axis_1: Vector, reference_1: Vector, reference_2: Vector) -> f32 {
+        let axis = self.body_1.rotation.rotate_vector(axis_1);
+        let reference_1 = self.body_1.rotation.rotate_vector(reference_1);
+        let reference_2 = self.body_2.rotation.rotate_vector(reference_2);
+        axis.dot(reference_1.cross(reference_2))
+            .atan2(reference_1.dot(reference_2))
+    }
+}
+
+/// impulse perpendicular to the axis giving the closest change to target
+fn solve_perpendicular(inertia: Matrix3<f32>, axis: Vector, target: Vector) -> Vector {
+    let tangent_1 = perpendicular(axis);
+    let tangent_2 = axis.cross(tangent_1);
+
+    // 2x2 inertia in the tangent plane
+    let k_11 = tangent_1.dot(inertia * tangent_1);
+    let k_12 = tangent_1.dot(inertia * tangent_2);
+    let k_22 = tangent_2.dot(inertia * tangent_2);
+    let det = k_11 * k_22 - k_12 * k_12;
+    if det.abs() < f32::EPSILON {
+        return Vector::zero();
+    }
+
+    let target_1 = tangent_1.dot(target);
+    let target_2 = tangent_2.dot(target);
+    let lambda_1 = (k_22 * target_1 - k_12 * target_2) / det;
+    let lambda_2 = (k_11 * target_2 - k_12 * target_1) / det;
+    lambda_1 * tangent_1 + lambda_2 * tangent_2
+}
+
+/// any unit vector perpendicular to the normalised vector
+fn perpendicular(vector: Vector) -> Vector {
+    let other = if vector.x.abs() < 0.9 {
+        Vector::unit_x()
+    } else {
+        Vector::unit_y()
+    };
+    vector.cross(other).normalize()
+}
+
+fn body_transform(
+    transforms: &TransformSystem,
+    rigidbody: &Arc<RwLock<RigidBody>>,
+) -> (Vector, Quaternion<f32>) {
+    let transform_id: TransformID = rigidbody.read().unwrap().transform;
+    let transform = transforms
+        .get_transform(&transform_id)
+        .unwrap()
+        .get_local_transform();
+    (*transform.translation, *transform.rotation)
+}
+
+#[cfg(test)]
+mod joint_tests {
+    use super::{solve_joints, Joint};
+    use crate::{
+        game_objects::transform::TransformSystem,
+        physics::{RigidBody, Vector},
+    };
+    use cgmath::{InnerSpace, Rotation, Zero};
+    use std::sync::{Arc, RwLock};
+
+    const DELTA: f32 = 1. / 60.;
+
+    fn body(
+        transforms: &mut TransformSystem,
+        position: Vector,
+        gravity: f32,
+    ) -> Arc<RwLock<RigidBody>> {
+        let mut rb = RigidBody::new(transforms.add_transform(position));
+        rb.gravity_multiplier = gravity;
+        rb.set_moi_as_cuboid((1., 1., 1.).into());
+        Arc::new(RwLock::new(rb))
+    }
+
+    fn step(
+        transforms: &mut TransformSystem,
+        bodies: &[&Arc<RwLock<RigidBody>>],
+        joints: &[Joint],
+        steps: u32,
+    ) {
+        for _ in 0..steps {
+            for rb in bodies {
+                let mut guard = rb.write().unwrap();
+                let id = guard.transform;
+                guard.update(transforms.get_transform_mut(&id).unwrap(), DELTA);
+            }
+            solve_joints(joints, transforms, DELTA);
+        }
+    }
+
+    fn position(transforms: &TransformSystem, rb: &Arc<RwLock<RigidBody>>) -> Vector {
+        let id = rb.read().unwrap().transform;
+        *transforms
+            .get_transform(&id)
+            .unwrap()
+            .get_local_transform()
+            .translation
+    }
+
+    #[test]
+    fn pendulum() {
+        let mut transforms = TransformSystem::new();
+        let rb = body(&mut transforms, (2., 0., 0.).into(), 1.);
+        let joints = [Joint::ball_socket(
+            &transforms,
+            rb.clone(),
+            None,
+            Vector::zero(),
+        )];
+
+        step(&mut transforms, &[&rb], &joints, 120);
+
+        let position = position(&transforms, &rb);
+        assert!((position.magnitude() - 2.).abs() < 0.05);
+        assert!(position.y < -0.5);
+    }
+
+    #[test]
+    fn hinge_limits() {
+        let mut transforms = TransformSystem::new();
+        let rb = body(&mut transforms, Vector::zero(), 0.);
+        rb.write().unwrap().bivelocity = (1., 0., 5.).into();
+        let joints = [Joint::hinge(
+            &transforms,
+            rb.clone(),
+            None,
+            Vector::zero(),
+            Vector::unit_z(),
+        )
+        .with_limits(-0.5, 0.5)];
+
+        step(&mut transforms, &[&rb], &joints, 60);
+
+        let angle = joints[0].hinge_angle(&transforms).unwrap();
+        assert!((-0.55..=0.55).contains(&angle));
+        // no rotation off the hinge axis
+        let id = rb.read().unwrap().transform;
+        let rotation = *transforms
+            .get_transform(&id)
+            .unwrap()
+            .get_local_transform()
+            .rotation;
+        assert!(
+            rotation
+                .rotate_vector(Vector::unit_z())
+                .dot(Vector::unit_z())
+                > 0.999
+        );
+    }
+
+    #[test]
+    fn hinge_motor() {
+        let mut transforms = TransformSystem::new();
+        let rb = body(&mut transforms, Vector::zero(), 0.);
+        let joints = [Joint::hinge(
+            &transforms,
+            rb.clone(),
+            None,
+            Vector::zero(),
+            Vector::unit_y(),
+        )
+        .with_motor(2., 100.)];
+
+        step(&mut transforms, &[&rb], &joints, 30);
+
+        let bivelocity = rb.read().unwrap().bivelocity;
+        // body 1 turns the opposite way to the world
+        assert!((bivelocity + 2. * Vector::unit_y()).magnitude() < 0.05);
+    }
+
+    #[test]
+    fn fixed_bodies_move_together() {
+        let mut transforms = TransformSystem::new();
+        let rb_1 = body(&mut transforms, Vector::zero(), 0.);
+        let rb_2 = body(&mut transforms, (1., 0., 0.).into(), 0.);
+        rb_2.write().unwrap().velocity = (0., 3., 0.).into();
+        let joints = [Joint::fixed(
+            &transforms,
+            rb_1.clone(),
+            Some(rb_2.clone()),
+            (0.5, 0., 0.).into(),
+        )];
+
+        step(&mut transforms, &[&rb_1, &rb_2], &joints, 30);
+
+        let offset = position(&transforms, &rb_2) - position(&transforms, &rb_1);
+        assert!((offset.magnitude() - 1.).abs() < 0.02);
+        assert!(position(&transforms, &rb_1).y > 0.1);
+    }
+
+    #[test]
+    fn slider_limits() {
+        let mut transforms = TransformSystem::new();
+        let rb = body(&mut transforms, Vector::zero(), 0.);
+        rb.write().unwrap().velocity = (5., 5., 0.).into();
+        let joints = [Joint::slider(
+            &transforms,
+            rb.clone(),
+            None,
+            Vector::zero(),
+            Vector::unit_x(),
+        )
+        .with_limits(-1., 1.)];
+
+        step(&mut transforms, &[&rb], &joints, 60);
+
+        let position = position(&transforms, &rb);
+        assert!(position.y.abs() < 0.02);
+        // slider position is from body 1 to the world anchor, so moving along +x hits the min
+        assert!((position.x - 1.).abs() < 0.05);
+    }
+
+    #[test]
+    fn spring_settles() {
+        let mut transforms = TransformSystem::new();
+        let rb = body(&mut transforms, (3., 0., 0.).into(), 0.);
+        let joints = [Joint::distance(
+            &transforms,
+            rb.clone(),
+            None,
+            (3., 0., 0.).into(),
+            Vector::zero(),
+        )
+        .with_length(1.)
+        .with_spring(50., 5.)];
+
+        step(&mut transforms, &[&rb], &joints, 300);
+
+        assert!((position(&transforms, &rb).magnitude() - 1.).abs() < 0.1);
+    }
+}
@@ -0,0 +1,127 @@
+/// How the values of 2 materials in contact are combined,
+/// when the rules differ the later one in this list is used
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum CombineRule {
+    #[default]
+    Average,
+    Min,
+    Multiply,
+    Max,
+}
+
+/// Surface properties of a collider
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PhysicsMaterial {
+    /// max ratio of tangent to normal impulse before sliding
+    pub static_friction: f32,
+    /// friction while sliding, the heap solver scales it by tangent velocity while the
+    /// sequential impulse solver uses it as the max ratio of tangent to normal impulse, capped
+    /// by the static friction
+    pub dynamic_friction: f32,
+    /// fraction of closing velocity kept after a bounce
+    pub restitution: f32,
+    pub friction_combine: CombineRule,
+    pub restitution_combine: CombineRule,
+}
+
+/// Material values for a single contact
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ContactMaterial {
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+    pub restitution: f32,
+}
+
+impl CombineRule {
+    pub fn combine(self, a: f32, b: f32) -> f32 {
+        match self {
+            CombineRule::Average => (a + b) / 2.,
+            CombineRule::Min => a.min(b),
+            CombineRule::Multiply => a * b,
+            CombineRule::Max => a.max(b),
+        }
+    }
+}
+
+impl PhysicsMaterial {
+    pub const CONCRETE: Self = Self {
+        static_friction: 2.,
+        dynamic_friction: 3.,
+        restitution: 0.5,
+        friction_combine: CombineRule::Average,
+        restitution_combine: CombineRule::Average,
+    };
+    pub const ICE: Self = Self {
+        static_friction: 0.05,
+        dynamic_friction: 0.05,
+        restitution: 0.1,
+        friction_combine: CombineRule::Min,
+        restitution_combine: CombineRule::Average,
+    };
+    pub const RUBBER: Self = Self {
+        static_friction: 3.,
+        dynamic_friction: 4.,
+        restitution: 0.9,
+        friction_combine: CombineRule::Average,
+        restitution_combine: CombineRule::Max,
+    };
+
+    pub fn new(static_friction: f32, dynamic_friction: f32, restitution: f32) -> Self {
+        Self {
+            static_friction,
+            dynamic_friction,
+            restitution,
+            friction_combine: CombineRule::default(),
+            restitution_combine: CombineRule::default(),
+        }
+    }
+
+    pub fn with_combine_rules(mut self, friction: CombineRule, restitution: CombineRule) -> Self {
+        self.friction_combine = friction;
+        self.restitution_combine = restitution;
+        self
+    }
+
+    /// values used for a contact between the 2 materials
+    pub fn combine(&self, other: &Self) -> ContactMaterial {
+        let friction_rule = self.friction_combine.max(other.friction_combine);
+        let restitution_rule = self.restitution_combine.max(other.restitution_combine);
+        ContactMaterial {
+            static_friction: friction_rule.combine(self.static_friction, other.static_friction),
+            dynamic_friction: friction_rule.combine(self.dynamic_friction, other.dynamic_friction),
+            restitution: restitution_rule.combine(self.restitution, other.restitution),
+        }
+    }
+}
+impl Default for PhysicsMaterial {
+    fn default() -> Self {
+        Self::CONCRETE
+    }
+}
+
+#[cfg(test)]
+mod material_tests {
+    use super::{CombineRule, PhysicsMaterial};
+
+    #[test]
+    fn combine_rules() {
+        let concrete = PhysicsMaterial::CONCRETE;
+
+        // ice uses min friction, so stays slippery on concrete
+        let ice = concrete.combine(&PhysicsMaterial::ICE);
+        assert_eq!(ice.static_friction, 0.05);
+        assert!((ice.restitution - 0.3).abs() < 1e-6);
+
+        // rubber uses max restitution
+        let rubber = PhysicsMaterial::RUBBER.combine(&concrete);
+        assert_eq!(rubber.restitution, 0.9);
+        assert_eq!(rubber.static_friction, 2.5);
+
+        let custom = PhysicsMaterial::new(0.5, 0.5, 0.5)
+            .with_combine_rules(CombineRule::Multiply, CombineRule::Multiply);
+        let combined = custom.combine(&PhysicsMaterial::RUBBER);
+        assert_eq!(combined.dynamic_friction, 2.);
+        // max beats multiply
+        assert_eq!(combined.restitution, 0.9);
+    }
+}
@@ -8,13 +8,18 @@ use cgmath::{InnerSpace, Quaternion, Rad, Rotation3, Vector3};
 
 use crate::{
     game_objects::{
-        light::PointLightComponent, transform::TransformCreateInfo, MaterialSwapper, Rotate,
-        TransformTracker, WorldLoader,
+        light::PointLightComponent, transform::TransformCreateInfo, GameWorld, MaterialSwapper,
+        Rotate, TransformTracker, WorldLoader,
     },
     load_object, load_transform_and_object,
-    physics::{CuboidCollider, RigidBody},
-    render::resource_manager::{MaterialID::*, MeshID::*, TextureID},
+    physics::{AnchoredSpring, CharacterController, Collider, ConvexHull, Drag, Joint, RigidBody},
+    render::resource_manager::{
+        MaterialID::{self, *},
+        MeshID::{self, *},
+        TextureID,
+    },
 };
+use legion::storage::Component;
 
 // type Mesh = std::sync::Arc<crate::vulkano_objects::buffers::MeshBuffers<VertexFull>>;
 
@@ -96,12 +101,15 @@ pub fn init_world(loader: WorldLoader) {
             .map(|(id, lit)| loader.resources.get_material(id, lit)),
         );
 
-        load_transform_and_object!(
-            loader.world,
-            TransformCreateInfo::from_parent(le_transform),
-            le_ro,
-            mat_swapper
+        let transform = loader
+            .world
+            .transforms
+            .add_transform(TransformCreateInfo::from_parent(le_transform));
+        let collider = loader.world.colliders.add(
+            Collider::triangle_mesh(transform, loader.resources.get_triangle_mesh(mesh)),
+            &mut loader.world.transforms,
         );
+        load_object!(loader.world.world, transform, le_ro, mat_swapper, collider);
     }
 
     // lights
@@ -152,7 +160,6 @@ pub fn init_phys_test(mut loader: WorldLoader) {
     // let plane_mesh = resources.get_mesh(Square);
     // let cube_mesh = resources.get_mesh(Cube);
 
-    // plane
     let yellow_mat = loader
         .resources
         .load_solid_material([1., 1., 0., 1.], true)
@@ -170,7 +177,7 @@ pub fn init_phys_test(mut loader: WorldLoader) {
         .load_solid_material([0., 0., 1., 1.], true)
         .0;
 
-    // plane collider
+    // plane
     let plane_trans = TransformCreateInfo {
         rotation: Quaternion::from_axis_angle([1., 0., 0.].into(), Rad(-PI / 2.)),
         scale: [10., 10., 1.].into(),
@@ -178,42 +185,6 @@ pub fn init_phys_test(mut loader: WorldLoader) {
     };
     loader.quick_ro(plane_trans, Square, yellow_mat, true);
 
-    let transform_info = TransformCreateInfo::default()
-        .with_translation([0., -0.5, 0.])
-        .with_scale([5., 0.5, 5.]);
-    let transform = loader.world.transforms.add_transform(transform_info);
-    let collider = loader.world.colliders.add(
-        CuboidCollider::new(transform, None),
-        &mut loader.world.transforms,
-    );
-    load_object!(loader.world.world, transform, collider);
-
-    // slope collider (0.1 rad)
-    let transform_info = TransformCreateInfo::default()
-        .with_translation([10., -0.5, 0.])
-        .with_scale([5., 0.5, 5.])
-        .set_rotation(Quaternion::from_axis_angle((1., 0., 0.).into(), Rad(0.1)));
-    let transform = loader.world.transforms.add_transform(transform_info);
-    let collider = loader.world.colliders.add(
-        CuboidCollider::new(transform, None),
-        &mut loader.world.transforms,
-    );
-    let ro = loader.resources.load_ro(Cube, yellow_mat, true);
-    load_object!(loader.world.world, transform, collider, ro);
-
-    // slope collider (0.2 rad)
-    let transform_info = TransformCreateInfo::default()
-        .with_translation([20., -0.5, 0.])
-        .with_scale([5., 0.5, 5.])
-        .set_rotation(Quaternion::from_axis_angle((1., 0., 0.).into(), Rad(0.2)));
-    let transform = loader.world.transforms.add_transform(transform_info);
-    let collider = loader.world.colliders.add(
-        CuboidCollider::new(transform, None),
-        &mut loader.world.transforms,
-    );
-    let ro = loader.resources.load_ro(Cube, yellow_mat, true);
-    load_object!(loader.world.world, transform, collider, ro);
-
     // axis
     loader.quick_ro(
         TransformCreateInfo::from((1., -10., 0.)).with_scale((0.1, 0.1, 0.1)),
@@ -234,73 +205,219 @@ pub fn init_phys_test(mut loader: WorldLoader) {
         true,
     );
 
+    // physics objects
+    let hull = loader.resources.get_convex_hull(Suzanne);
+    let materials = [yellow_mat, green_mat, red_mat, blue_mat];
+    init_phys_test_objects(loader.world, hull, materials, |mesh, material| {
+        loader.resources.load_ro(mesh, material, true)
+    });
+}
+
+/// Colliders, rigidbodies and joints of the physics test, without the rendering only objects
+///
+/// Materials are yellow, green, red and blue, `load_ro` creates the render object component of a mesh,
+/// so the scene can also be stepped without a renderer
+pub fn init_phys_test_objects<R: Component>(
+    world: &mut GameWorld,
+    hull: Arc<ConvexHull>,
+    [yellow_mat, green_mat, red_mat, blue_mat]: [MaterialID; 4],
+    mut load_ro: impl FnMut(MeshID, MaterialID) -> R,
+) {
+    // plane collider
+    let transform_info = TransformCreateInfo::default()
+        .with_translation([0., -0.5, 0.])
+        .with_scale([5., 0.5, 5.]);
+    let transform = world.transforms.add_transform(transform_info);
+    let collider = world
+        .colliders
+        .add(Collider::cuboid(transform, None), &mut world.transforms);
+    load_object!(world.world, transform, collider);
+
+    // slope collider (0.1 rad)
+    let transform_info = TransformCreateInfo::default()
+        .with_translation([10., -0.5, 0.])
+        .with_scale([5., 0.5, 5.])
+        .set_rotation(Quaternion::from_axis_angle((1., 0., 0.).into(), Rad(0.1)));
+    let transform = world.transforms.add_transform(transform_info);
+    let collider = world
+        .colliders
+        .add(Collider::cuboid(transform, None), &mut world.transforms);
+    let ro = load_ro(Cube, yellow_mat);
+    load_object!(world.world, transform, collider, ro);
+
+    // slope collider (0.2 rad)
+    let transform_info = TransformCreateInfo::default()
+        .with_translation([20., -0.5, 0.])
+        .with_scale([5., 0.5, 5.])
+        .set_rotation(Quaternion::from_axis_angle((1., 0., 0.).into(), Rad(0.2)));
+    let transform = world.transforms.add_transform(transform_info);
+    let collider = world
+        .colliders
+        .add(Collider::cuboid(transform, None), &mut world.transforms);
+    let ro = load_ro(Cube, yellow_mat);
+    load_object!(world.world, transform, collider, ro);
+
     // rigidbody test
-    let t = loader.world.transforms.add_transform([0., 1., 0.]);
-    let ro = loader.resources.load_ro(Cube, green_mat, true);
+    let t = world.transforms.add_transform([0., 1., 0.]);
+    let ro = load_ro(Cube, green_mat);
     let mut rb = RigidBody::new(t);
     rb.velocity = (1.0, 10.0, 0.0).into();
     rb.bivelocity = (0.0, 0.0, -5.0).into();
     rb.inv_mass = 0.5;
     rb.set_moi_as_cuboid((1., 1., 1.).into());
     let rb = Arc::new(RwLock::new(rb));
-    let collider = loader.world.colliders.add(
-        CuboidCollider::new(t, Some(rb.clone())),
-        &mut loader.world.transforms,
-    );
+    let collider = world
+        .colliders
+        .add(Collider::cuboid(t, Some(rb.clone())), &mut world.transforms);
     // println!("[DEBUG] rb id: {:?}", t);
-    load_object!(loader.world.world, t, ro, rb, collider);
+    load_object!(world.world, t, ro, rb, collider);
 
     // moving collider
-    let (pivot, _) = loader.add_1_comp([0., 0., 0.], Rotate([0., 1., 0.].into(), Rad(0.5)));
+    let (pivot, _) =
+        load_transform_and_object!(world, [0., 0., 0.], Rotate([0., 1., 0.].into(), Rad(0.5)));
 
-    let mover = loader
-        .world
+    let mover = world
         .transforms
         .add_transform(TransformCreateInfo::from([9., 0., 0.]).with_parent(Some(pivot)));
-    let collider = loader.world.colliders.add(
-        CuboidCollider::new(mover, None),
-        &mut loader.world.transforms,
-    );
-    let ro = loader.resources.load_ro(Cube, green_mat, true);
-    load_object!(loader.world.world, mover, collider, ro);
+    let collider = world
+        .colliders
+        .add(Collider::cuboid(mover, None), &mut world.transforms);
+    let ro = load_ro(Cube, green_mat);
+    load_object!(world.world, mover, collider, ro);
 
     // collider test
-    let transform = loader.world.transforms.add_transform([0., 5., 0.]);
+    let transform = world.transforms.add_transform([0., 5., 0.]);
     let mut rigidbody = RigidBody::new(transform);
     rigidbody.gravity_multiplier = 0.0;
     rigidbody.set_moi_as_cuboid((1., 1., 1.).into());
     let rigidbody = Arc::new(RwLock::new(rigidbody));
-    let collider = loader.world.colliders.add(
-        CuboidCollider::new(transform, Some(rigidbody.clone())),
-        &mut loader.world.transforms,
+    let collider = world.colliders.add(
+        Collider::cuboid(transform, Some(rigidbody.clone())),
+        &mut world.transforms,
     );
-    let ro = loader.resources.load_ro(Cube, red_mat, true);
+    let ro = load_ro(Cube, red_mat);
     load_object!(
-        loader.world.world,
+        world.world,
         transform,
         collider,
         ro,
         rigidbody // , TransformTracker("prefab")
     );
 
-    // let transform = loader.world.transforms.add_transform(
+    // let transform = world.transforms.add_transform(
     //     TransformCreateInfo::from([1.9, 5.4, 1.9]).set_rotation(Quaternion::from_axis_angle(
     //         [(0.5f32).sqrt(), 0., (0.5f32).sqrt()].into(),
     //         Rad(PI / 3.),
     //     )),
     // );
 
-    let transform = loader.world.transforms.add_transform([0., 7., 0.]);
+    let transform = world.transforms.add_transform([0., 7., 0.]);
     let mut rigidbody = RigidBody::new(transform);
     rigidbody.gravity_multiplier = 0.0;
     rigidbody.set_moi_as_cuboid((1., 1., 1.).into());
     let rigidbody = Arc::new(RwLock::new(rigidbody));
-    let collider = loader.world.colliders.add(
-        CuboidCollider::new(transform, Some(rigidbody.clone())),
-        &mut loader.world.transforms,
+    let collider = world.colliders.add(
+        Collider::cuboid(transform, Some(rigidbody.clone())),
+        &mut world.transforms,
     );
-    let ro = loader.resources.load_ro(Cube, red_mat, true);
-    load_object!(loader.world.world, transform, collider, ro, rigidbody);
+    let ro = load_ro(Cube, red_mat);
+    load_object!(world.world, transform, collider, ro, rigidbody);
+
+    // convex hull test
+    let transform = world.transforms.add_transform([3., 3., 0.]);
+    let mut rigidbody = RigidBody::new(transform);
+    rigidbody.set_moi_as_cuboid((1.4, 1., 0.8).into());
+    let rigidbody = Arc::new(RwLock::new(rigidbody));
+    let collider = world.colliders.add(
+        Collider::convex_hull(transform, Some(rigidbody.clone()), hull),
+        &mut world.transforms,
+    );
+    let ro = load_ro(Suzanne, blue_mat);
+    load_object!(world.world, transform, collider, ro, rigidbody);
+
+    // chain hanging from the world, each link is jointed to the one above
+    let mut above: Option<Arc<RwLock<RigidBody>>> = None;
+    for i in 0..4 {
+        let transform_info =
+            TransformCreateInfo::from([-8., 9. - 1.5 * i as f32, 0.]).with_scale([0.25, 0.5, 0.25]);
+        let transform = world.transforms.add_transform(transform_info);
+        let mut rigidbody = RigidBody::new(transform);
+        rigidbody.set_moi_as_cuboid((0.5, 1., 0.5).into());
+        let rigidbody = Arc::new(RwLock::new(rigidbody));
+        let joint = Joint::ball_socket(
+            &world.transforms,
+            rigidbody.clone(),
+            above.clone(),
+            [-8., 9.75 - 1.5 * i as f32, 0.].into(),
+        );
+        let collider = world.colliders.add(
+            Collider::cuboid(transform, Some(rigidbody.clone())),
+            &mut world.transforms,
+        );
+        let ro = load_ro(Cube, red_mat);
+        load_object!(
+            world.world,
+            transform,
+            collider,
+            ro,
+            rigidbody.clone(),
+            joint
+        );
+        above = Some(rigidbody);
+    }
+
+    // cube bobbing on a spring, slowed by drag
+    let transform = world.transforms.add_transform([8., 4., -6.]);
+    let mut rigidbody = RigidBody::new(transform);
+    rigidbody.set_moi_as_cuboid((1., 1., 1.).into());
+    let rigidbody = Arc::new(RwLock::new(rigidbody));
+    let spring = AnchoredSpring {
+        anchor: [8., 8., -6.].into(),
+        point: [0.4, 0.5, 0.].into(),
+        rest_length: 2.,
+        stiffness: 30.,
+        damping: 1.,
+    };
+    let drag = Drag {
+        linear: 0.2,
+        quadratic: 0.1,
+    };
+    let collider = world.colliders.add(
+        Collider::cuboid(transform, Some(rigidbody.clone())),
+        &mut world.transforms,
+    );
+    let ro = load_ro(Cube, blue_mat);
+    load_object!(
+        world.world,
+        transform,
+        collider,
+        ro,
+        rigidbody,
+        spring,
+        drag
+    );
+
+    // motorised door
+    let transform_info = TransformCreateInfo::from([-8., 1.5, 6.]).with_scale([1., 1.5, 0.1]);
+    let transform = world.transforms.add_transform(transform_info);
+    let mut rigidbody = RigidBody::new(transform);
+    rigidbody.set_moi_as_cuboid((2., 3., 0.2).into());
+    let rigidbody = Arc::new(RwLock::new(rigidbody));
+    let joint = Joint::hinge(
+        &world.transforms,
+        rigidbody.clone(),
+        None,
+        [-9., 1.5, 6.].into(),
+        [0., 1., 0.].into(),
+    )
+    .with_limits(-PI / 2., PI / 2.)
+    .with_motor(1., 20.);
+    let collider = world.colliders.add(
+        Collider::cuboid(transform, Some(rigidbody.clone())),
+        &mut world.transforms,
+    );
+    let ro = load_ro(Cube, yellow_mat);
+    load_object!(world.world, transform, collider, ro, rigidbody, joint);
 }
 
 /// controllable char
@@ -329,7 +446,7 @@ pub fn init_char_test(mut loader: WorldLoader) {
         .with_scale([10., 0.5, 10.]);
     let transform = loader.world.transforms.add_transform(transform_info);
     let collider = loader.world.colliders.add(
-        CuboidCollider::new(transform, None),
+        Collider::cuboid(transform, None),
         &mut loader.world.transforms,
     );
     let ro = loader.resources.load_ro(Cube, green_mat, true);
@@ -355,19 +472,195 @@ pub fn init_char_test(mut loader: WorldLoader) {
         true,
     );
 
-    // Char
+    // slopes (0.1 and 0.2 rad), only the first is shallow enough to walk up
+    for (x, angle) in [(-3., 0.1), (3., 0.2)] {
+        let transform_info = TransformCreateInfo::default()
+            .with_translation([x, -0.5, -5.])
+            .with_scale([2., 0.5, 5.])
+            .set_rotation(Quaternion::from_axis_angle((1., 0., 0.).into(), Rad(angle)));
+        let transform = loader.world.transforms.add_transform(transform_info);
+        let collider = loader.world.colliders.add(
+            Collider::cuboid(transform, None),
+            &mut loader.world.transforms,
+        );
+        let ro = loader.resources.load_ro(Cube, blue_mat, true);
+        load_object!(loader.world.world, transform, collider, ro);
+    }
+
+    // stairs
+    for i in 0..4 {
+        let height = 0.2 * (i + 1) as f32;
+        let transform_info = TransformCreateInfo::default()
+            .with_translation([6., height / 2., 2. + i as f32])
+            .with_scale([1., height / 2., 0.5]);
+        let transform = loader.world.transforms.add_transform(transform_info);
+        let collider = loader.world.colliders.add(
+            Collider::cuboid(transform, None),
+            &mut loader.world.transforms,
+        );
+        let ro = loader.resources.load_ro(Cube, blue_mat, true);
+        load_object!(loader.world.world, transform, collider, ro);
+    }
+
+    // pushable crate
     let transform_info = TransformCreateInfo::default()
-        .with_translation([0., 1., 0.])
-        .with_scale([0.5, 1., 0.5]);
+        .with_translation([0., 0.5, 3.])
+        .with_scale([0.5, 0.5, 0.5]);
     let transform = loader.world.transforms.add_transform(transform_info);
     let mut rb = RigidBody::new(transform);
-    rb.inv_mass = 1. / 70.;
-    rb.set_moi_as_cuboid([0.5, 1., 0.5].into());
+    rb.set_moi_as_cuboid((0.5, 0.5, 0.5).into());
     let rb = Arc::new(RwLock::new(rb));
     let collider = loader.world.colliders.add(
-        CuboidCollider::new(transform, Some(rb.clone())),
+        Collider::cuboid(transform, Some(rb.clone())),
         &mut loader.world.transforms,
     );
-    let ro = loader.resources.load_ro(Cube, yellow_mat, true);
+    let ro = loader.resources.load_ro(Cube, red_mat, true);
     load_object!(loader.world.world, ro, transform, collider, rb);
+
+    // Char, its collider has no rigidbody and is on a layer its controller ignores
+    const CHAR_LAYER: u32 = 2;
+    let transform_info = TransformCreateInfo::default()
+        .with_translation([0., 1.1, 0.])
+        .with_scale([0.5, 1., 0.5]);
+    let transform = loader.world.transforms.add_transform(transform_info);
+    let collider = loader.world.colliders.add(
+        Collider::capsule(transform, None, 0.5, 1.).with_layers(CHAR_LAYER, Collider::ALL_LAYERS),
+        &mut loader.world.transforms,
+    );
+    let controller = CharacterController::new(0.5, 0.5)
+        .with_mask(Collider::ALL_LAYERS & !CHAR_LAYER)
+        .with_max_slope(Rad(0.15));
+    let ro = loader.resources.load_ro(Cube, yellow_mat, true);
+    load_object!(loader.world.world, ro, transform, collider, controller);
+    loader.world.camera.target = Some(transform);
+}
+
+/// Heightfield terrain with cubes dropped onto it
+pub fn init_terrain_test(loader: WorldLoader) {
+    let green_mat = loader
+        .resources
+        .load_solid_material([0., 1., 0., 1.], true)
+        .0;
+    let red_mat = loader
+        .resources
+        .load_solid_material([1., 0., 0., 1.], true)
+        .0;
+
+    // terrain, collider and mesh share the same heightfield
+    let transform_info = TransformCreateInfo::default()
+        .with_translation([0., -5., 0.])
+        .with_scale([40., 10., 40.]);
+    let transform = loader.world.transforms.add_transform(transform_info);
+    let field = match loader.resources.get_heightfield(Terrain) {
+        Ok(field) => field,
+        Err(e) => {
+            println!("[Error] {e}");
+            return;
+        }
+    };
+    let collider = loader.world.colliders.add(
+        Collider::heightfield(transform, field),
+        &mut loader.world.transforms,
+    );
+    let ro = loader.resources.load_ro(Terrain, green_mat, true);
+    load_object!(loader.world.world, transform, collider, ro);
+
+    // falling cubes
+    for x in -2..=2 {
+        for z in -2..=2 {
+            let t = loader
+                .world
+                .transforms
+                .add_transform([(x * 6) as f32, 10., (z * 6) as f32]);
+            let mut rb = RigidBody::new(t);
+            rb.set_moi_as_cuboid((1., 1., 1.).into());
+            let rb = Arc::new(RwLock::new(rb));
+            let collider = loader.world.colliders.add(
+                Collider::cuboid(t, Some(rb.clone())),
+                &mut loader.world.transforms,
+            );
+            let ro = loader.resources.load_ro(Cube, red_mat, true);
+            load_object!(loader.world.world, t, ro, rb, collider);
+        }
+    }
+}
+
+#[cfg(test)]
+mod prefabs_tests {
+    use super::init_phys_test_objects;
+    use crate::{
+        game_objects::{transform::TransformID, GameWorld},
+        physics::ConvexHull,
+        render::resource_manager::MaterialID,
+    };
+    use cgmath::Matrix4;
+    use legion::IntoQuery;
+    use std::sync::Arc;
+
+    /// the physics test stepping deterministically, without render objects
+    fn phys_test_world() -> GameWorld {
+        let mut world = GameWorld::new();
+        world.set_deterministic(true);
+        // pointed box standing in for suzanne, whose hull needs its model loaded
+        let points = (0..8)
+            .map(|i| [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|b| b as f32 * 1.4 - 0.7))
+            .chain([[0., 1.2, 0.]]);
+        let hull = Arc::new(ConvexHull::new(points).unwrap());
+        // meshes stand in for render objects
+        let materials = [0, 1, 2, 3].map(MaterialID::Color);
+        init_phys_test_objects(&mut world, hull, materials, |mesh, _| mesh);
+        world.colliders.rebuild_tree();
+        world
+    }
+
+    /// global model of every object
+    fn models(world: &mut GameWorld) -> Vec<(TransformID, Matrix4<f32>)> {
+        let mut query = <&TransformID>::query();
+        let mut ids: Vec<_> = query.iter(&world.world).copied().collect();
+        ids.sort();
+        ids.into_iter()
+            .map(|id| (id, world.transforms.get_global_model(&id).unwrap()))
+            .collect()
+    }
+
+    fn step(world: &mut GameWorld, steps: usize) {
+        for _ in 0..steps {
+            world.update(0.02);
+        }
+    }
+
+    #[test]
+    fn deterministic_phys_test() {
+        let mut world = phys_test_world();
+        let start = models(&mut world);
+        step(&mut world, 300);
+        let stepped = models(&mut world);
+
+        let mut other = phys_test_world();
+        step(&mut other, 300);
+        assert_eq!(stepped, models(&mut other));
+        // the scene has to move for the comparison to mean anything
+        assert_ne!(stepped, start);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mut world = phys_test_world();
+        world.set_history_enabled(true);
+        step(&mut world, 50);
+        let snapshot = world.snapshot();
+        step(&mut world, 100);
+        let stepped = models(&mut world);
+
+        world.restore(&snapshot);
+        step(&mut world, 100);
+        assert_eq!(stepped, models(&mut world));
+
+        // stepping back through the history ends up at the snapshot
+        for _ in 0..100 {
+            assert!(world.step_back());
+        }
+        step(&mut world, 100);
+        assert_eq!(stepped, models(&mut world));
+    }
 }
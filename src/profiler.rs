@@ -21,6 +21,10 @@ impl<const COUNT: usize, const SAMPLES: usize> Profiler<COUNT, SAMPLES> {
     pub fn add_sample(&mut self, micro: u32, profile_i: usize) {
         self.profiles[profile_i].add_sample(micro, self.sample_i);
     }
+    /// average micros of a profile over the last samples
+    pub fn average(&self, profile_i: usize) -> u32 {
+        self.profiles[profile_i].average()
+    }
     pub fn end_frame(&mut self) {
         self.sample_i = (self.sample_i + 1) % SAMPLES;
     }
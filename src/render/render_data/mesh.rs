@@ -1,6 +1,6 @@
 use std::path::Path;
 
-use crate::VertexFull;
+use crate::{physics::HeightField, VertexFull};
 
 // use tobj::load_obj;
 
@@ -53,6 +53,27 @@ pub fn from_obj(file_name: &Path) -> Vec<(Vec<VertexFull>, Vec<u32>)> {
         .collect()
 }
 
+/// terrain mesh matching the triangles of a height field, uvs cover the whole field once
+///
+/// Normals are local, so the normal matrix corrects them for a non-uniform scale
+pub fn from_heightfield(field: &HeightField) -> (Vec<VertexFull>, Vec<u32>) {
+    let (width, depth) = (field.width(), field.depth());
+    let mut vertices = Vec::with_capacity(width * depth);
+
+    for z in 0..depth {
+        for x in 0..width {
+            vertices.push(VertexFull {
+                position: field.position(x, z).into(),
+                normal: field.normal(x, z).into(),
+                colour: [1., 1., 1.],
+                uv: [x as f32 / (width - 1) as f32, z as f32 / (depth - 1) as f32],
+            });
+        }
+    }
+
+    (vertices, field.indices())
+}
+
 // pub fn merge_meshes(meshes: &mut Vec<Mesh>) -> Self {
 //     let mut vertices = Vec::new();
 //     let mut indices = Vec::new();
@@ -1,6 +1,6 @@
 use std::{fs::File, io::BufReader, path::Path, sync::Arc};
 
-use png::ColorType;
+use png::{BitDepth, ColorType, Transformations};
 use vulkano::{
     buffer::{Buffer, BufferCreateInfo, BufferUsage},
     command_buffer::{
@@ -123,6 +123,40 @@ pub fn load_texture(allocators: &Allocators, queue: &Arc<Queue>, path: &Path) ->
     ImageView::new_default(image).unwrap()
 }
 
+/// load a png as heights between 0 and 1 from its first channel, returns (width, height, heights)
+///
+/// Lower bit depths and palettes are expanded, 16 bit images keep their full precision
+pub fn load_heightmap(path: &Path) -> Result<(usize, usize, Vec<f32>), String> {
+    let error = |e: &dyn std::fmt::Display| format!("Failed to load heightmap {path:?}: {e}");
+
+    let file = File::open(path).map_err(|e| error(&e))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| error(&e))?;
+    let (width, height) = reader.info().size();
+    let (color_type, bit_depth) = reader.output_color_type();
+
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| error(&"image too large"))?;
+    let mut buf = vec![0; size];
+    reader.next_frame(&mut buf).map_err(|e| error(&e))?;
+
+    let samples = color_type.samples();
+    let heights = match bit_depth {
+        BitDepth::Sixteen => buf
+            .chunks_exact(2 * samples)
+            .map(|p| u16::from_be_bytes([p[0], p[1]]) as f32 / u16::MAX as f32)
+            .collect(),
+        _ => buf
+            .chunks_exact(samples)
+            .map(|p| p[0] as f32 / u8::MAX as f32)
+            .collect(),
+    };
+
+    Ok((width as usize, height as usize, heights))
+}
+
 /// Create a texture sampler with the same mag_filter and min_filter
 pub fn create_sampler(device: Arc<Device>, filter: Filter) -> Arc<Sampler> {
     Sampler::new(
@@ -8,16 +8,17 @@ use vulkano::{
 };
 
 use crate::{
+    physics::{ConvexHull, HeightField, TriangleMesh},
     shaders::{self, SolidData},
     vulkano_objects::{buffers::MeshBuffers, pipeline::mod_to_stages},
     VertexFull,
 };
 
 use super::{
-    mesh::from_obj,
+    mesh::{from_heightfield, from_obj},
     render_data::{
         material::Shader,
-        texture::{create_sampler, load_texture},
+        texture::{create_sampler, load_heightmap, load_texture},
     },
     Context, DeferredRenderer, RenderObject, RenderSubmit,
 };
@@ -36,6 +37,8 @@ pub enum MeshID {
     LostEmpire(u8),
     Engine,
     Gun,
+    /// Generated from the heightmap at `models/terrain.png`, matches `get_heightfield`
+    Terrain,
 }
 
 const LOST_EMPIRE_MESH_COUNT: u8 = 45;
@@ -82,6 +85,9 @@ pub enum TextureID {
 /// Call `begin_retrieving` to retrieve resources
 pub struct ResourceManager {
     loaded_meshes: HashMap<MeshID, Arc<MeshBuffers<VertexFull>>>,
+    loaded_hulls: HashMap<MeshID, Arc<ConvexHull>>,
+    loaded_triangle_meshes: HashMap<MeshID, Arc<TriangleMesh>>,
+    loaded_heightfields: HashMap<MeshID, Arc<HeightField>>,
     loaded_materials: HashMap<(MaterialID, bool), RenderSubmit<()>>,
     loaded_colored: HashMap<(ColoredID, bool), RenderSubmit<Vector4<f32>>>,
     loaded_textures: HashMap<TextureID, Arc<ImageView>>,
@@ -123,6 +129,9 @@ impl ResourceManager {
     pub fn new(context: &Context) -> Self {
         ResourceManager {
             loaded_meshes: HashMap::new(),
+            loaded_hulls: HashMap::new(),
+            loaded_triangle_meshes: HashMap::new(),
+            loaded_heightfields: HashMap::new(),
             loaded_materials: HashMap::new(),
             loaded_colored: HashMap::new(),
             loaded_textures: HashMap::new(),
@@ -221,14 +230,21 @@ impl<'a> ResourceRetriever<'a> {
                             loaded_meshes.insert(MeshID::LostEmpire(i as u8), mesh);
                         }
                     }
+                    MeshID::Terrain => {
+                        let field =
+                            load_heightfield(&mut self.loaded_resources.loaded_heightfields, id)
+                                .unwrap_or_else(|e| panic!("{e}"));
+                        let (vertices, indices) = from_heightfield(&field);
+                        let mesh = Arc::new(MeshBuffers::initialize_device_local(
+                            &self.context.allocators,
+                            self.context.queue.clone(),
+                            vertices,
+                            indices,
+                        ));
+                        loaded_meshes.insert(id, mesh);
+                    }
                     _ => {
-                        let path = match id {
-                            MeshID::Cube => "models/default_cube.obj",
-                            MeshID::Suzanne => "models/suzanne.obj",
-                            MeshID::Engine => "models/engine.obj",
-                            MeshID::Gun => "models/gun.obj",
-                            _ => panic!("Unmatched mesh id"),
-                        };
+                        let path = single_mesh_path(id).expect("Unmatched mesh id");
                         loaded_meshes
                             .insert(id, mesh_from_file(self.context, path).next().unwrap());
                     }
@@ -239,6 +255,64 @@ impl<'a> ResourceRetriever<'a> {
         }
     }
 
+    /// Convex hull of a mesh's vertex positions for use with `Collider::convex_hull`
+    ///
+    /// Only meshes loaded from a single model file are supported
+    pub fn get_convex_hull(&mut self, id: MeshID) -> Arc<ConvexHull> {
+        self.loaded_resources
+            .loaded_hulls
+            .entry(id)
+            .or_insert_with(|| {
+                let path = single_mesh_path(id).expect("Convex hull needs a single mesh model");
+                let (vertices, _) = from_obj(Path::new(path)).into_iter().next().unwrap();
+                let hull = ConvexHull::new(vertices.into_iter().map(|v| v.position))
+                    .expect("Mesh is flat");
+                Arc::new(hull)
+            })
+            .clone()
+    }
+
+    /// Triangle mesh of a mesh for use with `Collider::triangle_mesh`
+    ///
+    /// Only the lost empire and meshes loaded from a single model file are supported
+    pub fn get_triangle_mesh(&mut self, id: MeshID) -> Arc<TriangleMesh> {
+        let loaded_triangle_meshes = &mut self.loaded_resources.loaded_triangle_meshes;
+        if let Some(mesh) = loaded_triangle_meshes.get(&id) {
+            return mesh.clone();
+        }
+
+        match id {
+            MeshID::LostEmpire(n) => {
+                assert!(
+                    n < LOST_EMPIRE_MESH_COUNT,
+                    "Lost empire only has 45 sub-meshes"
+                );
+                for (i, (vertices, indices)) in from_obj(Path::new("models/lost_empire.obj"))
+                    .into_iter()
+                    .enumerate()
+                {
+                    let mesh =
+                        TriangleMesh::new(vertices.into_iter().map(|v| v.position), &indices);
+                    loaded_triangle_meshes.insert(MeshID::LostEmpire(i as u8), Arc::new(mesh));
+                }
+            }
+            _ => {
+                let path = single_mesh_path(id).expect("Triangle mesh needs a single mesh model");
+                let (vertices, indices) = from_obj(Path::new(path)).into_iter().next().unwrap();
+                let mesh = TriangleMesh::new(vertices.into_iter().map(|v| v.position), &indices);
+                loaded_triangle_meshes.insert(id, Arc::new(mesh));
+            }
+        }
+        loaded_triangle_meshes[&id].clone()
+    }
+
+    /// Height field for use with `Collider::heightfield`, shares its data with the mesh of the same id
+    ///
+    /// Only terrain meshes are supported
+    pub fn get_heightfield(&mut self, id: MeshID) -> Result<Arc<HeightField>, String> {
+        load_heightfield(&mut self.loaded_resources.loaded_heightfields, id)
+    }
+
     pub fn get_material(&mut self, id: MaterialID, lit: bool) -> RenderSubmit<()> {
         match self.loaded_resources.loaded_materials.get(&(id, lit)) {
             Some(mat) => mat.clone(),
@@ -464,6 +538,36 @@ impl<'a> ResourceRetriever<'a> {
     }
 }
 
+/// path of meshes loaded from a model file with only one mesh
+fn single_mesh_path(id: MeshID) -> Option<&'static str> {
+    match id {
+        MeshID::Cube => Some("models/default_cube.obj"),
+        MeshID::Suzanne => Some("models/suzanne.obj"),
+        MeshID::Engine => Some("models/engine.obj"),
+        MeshID::Gun => Some("models/gun.obj"),
+        _ => None,
+    }
+}
+
+/// fetch height field, loading it from its heightmap if needed
+fn load_heightfield(
+    loaded_heightfields: &mut HashMap<MeshID, Arc<HeightField>>,
+    id: MeshID,
+) -> Result<Arc<HeightField>, String> {
+    if let Some(field) = loaded_heightfields.get(&id) {
+        return Ok(field.clone());
+    }
+
+    let path = match id {
+        MeshID::Terrain => "models/terrain.png",
+        _ => panic!("Height field needs a terrain mesh"),
+    };
+    let (width, depth, heights) = load_heightmap(Path::new(path))?;
+    let field = Arc::new(HeightField::new(width, depth, heights));
+    loaded_heightfields.insert(id, field.clone());
+    Ok(field)
+}
+
 fn mesh_from_file<'a>(
     context: &'a Context,
     path: &str,
@@ -71,6 +71,12 @@ pub fn main_menu(ctx: &Context, option_selected: &mut MenuOption) {
                             {
                                 *option_selected = MenuOption::LoadLevel(3);
                             }
+                            if ui
+                                .button(RichText::new("Load Terrain Test").color(Color32::WHITE))
+                                .clicked()
+                            {
+                                *option_selected = MenuOption::LoadLevel(4);
+                            }
                             if ui
                                 .button(RichText::new("Quit").color(Color32::WHITE))
                                 .clicked()
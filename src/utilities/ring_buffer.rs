@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+
+/// Queue holding at most `capacity` items, pushing onto a full buffer drops the oldest item
+pub struct RingBuffer<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> RingBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+    }
+
+    /// take the most recently pushed item
+    pub fn pop(&mut self) -> Option<T> {
+        self.items.pop_back()
+    }
+
+    pub fn newest(&self) -> Option<&T> {
+        self.items.back()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod ring_buffer_tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn push_past_capacity() {
+        let mut buffer = RingBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(i);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.capacity(), 3);
+        assert_eq!(buffer.newest(), Some(&4));
+
+        // the oldest items were dropped
+        assert_eq!(buffer.pop(), Some(4));
+        assert_eq!(buffer.pop(), Some(3));
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn pop_newest_first() {
+        let mut buffer = RingBuffer::new(4);
+        buffer.push('a');
+        buffer.push('b');
+        assert_eq!(buffer.pop(), Some('b'));
+        buffer.push('c');
+        assert_eq!(buffer.pop(), Some('c'));
+        assert_eq!(buffer.pop(), Some('a'));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn clear() {
+        let mut buffer = RingBuffer::new(2);
+        buffer.push(1);
+        buffer.push(2);
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.newest(), None);
+        assert_eq!(buffer.capacity(), 2);
+
+        buffer.push(3);
+        assert_eq!(buffer.pop(), Some(3));
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut buffer = RingBuffer::new(0);
+        buffer.push(1);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop(), None);
+    }
+}